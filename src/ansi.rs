@@ -0,0 +1,228 @@
+use cursive::theme::{BaseColor, Color, ColorStyle, Effect, Style};
+
+use crate::styled::{StyledIndexedSpan, StyledString};
+
+const ESC: char = '\x1b';
+
+/// The styling that SGR (`ESC [ ... m`) sequences have built up so far.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct SgrState {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    reverse: bool,
+    front: Option<Color>,
+    back: Option<Color>,
+}
+impl SgrState {
+    /// Applies the parameters of an SGR sequence. Parameters that could not be parsed are `None`.
+    /// Codes we don't support are ignored.
+    fn apply(&mut self, params: &[Option<u32>]) {
+        // `ESC [ m` is the same as a reset.
+        if params.is_empty() {
+            *self = SgrState::default();
+            return;
+        }
+
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            let param = if let Some(param) = param {
+                param
+            } else {
+                continue;
+            };
+            match param {
+                0 => *self = SgrState::default(),
+                1 => self.bold = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.reverse = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.reverse = false,
+                30..=37 => self.front = Some(Color::Dark(BaseColor::from((param - 30) as u8))),
+                38 => match extended_color(&mut params) {
+                    Some(color) => self.front = Some(color),
+                    // The rest of the parameters can't be told apart from the color's.
+                    None => return,
+                },
+                39 => self.front = None,
+                40..=47 => self.back = Some(Color::Dark(BaseColor::from((param - 40) as u8))),
+                48 => match extended_color(&mut params) {
+                    Some(color) => self.back = Some(color),
+                    None => return,
+                },
+                49 => self.back = None,
+                90..=97 => self.front = Some(Color::Light(BaseColor::from((param - 90) as u8))),
+                100..=107 => self.back = Some(Color::Light(BaseColor::from((param - 100) as u8))),
+                _ => {}
+            }
+        }
+    }
+
+    /// The style of text written in this state, or `None` if it is unstyled.
+    fn style(&self) -> Option<Style> {
+        let mut parts: Vec<Style> = Vec::new();
+        if self.bold {
+            parts.push(Effect::Bold.into());
+        }
+        if self.italic {
+            parts.push(Effect::Italic.into());
+        }
+        if self.underline {
+            parts.push(Effect::Underline.into());
+        }
+        if self.reverse {
+            parts.push(Effect::Reverse.into());
+        }
+        if let Some(front) = self.front {
+            parts.push(ColorStyle::front(front).into());
+        }
+        if let Some(back) = self.back {
+            parts.push(ColorStyle::back(back).into());
+        }
+
+        if parts.is_empty() {
+            None
+        } else {
+            Some(Style::merge(&parts))
+        }
+    }
+}
+
+/// Reads the color of a `38;5;n` or `38;2;r;g;b` sequence, after the `38`.
+fn extended_color(params: &mut impl Iterator<Item = Option<u32>>) -> Option<Color> {
+    let mut next = || params.next().flatten();
+    match next()? {
+        5 => Some(Color::from_256colors(next()? as u8)),
+        2 => Some(Color::Rgb(next()? as u8, next()? as u8, next()? as u8)),
+        _ => None,
+    }
+}
+
+/// Converts the ANSI SGR sequences in the text into styling, removing them from the text.
+/// Other escape sequences, such as cursor movement, are removed without doing anything.
+pub fn parse_ansi(text: &str) -> StyledString {
+    let mut source = String::with_capacity(text.len());
+    let mut spans = Vec::new();
+    let mut state = SgrState::default();
+    // Where the text styled by the current state starts.
+    let mut run_start = 0;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ESC {
+            source.push(c);
+            continue;
+        }
+
+        // Only CSI sequences are understood, anything else just has the escape removed.
+        if chars.peek() != Some(&'[') {
+            continue;
+        }
+        chars.next();
+
+        // Parameter and intermediate bytes, up until the final byte.
+        let mut params = String::new();
+        let mut end = None;
+        for c in &mut chars {
+            if ('\x40'..='\x7e').contains(&c) {
+                end = Some(c);
+                break;
+            }
+            params.push(c);
+        }
+
+        if end != Some('m') {
+            continue;
+        }
+
+        let params: Vec<Option<u32>> = if params.is_empty() {
+            Vec::new()
+        } else {
+            params
+                .split(';')
+                .map(|param| {
+                    if param.is_empty() {
+                        Some(0)
+                    } else {
+                        param.parse().ok()
+                    }
+                })
+                .collect()
+        };
+
+        let mut new_state = state.clone();
+        new_state.apply(&params);
+        if new_state != state {
+            if let Some(style) = state.style() {
+                spans.push(StyledIndexedSpan::new_range(run_start..source.len(), style));
+            }
+            state = new_state;
+            run_start = source.len();
+        }
+    }
+
+    if let Some(style) = state.style() {
+        spans.push(StyledIndexedSpan::new_range(run_start..source.len(), style));
+    }
+
+    // Styles that ended up covering no text have nothing to style.
+    spans.retain(|span| !span.is_empty());
+    StyledString::with_spans(source, spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use cursive::theme::{BaseColor, Color, ColorStyle, Effect, Style};
+
+    use super::parse_ansi;
+    use crate::styled::StyledIndexedSpan;
+
+    #[test]
+    fn test_parse_ansi() {
+        let bold_red = Style::merge(&[
+            Effect::Bold.into(),
+            ColorStyle::front(Color::Dark(BaseColor::Red)).into(),
+        ]);
+        let styled = parse_ansi("\x1b[1;31mhello\x1b[0m there");
+        assert_eq!(styled.source(), "hello there");
+        assert_eq!(
+            styled.spans(),
+            &[StyledIndexedSpan::new_range(0..5, bold_red)]
+        );
+
+        // Styles that are turned off part way through.
+        let styled = parse_ansi("\x1b[4ma\x1b[1mb\x1b[22mc");
+        assert_eq!(styled.source(), "abc");
+        let underline: Style = Effect::Underline.into();
+        assert_eq!(
+            styled.spans(),
+            &[
+                StyledIndexedSpan::new_range(0..1, underline),
+                StyledIndexedSpan::new_range(1..2, Style::merge(&[underline, Effect::Bold.into()])),
+                StyledIndexedSpan::new_range(2..3, underline),
+            ]
+        );
+
+        let styled = parse_ansi("\x1b[38;2;1;2;3mrgb\x1b[m");
+        assert_eq!(
+            styled.spans(),
+            &[StyledIndexedSpan::new_range(
+                0..3,
+                ColorStyle::front(Color::Rgb(1, 2, 3)).into()
+            )]
+        );
+
+        // Unsupported codes, such as blinking, and other CSI sequences are dropped.
+        // Sequences that aren't CSI only lose their escape.
+        let styled = parse_ansi("\x1b[5mblink\x1b[2K \x1b(Bdone\x1b[");
+        assert_eq!(styled.source(), "blink (Bdone");
+        assert!(styled.spans().is_empty());
+
+        let styled = parse_ansi("no escapes");
+        assert_eq!(styled.source(), "no escapes");
+        assert!(styled.spans().is_empty());
+    }
+}
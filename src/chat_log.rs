@@ -0,0 +1,483 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use hack_chat_types::{Nickname, Trip};
+
+use crate::{client_manager::SeenInfo, ChatMessage, MessageName};
+
+/// Keeps what happened in a channel across sessions: its messages, and when users were last seen.
+pub trait HistoryStore: fmt::Debug {
+    fn append(&mut self, message: &ChatMessage) -> io::Result<()>;
+
+    /// The last `limit` messages, oldest first.
+    fn recent(&self, limit: usize) -> io::Result<Vec<ChatMessage>>;
+
+    /// Records when the user was last seen, replacing what was recorded for them before.
+    fn record_last_seen(&mut self, nick: &str, info: &SeenInfo) -> io::Result<()>;
+
+    /// When each of the recorded users was last seen, by their nick.
+    fn last_seen(&self) -> io::Result<HashMap<Nickname, SeenInfo>>;
+}
+
+/// The file that the messages of a channel are logged to, within the log directory.
+pub fn log_path(dir: &Path, channel: &str) -> PathBuf {
+    dir.join(format!("{}.log", file_name(channel)))
+}
+
+/// The channel as a file name that stays within the directory it's joined to. Anything other
+/// than letters, digits, `-` and `_` is percent-encoded, so that channels such as `../x` or
+/// `a/b` can't name another file, while no two channels share one.
+fn file_name(channel: &str) -> String {
+    let mut name = String::with_capacity(channel.len());
+    for c in channel.chars() {
+        if c.is_alphanumeric() || c == '-' || c == '_' {
+            name.push(c);
+        } else {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                name += &format!("%{:02X}", byte);
+            }
+        }
+    }
+    name
+}
+
+/// Keeps the history in files: the messages of a channel in its log within the log directory,
+/// and when users were last seen in the file given by `--seen-file`. Either can be left out.
+#[derive(Debug)]
+pub struct FileHistoryStore {
+    /// The channel's log, along with what appends to it.
+    log: Option<(PathBuf, ChatLogWriter)>,
+    seen_path: Option<PathBuf>,
+}
+impl FileHistoryStore {
+    /// Logs the messages of the channel, without keeping when users were last seen.
+    pub fn open(dir: &Path, channel: &str) -> io::Result<Self> {
+        let path = log_path(dir, channel);
+        let writer = ChatLogWriter::open(&path)?;
+        Ok(Self {
+            log: Some((path, writer)),
+            seen_path: None,
+        })
+    }
+
+    /// Keeps when users were last seen in the file, without logging messages.
+    pub fn seen_file(path: PathBuf) -> Self {
+        Self {
+            log: None,
+            seen_path: Some(path),
+        }
+    }
+}
+impl HistoryStore for FileHistoryStore {
+    fn append(&mut self, message: &ChatMessage) -> io::Result<()> {
+        match &mut self.log {
+            Some((_, writer)) => writer.write(message),
+            None => Ok(()),
+        }
+    }
+
+    fn recent(&self, limit: usize) -> io::Result<Vec<ChatMessage>> {
+        match &self.log {
+            Some((path, _)) => load(path, limit),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn record_last_seen(&mut self, nick: &str, info: &SeenInfo) -> io::Result<()> {
+        let path = match &self.seen_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut seen = self.last_seen()?;
+        seen.insert(nick.to_owned(), info.clone());
+        let mut text = String::with_capacity(seen.len() * 32);
+        for (nick, info) in seen.iter() {
+            text += &format_seen_line(nick, info);
+            text.push('\n');
+        }
+        fs::write(path, text)
+    }
+
+    fn last_seen(&self) -> io::Result<HashMap<Nickname, SeenInfo>> {
+        let path = match &self.seen_path {
+            Some(path) => path,
+            None => return Ok(HashMap::new()),
+        };
+        match fs::read_to_string(path) {
+            // Malformed lines are skipped, as losing an entry is not important.
+            Ok(text) => Ok(text.lines().filter_map(parse_seen_line).collect()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Formats when the user was last seen as `nick\tunix seconds\tlast message`.
+fn format_seen_line(nick: &str, info: &SeenInfo) -> String {
+    let secs = info
+        .time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // Tabs and newlines are the separators, so they can't be kept in the message.
+    let message = info
+        .last_message
+        .as_deref()
+        .unwrap_or("")
+        .replace(|c: char| c == '\t' || c == '\n' || c == '\r', " ");
+    format!("{}\t{}\t{}", nick, secs, message)
+}
+
+/// Parses a line in the form of `nick\tunix seconds\tlast message`.
+fn parse_seen_line(line: &str) -> Option<(Nickname, SeenInfo)> {
+    let mut parts = line.splitn(3, '\t');
+    let nick = parts.next()?;
+    let secs = parts.next()?.parse::<u64>().ok()?;
+    let message = parts.next().filter(|message| !message.is_empty());
+    Some((
+        nick.to_owned(),
+        SeenInfo {
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            last_message: message.map(str::to_owned),
+        },
+    ))
+}
+
+/// Appends every message to a file, one per line, so that they can be read back later.
+#[derive(Debug)]
+pub struct ChatLogWriter {
+    file: File,
+}
+impl ChatLogWriter {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn write(&mut self, message: &ChatMessage) -> io::Result<()> {
+        writeln!(self.file, "{}", format_line(message))
+    }
+}
+
+/// Reads the last `limit` messages of the log, or none if there is no log yet.
+pub fn load(path: &Path, limit: usize) -> io::Result<Vec<ChatMessage>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut messages = parse_log(&text);
+    let start = messages.len().saturating_sub(limit);
+    Ok(messages.split_off(start))
+}
+
+/// Parses a log back into its messages. Malformed lines are skipped, as they are only context.
+pub fn parse_log(text: &str) -> Vec<ChatMessage> {
+    text.lines().filter_map(parse_line).collect()
+}
+
+/// Formats a message as `secs\tkind\tnick\ttrip\ttext`, with the text escaped so that it stays on
+/// a single line.
+pub fn format_line(message: &ChatMessage) -> String {
+    let secs = message
+        .timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (kind, nick) = match &message.from {
+        MessageName::User(nick) => ("user", nick.as_str()),
+        MessageName::Whisper(nick) => ("whisper", nick.as_str()),
+        MessageName::Server => ("server", ""),
+        MessageName::ServerWarn => ("warn", ""),
+        MessageName::None => ("none", ""),
+    };
+    let trip = message.trip.as_ref().map_or("", |trip| trip.0.as_str());
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        secs,
+        kind,
+        nick,
+        trip,
+        escape(&message.text)
+    )
+}
+
+pub fn parse_line(line: &str) -> Option<ChatMessage> {
+    let mut fields = line.splitn(5, '\t');
+    let secs: u64 = fields.next()?.parse().ok()?;
+    let kind = fields.next()?;
+    let nick = fields.next()?;
+    let trip = fields.next()?;
+    let text = unescape(fields.next()?);
+
+    let from = match kind {
+        "user" => MessageName::User(nick.to_owned()),
+        "whisper" => MessageName::Whisper(nick.to_owned()),
+        "server" => MessageName::Server,
+        "warn" => MessageName::ServerWarn,
+        "none" => MessageName::None,
+        _ => return None,
+    };
+    let trip = if trip.is_empty() {
+        None
+    } else {
+        Some(Trip(trip.to_owned()))
+    };
+
+    Some(ChatMessage {
+        from,
+        trip,
+        text,
+        timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+        id: 0,
+    })
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs, io,
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    use hack_chat_types::{Nickname, Trip};
+
+    use super::{
+        format_line, format_seen_line, log_path, parse_log, parse_seen_line, FileHistoryStore,
+        HistoryStore,
+    };
+    use crate::{client_manager::SeenInfo, ChatMessage, MessageName};
+
+    /// Keeps the history in memory, to check what is expected of every store.
+    #[derive(Debug, Default)]
+    struct MemoryHistoryStore {
+        messages: Vec<ChatMessage>,
+        seen: HashMap<Nickname, SeenInfo>,
+    }
+    impl HistoryStore for MemoryHistoryStore {
+        fn append(&mut self, message: &ChatMessage) -> io::Result<()> {
+            self.messages.push(message.clone());
+            Ok(())
+        }
+
+        fn recent(&self, limit: usize) -> io::Result<Vec<ChatMessage>> {
+            let start = self.messages.len().saturating_sub(limit);
+            Ok(self.messages[start..].to_vec())
+        }
+
+        fn record_last_seen(&mut self, nick: &str, info: &SeenInfo) -> io::Result<()> {
+            self.seen.insert(nick.to_owned(), info.clone());
+            Ok(())
+        }
+
+        fn last_seen(&self) -> io::Result<HashMap<Nickname, SeenInfo>> {
+            Ok(self.seen.clone())
+        }
+    }
+
+    fn message(text: &str) -> ChatMessage {
+        ChatMessage {
+            from: MessageName::User("bob".to_owned()),
+            trip: None,
+            text: text.to_owned(),
+            timestamp: SystemTime::UNIX_EPOCH,
+            id: 0,
+        }
+    }
+
+    fn seen(secs: u64, last_message: Option<&str>) -> SeenInfo {
+        SeenInfo {
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            last_message: last_message.map(str::to_owned),
+        }
+    }
+
+    /// Checks what is expected of every store's messages, with the store that is given.
+    fn check_store(store: &mut dyn HistoryStore) {
+        assert_eq!(store.recent(10).unwrap(), Vec::new());
+        for text in ["one", "two", "three"].iter() {
+            store.append(&message(text)).unwrap();
+        }
+        assert_eq!(
+            store.recent(2).unwrap(),
+            vec![message("two"), message("three")]
+        );
+        assert_eq!(store.recent(10).unwrap().len(), 3);
+    }
+
+    /// Checks what is expected of every store's last seen users, with the store that is given.
+    fn check_seen_store(store: &mut dyn HistoryStore) {
+        assert_eq!(store.last_seen().unwrap(), HashMap::new());
+        store
+            .record_last_seen("bob", &seen(60, Some("hi")))
+            .unwrap();
+        store.record_last_seen("alice", &seen(30, None)).unwrap();
+        // Recording a user again replaces what was recorded for them.
+        store.record_last_seen("bob", &seen(90, None)).unwrap();
+        let last_seen = store.last_seen().unwrap();
+        assert_eq!(last_seen.len(), 2);
+        assert_eq!(last_seen["bob"], seen(90, None));
+        assert_eq!(last_seen["alice"], seen(30, None));
+    }
+
+    #[test]
+    fn test_history_store() {
+        check_store(&mut MemoryHistoryStore::default());
+        check_seen_store(&mut MemoryHistoryStore::default());
+    }
+
+    #[test]
+    fn test_file_history_store() {
+        let dir = std::env::temp_dir().join(format!("fiskar-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(log_path(&dir, "programming"));
+
+        check_store(&mut FileHistoryStore::open(&dir, "programming").unwrap());
+        // Read back from the log by the store of a later session.
+        let store = FileHistoryStore::open(&dir, "programming").unwrap();
+        assert_eq!(
+            store.recent(10).unwrap(),
+            vec![message("one"), message("two"), message("three")]
+        );
+        // Each channel has a log of its own.
+        let store = FileHistoryStore::open(&dir, "math").unwrap();
+        assert_eq!(store.recent(10).unwrap(), Vec::new());
+
+        let path = dir.join("seen");
+        check_seen_store(&mut FileHistoryStore::seen_file(path.clone()));
+        // Read back from the file by the store of a later session.
+        let last_seen = FileHistoryStore::seen_file(path).last_seen().unwrap();
+        assert_eq!(last_seen["bob"], seen(90, None));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_seen_line() {
+        let (nick, info) = parse_seen_line("bob\t60\thi there").unwrap();
+        assert_eq!(nick, "bob");
+        assert_eq!(info, seen(60, Some("hi there")));
+
+        let (_, info) = parse_seen_line("alice\t60\t").unwrap();
+        assert_eq!(info.last_message, None);
+
+        assert_eq!(parse_seen_line("bob\tnot a number\thi"), None);
+        assert_eq!(parse_seen_line("bob"), None);
+
+        // The separators in a message are replaced, so that it stays on its line.
+        let line = format_seen_line("bob", &seen(60, Some("a\tb\nc")));
+        assert_eq!(line, "bob\t60\ta b c");
+        assert_eq!(
+            parse_seen_line(&line),
+            Some(("bob".to_owned(), seen(60, Some("a b c"))))
+        );
+    }
+
+    #[test]
+    fn test_log_path() {
+        let dir = Path::new("logs");
+        assert_eq!(log_path(dir, "programming"), dir.join("programming.log"));
+        assert_eq!(log_path(dir, "math-2_b"), dir.join("math-2_b.log"));
+        assert_eq!(log_path(dir, "日本"), dir.join("日本.log"));
+        // Nothing the channel is called puts the log outside of the directory.
+        assert_eq!(log_path(dir, "../etc"), dir.join("%2E%2E%2Fetc.log"));
+        assert_eq!(log_path(dir, "a/b"), dir.join("a%2Fb.log"));
+        assert_eq!(log_path(dir, "/root"), dir.join("%2Froot.log"));
+        assert_eq!(log_path(dir, ".."), dir.join("%2E%2E.log"));
+        assert_eq!(log_path(dir, "a\\b"), dir.join("a%5Cb.log"));
+        // Escaped channels don't collide with those written like their escapes.
+        assert_ne!(log_path(dir, "a b"), log_path(dir, "a%20b"));
+        for channel in &["../etc", "a/b", "/root", "..", "a\\b", "C:x"] {
+            assert_eq!(log_path(dir, channel).parent(), Some(dir));
+        }
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let time = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let messages = vec![
+            ChatMessage {
+                from: MessageName::User("bob".to_owned()),
+                trip: Some(Trip("abcdef".to_owned())),
+                text: "multiple\nlines\twith a \\ backslash".to_owned(),
+                timestamp: time(1600000000),
+                id: 0,
+            },
+            ChatMessage {
+                from: MessageName::Server,
+                trip: None,
+                text: "bob joined".to_owned(),
+                timestamp: time(1600000001),
+                id: 0,
+            },
+            ChatMessage {
+                from: MessageName::ServerWarn,
+                trip: None,
+                text: "You are being rate limited".to_owned(),
+                timestamp: time(1600000002),
+                id: 0,
+            },
+            ChatMessage {
+                from: MessageName::Whisper("bob".to_owned()),
+                trip: None,
+                text: "psst".to_owned(),
+                timestamp: time(1600000003),
+                id: 0,
+            },
+        ];
+
+        let mut log = String::new();
+        for message in messages.iter() {
+            log += &format_line(message);
+            log += "\n";
+        }
+        assert_eq!(log.lines().count(), 4);
+        assert_eq!(parse_log(&log), messages);
+
+        // Lines that can't be parsed are skipped.
+        let log = format!("garbage\n1\tmod\t\t\thi\n{}", format_line(&messages[1]));
+        assert_eq!(parse_log(&log), vec![messages[1].clone()]);
+    }
+}
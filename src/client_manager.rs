@@ -1,27 +1,79 @@
 use std::{
+    cmp::Ordering,
+    collections::{hash_map::RandomState, BinaryHeap, HashMap, HashSet, VecDeque},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io,
     sync::mpsc::{Receiver, SendError, Sender},
-    time,
+    time::{Duration, Instant, SystemTime},
 };
 
 use hack_chat_types::{
     client, id, server, util::ClientCommand, util::Command, util::FromJson, util::FromJsonError,
     util::IntoJson, util::MaybeExist, AccessUserId, Channel, Nickname, Password, ServerApi,
-    SessionId, UserInfo, Users,
+    SessionId, Text, Trip, UserInfo, Users,
 };
 use json::JsonValue;
 use slog::{crit, warn};
 use tungstenite::{
     client::{AutoStream, IntoClientRequest},
+    protocol::CloseFrame,
     util::NonBlockingResult,
     Message, WebSocket,
 };
 use url::Url;
 
-use crate::DisplayAction;
+use crate::{
+    chat_log::HistoryStore,
+    transport::{FrameDirection, FrameObserver, FrameRecord, Transport},
+    ConnectionStatus, DisplayAction,
+};
 
 // FIXME: Implement client action and use non-blocking methods so that we can check the event loop in the thread.
 pub enum ClientAction {
     SendChatMessage(String),
+    /// Display when the given nick was last seen.
+    Seen(Nickname),
+    /// Try joining again with a different nick, after ours was taken.
+    Rejoin(Nickname),
+    /// Display whether the given nick is online, and their trip.
+    Whois(Nickname),
+    /// Invite the user to a new channel.
+    Invite(Nickname),
+    /// Change our nick, without reconnecting.
+    ChangeNick(Nickname),
+    /// Send a message that only the user sees.
+    SendWhisper {
+        nick: Nickname,
+        text: String,
+    },
+    /// Send an emote, like `/me waves`.
+    SendEmote(String),
+    /// Leave the channel for another.
+    SwitchChannel(Channel),
+    /// Join another channel alongside this one, in a new tab with its own connection.
+    OpenChannel(Channel),
+    /// Leave the channel for another on the same connection, going back to it if the server
+    /// refuses.
+    JoinChannel(Channel),
+    /// Reconnect after losing the connection, when we wait for the user to ask.
+    Reconnect,
+    /// Close the connection cleanly and stop, as we're quitting.
+    Quit,
+    /// Stop showing what the user says.
+    Ignore(Ignore),
+    /// Show what the user says again.
+    Unignore(Ignore),
+    /// Display who is being ignored.
+    ListIgnored,
+}
+
+/// What we knew of a channel that we've left for another, for going back to it.
+pub struct PreviousChannel {
+    pub channel: Channel,
+    pub users: Users,
+    pub levels: HashMap<Nickname, UserLevel>,
+    pub topic: Option<Text>,
 }
 
 #[derive(Debug)]
@@ -46,11 +98,13 @@ pub struct Connection {
     /// A place to receive ClientActions from the main thread that it wishes to have performed
     pub action_receiver: Receiver<ClientAction>,
     /// The socket that is connected to the chat.
-    pub socket: WebSocket<AutoStream>,
+    pub socket: Box<dyn Transport>,
     /// Decides how to interpret and send certain data between the client and server.
     pub server_api: ServerApi,
     /// Keep track of tthe users
     pub users: Users,
+    /// The staff of the channel, by nick, for servers that say who they are.
+    pub levels: HashMap<Nickname, UserLevel>,
     /// V2 session id of the client, if applicable
     session_id: Option<SessionId>,
     /// The address of the server
@@ -61,12 +115,62 @@ pub struct Connection {
     pub password: Option<Password>,
     /// The channel that was joined.
     pub channel: Channel,
+    /// The topic (or message of the day) of the channel, if the server sent one.
+    pub topic: Option<Text>,
+    /// When users were last seen, which is kept even after they leave.
+    pub last_seen: LastSeen,
+    /// Receives every frame read or written, for debugging the protocol.
+    pub frame_observer: Option<FrameObserver>,
+    /// Set when the server has made it clear we shouldn't reconnect, such as being banned.
+    /// Holds the reason given.
+    pub fatal: Option<String>,
+    /// Why the server closed the connection, if it said, until the closing is handled.
+    pub close: Option<CloseReason>,
+    /// Commands waiting to be sent, see [`Connection::flush_outgoing`].
+    pub outgoing: OutgoingQueue,
+    /// How long to wait between sends after the socket's send queue fills up.
+    pub send_backoff: Duration,
+    /// Whether we reconnect by ourselves after losing the connection.
+    pub reconnect_policy: ReconnectPolicy,
+    /// While sends are slowed down, when the next may be sent.
+    next_send: Option<Instant>,
+    /// Chat messages and emotes waiting their turn, see [`Connection::pump_outgoing`].
+    chats: VecDeque<QueuedChat>,
+    /// The least time between chat messages, so that the server doesn't rate-limit us.
+    pub chat_interval: Duration,
+    /// When the next chat message may be queued to be sent.
+    next_chat: Option<Instant>,
+    /// Until when chat messages are sent further apart, after the server rate-limited us.
+    rate_limited_until: Option<Instant>,
+    /// Our chat messages that were shown as soon as they were sent, until the server echoes
+    /// them back, oldest first.
+    echoes: VecDeque<Text>,
+    /// When the connection was lost, if it has been.
+    pub disconnected_at: Option<Instant>,
+    /// The session we asked the server to resume, until it replies.
+    resuming: Option<SessionId>,
+    /// Sent once we've joined the channel.
+    pub greeting: Option<Greeting>,
+    /// When the greeting was last sent, so that it isn't sent again on every reconnect.
+    greeted_at: Option<Instant>,
+    /// Whether the server has told us we're in the channel.
+    pub in_channel: bool,
+    /// Appended to our nick to join again when it is taken, rather than asking for a new one.
+    pub nick_suffix: Option<String>,
+    /// The envelope of the command that is currently being handled.
+    pub envelope: Option<Envelope>,
+    /// When we last read from or wrote to the socket.
+    pub last_activity: Instant,
+    /// When the keepalive ping was sent, until anything is read back.
+    keepalive_sent: Option<Instant>,
+    /// The channel we left for another, until the server lets us in or refuses.
+    pub pending_join: Option<PreviousChannel>,
 }
 impl Connection {
     pub fn new(
         action_sender: Sender<DisplayAction>,
         action_receiver: Receiver<ClientAction>,
-        socket: WebSocket<AutoStream>,
+        socket: Box<dyn Transport>,
         address: String,
         server_api: ServerApi,
         nick: Nickname,
@@ -84,6 +188,31 @@ impl Connection {
             channel,
             session_id: None,
             users: Users::default(),
+            levels: HashMap::new(),
+            topic: None,
+            last_seen: LastSeen::default(),
+            frame_observer: None,
+            fatal: None,
+            close: None,
+            outgoing: OutgoingQueue::default(),
+            send_backoff: DEFAULT_SEND_BACKOFF,
+            reconnect_policy: ReconnectPolicy::Automatic,
+            next_send: None,
+            chats: VecDeque::new(),
+            chat_interval: DEFAULT_CHAT_INTERVAL,
+            next_chat: None,
+            rate_limited_until: None,
+            echoes: VecDeque::new(),
+            disconnected_at: None,
+            resuming: None,
+            greeting: None,
+            greeted_at: None,
+            in_channel: false,
+            nick_suffix: None,
+            envelope: None,
+            last_activity: Instant::now(),
+            keepalive_sent: None,
+            pending_join: None,
         }
     }
 
@@ -96,11 +225,14 @@ impl Connection {
         password: Option<Password>,
         channel: Channel,
     ) -> tungstenite::Result<Self> {
+        // Whether a `ws://` address may be used is up to the caller, but anything that isn't a
+        // websocket address at all is refused before connecting.
+        check_server_address(&address, true).map_err(|err| tungstenite::Error::Url(err.into()))?;
         let (socket, _response) = tungstenite::connect(address.as_str())?;
         Ok(Self::new(
             action_sender,
             action_receiver,
-            socket,
+            Box::new(socket),
             address,
             server_api,
             nick,
@@ -112,11 +244,37 @@ impl Connection {
     /// Recreates the socket.
     /// Note that it does _not_ send the opening salvo.
     pub fn reconnect(&mut self) -> tungstenite::Result<()> {
-        let (socket, _response) = tungstenite::connect(self.address.as_str())?;
-        self.socket = socket;
+        let (socket, _response) = match tungstenite::connect(self.address.as_str()) {
+            Ok(connected) => connected,
+            Err(err) => {
+                self.note_connect_error(&err);
+                return Err(err);
+            }
+        };
+        self.socket = Box::new(socket);
+        self.in_channel = false;
+        self.last_activity = Instant::now();
+        self.keepalive_sent = None;
         Ok(())
     }
 
+    /// When the server says the command being handled was sent, falling back to now if it
+    /// didn't say.
+    pub fn command_time(&self) -> SystemTime {
+        self.envelope
+            .as_ref()
+            .and_then(|envelope| envelope.time)
+            .map(|time| SystemTime::UNIX_EPOCH + Duration::from_millis(time))
+            .unwrap_or_else(SystemTime::now)
+    }
+
+    /// Stops us from reconnecting if the error means that trying again won't help.
+    pub fn note_connect_error(&mut self, err: &tungstenite::Error) {
+        if let Some(hint) = connect_error_hint(err) {
+            self.fatal = Some(hint);
+        }
+    }
+
     /// This is meant to register handlers relating directly to the connection.
     /// The most notable of that being tracking the userid -> username mapping
     /// that the v2 server requires.
@@ -124,7 +282,20 @@ impl Connection {
         // TODO: for many of the functions we could work to grab as much info as possible from them
         // to keep track of user information for later use. (Because, the server, especially on
         // legacy, does not provide us all pertinent information (trips, hashes) on join.)
-        handlers.session.addg(|con, _, session| {
+        handlers.session.addg(|con, state, session| {
+            // A different session means the server didn't resume ours, and so we aren't back in
+            // the channel.
+            if let Some(resumed) = con.resuming.take() {
+                if resumed != session.session_id {
+                    if let Err(err) = con.send_join() {
+                        warn!(
+                            state.log,
+                            "Failed to rejoin after session resume was refused: {}", err
+                        );
+                    }
+                }
+            }
+
             // TODO: log if we already had a session id and are getting a new one.
             // We are forced to clone the session id here rather than taking ownership of it because
             // of not receiving ownership of the session command.
@@ -139,6 +310,8 @@ impl Connection {
             // We clear the tracked users as they have been set.
             // As the online set command is only ran when the client connects.
             con.users.clear();
+            // How many users have our nick, as more than one is probably us from elsewhere.
+            let mut own_nick_count = 0;
             if let Some(users) = &online_set.users {
                 let mut found_self = false;
                 let mut found_self_from_me_field = false;
@@ -151,6 +324,9 @@ impl Connection {
                         .unwrap_or_else(|| con.users.generate_id());
 
                     let nick = user.nick.clone();
+                    if nick == con.joined_nick {
+                        own_nick_count += 1;
+                    }
 
                     let trip = user.trip.clone();
 
@@ -206,7 +382,7 @@ impl Connection {
                     let user_id = con.users.generate_id();
 
                     if nick == &con.joined_nick {
-                        // TODO: log if we found ourself twice.
+                        own_nick_count += 1;
                         found_self = true;
                         con.users.ourself = Some(user_id);
                     }
@@ -241,15 +417,58 @@ impl Connection {
                 // TODO: Log error in this case.
                 crit!(state.log, "Did not receive any user information from onlineSet. This could be quite bad for behavior of program.");
             }
+
+            if own_nick_count > 1 {
+                let nick = con.joined_nick.clone();
+                if let Err(err) = con.warn_duplicate_session(&nick) {
+                    warn!(state.log, "Failed to send duplicate session warning: {}", err);
+                }
+            }
+        });
+
+        // The online set is how we know that we're in the channel.
+        handlers.online_set.addg(|con, state, _| {
+            con.in_channel = true;
+            // The old channel's chat is only given up on now, as the join could've been refused.
+            if con.accept_join() {
+                let channel = con.channel.clone();
+                let actions = vec![
+                    DisplayAction::ClearChat,
+                    DisplayAction::SetStatus(ConnectionStatus::Connected(channel.clone())),
+                    DisplayAction::ReopenHistory(channel),
+                ];
+                for action in actions {
+                    if let Err(err) = con.act(action) {
+                        warn!(state.log, "Failed to send joined channel action: {}", err);
+                    }
+                }
+            }
+            con.greet(Instant::now());
         });
 
-        handlers.online_add.addg(|con, _, add| {
+        handlers.online_add.addg(|con, state, add| {
             // TODO: if channel is wrong then comment that the channel is incorrect
+            // Legacy servers don't give user ids, so we make one up and users are told apart by
+            // their nicks alone.
             let user_id = add
                 .user_id
                 .map(AccessUserId::Server)
                 .unwrap_or_else(|| con.users.generate_id());
 
+            // Without a user id there's no telling a second session apart from us, but then the
+            // server wouldn't let anyone else join with our nick either.
+            if add.user_id.is_some()
+                && add.nick == con.own_nick()
+                && con.users.ourself != Some(user_id)
+            {
+                if let Err(err) = con.warn_duplicate_session(&add.nick) {
+                    warn!(
+                        state.log,
+                        "Failed to send duplicate session warning: {}", err
+                    );
+                }
+            }
+
             con.users.insert(
                 user_id,
                 UserInfo {
@@ -260,7 +479,86 @@ impl Connection {
             )
         });
 
+        handlers.chat.addg(|con, _, chat| {
+            con.last_seen
+                .record_message(&chat.nick, &chat.text, SystemTime::now());
+        });
+
+        handlers.online_remove.addg(|con, state, remove| {
+            if let Err(err) = con.last_seen.record_leave(&remove.nick, SystemTime::now()) {
+                warn!(state.log, "Failed to save last seen information: {}", err);
+            }
+        });
+
+        handlers.warn.addg(|con, state, warn| {
+            let kind = WarnKind::classify(&warn.text);
+            if kind.is_fatal() {
+                crit!(
+                    state.log,
+                    "Received a warning that we can't continue from: {}",
+                    warn.text
+                );
+                con.fatal = Some(warn.text.clone());
+            }
+            // What we say while told to wait is kept until we may speak again.
+            match kind {
+                WarnKind::RateLimited => con.rate_limited(Instant::now()),
+                WarnKind::Cooldown { seconds } => {
+                    con.hold_chats(Instant::now() + Duration::from_secs(seconds))
+                }
+                _ => {}
+            }
+            // Warnings about anything else, such as a cooldown, leave the join waiting.
+            let refused = con.pending_join.is_some() && kind.refuses_join(&warn.text);
+            if let (true, Some(channel)) = (refused, con.reject_join()) {
+                let channel = channel.clone();
+                warn!(
+                    state.log,
+                    "Staying in ?{}, as joining another was refused: {}", channel, warn.text
+                );
+                if let Err(err) = con.act(DisplayAction::SetStatus(ConnectionStatus::Connected(
+                    channel,
+                ))) {
+                    warn!(state.log, "Failed to send status action: {}", err);
+                }
+                let topic = con.topic.clone();
+                if let Err(err) = con.act(DisplayAction::SetTopic(topic)) {
+                    warn!(state.log, "Failed to send topic action: {}", err);
+                }
+                return;
+            }
+            // Once we're in the channel, it is about someone else's nick.
+            if kind == WarnKind::NickTaken && !con.in_channel {
+                if let Err(err) = con.retry_nick() {
+                    warn!(
+                        state.log,
+                        "Failed to retry joining with another nick: {}", err
+                    );
+                }
+            }
+        });
+
+        handlers.topic.addg(|con, _, topic| {
+            con.topic = Some(topic.text.clone());
+        });
+
+        handlers.nick_change.addg(|con, _, change| {
+            // Only the nick changes, so the user keeps their id and trip.
+            let ourself = change.user_id.is_some() && change.user_id == con.users.ourself;
+            if let Some(info) = change.user_id.and_then(|id| con.users.get_mut(id)) {
+                info.nick = change.nick.clone();
+            }
+            if let Some(level) = con.levels.remove(&change.old_nick) {
+                con.levels.insert(change.nick.clone(), level);
+            }
+            // So that reconnecting doesn't take back the old nick.
+            if ourself {
+                con.joined_nick = change.nick.clone();
+            }
+        });
+
         handlers.online_remove.addg(|con, _, remove| {
+            // Legacy servers only say the nick of whoever left.
             let user_id = remove
                 .user_id
                 .map(AccessUserId::Server)
@@ -284,6 +582,61 @@ impl Connection {
         });
     }
 
+    /// The nick we are known as, which is the one we joined with unless the server told us
+    /// otherwise.
+    pub fn own_nick(&self) -> &str {
+        self.users
+            .ourself
+            .and_then(|id| self.users.get(id))
+            .map(|info| info.nick.as_str())
+            .unwrap_or_else(|| self.joined_nick.as_str())
+    }
+
+    /// Our trip, if the server has told us it.
+    pub fn own_trip(&self) -> Option<Trip> {
+        self.users
+            .ourself
+            .and_then(|id| self.users.get(id))
+            .and_then(|info| info.trip.clone().into())
+    }
+
+    /// Notes that our chat message was shown as we sent it, so that it isn't shown again when
+    /// the server echoes it back. Only the latest few are kept, for servers that don't echo.
+    pub fn record_echo(&mut self, text: Text) {
+        if self.echoes.len() == MAX_ECHOES {
+            self.echoes.pop_front();
+        }
+        self.echoes.push_back(text);
+    }
+
+    /// Whether the chat message is the server echoing one of ours that was already shown,
+    /// which is then no longer waited on. The server may trim what we sent.
+    pub fn take_echo(&mut self, nick: &str, text: &str) -> bool {
+        if nick != self.own_nick() {
+            return false;
+        }
+        match self
+            .echoes
+            .iter()
+            .position(|echo| echo.trim() == text.trim())
+        {
+            Some(i) => {
+                self.echoes.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Tells the user that someone else in the channel has our nick, which is likely our account
+    /// connected from elsewhere.
+    pub fn warn_duplicate_session(&mut self, nick: &str) -> Result<(), SendError<DisplayAction>> {
+        self.act(DisplayAction::DisplayDialog(format!(
+            "'{}' is in the channel more than once, you may be connected elsewhere.",
+            nick
+        )))
+    }
+
     /// Send an action to be performed over the channel.
     pub fn act(&mut self, action: DisplayAction) -> Result<(), SendError<DisplayAction>> {
         self.action_sender.send(action)
@@ -296,14 +649,211 @@ impl Connection {
         T: Sized + ClientCommand + IntoJson,
     {
         let message = message.into_json(self.server_api).dump();
-        self.socket.write_message(Message::Text(message))
+        self.write_message(Message::Text(message))
+    }
+
+    /// Queue a command to be sent by the next [`Connection::flush_outgoing`].
+    pub fn queue<T>(&mut self, priority: Priority, message: T)
+    where
+        T: Sized + ClientCommand + IntoJson,
+    {
+        let command = OutgoingCommand::new(priority, message, self.server_api);
+        self.outgoing.push(command);
+    }
+
+    /// Queue a chat message in the channel, to be sent once those before it have been and enough
+    /// time has passed since the last, see [`Connection::pump_outgoing`].
+    pub fn enqueue_chat(&mut self, text: Text) {
+        self.chats.push_back(QueuedChat::Chat(text));
+    }
+
+    /// How many chat messages are waiting their turn to be sent.
+    pub fn pending_chats(&self) -> usize {
+        self.chats.len()
+    }
+
+    /// Queues the next chat message if it's its turn, and then sends every queued command. This
+    /// is called on each pass of the socket loop, so that chat messages go out one at a time.
+    pub fn pump_outgoing(&mut self) -> Result<(), tungstenite::Error> {
+        self.pump_outgoing_at(Instant::now())
+    }
+
+    /// Like [`Connection::pump_outgoing`], but as if it were `now`.
+    pub fn pump_outgoing_at(&mut self, now: Instant) -> Result<(), tungstenite::Error> {
+        let turn = self.next_chat.map_or(true, |next_chat| now >= next_chat);
+        if turn {
+            if let Some(chat) = self.chats.pop_front() {
+                // The channel is the one we're in when it's sent, which a join may have changed.
+                let command = match chat {
+                    QueuedChat::Chat(text) => {
+                        let msg = client::Chat {
+                            channel: Some(self.channel.clone()),
+                            text,
+                        };
+                        OutgoingCommand::new(Priority::Low, msg, self.server_api)
+                    }
+                    QueuedChat::Emote(text) => {
+                        OutgoingCommand::new(Priority::Low, client::Emote { text }, self.server_api)
+                    }
+                };
+                self.outgoing.push(command);
+                self.next_chat = Some(now + self.current_chat_interval(now));
+            }
+        }
+        self.flush_outgoing_at(now)
+    }
+
+    /// The time between chat messages, which is longer for a while after being rate-limited.
+    fn current_chat_interval(&self, now: Instant) -> Duration {
+        match self.rate_limited_until {
+            Some(until) if now < until => self.chat_interval * RATE_LIMIT_SLOWDOWN,
+            _ => self.chat_interval,
+        }
+    }
+
+    /// The server rate-limited us, so chat messages are sent further apart for a while, starting
+    /// with the next.
+    pub fn rate_limited(&mut self, now: Instant) {
+        self.rate_limited_until = Some(now + RATE_LIMIT_PENALTY);
+        self.next_chat = Some(now + self.current_chat_interval(now));
+    }
+
+    /// Holds back chat messages until then, such as when the server tells us to wait.
+    pub fn hold_chats(&mut self, until: Instant) {
+        if self.next_chat.map_or(true, |next_chat| next_chat < until) {
+            self.next_chat = Some(until);
+        }
+    }
+
+    /// Send every queued command, highest priority first.
+    /// If a send fails, that command and the ones after it are kept for the next flush.
+    pub fn flush_outgoing(&mut self) -> Result<(), tungstenite::Error> {
+        self.flush_outgoing_at(Instant::now())
+    }
+
+    /// Like [`Connection::flush_outgoing`], but as if it were `now`. While sends are slowed down,
+    /// only one command is sent per [`Connection::send_backoff`].
+    pub fn flush_outgoing_at(&mut self, now: Instant) -> Result<(), tungstenite::Error> {
+        loop {
+            if let Some(next_send) = self.next_send {
+                if now < next_send {
+                    return Ok(());
+                }
+                // We've caught up, so sends can go at full speed again.
+                if self.outgoing.is_empty() {
+                    self.next_send = None;
+                    // The status is only a hint, so it not being shown doesn't matter.
+                    let _ = self.act(DisplayAction::SetSlowSends(false));
+                }
+            }
+            let message = match self.outgoing.peek() {
+                Some(command) => Message::Text(command.message.clone()),
+                None => return Ok(()),
+            };
+            match self.write_message(message) {
+                Ok(()) => {}
+                // The command is still queued, so it is retried once we've slowed down.
+                Err(tungstenite::Error::SendQueueFull(_)) => {
+                    self.slow_down(now);
+                    return Ok(());
+                }
+                Err(err) => return Err(err),
+            }
+            self.outgoing.pop();
+            if self.next_send.is_some() {
+                self.next_send = Some(now + self.send_backoff);
+            }
+        }
+    }
+
+    /// The socket's send queue was full, so the message is queued to be retried, and sends are
+    /// slowed down until we catch up.
+    pub fn send_queue_full(&mut self, message: Message, now: Instant) {
+        // Only our commands are worth retrying, the rest are control frames.
+        if let Message::Text(message) = message {
+            self.outgoing.push(OutgoingCommand {
+                priority: Priority::High,
+                message,
+            });
+        }
+        self.slow_down(now);
+    }
+
+    fn slow_down(&mut self, now: Instant) {
+        if self.next_send.is_none() {
+            let _ = self.act(DisplayAction::SetSlowSends(true));
+        }
+        self.next_send = Some(now + self.send_backoff);
+    }
+
+    /// Write a raw message to the socket.
+    pub fn write_message(&mut self, message: Message) -> Result<(), tungstenite::Error> {
+        self.observe_frame(FrameDirection::Outbound, &message);
+        self.socket.write_message(message)?;
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Pings the server, so that a connection which has gone quiet is found out if it has died.
+    /// Anything read back counts as the answer.
+    pub fn send_keepalive(&mut self, now: Instant) -> Result<(), tungstenite::Error> {
+        self.write_message(Message::Ping(Vec::new()))?;
+        self.keepalive_sent = Some(now);
+        Ok(())
+    }
+
+    /// Advances writes that the socket couldn't finish straight away, without waiting on a read.
+    pub fn flush(&mut self) -> Result<(), tungstenite::Error> {
+        self.socket.write_pending().no_block()?;
+        Ok(())
+    }
+
+    /// Tells the server that we're leaving, closing the socket.
+    pub fn close(&mut self) -> Result<(), tungstenite::Error> {
+        self.write_message(Message::Close(None))?;
+        self.flush()
+    }
+
+    /// Leaves for good, sending what is still queued before closing the socket. It waits up to
+    /// the timeout for it all to be written, so that the server sees us leave rather than timing
+    /// us out.
+    pub fn quit(&mut self, timeout: Duration) -> Result<(), tungstenite::Error> {
+        let deadline = Instant::now() + timeout;
+        self.flush_outgoing()?;
+        self.close()?;
+        while self.socket.write_pending().no_block()?.is_none() {
+            if Instant::now() >= deadline {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
     }
 
     // TODO: handle closing error from this
-    // TODO: call write_pending ourselves to advance it?
     /// Read a message from the server. Non-blocking.
     pub fn read_message(&mut self) -> Result<Option<Message>, tungstenite::Error> {
-        self.socket.read_message().no_block()
+        let message = self.socket.read_message().no_block()?;
+        if let Some(message) = &message {
+            self.observe_frame(FrameDirection::Inbound, message);
+            self.last_activity = Instant::now();
+            self.keepalive_sent = None;
+        }
+        // The close frame comes before the error saying that the connection was closed.
+        if let Some(Message::Close(Some(frame))) = &message {
+            let close = CloseReason::from(frame);
+            if close.is_fatal() {
+                self.fatal = Some(close.to_string());
+            }
+            self.close = Some(close);
+        }
+        Ok(message)
+    }
+
+    fn observe_frame(&mut self, direction: FrameDirection, message: &Message) {
+        if let Some(observer) = &mut self.frame_observer {
+            observer(&FrameRecord::new(direction, message));
+        }
     }
 
     /// Read a message as json from the server, ignoring the rest. Non-blocking.
@@ -316,241 +866,3001 @@ impl Connection {
         }
     }
 
+    /// Gets us into the channel on a fresh connection.
+    /// Servers with the v2 api start with a session, which lets a quick reconnect resume it. Legacy
+    /// servers have no sessions, so they're only sent the join.
     pub fn send_opening_commands(&mut self) -> Result<(), tungstenite::Error> {
-        if self.server_api == ServerApi::HackChatV2 {
-            self.send(client::Session {
+        match self.server_api {
+            ServerApi::HackChatV2 => self.send(client::Session {
                 id: None,
                 is_bot: false,
-            })?;
+            })?,
+            ServerApi::HackChatLegacy => {}
         }
 
+        self.send_join()
+    }
+
+    pub fn send_join(&mut self) -> Result<(), tungstenite::Error> {
         self.send(client::Join {
             nick: self.joined_nick.clone(),
             channel: self.channel.clone(),
             password: self.password.clone(),
-        })?;
+        })
+    }
 
+    /// Our nick was taken while joining, so join again with the suffix added if there is one, or
+    /// otherwise ask for a new nick.
+    pub fn retry_nick(&mut self) -> Result<(), RetryNickError> {
+        if let Some(suffix) = &self.nick_suffix {
+            self.joined_nick.push_str(suffix);
+            self.send_join()?;
+        } else {
+            self.act(DisplayAction::PromptNick(self.joined_nick.clone()))?;
+        }
         Ok(())
     }
-}
 
-// NOTE: This requires a connection reference rather than being completely generic as Rust can be a
-// pain.
-// If you have a structure that is generic (aka CommandHandlers, but that goes to this Handler)
-// then if want to have a single type parameter that is user-defined parameters to the function
-// then if they want to pass in a tuple, to allow for multiple arguments, they for some
-// forsaken reason _have_ to give lifetime parameters.
-// Ex: struct Thing<T> { handler: Handler<T, Other>, }
-// Use: struct Alpha { thing: Thing<(u64, &mut File)>, file: File }
-//                                         ^-- required lifetime here!
-// I ran into the issue that if I specified the lifetime, then I also had to give a lifetime to my
-// structure in the function that was calling it or a lifetime that lived at least as long as it.
-// Ex: fn do_thing<'a>(&'a self) { (self.thing.handler)((5, &mut self.file)) }
-// But for some reason Rust could not figure out that the function (handler) that was called would
-// not keep the value given and so I could no longer use the fields that were given over.
-// I'm not sure why all generics are required to have lifetimes, if they aren't used in a location
-// where that is needed. If this had variadic types that could have also helped avoid the issue
-//   (though it could still be ran into if it was still required...)
-// As well, since the extra `T` parameter (custom state) is also generic, I have to at least specify
-// `&mut` upon it, otherwise I would be forced to specify a lifetime as well.
-// Having a way to map a tuple of types to a tuple of (mut-)references to those types would be nice.
-pub type Handler<T, C> = Box<dyn Fn(&mut Connection, &mut T, &C)>;
-pub struct HandlerList<T, C> {
-    handlers: Vec<Handler<T, C>>,
-}
-impl<T, C> HandlerList<T, C> {
-    /// Calls each handler in order.
-    /// Returns `true` if any amount of handlers were called.
-    pub fn call(&self, con: &mut Connection, v: &mut T, c: &C) -> bool {
-        for handler in self.handlers.iter() {
-            (handler)(con, v, c)
+    /// Queues the greeting, unless there is none or it was sent recently, such as before a
+    /// reconnect. Returns whether it was queued.
+    pub fn greet(&mut self, now: Instant) -> bool {
+        let greeting = if let Some(greeting) = &self.greeting {
+            greeting
+        } else {
+            return false;
+        };
+        let recent = self.greeted_at.map_or(false, |at| {
+            now.saturating_duration_since(at) < GREETING_COOLDOWN
+        });
+        if recent {
+            return false;
         }
 
-        !self.handlers.is_empty()
+        let msg = client::Chat {
+            channel: Some(self.channel.clone()),
+            text: greeting.text(),
+        };
+        self.queue(Priority::Low, msg);
+        self.greeted_at = Some(now);
+        true
     }
 
-    pub fn add(&mut self, handler: Handler<T, C>) {
-        self.handlers.push(handler)
+    /// Queues an invite for the user to a new channel, which the server tells us the name of.
+    pub fn invite(&mut self, nick: Nickname) {
+        let msg = client::Invite {
+            channel: Some(self.channel.clone()),
+            nick,
+        };
+        self.queue(Priority::Normal, msg);
     }
 
-    pub fn addg<F>(&mut self, func: F)
-    where
-        F: 'static + Fn(&mut Connection, &mut T, &C),
-    {
-        self.add(Box::new(func))
+    /// Queues a whisper to the user, which the server tells us it sent as an info.
+    pub fn whisper(&mut self, nick: Nickname, text: String) {
+        self.queue(Priority::Low, client::Whisper { nick, text });
     }
-}
-impl<T, C> Default for HandlerList<T, C> {
-    fn default() -> Self {
-        Self {
-            handlers: Vec::with_capacity(2),
-        }
+
+    /// Queues an emote, which the server echoes back to us like any other. It's paced along with
+    /// the chat messages, in the order they were said.
+    pub fn emote(&mut self, text: String) {
+        self.chats.push_back(QueuedChat::Emote(text));
     }
-}
-pub struct CommandHandlers<T>
-where
-    T: Sized,
-{
-    // TODO; handlers for raw invite and emote commands, which we can do since we're only passing
-    // references
-    pub session: HandlerList<T, server::Session>,
-    pub online_set: HandlerList<T, server::OnlineSet>,
-    pub info: HandlerList<T, server::Info>,
-    pub chat: HandlerList<T, server::Chat>,
-    pub captcha: HandlerList<T, server::Captcha>,
-    pub emote: HandlerList<T, server::synthetic::Emote>,
-    pub invite: HandlerList<T, server::synthetic::Invite>,
-    pub online_add: HandlerList<T, server::OnlineAdd>,
-    pub online_remove: HandlerList<T, server::OnlineRemove>,
-    pub warn: HandlerList<T, server::Warn>,
-}
-impl<T> Default for CommandHandlers<T>
-where
-    T: Sized,
-{
-    fn default() -> Self {
-        CommandHandlers {
-            session: HandlerList::default(),
-            online_set: HandlerList::default(),
-            info: HandlerList::default(),
-            chat: HandlerList::default(),
-            captcha: HandlerList::default(),
-            emote: HandlerList::default(),
-            invite: HandlerList::default(),
-            online_add: HandlerList::default(),
-            online_remove: HandlerList::default(),
-            warn: HandlerList::default(),
-        }
+
+    /// Queues a change of our nick. It's assumed to succeed, so that reconnecting uses it, and if
+    /// the nick is taken the server warns us as usual.
+    pub fn change_nick(&mut self, nick: Nickname) {
+        self.joined_nick = nick.clone();
+        self.queue(Priority::Normal, client::ChangeNick { nick });
     }
-}
 
-#[derive(Debug, Clone)]
-pub enum HandleCommandError {
-    FromJson(FromJsonError),
-    EmoteConversion(server::synthetic::EmoteConversionError),
-}
-impl From<FromJsonError> for HandleCommandError {
-    fn from(err: FromJsonError) -> Self {
-        Self::FromJson(err)
+    /// Leaves for the channel by closing the socket, so that the reconnect joins it instead.
+    /// The session isn't resumed, as that would put us back in the old channel.
+    pub fn switch_channel(&mut self, channel: Channel) -> Result<(), tungstenite::Error> {
+        self.channel = channel;
+        self.session_id = None;
+        self.close()
     }
-}
-impl From<server::synthetic::EmoteConversionError> for HandleCommandError {
-    fn from(err: server::synthetic::EmoteConversionError) -> Self {
-        Self::EmoteConversion(err)
+
+    /// Joins the channel in place of the one we're in, without reconnecting. What we knew of the
+    /// old one is kept until the server lets us in, so that it can be gone back to if it doesn't.
+    pub fn join_channel(&mut self, channel: Channel) -> Result<(), tungstenite::Error> {
+        let previous = PreviousChannel {
+            channel: std::mem::replace(&mut self.channel, channel),
+            users: std::mem::take(&mut self.users),
+            levels: std::mem::take(&mut self.levels),
+            topic: self.topic.take(),
+        };
+        // A join already waiting on the server is given up on, so the oldest is gone back to.
+        if self.pending_join.is_none() {
+            self.pending_join = Some(previous);
+        }
+        self.send_join()
     }
-}
 
-pub struct Client {
-    pub con: Connection,
-    pub handlers: CommandHandlers<ClientState>,
-    pub state: ClientState,
-    /// The amount of time we're sleeping in between connection attempts.
-    pub timeout: time::Duration,
-}
-impl Client {
-    pub fn new(con: Connection, state: ClientState) -> Self {
-        let mut handlers = CommandHandlers::default();
-        Connection::register_handlers(&mut handlers);
-        Self {
-            con,
-            state,
-            handlers,
-            // 500ms
-            timeout: time::Duration::from_millis(500),
+    /// The server let us into the channel we were joining. Returns whether we were joining one.
+    pub fn accept_join(&mut self) -> bool {
+        if self.pending_join.take().is_none() {
+            return false;
         }
+        // Resuming the session would put us back in the old channel.
+        self.session_id = None;
+        true
     }
 
-    pub fn handle_json(&mut self, json: JsonValue) -> Result<(), HandleCommandError> {
-        let cmd = json[id::CMD].as_str();
-        if let Some(cmd) = cmd {
-            let server_api = self.con.server_api;
-            let state = &mut self.state;
-            let con = &mut self.con;
-            // TODO: add the rest of the commands
-            // TODO: add synthesized commands.
-            let _ran_cmd = match cmd {
-                server::Session::CMD => self.handlers.session.call(
-                    con,
-                    state,
-                    &server::Session::from_json(json, server_api)?,
-                ),
-                server::OnlineSet::CMD => self.handlers.online_set.call(
-                    con,
-                    state,
-                    &server::OnlineSet::from_json(json, server_api)?,
-                ),
-                server::Info::CMD => {
-                    let info = server::Info::from_json(json, server_api)?;
-                    // Break apart info into separate commands.
-                    if let Ok(invite) = server::synthetic::Invite::from_info(&con.users, &info) {
-                        self.handlers.invite.call(con, state, &invite)
-                    } else if let Ok(emote) = server::synthetic::Emote::from_info(&con.users, &info)
-                    {
-                        self.handlers.emote.call(con, state, &emote)
-                    } else {
-                        self.handlers.info.call(con, state, &info)
-                    }
-                }
-                server::Chat::CMD => {
-                    self.handlers
-                        .chat
-                        .call(con, state, &server::Chat::from_json(json, server_api)?)
-                }
-                server::OnlineAdd::CMD => self.handlers.online_add.call(
-                    con,
-                    state,
-                    &server::OnlineAdd::from_json(json, server_api)?,
-                ),
-                server::OnlineRemove::CMD => self.handlers.online_remove.call(
-                    con,
-                    state,
-                    &server::OnlineRemove::from_json(json, server_api)?,
-                ),
-                server::Captcha::CMD => self.handlers.captcha.call(
-                    con,
-                    state,
-                    &server::Captcha::from_json(json, server_api)?,
-                ),
-                server::Invite::CMD => {
-                    let invite = server::Invite::from_json(json, server_api)?;
-                    let invite = server::synthetic::Invite::from_invite(&con.users, invite);
-                    self.handlers.invite.call(con, state, &invite)
-                }
-                server::Emote::CMD => {
-                    let emote = server::Emote::from_json(json, server_api)?;
-                    let emote = server::synthetic::Emote::from_emote(&con.users, &emote)?;
-                    self.handlers.emote.call(con, state, &emote)
-                }
-                server::Warn::CMD => {
-                    // TODO: break warn down into component 'commands' like ratelimit and such
-                    self.handlers
-                        .warn
-                        .call(con, state, &server::Warn::from_json(json, server_api)?)
-                }
-                _ => {
-                    // We ignore the command.
-                    warn!(
-                        self.log(),
-                        "Unhandled command from websocket: '{}', JSON: '{:?}'",
-                        id::CMD,
-                        json.pretty(2)
-                    );
-                    // TODO: log that we got an unknown command value
-                    false
+    /// The server refused to let us into the channel we were joining, so we go back to the one
+    /// we were in. Returns it, if we were joining.
+    pub fn reject_join(&mut self) -> Option<&Channel> {
+        let previous = self.pending_join.take()?;
+        self.channel = previous.channel;
+        self.users = previous.users;
+        self.levels = previous.levels;
+        self.topic = previous.topic;
+        Some(&self.channel)
+    }
+
+    /// Note that the connection was lost in a way that reconnecting could fix, returning whether
+    /// to reconnect now. Otherwise the user is asked, see [`Connection::wait_for_reconnect`].
+    pub fn lost_connection(&mut self) -> Result<bool, SendError<DisplayAction>> {
+        self.mark_disconnected();
+        match self.reconnect_policy {
+            ReconnectPolicy::Automatic => Ok(true),
+            ReconnectPolicy::Manual => {
+                self.act(DisplayAction::PromptReconnect)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Blocks until the user asks to reconnect. Anything else they ask for is dropped, as there
+    /// is no connection to do it on. Returns false if they quit or the display is gone, so it
+    /// never will.
+    pub fn wait_for_reconnect(&self) -> bool {
+        loop {
+            match self.action_receiver.recv() {
+                Ok(ClientAction::Reconnect) => return true,
+                Ok(ClientAction::Quit) | Err(_) => return false,
+                Ok(_) => {}
+            }
+        }
+    }
+
+    /// Note that the connection was lost, so that a quick reconnect can resume the session.
+    pub fn mark_disconnected(&mut self) {
+        self.disconnected_at = Some(Instant::now());
+    }
+
+    /// How we should get back into the channel after reconnecting at `now`.
+    pub fn opening_kind(&self, now: Instant) -> OpeningKind {
+        let recent = self.disconnected_at.map_or(false, |at| {
+            now.saturating_duration_since(at) <= RESUME_WINDOW
+        });
+        match &self.session_id {
+            Some(session_id) if recent && self.server_api == ServerApi::HackChatV2 => {
+                OpeningKind::Resume(session_id.clone())
+            }
+            _ => OpeningKind::Full,
+        }
+    }
+
+    /// Sends the commands to get back into the channel after reconnecting, resuming our session
+    /// if we were only gone for a moment.
+    pub fn send_reopening_commands(&mut self, now: Instant) -> Result<(), tungstenite::Error> {
+        let kind = self.opening_kind(now);
+        self.disconnected_at = None;
+        match kind {
+            OpeningKind::Resume(session_id) => {
+                self.resuming = Some(session_id.clone());
+                self.send(client::Session {
+                    id: Some(session_id),
+                    is_bot: false,
+                })
+            }
+            OpeningKind::Full => self.send_opening_commands(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RetryNickError {
+    Socket(tungstenite::Error),
+    Act(SendError<DisplayAction>),
+}
+impl From<tungstenite::Error> for RetryNickError {
+    fn from(err: tungstenite::Error) -> Self {
+        RetryNickError::Socket(err)
+    }
+}
+impl From<SendError<DisplayAction>> for RetryNickError {
+    fn from(err: SendError<DisplayAction>) -> Self {
+        RetryNickError::Act(err)
+    }
+}
+
+/// How long after greeting that joining again won't greet, so that reconnects don't spam it.
+pub const GREETING_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// A message sent after joining a channel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Greeting {
+    Chat(Text),
+    /// Sent as `/me <text>`.
+    Emote(Text),
+}
+impl Greeting {
+    /// The chat text that the greeting is sent as.
+    pub fn text(&self) -> Text {
+        match self {
+            Greeting::Chat(text) => text.clone(),
+            Greeting::Emote(text) => format!("/me {}", text),
+        }
+    }
+}
+
+/// How long to wait between sends after the socket's send queue fills up, unless configured.
+pub const DEFAULT_SEND_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The least time between chat messages, unless configured. hack.chat allows short bursts, but
+/// a steady stream faster than this gets rate-limited.
+pub const DEFAULT_CHAT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How many times longer the time between chat messages is after being rate-limited.
+pub const RATE_LIMIT_SLOWDOWN: u32 = 4;
+
+/// How long chat messages are slowed down for after being rate-limited.
+pub const RATE_LIMIT_PENALTY: Duration = Duration::from_secs(30);
+
+/// How many of our chat messages are waited on to be echoed back by the server.
+const MAX_ECHOES: usize = 32;
+
+/// Whether to reconnect by ourselves after losing the connection, or wait for the user to ask.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReconnectPolicy {
+    Automatic,
+    /// For when rejoining by surprise would be unwelcome, such as after leaving on purpose.
+    Manual,
+}
+
+/// How long to wait before the first attempt to reconnect, doubled for each one that fails.
+pub const DEFAULT_BACKOFF_BASE: Duration = Duration::from_millis(500);
+
+/// The longest wait between attempts to reconnect, not counting the jitter.
+pub const DEFAULT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// How long the connection can be quiet before we ping the server, unless configured.
+pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long we wait for anything after pinging before taking the connection to be dead.
+pub const DEFAULT_KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A random duration up to `max`, so that clients which lost their connection together don't all
+/// come back at once.
+fn jitter(max: Duration) -> Duration {
+    // Each `RandomState` is randomly keyed, which is all the randomness this needs.
+    let random = RandomState::new().build_hasher().finish();
+    let max = max.as_nanos() as u64;
+    Duration::from_nanos(random % max.saturating_add(1))
+}
+
+/// How long we wait when quitting for the close to be sent, before giving up on a clean close.
+pub const QUIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long after being disconnected we try to resume the session, rather than joining again.
+pub const RESUME_WINDOW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpeningKind {
+    /// Reattach to the session, which keeps us in the channel without a new join notice.
+    Resume(SessionId),
+    /// Start a new session and join the channel.
+    Full,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Priority {
+    /// Bulk chat, which can wait behind anything else.
+    Low,
+    Normal,
+    /// Commands such as nick changes and moderation, which shouldn't wait behind chat.
+    High,
+}
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+/// Something said in the channel, waiting its turn to be sent, see [`Connection::pump_outgoing`].
+#[derive(Debug, Clone, PartialEq)]
+enum QueuedChat {
+    Chat(Text),
+    Emote(Text),
+}
+
+/// A command waiting in the [`OutgoingQueue`] to be sent to the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingCommand {
+    pub priority: Priority,
+    /// The json that will be sent.
+    pub message: String,
+}
+impl OutgoingCommand {
+    pub fn new<T>(priority: Priority, message: T, server_api: ServerApi) -> Self
+    where
+        T: Sized + ClientCommand + IntoJson,
+    {
+        Self {
+            priority,
+            message: message.into_json(server_api).dump(),
+        }
+    }
+}
+
+/// A command along with the order it was queued in, so that commands of the same priority
+/// keep their order.
+#[derive(Debug)]
+struct QueuedCommand {
+    index: u64,
+    command: OutgoingCommand,
+}
+impl Ord for QueuedCommand {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Earlier commands are greater, as the heap gives the greatest first.
+        self.command
+            .priority
+            .cmp(&other.command.priority)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+impl PartialOrd for QueuedCommand {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for QueuedCommand {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for QueuedCommand {}
+
+/// Commands waiting to be sent, which come out highest priority first and otherwise in the order
+/// they were pushed.
+#[derive(Debug, Default)]
+pub struct OutgoingQueue {
+    commands: BinaryHeap<QueuedCommand>,
+    next_index: u64,
+}
+impl OutgoingQueue {
+    pub fn push(&mut self, command: OutgoingCommand) {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.commands.push(QueuedCommand { index, command });
+    }
+
+    pub fn peek(&self) -> Option<&OutgoingCommand> {
+        self.commands.peek().map(|queued| &queued.command)
+    }
+
+    pub fn pop(&mut self) -> Option<OutgoingCommand> {
+        self.commands.pop().map(|queued| queued.command)
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeenInfo {
+    pub time: SystemTime,
+    /// The last message they sent, if we saw one.
+    pub last_message: Option<Text>,
+}
+
+/// Tracks when users were last seen, so that it can be queried after they have left.
+#[derive(Debug, Default)]
+pub struct LastSeen {
+    seen: HashMap<Nickname, SeenInfo>,
+    /// Where entries are saved when a user leaves, if anywhere.
+    store: Option<Box<dyn HistoryStore + Send>>,
+}
+impl LastSeen {
+    /// Loads the entries kept by the store, and saves to it from then on.
+    pub fn load(store: Box<dyn HistoryStore + Send>) -> io::Result<Self> {
+        Ok(Self {
+            seen: store.last_seen()?,
+            store: Some(store),
+        })
+    }
+
+    pub fn get(&self, nick: &str) -> Option<&SeenInfo> {
+        self.seen.get(nick)
+    }
+
+    pub fn record_message(&mut self, nick: &str, text: &str, time: SystemTime) {
+        self.seen.insert(
+            nick.to_owned(),
+            SeenInfo {
+                time,
+                last_message: Some(text.to_owned()),
+            },
+        );
+    }
+
+    /// Records that the user left, keeping their last message, and saves it if there is a store.
+    pub fn record_leave(&mut self, nick: &str, time: SystemTime) -> io::Result<()> {
+        let info = self.seen.entry(nick.to_owned()).or_insert(SeenInfo {
+            time,
+            last_message: None,
+        });
+        info.time = time;
+        match &mut self.store {
+            Some(store) => store.record_last_seen(nick, info),
+            None => Ok(()),
+        }
+    }
+
+    /// A user-facing description of when the user was last seen.
+    pub fn describe(&self, nick: &str, now: SystemTime) -> String {
+        match self.get(nick) {
+            Some(info) => {
+                let elapsed = now.duration_since(info.time).unwrap_or_default();
+                let mut text = format!("{} was last seen {} ago", nick, format_elapsed(elapsed));
+                if let Some(message) = &info.last_message {
+                    text += ", saying: ";
+                    text += message;
                 }
-            };
-        } else {
-            warn!(self.log(), "Received command from websocket server without a '{}' field for identification. JSON: '{:?}'", id::CMD, json.pretty(2));
+                text
+            }
+            None => format!("{} has not been seen", nick),
         }
-        Ok(())
     }
+}
 
-    pub fn log(&self) -> &slog::Logger {
-        &self.state.log
+/// Formats a duration in its largest unit, such as `5m` or `2d`.
+pub fn format_elapsed(elapsed: Duration) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = MINUTE * 60;
+    const DAY: u64 = HOUR * 24;
+    let secs = elapsed.as_secs();
+    if secs < MINUTE {
+        format!("{}s", secs)
+    } else if secs < HOUR {
+        format!("{}m", secs / MINUTE)
+    } else if secs < DAY {
+        format!("{}h", secs / HOUR)
+    } else {
+        format!("{}d", secs / DAY)
     }
 }
 
-pub struct ClientState {
-    pub log: slog::Logger,
+/// Formats how long ago something happened, such as `3m ago`, or `now` if it was under a minute.
+pub fn format_relative(elapsed: Duration) -> String {
+    if elapsed.as_secs() < 60 {
+        "now".to_owned()
+    } else {
+        format!("{} ago", format_elapsed(elapsed))
+    }
+}
+
+// NOTE: This requires a connection reference rather than being completely generic as Rust can be a
+// pain.
+// If you have a structure that is generic (aka CommandHandlers, but that goes to this Handler)
+// then if want to have a single type parameter that is user-defined parameters to the function
+// then if they want to pass in a tuple, to allow for multiple arguments, they for some
+// forsaken reason _have_ to give lifetime parameters.
+// Ex: struct Thing<T> { handler: Handler<T, Other>, }
+// Use: struct Alpha { thing: Thing<(u64, &mut File)>, file: File }
+//                                         ^-- required lifetime here!
+// I ran into the issue that if I specified the lifetime, then I also had to give a lifetime to my
+// structure in the function that was calling it or a lifetime that lived at least as long as it.
+// Ex: fn do_thing<'a>(&'a self) { (self.thing.handler)((5, &mut self.file)) }
+// But for some reason Rust could not figure out that the function (handler) that was called would
+// not keep the value given and so I could no longer use the fields that were given over.
+// I'm not sure why all generics are required to have lifetimes, if they aren't used in a location
+// where that is needed. If this had variadic types that could have also helped avoid the issue
+//   (though it could still be ran into if it was still required...)
+// As well, since the extra `T` parameter (custom state) is also generic, I have to at least specify
+// `&mut` upon it, otherwise I would be forced to specify a lifetime as well.
+// Having a way to map a tuple of types to a tuple of (mut-)references to those types would be nice.
+pub type Handler<T, C> = Box<dyn Fn(&mut Connection, &mut T, &C)>;
+pub struct HandlerList<T, C> {
+    handlers: Vec<Handler<T, C>>,
+}
+impl<T, C> HandlerList<T, C> {
+    /// Calls each handler in order.
+    /// Returns `true` if any amount of handlers were called.
+    pub fn call(&self, con: &mut Connection, v: &mut T, c: &C) -> bool {
+        for handler in self.handlers.iter() {
+            (handler)(con, v, c)
+        }
+
+        !self.handlers.is_empty()
+    }
+
+    pub fn add(&mut self, handler: Handler<T, C>) {
+        self.handlers.push(handler)
+    }
+
+    pub fn addg<F>(&mut self, func: F)
+    where
+        F: 'static + Fn(&mut Connection, &mut T, &C),
+    {
+        self.add(Box::new(func))
+    }
+}
+impl<T, C> Default for HandlerList<T, C> {
+    fn default() -> Self {
+        Self {
+            handlers: Vec::with_capacity(2),
+        }
+    }
+}
+pub struct CommandHandlers<T>
+where
+    T: Sized,
+{
+    // TODO; handlers for raw invite and emote commands, which we can do since we're only passing
+    // references
+    pub session: HandlerList<T, server::Session>,
+    pub online_set: HandlerList<T, server::OnlineSet>,
+    pub info: HandlerList<T, server::Info>,
+    pub chat: HandlerList<T, server::Chat>,
+    pub captcha: HandlerList<T, server::Captcha>,
+    pub emote: HandlerList<T, server::synthetic::Emote>,
+    pub invite: HandlerList<T, server::synthetic::Invite>,
+    pub online_add: HandlerList<T, server::OnlineAdd>,
+    pub online_remove: HandlerList<T, server::OnlineRemove>,
+    pub warn: HandlerList<T, server::Warn>,
+    pub topic: HandlerList<T, Topic>,
+    pub whisper: HandlerList<T, Whisper>,
+    pub nick_change: HandlerList<T, NickChange>,
+    /// Called for every chat, emote, and whisper, after their own handlers.
+    pub any_message: HandlerList<T, AnyMessage>,
+    /// Called for any command that none of the others are for.
+    pub raw: HandlerList<T, RawCommand>,
+}
+impl<T> CommandHandlers<T>
+where
+    T: Sized,
+{
+    /// Registers a handler for any message a user sent, whether chat, emote, or whisper.
+    pub fn on_any_message<F>(&mut self, func: F)
+    where
+        F: 'static + Fn(&mut Connection, &mut T, &AnyMessage),
+    {
+        self.any_message.addg(func)
+    }
+
+    /// Registers a handler for a command that none of the others are for, such as one that a
+    /// server is trying out, by its `cmd`.
+    pub fn on_raw<F>(&mut self, cmd: &str, func: F)
+    where
+        F: 'static + Fn(&mut Connection, &mut T, &JsonValue),
+    {
+        let cmd = cmd.to_owned();
+        self.raw.addg(move |con, state, raw| {
+            if raw.cmd == cmd {
+                func(con, state, &raw.json)
+            }
+        })
+    }
+}
+impl<T> Default for CommandHandlers<T>
+where
+    T: Sized,
+{
+    fn default() -> Self {
+        CommandHandlers {
+            session: HandlerList::default(),
+            online_set: HandlerList::default(),
+            info: HandlerList::default(),
+            chat: HandlerList::default(),
+            captcha: HandlerList::default(),
+            emote: HandlerList::default(),
+            invite: HandlerList::default(),
+            online_add: HandlerList::default(),
+            online_remove: HandlerList::default(),
+            warn: HandlerList::default(),
+            topic: HandlerList::default(),
+            whisper: HandlerList::default(),
+            nick_change: HandlerList::default(),
+            any_message: HandlerList::default(),
+            raw: HandlerList::default(),
+        }
+    }
+}
+
+/// Checks that the server address is a websocket URL, so that a mistake in it is explained before
+/// connecting rather than by however the connection fails. Addresses without TLS, `ws://`, are
+/// only allowed if asked for, as everything sent over them can be read along the way.
+pub fn check_server_address(address: &str, allow_unencrypted: bool) -> Result<Url, String> {
+    let url = Url::parse(address)
+        .map_err(|err| format!("The server address '{}' is invalid: {}", address, err))?;
+    if url.host().is_none() {
+        return Err(format!("The server address '{}' has no host", address));
+    }
+    match url.scheme() {
+        "wss" => Ok(url),
+        "ws" if allow_unencrypted => Ok(url),
+        "ws" => Err(format!(
+            "The server address '{}' is unencrypted, give --no-tls to connect to it anyway",
+            address
+        )),
+        scheme => Err(format!(
+            "The server address '{}' should start with wss:// or ws://, rather than {}://",
+            address, scheme
+        )),
+    }
+}
+
+/// Whether the address is of a server that is connected to without TLS.
+pub fn is_unencrypted(address: &str) -> bool {
+    Url::parse(address)
+        .map(|url| url.scheme() == "ws")
+        .unwrap_or(false)
+}
+
+/// Explains an error from connecting that means trying again won't help, such as the address not
+/// being a websocket endpoint. Errors that could go away, such as timeouts, have no hint.
+pub fn connect_error_hint(err: &tungstenite::Error) -> Option<String> {
+    match err {
+        tungstenite::Error::Protocol(reason) => Some(format!(
+            "The server did not accept the websocket handshake ({}). The address may not be a \
+             websocket endpoint, check --server.",
+            reason
+        )),
+        tungstenite::Error::Url(reason) => Some(format!(
+            "The server address is invalid ({}), check --server.",
+            reason
+        )),
+        tungstenite::Error::Http(status) => Some(format!(
+            "The server responded with {} rather than accepting the websocket, check --server.",
+            status
+        )),
+        _ => None,
+    }
+}
+
+/// The code and reason that the server closed the connection with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseReason {
+    pub code: u16,
+    pub reason: String,
+}
+impl CloseReason {
+    /// Whether reconnecting would just be refused, as the server objected to us rather than going
+    /// away for a while.
+    pub fn is_fatal(&self) -> bool {
+        match self.code {
+            // Unsupported data, and policy violation.
+            1003 | 1008 => true,
+            _ => false,
+        }
+    }
+
+    fn code_name(&self) -> Option<&'static str> {
+        Some(match self.code {
+            1000 => "normal closure",
+            1001 => "going away",
+            1002 => "protocol error",
+            1003 => "unsupported data",
+            1007 => "invalid data",
+            1008 => "policy violation",
+            1009 => "message too big",
+            1011 => "server error",
+            1012 => "restarting",
+            1013 => "try again later",
+            _ => return None,
+        })
+    }
+}
+impl From<&CloseFrame<'_>> for CloseReason {
+    fn from(frame: &CloseFrame<'_>) -> Self {
+        Self {
+            code: frame.code.into(),
+            reason: frame.reason.to_string(),
+        }
+    }
+}
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The server closed the connection ({}", self.code)?;
+        if let Some(name) = self.code_name() {
+            write!(f, " {}", name)?;
+        }
+        write!(f, ")")?;
+        if !self.reason.is_empty() {
+            write!(f, ": {}", self.reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a warning from the server is about, as hack.chat only sends them as text.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WarnKind {
+    /// We have to wait before doing the same again, such as `Wait 5 seconds and try again`.
+    Cooldown {
+        seconds: u64,
+    },
+    RateLimited,
+    Kicked,
+    Banned,
+    NickTaken,
+    Other,
+}
+impl WarnKind {
+    pub fn classify(text: &str) -> Self {
+        let text = text.to_lowercase();
+        if text.contains("banned") {
+            WarnKind::Banned
+        } else if text.contains("kicked") {
+            WarnKind::Kicked
+        } else if let Some(seconds) = cooldown_seconds(&text) {
+            WarnKind::Cooldown { seconds }
+        } else if text.contains("rate-limited") || text.contains("rate limited") {
+            WarnKind::RateLimited
+        } else if text.contains("nickname taken") {
+            WarnKind::NickTaken
+        } else {
+            WarnKind::Other
+        }
+    }
+
+    /// Whether this is the server refusing to let us into a channel, such as when the nick is
+    /// taken there or we're joining too fast, rather than a warning about anything else.
+    pub fn refuses_join(self, text: &str) -> bool {
+        match self {
+            WarnKind::NickTaken | WarnKind::Banned => true,
+            WarnKind::RateLimited | WarnKind::Other => {
+                let text = text.to_lowercase();
+                text.contains("join") || text.contains("channel")
+            }
+            WarnKind::Cooldown { .. } | WarnKind::Kicked => false,
+        }
+    }
+
+    /// Whether reconnecting after this would just be refused.
+    pub fn is_fatal(self) -> bool {
+        match self {
+            WarnKind::Kicked | WarnKind::Banned => true,
+            WarnKind::Cooldown { .. }
+            | WarnKind::RateLimited
+            | WarnKind::NickTaken
+            | WarnKind::Other => false,
+        }
+    }
+}
+
+/// The longest a warning is taken to make us wait, as its number comes from the server's text.
+const MAX_COOLDOWN_SECONDS: u64 = 24 * 60 * 60;
+
+/// Finds how long a warning says to wait, from a number followed by seconds such as `5 seconds`,
+/// capped at [`MAX_COOLDOWN_SECONDS`].
+fn cooldown_seconds(text: &str) -> Option<u64> {
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    words.windows(2).find_map(|pair| match pair {
+        [number, unit] if unit.starts_with("sec") => number
+            .parse::<u64>()
+            .ok()
+            .map(|seconds| seconds.min(MAX_COOLDOWN_SECONDS)),
+        _ => None,
+    })
+}
+
+/// The fields that every command from the server may have, read once before the command itself
+/// is parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub cmd: String,
+    pub channel: Option<Channel>,
+    /// Milliseconds since the unix epoch, as the server sends it.
+    pub time: Option<u64>,
+}
+impl Envelope {
+    /// Reads the envelope, if the json has the command name that every command needs.
+    pub fn of(json: &JsonValue) -> Option<Self> {
+        let cmd = json[id::CMD].as_str()?;
+        Some(Self {
+            cmd: cmd.to_owned(),
+            channel: json["channel"].as_str().map(Channel::from),
+            time: json["time"].as_u64(),
+        })
+    }
+
+    /// Whether the command is for the channel, which it is if it doesn't say.
+    pub fn is_for(&self, channel: &Channel) -> bool {
+        self.channel.as_ref().map_or(true, |ours| ours == channel)
+    }
+}
+
+/// Staff of the channel, which the server may say users are when they join. This isn't kept by
+/// the parsed commands, so it is read from their json.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserLevel {
+    Mod,
+    Admin,
+}
+impl UserLevel {
+    /// The levels that hack.chat gives its staff, for when only the number is sent.
+    const MOD_LEVEL: u64 = 999_999;
+    const ADMIN_LEVEL: u64 = 9_999_999;
+
+    /// Reads the level from a user's `uType`, or else their `level`. Users that aren't staff,
+    /// or whose level isn't given, have none.
+    pub fn of_user(json: &JsonValue) -> Option<Self> {
+        match json["uType"].as_str() {
+            Some("admin") => Some(UserLevel::Admin),
+            Some("mod") => Some(UserLevel::Mod),
+            Some(_) => None,
+            None => match json["level"].as_u64()? {
+                level if level >= Self::ADMIN_LEVEL => Some(UserLevel::Admin),
+                level if level >= Self::MOD_LEVEL => Some(UserLevel::Mod),
+                _ => None,
+            },
+        }
+    }
+
+    /// The staff among the users of an online set, by nick.
+    pub fn of_users(json: &JsonValue) -> HashMap<Nickname, Self> {
+        json["users"]
+            .members()
+            .filter_map(|user| Some((user["nick"].as_str()?.to_owned(), Self::of_user(user)?)))
+            .collect()
+    }
+
+    /// Shown before the nick of the user.
+    pub fn badge(self) -> &'static str {
+        match self {
+            UserLevel::Mod => "@",
+            UserLevel::Admin => "&",
+        }
+    }
+}
+
+/// The topic or message of the day of a channel.
+/// This is not a standard hack.chat command, but some servers send it on join, and so the name of
+/// the command it is sent as is configurable on the `Client`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topic {
+    pub channel: Option<Channel>,
+    pub text: Text,
+}
+impl Topic {
+    pub const DEFAULT_CMD: &'static str = "topic";
+
+    /// Parses the topic, accepting it in either a `text` or `topic` field.
+    pub fn from_json(envelope: &Envelope, json: &JsonValue) -> Result<Self, HandleCommandError> {
+        let text = json["text"]
+            .as_str()
+            .or_else(|| json["topic"].as_str())
+            .ok_or(HandleCommandError::MissingField("text"))?;
+        Ok(Self {
+            channel: envelope.channel.clone(),
+            text: text.to_owned(),
+        })
+    }
+}
+
+/// A message whispered to us, which hack.chat sends as an info with a type of `whisper`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Whisper {
+    pub from: Nickname,
+    pub to: Nickname,
+    pub trip: Option<Trip>,
+    pub text: Text,
+}
+impl Whisper {
+    /// Parses the info as a whisper to us, if it is one.
+    pub fn from_info_json(json: &JsonValue, own_nick: &str) -> Option<Self> {
+        if json["type"].as_str() != Some("whisper") {
+            return None;
+        }
+        let from = json["from"].as_str()?;
+        let text = json["text"].as_str()?;
+        // The server formats the text as `bob whispered: hi`, when we only want what was said.
+        let text = text
+            .strip_prefix(from)
+            .and_then(|text| text.strip_prefix(" whispered: "))
+            .unwrap_or(text);
+        Some(Self {
+            from: from.to_owned(),
+            to: json["to"].as_str().unwrap_or(own_nick).to_owned(),
+            trip: json["trip"].as_str().map(|trip| Trip(trip.to_owned())),
+            text: text.to_owned(),
+        })
+    }
+}
+
+/// A user taking another nick. hack.chat sends it as an `updateUser` with the id of the user,
+/// while some servers send a `changeNick` with both of the nicks instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NickChange {
+    /// The user, if we know of them.
+    pub user_id: Option<AccessUserId>,
+    pub old_nick: Nickname,
+    pub nick: Nickname,
+}
+impl NickChange {
+    pub const UPDATE_USER_CMD: &'static str = "updateUser";
+    pub const CHANGE_NICK_CMD: &'static str = "changeNick";
+
+    /// Parses the change, finding whichever of the user's id or old nick wasn't given among the
+    /// users. Updates that don't change the nick, such as of a user's color, are `None`.
+    pub fn from_json(json: &JsonValue, users: &Users) -> Option<Self> {
+        let nick = json["nick"].as_str().or_else(|| json["newNick"].as_str())?;
+        let given_old_nick = json["oldNick"]
+            .as_str()
+            .or_else(|| json["old_nick"].as_str());
+        let user_id = json["userid"]
+            .as_u64()
+            .map(AccessUserId::Server)
+            .or_else(|| Some(users.find_online_nick(given_old_nick?)?.0));
+        let old_nick = given_old_nick
+            .map(str::to_owned)
+            .or_else(|| Some(users.get(user_id?)?.nick.clone()))?;
+        if old_nick == nick {
+            return None;
+        }
+        Some(Self {
+            user_id,
+            old_nick,
+            nick: nick.to_owned(),
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MessageKind {
+    Chat,
+    Emote,
+    Whisper,
+}
+
+/// Any message a user sent, for handlers that treat chats, emotes, and whispers alike.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnyMessage {
+    pub kind: MessageKind,
+    /// Who sent it, if the server said.
+    pub nick: Option<Nickname>,
+    pub trip: Option<Trip>,
+    pub text: Text,
+}
+impl AnyMessage {
+    pub fn from_chat(chat: &server::Chat) -> Self {
+        Self {
+            kind: MessageKind::Chat,
+            nick: Some(chat.nick.clone()),
+            trip: chat.trip.clone().into(),
+            text: chat.text.clone(),
+        }
+    }
+
+    pub fn from_emote(emote: &server::synthetic::Emote, nick: Option<Nickname>) -> Self {
+        Self {
+            kind: MessageKind::Emote,
+            nick,
+            trip: None,
+            text: emote.text.clone(),
+        }
+    }
+
+    pub fn from_whisper(whisper: &Whisper) -> Self {
+        Self {
+            kind: MessageKind::Whisper,
+            nick: Some(whisper.from.clone()),
+            trip: whisper.trip.clone(),
+            text: whisper.text.clone(),
+        }
+    }
+}
+
+/// A command that none of the other handlers are for, as it was received, so that handlers
+/// registered from outside can act on commands we don't know of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawCommand {
+    pub cmd: String,
+    pub json: JsonValue,
+}
+
+#[derive(Debug, Clone)]
+pub enum HandleCommandError {
+    FromJson(FromJsonError),
+    EmoteConversion(server::synthetic::EmoteConversionError),
+    /// A command we parse ourselves lacked a required field.
+    MissingField(&'static str),
+}
+impl From<FromJsonError> for HandleCommandError {
+    fn from(err: FromJsonError) -> Self {
+        Self::FromJson(err)
+    }
+}
+impl From<server::synthetic::EmoteConversionError> for HandleCommandError {
+    fn from(err: server::synthetic::EmoteConversionError) -> Self {
+        Self::EmoteConversion(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum SendAndWaitError {
+    Socket(tungstenite::Error),
+    Read(ReadJsonMessageError),
+    Handle(HandleCommandError),
+    /// The message was sent, but the server did not echo it back in time.
+    Timeout,
+}
+impl fmt::Display for SendAndWaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Explained the same way as when the interface fails to connect.
+            SendAndWaitError::Socket(err) => match connect_error_hint(err) {
+                Some(hint) => write!(f, "{}", hint),
+                None => write!(f, "{}", err),
+            },
+            SendAndWaitError::Read(ReadJsonMessageError::Socket(err)) => write!(f, "{}", err),
+            SendAndWaitError::Read(ReadJsonMessageError::Json(err)) => {
+                write!(f, "The server sent invalid json ({})", err)
+            }
+            SendAndWaitError::Handle(err) => {
+                write!(
+                    f,
+                    "The server sent a message that could not be understood ({:?})",
+                    err
+                )
+            }
+            SendAndWaitError::Timeout => write!(
+                f,
+                "The message was sent, but the server did not echo it back in time"
+            ),
+        }
+    }
+}
+impl From<tungstenite::Error> for SendAndWaitError {
+    fn from(err: tungstenite::Error) -> Self {
+        Self::Socket(err)
+    }
+}
+impl From<ReadJsonMessageError> for SendAndWaitError {
+    fn from(err: ReadJsonMessageError) -> Self {
+        Self::Read(err)
+    }
+}
+impl From<HandleCommandError> for SendAndWaitError {
+    fn from(err: HandleCommandError) -> Self {
+        Self::Handle(err)
+    }
+}
+impl From<FromJsonError> for SendAndWaitError {
+    fn from(err: FromJsonError) -> Self {
+        Self::Handle(HandleCommandError::FromJson(err))
+    }
+}
+
+pub struct Client {
+    pub con: Connection,
+    pub handlers: CommandHandlers<ClientState>,
+    pub state: ClientState,
+    /// The time slept before the first attempt to reconnect, see [`Client::next_backoff`].
+    pub backoff_base: Duration,
+    /// The most time slept between attempts to reconnect, not counting the jitter.
+    pub backoff_max: Duration,
+    /// How many attempts to reconnect there have been since we were last connected.
+    pub backoff_attempts: u32,
+    /// The command name that topics are received as, since forks of hack.chat differ.
+    pub topic_cmd: String,
+    /// How long the connection can be quiet before we ping the server, or `None` to never ping.
+    pub keepalive_interval: Option<Duration>,
+    /// How long to wait for an answer to the ping before the connection is taken to be dead.
+    pub keepalive_timeout: Duration,
+}
+impl Client {
+    pub fn new(con: Connection, state: ClientState) -> Self {
+        let mut handlers = CommandHandlers::default();
+        Connection::register_handlers(&mut handlers);
+        Self {
+            con,
+            state,
+            handlers,
+            backoff_base: DEFAULT_BACKOFF_BASE,
+            backoff_max: DEFAULT_BACKOFF_MAX,
+            backoff_attempts: 0,
+            topic_cmd: Topic::DEFAULT_CMD.to_owned(),
+            keepalive_interval: Some(DEFAULT_KEEPALIVE_INTERVAL),
+            keepalive_timeout: DEFAULT_KEEPALIVE_TIMEOUT,
+        }
+    }
+
+    /// Pings the server once the connection has been quiet for [`Client::keepalive_interval`] at
+    /// `now`. Returns false if a ping has gone unanswered for [`Client::keepalive_timeout`], as the
+    /// connection has died.
+    pub fn keep_alive(&mut self, now: Instant) -> Result<bool, tungstenite::Error> {
+        let interval = match self.keepalive_interval {
+            Some(interval) => interval,
+            None => return Ok(true),
+        };
+        if let Some(sent) = self.con.keepalive_sent {
+            return Ok(now.saturating_duration_since(sent) < self.keepalive_timeout);
+        }
+        if now.saturating_duration_since(self.con.last_activity) >= interval {
+            self.con.send_keepalive(now)?;
+        }
+        Ok(true)
+    }
+
+    /// How long to sleep before the next attempt to reconnect: doubled for each attempt that has
+    /// failed, up to [`Client::backoff_max`], with a little jitter on top.
+    pub fn next_backoff(&mut self) -> Duration {
+        let backoff = 2u32
+            .checked_pow(self.backoff_attempts)
+            .and_then(|factor| self.backoff_base.checked_mul(factor))
+            .map_or(self.backoff_max, |backoff| backoff.min(self.backoff_max));
+        self.backoff_attempts = self.backoff_attempts.saturating_add(1);
+        backoff + jitter(backoff / 4)
+    }
+
+    /// Starts the backoff over from [`Client::backoff_base`], for once we've connected again.
+    pub fn reset_backoff(&mut self) {
+        self.backoff_attempts = 0;
+    }
+
+    pub fn handle_json(&mut self, json: JsonValue) -> Result<(), HandleCommandError> {
+        // Replaced for every command, so that one without an envelope isn't given the time of
+        // the last one that had it.
+        self.con.envelope = Envelope::of(&json);
+        if let Some(envelope) = self.con.envelope.clone() {
+            let cmd = envelope.cmd.as_str();
+            let server_api = self.con.server_api;
+            let state = &mut self.state;
+            let con = &mut self.con;
+            // TODO: add the rest of the commands
+            // TODO: add synthesized commands.
+            let _ran_cmd = match cmd {
+                server::Session::CMD => self.handlers.session.call(
+                    con,
+                    state,
+                    &server::Session::from_json(json, server_api)?,
+                ),
+                server::OnlineSet::CMD => {
+                    con.levels = UserLevel::of_users(&json);
+                    self.handlers.online_set.call(
+                        con,
+                        state,
+                        &server::OnlineSet::from_json(json, server_api)?,
+                    )
+                }
+                server::Info::CMD => {
+                    if let Some(whisper) = Whisper::from_info_json(&json, con.own_nick()) {
+                        let ran = self.handlers.whisper.call(con, state, &whisper);
+                        let message = AnyMessage::from_whisper(&whisper);
+                        self.handlers.any_message.call(con, state, &message);
+                        ran
+                    } else {
+                        let info = server::Info::from_json(json, server_api)?;
+                        // Break apart info into separate commands.
+                        if let Ok(invite) = server::synthetic::Invite::from_info(&con.users, &info)
+                        {
+                            self.handlers.invite.call(con, state, &invite)
+                        } else if let Ok(emote) =
+                            server::synthetic::Emote::from_info(&con.users, &info)
+                        {
+                            let ran = self.handlers.emote.call(con, state, &emote);
+                            let message = AnyMessage::from_emote(&emote, None);
+                            self.handlers.any_message.call(con, state, &message);
+                            ran
+                        } else {
+                            self.handlers.info.call(con, state, &info)
+                        }
+                    }
+                }
+                // Each connection is in a single channel, so chat for any other isn't ours to
+                // show, such as what arrives while leaving for another channel.
+                server::Chat::CMD if !envelope.is_for(&con.channel) => {
+                    warn!(
+                        state.log,
+                        "Ignored chat for channel {:?} while in '{}'",
+                        envelope.channel,
+                        con.channel
+                    );
+                    false
+                }
+                server::Chat::CMD => {
+                    let chat = server::Chat::from_json(json, server_api)?;
+                    let ran = self.handlers.chat.call(con, state, &chat);
+                    let message = AnyMessage::from_chat(&chat);
+                    self.handlers.any_message.call(con, state, &message);
+                    ran
+                }
+                server::OnlineAdd::CMD => {
+                    if let Some(nick) = json["nick"].as_str() {
+                        // Someone without a level could have taken the nick of staff that left.
+                        match UserLevel::of_user(&json) {
+                            Some(level) => con.levels.insert(nick.to_owned(), level),
+                            None => con.levels.remove(nick),
+                        };
+                    }
+                    self.handlers.online_add.call(
+                        con,
+                        state,
+                        &server::OnlineAdd::from_json(json, server_api)?,
+                    )
+                }
+                server::OnlineRemove::CMD => self.handlers.online_remove.call(
+                    con,
+                    state,
+                    &server::OnlineRemove::from_json(json, server_api)?,
+                ),
+                server::Captcha::CMD => self.handlers.captcha.call(
+                    con,
+                    state,
+                    &server::Captcha::from_json(json, server_api)?,
+                ),
+                server::Invite::CMD => {
+                    let invite = server::Invite::from_json(json, server_api)?;
+                    let invite = server::synthetic::Invite::from_invite(&con.users, invite);
+                    self.handlers.invite.call(con, state, &invite)
+                }
+                server::Emote::CMD => {
+                    let nick = json["nick"].as_str().map(str::to_owned);
+                    let emote = server::Emote::from_json(json, server_api)?;
+                    let emote = server::synthetic::Emote::from_emote(&con.users, &emote)?;
+                    let ran = self.handlers.emote.call(con, state, &emote);
+                    let message = AnyMessage::from_emote(&emote, nick);
+                    self.handlers.any_message.call(con, state, &message);
+                    ran
+                }
+                server::Warn::CMD => {
+                    // TODO: break warn down into component 'commands' like ratelimit and such
+                    self.handlers
+                        .warn
+                        .call(con, state, &server::Warn::from_json(json, server_api)?)
+                }
+                NickChange::UPDATE_USER_CMD | NickChange::CHANGE_NICK_CMD => {
+                    match NickChange::from_json(&json, &con.users) {
+                        Some(change) => self.handlers.nick_change.call(con, state, &change),
+                        None => false,
+                    }
+                }
+                _ if cmd == self.topic_cmd => {
+                    self.handlers
+                        .topic
+                        .call(con, state, &Topic::from_json(&envelope, &json)?)
+                }
+                _ => {
+                    let raw = RawCommand {
+                        cmd: cmd.to_owned(),
+                        json,
+                    };
+                    let ran = self.handlers.raw.call(con, state, &raw);
+                    if !ran {
+                        // We ignore the command.
+                        warn!(
+                            self.log(),
+                            "Unhandled command from websocket: '{}', JSON: '{:?}'",
+                            id::CMD,
+                            raw.json.pretty(2)
+                        );
+                    }
+                    // TODO: log that we got an unknown command value
+                    ran
+                }
+            };
+        } else {
+            warn!(self.log(), "Received command from websocket server without a '{}' field for identification. JSON: '{:?}'", id::CMD, json.pretty(2));
+        }
+        Ok(())
+    }
+
+    /// Sends a chat message and blocks until the server echoes it back to us, for scripting.
+    /// Every command received while waiting is handled as usual.
+    pub fn send_chat_and_wait(
+        &mut self,
+        text: Text,
+        timeout: Duration,
+    ) -> Result<server::Chat, SendAndWaitError> {
+        // How long to sleep for when there is nothing to read.
+        const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+        self.con.send(client::Chat {
+            channel: Some(self.con.channel.clone()),
+            text: text.clone(),
+        })?;
+
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            let json = if let Some(json) = self.con.read_json_message()? {
+                json
+            } else {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            };
+
+            let is_chat =
+                Envelope::of(&json).map_or(false, |envelope| envelope.cmd == server::Chat::CMD);
+            let echo = if is_chat {
+                Some(server::Chat::from_json(json.clone(), self.con.server_api)?)
+            } else {
+                None
+            };
+            self.handle_json(json)?;
+
+            if let Some(echo) = echo {
+                if echo.text == text && echo.nick == self.own_nick() {
+                    return Ok(echo);
+                }
+            }
+        }
+
+        Err(SendAndWaitError::Timeout)
+    }
+
+    /// Joins, sends a single chat message, and closes the connection once it is echoed back.
+    /// This is for sending from scripts, without the chat being shown.
+    pub fn send_once(
+        &mut self,
+        text: Text,
+        timeout: Duration,
+    ) -> Result<server::Chat, SendAndWaitError> {
+        self.con.send_opening_commands()?;
+        let echo = self.send_chat_and_wait(text, timeout)?;
+        self.con.close()?;
+        Ok(echo)
+    }
+
+    /// The nick we are known as, which is the one we joined with unless the server told us
+    /// otherwise.
+    pub fn own_nick(&self) -> &str {
+        self.con.own_nick()
+    }
+
+    pub fn log(&self) -> &slog::Logger {
+        &self.state.log
+    }
+}
+
+/// Proposes answers to captchas.
+/// This allows hooking in something like an OCR without this crate depending on one.
+pub trait CaptchaSolver {
+    /// Given the captcha's text art, returns the answer if it has one.
+    fn solve(&self, _captcha: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Leaves captchas for the user to solve.
+pub struct ManualCaptcha;
+impl CaptchaSolver for ManualCaptcha {}
+
+pub struct ClientState {
+    pub log: slog::Logger,
+    pub captcha_solver: Box<dyn CaptchaSolver>,
+    /// Users whose messages aren't shown. This only lasts the session.
+    pub ignored: HashSet<Ignore>,
+}
+impl ClientState {
+    pub fn new(log: slog::Logger) -> Self {
+        Self {
+            log,
+            captcha_solver: Box::new(ManualCaptcha),
+            ignored: HashSet::new(),
+        }
+    }
+
+    /// Whether what the user with the nick and trip says is hidden.
+    pub fn is_ignored(&self, nick: &str, trip: Option<&str>) -> bool {
+        self.ignored.iter().any(|ignore| ignore.matches(nick, trip))
+    }
+}
+
+/// Someone whose messages are hidden, by their nick and optionally their trip. With a trip, only
+/// the user with that trip is ignored, so that someone else taking the nick isn't, and ignoring
+/// an impersonator by their nick alone doesn't hide the user they're impersonating.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ignore {
+    pub nick: Nickname,
+    pub trip: Option<String>,
+}
+impl Ignore {
+    /// Parses `nick` or `nick#trip`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (nick, trip) = match text.find('#') {
+            Some(idx) => (&text[..idx], Some(&text[idx + 1..])),
+            None => (text, None),
+        };
+        if nick.is_empty() || trip == Some("") {
+            return None;
+        }
+        Some(Self {
+            nick: nick.to_owned(),
+            trip: trip.map(str::to_owned),
+        })
+    }
+
+    /// Whether the user with the nick and trip is the one ignored. Nicks are compared ignoring
+    /// case, as the server doesn't let them differ only by case.
+    pub fn matches(&self, nick: &str, trip: Option<&str>) -> bool {
+        self.nick.eq_ignore_ascii_case(nick)
+            && self
+                .trip
+                .as_deref()
+                .map_or(true, |ignored| Some(ignored) == trip)
+    }
+}
+impl fmt::Display for Ignore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.trip {
+            Some(trip) => write!(f, "{}#{}", self.nick, trip),
+            None => write!(f, "{}", self.nick),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test_util {
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    use hack_chat_types::{AccessUserId, ServerApi, Trip, UserInfo, Users};
+
+    use super::{Client, ClientAction, ClientState, Connection};
+    use crate::{transport::mock::MockTransport, DisplayAction};
+
+    /// The nick that mock connections join with.
+    pub const MOCK_NICK: &str = "me";
+
+    pub fn test_log() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    /// A connection over a mock transport, along with the other ends of its channels.
+    pub fn mock_connection() -> (
+        Connection,
+        MockTransport,
+        Receiver<DisplayAction>,
+        Sender<ClientAction>,
+    ) {
+        let (display_sender, display_receiver) = mpsc::channel();
+        let (client_sender, client_receiver) = mpsc::channel();
+        let transport = MockTransport::default();
+        let con = Connection::new(
+            display_sender,
+            client_receiver,
+            Box::new(transport.clone()),
+            "wss://example.com/chat-ws".to_owned(),
+            ServerApi::HackChatV2,
+            MOCK_NICK.to_owned(),
+            None,
+            "programming".into(),
+        );
+        (con, transport, display_receiver, client_sender)
+    }
+
+    pub fn mock_client() -> (Client, MockTransport) {
+        let (con, transport, _display, _client) = mock_connection();
+        (Client::new(con, ClientState::new(test_log())), transport)
+    }
+
+    /// Builds up the users of a channel, for setting `Connection::users` in tests.
+    #[derive(Default)]
+    pub struct UsersBuilder {
+        users: Users,
+    }
+    impl UsersBuilder {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Add an online user.
+        pub fn user(mut self, nick: &str, trip: Option<&str>) -> Self {
+            self.add(nick, trip, true);
+            self
+        }
+
+        /// Add a user who has left.
+        pub fn offline(mut self, nick: &str, trip: Option<&str>) -> Self {
+            self.add(nick, trip, false);
+            self
+        }
+
+        /// Add an online user that is this connection.
+        pub fn ourself(mut self, nick: &str, trip: Option<&str>) -> Self {
+            let user_id = self.add(nick, trip, true);
+            self.users.ourself = Some(user_id);
+            self
+        }
+
+        pub fn build(self) -> Users {
+            self.users
+        }
+
+        fn add(&mut self, nick: &str, trip: Option<&str>, online: bool) -> AccessUserId {
+            let user_id = self.users.generate_id();
+            self.users.insert(
+                user_id,
+                UserInfo {
+                    nick: nick.to_owned(),
+                    trip: trip.map(|trip| Trip(trip.to_owned())).into(),
+                    online,
+                },
+            );
+            user_id
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        fs, io,
+        rc::Rc,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use hack_chat_types::{client, util::IntoJson, AccessUserId, ServerApi, Trip, UserInfo};
+    use tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    };
+
+    use super::{
+        check_server_address, format_elapsed, format_relative, is_unencrypted,
+        test_util::{mock_client, mock_connection, test_log, UsersBuilder},
+        Client, ClientAction, ClientState, CloseReason, Envelope, Greeting, Ignore, LastSeen,
+        MessageKind, NickChange, OpeningKind, OutgoingCommand, OutgoingQueue, Priority,
+        ReconnectPolicy, SendAndWaitError, Topic, UserLevel, WarnKind, Whisper,
+        DEFAULT_KEEPALIVE_INTERVAL, DEFAULT_KEEPALIVE_TIMEOUT, GREETING_COOLDOWN,
+        MAX_COOLDOWN_SECONDS, QUIT_TIMEOUT, RATE_LIMIT_PENALTY, RATE_LIMIT_SLOWDOWN, RESUME_WINDOW,
+    };
+    use crate::{
+        chat_log::FileHistoryStore,
+        transport::{mock::MockTransport, FrameDirection},
+        ConnectionStatus, DisplayAction,
+    };
+
+    fn chat_json(nick: &str, text: &str) -> Message {
+        Message::Text(
+            json::object! {
+                cmd: "chat",
+                nick: nick,
+                text: text,
+                channel: "programming",
+                userid: 1,
+                uType: "user",
+                level: 100,
+                time: 0,
+            }
+            .dump(),
+        )
+    }
+
+    #[test]
+    fn test_send_chat_and_wait() {
+        let (mut cli, transport) = mock_client();
+        // Someone else saying the same thing is not our echo.
+        transport.push_inbound(chat_json("other", "hello"));
+        transport.push_inbound(chat_json("me", "hello"));
+        let echo = cli
+            .send_chat_and_wait("hello".to_owned(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(echo.nick, "me");
+        assert_eq!(echo.text, "hello");
+        assert_eq!(transport.outbound().len(), 1);
+
+        // Nothing is echoed back.
+        let result = cli.send_chat_and_wait("again".to_owned(), Duration::from_millis(30));
+        assert!(matches!(result, Err(SendAndWaitError::Timeout)));
+        assert_eq!(transport.outbound().len(), 2);
+    }
+
+    #[test]
+    fn test_invite() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        con.invite("bob".to_owned());
+        con.flush_outgoing().unwrap();
+
+        let outbound = transport.outbound();
+        let json = match outbound.as_slice() {
+            [Message::Text(text)] => json::parse(text).unwrap(),
+            _ => panic!("Expected a single text message, got {:?}", outbound),
+        };
+        assert_eq!(json["cmd"], "invite");
+        assert_eq!(json["nick"], "bob");
+        assert_eq!(json["channel"], "programming");
+    }
+
+    #[test]
+    fn test_emote() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        con.emote("waves".to_owned());
+        con.pump_outgoing().unwrap();
+
+        let outbound = transport.outbound();
+        let json = match outbound.as_slice() {
+            [Message::Text(text)] => json::parse(text).unwrap(),
+            _ => panic!("Expected a single text message, got {:?}", outbound),
+        };
+        assert_eq!(json["cmd"], "emote");
+        assert_eq!(json["text"], "waves");
+    }
+
+    #[test]
+    fn test_emote_paced() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        let sent = |n: usize| json::parse(transport.outbound()[n].to_text().unwrap()).unwrap();
+        let now = Instant::now();
+        con.hold_chats(now + Duration::from_secs(5));
+        con.enqueue_chat("hello".to_owned());
+        con.emote("waves".to_owned());
+
+        // Held back like chat messages, and kept behind those said before.
+        con.pump_outgoing_at(now).unwrap();
+        assert!(transport.outbound().is_empty());
+        let now = now + Duration::from_secs(5);
+        con.pump_outgoing_at(now).unwrap();
+        assert_eq!(sent(0)["cmd"], "chat");
+        assert_eq!(con.pending_chats(), 1);
+        con.pump_outgoing_at(now + con.chat_interval).unwrap();
+        assert_eq!(sent(1)["cmd"], "emote");
+    }
+
+    #[test]
+    fn test_queued_chat_channel() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        let now = Instant::now();
+        con.hold_chats(now + Duration::from_secs(5));
+        con.enqueue_chat("hello".to_owned());
+        con.join_channel("math".into()).unwrap();
+
+        // Still waiting when we joined, so it's said in the channel we're in by then.
+        con.pump_outgoing_at(now + Duration::from_secs(5)).unwrap();
+        let outbound = transport.outbound();
+        let chat = json::parse(outbound.last().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(chat["cmd"], "chat");
+        assert_eq!(chat["channel"], "math");
+    }
+
+    #[test]
+    fn test_change_nick() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        con.change_nick("bob".to_owned());
+        assert_eq!(con.joined_nick, "bob");
+        con.flush_outgoing().unwrap();
+
+        let outbound = transport.outbound();
+        let json = match outbound.as_slice() {
+            [Message::Text(text)] => json::parse(text).unwrap(),
+            _ => panic!("Expected a single text message, got {:?}", outbound),
+        };
+        assert_eq!(json["cmd"], "changenick");
+        assert_eq!(json["nick"], "bob");
+    }
+
+    #[test]
+    fn test_switch_channel() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        con.session_id = Some("abc".into());
+        con.mark_disconnected();
+        con.switch_channel("lounge".into()).unwrap();
+        assert_eq!(transport.outbound(), vec![Message::Close(None)]);
+        // The reconnect joins the new channel, rather than resuming in the old one.
+        assert_eq!(con.opening_kind(Instant::now()), OpeningKind::Full);
+        con.send_join().unwrap();
+        let outbound = transport.outbound();
+        let json = match outbound.last() {
+            Some(Message::Text(text)) => json::parse(text).unwrap(),
+            _ => panic!("Expected a join, got {:?}", outbound),
+        };
+        assert_eq!(json["channel"], "lounge");
+    }
+
+    #[test]
+    fn test_reconnect_policy() {
+        let (mut con, _transport, display, client) = mock_connection();
+        assert!(con.lost_connection().unwrap());
+        assert!(display.try_recv().is_err());
+
+        // Rather than reconnecting, the user is asked to.
+        con.reconnect_policy = ReconnectPolicy::Manual;
+        assert!(!con.lost_connection().unwrap());
+        assert!(matches!(
+            display.try_recv(),
+            Ok(DisplayAction::PromptReconnect)
+        ));
+        assert!(con.disconnected_at.is_some());
+
+        client
+            .send(ClientAction::SendChatMessage("lost".to_owned()))
+            .unwrap();
+        client.send(ClientAction::Reconnect).unwrap();
+        assert!(con.wait_for_reconnect());
+        assert!(con.action_receiver.try_recv().is_err());
+
+        drop(client);
+        assert!(!con.wait_for_reconnect());
+    }
+
+    #[test]
+    fn test_next_backoff() {
+        let (mut cli, _transport) = mock_client();
+        cli.backoff_base = Duration::from_millis(100);
+        cli.backoff_max = Duration::from_secs(1);
+        let within = |backoff: Duration, ms: u64| {
+            let expected = Duration::from_millis(ms);
+            backoff >= expected && backoff <= expected + expected / 4
+        };
+        for &ms in &[100, 200, 400, 800, 1000, 1000] {
+            let backoff = cli.next_backoff();
+            assert!(within(backoff, ms), "{:?} isn't around {}ms", backoff, ms);
+        }
+
+        // Far too many attempts to double for is still capped.
+        cli.backoff_attempts = 100;
+        assert!(within(cli.next_backoff(), 1000));
+
+        cli.reset_backoff();
+        assert!(within(cli.next_backoff(), 100));
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let (mut cli, transport) = mock_client();
+        let start = cli.con.last_activity;
+        let interval = DEFAULT_KEEPALIVE_INTERVAL;
+        let timeout = DEFAULT_KEEPALIVE_TIMEOUT;
+        let pings = || {
+            transport
+                .outbound()
+                .iter()
+                .filter(|message| matches!(message, Message::Ping(_)))
+                .count()
+        };
+
+        assert!(cli.keep_alive(start).unwrap());
+        assert_eq!(pings(), 0);
+        // Quiet for long enough, so the server is pinged, but only once.
+        let pinged = start + interval;
+        assert!(cli.keep_alive(pinged).unwrap());
+        assert!(cli.keep_alive(pinged + timeout / 2).unwrap());
+        assert_eq!(pings(), 1);
+        // Nothing came back in time.
+        assert!(!cli.keep_alive(pinged + timeout).unwrap());
+
+        // Anything read back answers it.
+        transport.push_inbound(Message::Pong(Vec::new()));
+        cli.con.read_message().unwrap();
+        let answered = cli.con.last_activity;
+        assert!(cli.keep_alive(answered + timeout).unwrap());
+        assert_eq!(pings(), 1);
+
+        cli.keepalive_interval = None;
+        assert!(cli.keep_alive(start + interval * 10).unwrap());
+        assert_eq!(pings(), 1);
+    }
+
+    #[test]
+    fn test_ignore() {
+        assert_eq!(
+            Ignore::parse("bob"),
+            Some(Ignore {
+                nick: "bob".to_owned(),
+                trip: None
+            })
+        );
+        let with_trip = Ignore::parse("bob#abcdef").unwrap();
+        assert_eq!(with_trip.trip.as_deref(), Some("abcdef"));
+        assert_eq!(with_trip.to_string(), "bob#abcdef");
+        assert_eq!(Ignore::parse(""), None);
+        assert_eq!(Ignore::parse("#abcdef"), None);
+        assert_eq!(Ignore::parse("bob#"), None);
+
+        let mut state = ClientState::new(test_log());
+        state.ignored.insert(Ignore::parse("Bob").unwrap());
+        state.ignored.insert(with_trip);
+        assert!(state.is_ignored("bob", None));
+        assert!(!state.is_ignored("alice", None));
+
+        // With a trip, someone else taking the nick isn't ignored.
+        state.ignored.remove(&Ignore::parse("Bob").unwrap());
+        assert!(state.is_ignored("bob", Some("abcdef")));
+        assert!(!state.is_ignored("bob", Some("zzzzzz")));
+        assert!(!state.is_ignored("bob", None));
+    }
+
+    #[test]
+    fn test_send_once() {
+        let (mut cli, transport) = mock_client();
+        transport.push_inbound(chat_json("me", "hello"));
+        transport.push_inbound(chat_json("other", "after"));
+        let echo = cli
+            .send_once("hello".to_owned(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(echo.text, "hello");
+
+        // The session and join, the message, and then the close once it was echoed.
+        let outbound = transport.outbound();
+        assert_eq!(outbound.len(), 4);
+        assert_eq!(outbound.last(), Some(&Message::Close(None)));
+        // Nothing after the echo is read.
+        assert_eq!(transport.state.borrow().inbound.len(), 1);
+    }
+
+    #[test]
+    fn test_warn_kind() {
+        assert_eq!(
+            WarnKind::classify("You are being rate-limited or blocked."),
+            WarnKind::RateLimited
+        );
+        assert_eq!(WarnKind::classify("You have been BANNED"), WarnKind::Banned);
+        assert_eq!(WarnKind::classify("You were kicked"), WarnKind::Kicked);
+        assert_eq!(WarnKind::classify("Nickname taken"), WarnKind::NickTaken);
+        assert_eq!(WarnKind::classify("Something else"), WarnKind::Other);
+        assert_eq!(
+            WarnKind::classify("You are sending too fast. Wait 5 seconds and try again."),
+            WarnKind::Cooldown { seconds: 5 }
+        );
+        assert_eq!(
+            WarnKind::classify("Rate limited, please wait 1 sec."),
+            WarnKind::Cooldown { seconds: 1 }
+        );
+        assert_eq!(WarnKind::classify("Wait a few seconds"), WarnKind::Other);
+        assert_eq!(
+            WarnKind::classify("Wait 18446744073709551615 seconds"),
+            WarnKind::Cooldown {
+                seconds: MAX_COOLDOWN_SECONDS
+            }
+        );
+        assert!(!WarnKind::Cooldown { seconds: 5 }.is_fatal());
+        assert!(WarnKind::Banned.is_fatal());
+        assert!(WarnKind::Kicked.is_fatal());
+        assert!(!WarnKind::RateLimited.is_fatal());
+    }
+
+    #[test]
+    fn test_close_reason() {
+        let close = |code: u16, reason: &str| CloseReason {
+            code,
+            reason: reason.to_owned(),
+        };
+
+        let policy = close(1008, "Banned");
+        assert!(policy.is_fatal());
+        assert_eq!(
+            policy.to_string(),
+            "The server closed the connection (1008 policy violation): Banned"
+        );
+        assert!(close(1003, "").is_fatal());
+
+        let restart = close(1012, "");
+        assert!(!restart.is_fatal());
+        assert_eq!(
+            restart.to_string(),
+            "The server closed the connection (1012 restarting)"
+        );
+        assert!(!close(1000, "bye").is_fatal());
+        assert!(!close(1001, "").is_fatal());
+        assert_eq!(
+            close(4000, "custom").to_string(),
+            "The server closed the connection (4000): custom"
+        );
+
+        // Reading the close frame keeps its reason, and stops us reconnecting if it's fatal.
+        let (mut con, transport, _display, _client) = mock_connection();
+        transport.push_inbound(Message::Close(Some(CloseFrame {
+            code: CloseCode::Policy,
+            reason: "Banned".into(),
+        })));
+        con.read_message().unwrap();
+        assert_eq!(con.close, Some(policy.clone()));
+        assert_eq!(con.fatal, Some(policy.to_string()));
+
+        let (mut con, transport, _display, _client) = mock_connection();
+        transport.push_inbound(Message::Close(Some(CloseFrame {
+            code: CloseCode::Restart,
+            reason: "".into(),
+        })));
+        con.read_message().unwrap();
+        assert_eq!(con.close, Some(restart));
+        assert_eq!(con.fatal, None);
+    }
+
+    #[test]
+    fn test_ban_is_fatal() {
+        let (mut cli, _transport) = mock_client();
+        let warn = json::object! {
+            cmd: "warn",
+            text: "You are being rate-limited or blocked.",
+            channel: "programming",
+            time: 0,
+        };
+        cli.handle_json(warn).unwrap();
+        assert_eq!(cli.con.fatal, None);
+
+        let warn = json::object! {
+            cmd: "warn",
+            text: "You are banned",
+            channel: "programming",
+            time: 0,
+        };
+        cli.handle_json(warn).unwrap();
+        assert_eq!(cli.con.fatal.as_deref(), Some("You are banned"));
+    }
+
+    #[test]
+    fn test_nick_taken() {
+        let nick_taken = || {
+            json::object! {
+                cmd: "warn",
+                text: "Nickname taken",
+                channel: "programming",
+                time: 0,
+            }
+        };
+
+        let (con, _transport, display, _client) = mock_connection();
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.handle_json(nick_taken()).unwrap();
+        match display.try_recv() {
+            Ok(DisplayAction::PromptNick(nick)) => assert_eq!(nick, "me"),
+            _ => panic!("Expected to be asked for a new nick"),
+        }
+        // It isn't fatal, as we can still join with another nick.
+        assert_eq!(cli.con.fatal, None);
+
+        // Once in the channel, it's about someone else.
+        cli.con.in_channel = true;
+        cli.handle_json(nick_taken()).unwrap();
+        assert!(display.try_recv().is_err());
+
+        // With a suffix, we join again without asking.
+        let (con, transport, display, _client) = mock_connection();
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.con.nick_suffix = Some("_".to_owned());
+        cli.handle_json(nick_taken()).unwrap();
+        assert!(display.try_recv().is_err());
+        assert_eq!(cli.con.joined_nick, "me_");
+        assert_eq!(transport.outbound().len(), 1);
+    }
+
+    #[test]
+    fn test_join_channel() {
+        let (mut con, transport, display, _client) = mock_connection();
+        // So that the online set only needs nicks.
+        con.server_api = ServerApi::HackChatLegacy;
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.con.users = UsersBuilder::new()
+            .ourself("me", None)
+            .user("bob", None)
+            .build();
+        cli.con.in_channel = true;
+
+        // Refused, so we go back to what we knew of the old channel.
+        cli.con.join_channel("math".into()).unwrap();
+        assert_eq!(cli.con.channel, "math");
+        assert!(cli.con.users.find_online_nick("bob").is_none());
+        // The old channel's chat is kept until we know whether we're let in.
+        assert!(display.try_recv().is_err());
+        match transport.outbound().as_slice() {
+            [Message::Text(text)] => {
+                let join = json::parse(text).unwrap();
+                assert_eq!(join["cmd"], "join");
+                assert_eq!(join["channel"], "math");
+            }
+            outbound => panic!("Expected a join, got {:?}", outbound),
+        }
+        cli.handle_json(json::object! {
+            cmd: "warn",
+            text: "You may not join that channel",
+            channel: "math",
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(cli.con.channel, "programming");
+        assert!(cli.con.users.find_online_nick("bob").is_some());
+        assert!(matches!(
+            display.try_recv(),
+            Ok(DisplayAction::SetStatus(ConnectionStatus::Connected(channel))) if channel == "programming"
+        ));
+        assert!(matches!(
+            display.try_recv(),
+            Ok(DisplayAction::SetTopic(None))
+        ));
+
+        // Let in, so there's nothing to go back to.
+        cli.con.session_id = Some("abc".into());
+        cli.con.join_channel("math".into()).unwrap();
+        cli.handle_json(json::object! {
+            cmd: "onlineSet",
+            nicks: ["me", "alice"],
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(cli.con.channel, "math");
+        assert!(cli.con.users.find_online_nick("alice").is_some());
+        assert!(cli.con.pending_join.is_none());
+        assert!(cli.con.session_id.is_none());
+        assert!(matches!(display.try_recv(), Ok(DisplayAction::ClearChat)));
+        assert!(matches!(
+            display.try_recv(),
+            Ok(DisplayAction::SetStatus(ConnectionStatus::Connected(channel))) if channel == "math"
+        ));
+        assert!(matches!(
+            display.try_recv(),
+            Ok(DisplayAction::ReopenHistory(channel)) if channel == "math"
+        ));
+
+        // Being in the channel already, nothing is started afresh.
+        cli.handle_json(json::object! {
+            cmd: "onlineSet",
+            nicks: ["me", "alice"],
+            time: 0,
+        })
+        .unwrap();
+        assert!(display.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_join_unrelated_warn() {
+        let (mut con, _transport, _display, _client) = mock_connection();
+        con.server_api = ServerApi::HackChatLegacy;
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.con.in_channel = true;
+
+        cli.con.join_channel("math".into()).unwrap();
+        cli.handle_json(json::object! {
+            cmd: "warn",
+            text: "Wait 5 seconds and try again",
+            time: 0,
+        })
+        .unwrap();
+        // A warning that isn't about the join leaves it waiting on the server.
+        assert_eq!(cli.con.channel, "math");
+        assert!(cli.con.pending_join.is_some());
+
+        cli.handle_json(json::object! {
+            cmd: "warn",
+            text: "You are joining channels too fast. Wait a moment and try again.",
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(cli.con.channel, "programming");
+        assert!(cli.con.pending_join.is_none());
+    }
+
+    #[test]
+    fn test_duplicate_session() {
+        let online_add = |nick: &str, user_id: u64| {
+            json::object! {
+                cmd: "onlineAdd",
+                nick: nick,
+                trip: "",
+                uType: "user",
+                hash: "abcdef",
+                level: 100,
+                userid: user_id,
+                isBot: false,
+                color: false,
+                channel: "programming",
+                time: 0,
+            }
+        };
+
+        let (con, _transport, display, _client) = mock_connection();
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.con.users = UsersBuilder::new().ourself("me", None).build();
+
+        cli.handle_json(online_add("alice", 1)).unwrap();
+        assert!(display.try_recv().is_err());
+
+        // Someone else with our nick is likely us from elsewhere.
+        cli.handle_json(online_add("me", 2)).unwrap();
+        match display.try_recv() {
+            Ok(DisplayAction::DisplayDialog(text)) => assert!(text.contains("elsewhere")),
+            _ => panic!("Expected a warning about being connected elsewhere"),
+        }
+    }
+
+    #[test]
+    fn test_quit() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        con.emote("waves bye".to_owned());
+        con.quit(QUIT_TIMEOUT).unwrap();
+        // What was queued goes out before the close.
+        let outbound = transport.outbound();
+        assert_eq!(outbound.len(), 2);
+        assert!(matches!(&outbound[0], Message::Text(text) if text.contains("bye")));
+        assert_eq!(outbound[1], Message::Close(None));
+
+        // Quitting while waiting to reconnect stops waiting.
+        let (con, _transport, _display, client) = mock_connection();
+        client.send(ClientAction::Quit).unwrap();
+        assert!(!con.wait_for_reconnect());
+    }
+
+    #[test]
+    fn test_opening_commands() {
+        fn sent_commands(transport: &MockTransport) -> Vec<String> {
+            transport
+                .outbound()
+                .iter()
+                .map(|message| match message {
+                    Message::Text(text) => json::parse(text).unwrap()["cmd"].to_string(),
+                    _ => panic!("Expected a text message, got {:?}", message),
+                })
+                .collect()
+        }
+
+        let (mut con, transport, _display, _client) = mock_connection();
+        con.send_opening_commands().unwrap();
+        assert_eq!(sent_commands(&transport), vec!["session", "join"]);
+
+        // Legacy servers have no sessions.
+        let (mut con, transport, _display, _client) = mock_connection();
+        con.server_api = ServerApi::HackChatLegacy;
+        con.send_opening_commands().unwrap();
+        assert_eq!(sent_commands(&transport), vec!["join"]);
+        // So there's never one to resume.
+        con.session_id = Some("abc".into());
+        con.mark_disconnected();
+        assert_eq!(con.opening_kind(Instant::now()), OpeningKind::Full);
+    }
+
+    #[test]
+    fn test_resume_session() {
+        let session = |session_id: &str| {
+            json::object! {
+                cmd: "session",
+                sessionID: session_id,
+                time: 0,
+            }
+        };
+        let sent = |message: &Message| match message {
+            Message::Text(text) => json::parse(text).unwrap(),
+            _ => panic!("Expected a text message, got {:?}", message),
+        };
+
+        let (con, transport, _display, _client) = mock_connection();
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.handle_json(session("abc")).unwrap();
+        cli.con.mark_disconnected();
+        // The session the server gave us is the one we ask to resume.
+        cli.con.send_reopening_commands(Instant::now()).unwrap();
+        let outbound = transport.outbound();
+        assert_eq!(outbound.len(), 1);
+        let resume = sent(&outbound[0]);
+        let expected = client::Session {
+            id: Some("abc".into()),
+            is_bot: false,
+        };
+        assert_eq!(resume, expected.into_json(ServerApi::HackChatV2));
+
+        // Being given another means it wasn't resumed, so we join as if we were new.
+        cli.handle_json(session("def")).unwrap();
+        let outbound = transport.outbound();
+        assert_eq!(outbound.len(), 2);
+        assert_eq!(sent(&outbound[1])["cmd"], "join");
+        assert_eq!(cli.con.session_id, Some("def".into()));
+    }
+
+    #[test]
+    fn test_legacy_users() {
+        let (mut con, _transport, display, _client) = mock_connection();
+        con.server_api = ServerApi::HackChatLegacy;
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        let with_nick = |cmd: &str, nick: &str| {
+            json::object! {
+                cmd: cmd,
+                nick: nick,
+                time: 0,
+            }
+        };
+
+        cli.handle_json(json::object! {
+            cmd: "onlineSet",
+            nicks: ["me", "alice"],
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(cli.con.own_nick(), "me");
+        assert!(cli.con.users.find_online_nick("alice").is_some());
+
+        cli.handle_json(with_nick("onlineAdd", "bob")).unwrap();
+        assert!(cli.con.users.find_online_nick("bob").is_some());
+        cli.handle_json(with_nick("onlineRemove", "bob")).unwrap();
+        assert!(cli.con.users.find_online_nick("bob").is_none());
+
+        // Without user ids, there's nothing to say that a join with our nick isn't us.
+        cli.handle_json(with_nick("onlineAdd", "me")).unwrap();
+        assert!(!display
+            .try_iter()
+            .any(|action| matches!(action, DisplayAction::DisplayDialog(_))));
+    }
+
+    #[test]
+    fn test_user_levels() {
+        let user = |nick: &str, user_type: &str, level: u64, user_id: u64| {
+            json::object! {
+                nick: nick,
+                trip: "",
+                uType: user_type,
+                hash: "abcdef",
+                level: level,
+                userid: user_id,
+                isme: nick == "me",
+                isBot: false,
+                color: false,
+                channel: "programming",
+            }
+        };
+        let online_add = |nick: &str, user_type: &str, level: u64, user_id: u64| {
+            let mut add = user(nick, user_type, level, user_id);
+            add["cmd"] = "onlineAdd".into();
+            add["time"] = 0.into();
+            add
+        };
+
+        let (con, _transport, _display, _client) = mock_connection();
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.handle_json(json::object! {
+            cmd: "onlineSet",
+            nicks: ["me", "alice", "bob"],
+            users: [
+                user("me", "user", 100, 1),
+                user("alice", "mod", 999_999, 2),
+                user("bob", "admin", 9_999_999, 3),
+            ],
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(cli.con.levels.len(), 2);
+        assert_eq!(cli.con.levels["alice"], UserLevel::Mod);
+        assert_eq!(cli.con.levels["bob"], UserLevel::Admin);
+
+        cli.handle_json(online_add("carol", "mod", 999_999, 4))
+            .unwrap();
+        assert_eq!(cli.con.levels["carol"], UserLevel::Mod);
+        // Someone else joining with the nick of staff that left isn't staff.
+        cli.handle_json(online_add("alice", "user", 100, 5))
+            .unwrap();
+        assert!(!cli.con.levels.contains_key("alice"));
+
+        // Without a `uType`, the number is enough.
+        assert_eq!(
+            UserLevel::of_user(&json::object! { nick: "dave", level: 9_999_999 }),
+            Some(UserLevel::Admin)
+        );
+        // Servers that don't say leave everyone without a level.
+        assert_eq!(UserLevel::of_user(&json::object! { nick: "dave" }), None);
+    }
+
+    #[test]
+    fn test_nick_change() {
+        let (con, _transport, _display, _client) = mock_connection();
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.con.users = UsersBuilder::new()
+            .ourself("me", None)
+            .user("alice", Some("Xk2Fo3"))
+            .build();
+        cli.con.levels.insert("alice".to_owned(), UserLevel::Mod);
+        let (alice_id, _) = cli.con.users.find_online_nick("alice").unwrap();
+
+        cli.handle_json(json::object! {
+            cmd: "changeNick",
+            oldNick: "alice",
+            nick: "alicia",
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+        assert!(cli.con.users.find_online_nick("alice").is_none());
+        let (id, info) = cli.con.users.find_online_nick("alicia").unwrap();
+        assert_eq!(id, alice_id);
+        let trip: Option<Trip> = info.trip.clone().into();
+        assert_eq!(trip, Some(Trip("Xk2Fo3".to_owned())));
+        assert_eq!(cli.con.levels["alicia"], UserLevel::Mod);
+        assert!(!cli.con.levels.contains_key("alice"));
+
+        // hack.chat only gives the id of who it was.
+        cli.con.users.insert(
+            AccessUserId::Server(7),
+            UserInfo {
+                nick: "bob".to_owned(),
+                trip: None.into(),
+                online: true,
+            },
+        );
+        cli.handle_json(json::object! {
+            cmd: "updateUser",
+            nick: "robert",
+            userid: 7,
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(
+            cli.con.users.get(AccessUserId::Server(7)).unwrap().nick,
+            "robert"
+        );
+        // Without either, there's no knowing whose nick it was.
+        let update = json::object! { cmd: "updateUser", nick: "ally" };
+        assert_eq!(NickChange::from_json(&update, &cli.con.users), None);
+
+        // Updates that aren't of the nick are nothing to us.
+        let color = json::object! { cmd: "updateUser", nick: "alicia", oldNick: "alicia" };
+        assert_eq!(NickChange::from_json(&color, &cli.con.users), None);
+
+        let ourself = cli.con.users.ourself;
+        cli.handle_json(json::object! {
+            cmd: "changeNick",
+            oldNick: "me",
+            nick: "me2",
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(cli.con.users.ourself, ourself);
+        assert_eq!(cli.con.own_nick(), "me2");
+        assert_eq!(cli.con.joined_nick, "me2");
+    }
+
+    #[test]
+    fn test_raw_handlers() {
+        let (mut cli, _transport) = mock_client();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen_by_handler = seen.clone();
+        cli.handlers.on_raw("poll", move |_, _, json| {
+            seen_by_handler
+                .borrow_mut()
+                .push(json["question"].to_string())
+        });
+
+        cli.handle_json(json::object! {
+            cmd: "poll",
+            question: "tabs or spaces?",
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+        // Only commands none of the others are for reach them.
+        cli.handle_json(json::object! {
+            cmd: "vote",
+            question: "tabs or spaces?",
+            time: 0,
+        })
+        .unwrap();
+        cli.handle_json(json::object! {
+            cmd: "topic",
+            question: "not a poll",
+            text: "tabs",
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(*seen.borrow(), vec!["tabs or spaces?".to_owned()]);
+    }
+
+    #[test]
+    fn test_check_server_address() {
+        assert!(check_server_address("wss://hack.chat/chat-ws", false).is_ok());
+        assert!(check_server_address("ws://localhost:6060", true).is_ok());
+        assert!(check_server_address("hack.chat", true).is_err());
+        assert!(check_server_address("wss+ws://hack.chat", true).is_err());
+        let err = check_server_address("https://hack.chat", true).unwrap_err();
+        assert!(err.contains("wss://"), "{}", err);
+        // Going without TLS has to be asked for.
+        let err = check_server_address("ws://localhost:6060", false).unwrap_err();
+        assert!(err.contains("--no-tls"), "{}", err);
+
+        assert!(is_unencrypted("ws://localhost:6060"));
+        assert!(!is_unencrypted("wss://hack.chat/chat-ws"));
+        assert!(!is_unencrypted("not an address"));
+    }
+
+    #[test]
+    fn test_connect_error_hint() {
+        let (mut con, _transport, _display, _client) = mock_connection();
+        // Failing to reach the server may be temporary.
+        con.note_connect_error(&tungstenite::Error::Io(io::ErrorKind::TimedOut.into()));
+        assert_eq!(con.fatal, None);
+
+        con.note_connect_error(&tungstenite::Error::Protocol(
+            "Handshake not finished".into(),
+        ));
+        let reason = con.fatal.unwrap();
+        assert!(reason.contains("Handshake not finished"));
+        assert!(reason.contains("--server"));
+
+        // Sending with --send explains it the same way.
+        let err = SendAndWaitError::Socket(tungstenite::Error::Protocol(
+            "Handshake not finished".into(),
+        ));
+        assert_eq!(err.to_string(), reason);
+    }
+
+    #[test]
+    fn test_frame_observer() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        let records = Rc::new(RefCell::new(Vec::new()));
+        let observed = records.clone();
+        con.frame_observer = Some(Box::new(move |record| {
+            observed.borrow_mut().push(record.clone())
+        }));
+
+        // Nothing to read, so nothing is recorded.
+        assert_eq!(con.read_message().unwrap(), None);
+        assert!(records.borrow().is_empty());
+
+        transport.push_inbound(Message::Text("in".to_owned()));
+        assert_eq!(
+            con.read_message().unwrap(),
+            Some(Message::Text("in".to_owned()))
+        );
+        con.write_message(Message::Text("out".to_owned())).unwrap();
+        assert_eq!(transport.outbound(), vec![Message::Text("out".to_owned())]);
+
+        let records = records.borrow();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, FrameDirection::Inbound);
+        assert_eq!(records[0].description, "text: in");
+        assert_eq!(records[1].direction, FrameDirection::Outbound);
+        assert_eq!(records[1].description, "text: out");
+    }
+
+    #[test]
+    fn test_users_builder() {
+        let users = UsersBuilder::new()
+            .user("alice", Some("abcdef"))
+            .ourself("me", None)
+            .offline("bob", None)
+            .build();
+
+        let ourself = users.ourself.and_then(|id| users.get(id)).unwrap();
+        assert_eq!(ourself.nick, "me");
+        assert!(ourself.online);
+
+        let (alice_id, alice) = users.find_online_nick("alice").unwrap();
+        assert_eq!(alice.nick, "alice");
+        assert_eq!(
+            Option::<Trip>::from(alice.trip.clone()),
+            Some(Trip("abcdef".to_owned()))
+        );
+        assert_ne!(Some(alice_id), users.ourself);
+        // They left, so they can't be found as online.
+        assert!(users.find_online_nick("bob").is_none());
+        assert!(users.find_online_nick("carol").is_none());
+    }
+
+    #[test]
+    fn test_own_nick() {
+        let (mut cli, _transport) = mock_client();
+        // Nothing is known about the users, so it's the nick we joined with.
+        assert_eq!(cli.own_nick(), "me");
+
+        cli.con.users = UsersBuilder::new()
+            .user("alice", None)
+            .ourself("me_", None)
+            .build();
+        assert_eq!(cli.own_nick(), "me_");
+    }
+
+    #[test]
+    fn test_outgoing_priority() {
+        let mut queue = OutgoingQueue::default();
+        let command = |priority, message: &str| OutgoingCommand {
+            priority,
+            message: message.to_owned(),
+        };
+        queue.push(command(Priority::Low, "chat 1"));
+        queue.push(command(Priority::Low, "chat 2"));
+        queue.push(command(Priority::Normal, "normal"));
+        queue.push(command(Priority::High, "changenick"));
+        queue.push(command(Priority::Low, "chat 3"));
+        assert_eq!(queue.len(), 5);
+
+        let order: Vec<String> = std::iter::from_fn(|| queue.pop())
+            .map(|command| command.message)
+            .collect();
+        assert_eq!(
+            order,
+            vec!["changenick", "normal", "chat 1", "chat 2", "chat 3"]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_pump_outgoing() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        let sent = || transport.outbound().len();
+        let now = Instant::now();
+        for text in &["one", "two", "three"] {
+            con.enqueue_chat((*text).to_owned());
+        }
+
+        // Only one chat message is sent per interval, with the rest kept.
+        con.pump_outgoing_at(now).unwrap();
+        con.pump_outgoing_at(now).unwrap();
+        assert_eq!(sent(), 1);
+        assert_eq!(con.pending_chats(), 2);
+        let now = now + con.chat_interval;
+        con.pump_outgoing_at(now).unwrap();
+        assert_eq!(sent(), 2);
+
+        // Being rate-limited spaces them further apart for a while.
+        con.rate_limited(now);
+        let now = now + con.chat_interval;
+        con.pump_outgoing_at(now).unwrap();
+        assert_eq!(sent(), 2);
+        let now = now + con.chat_interval * (RATE_LIMIT_SLOWDOWN - 1);
+        con.pump_outgoing_at(now).unwrap();
+        assert_eq!(sent(), 3);
+        assert!(transport.outbound()[2].to_text().unwrap().contains("three"));
+
+        // Told to wait, what's said in the meantime is held rather than dropped.
+        let now = now + RATE_LIMIT_PENALTY;
+        con.hold_chats(now + Duration::from_secs(5));
+        con.enqueue_chat("four".to_owned());
+        con.pump_outgoing_at(now).unwrap();
+        assert_eq!(con.pending_chats(), 1);
+        con.pump_outgoing_at(now + Duration::from_secs(5)).unwrap();
+        assert_eq!(sent(), 4);
+        assert_eq!(con.pending_chats(), 0);
+    }
+
+    #[test]
+    fn test_rate_limit_warn() {
+        let (con, _transport, _display, _client) = mock_connection();
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.con.enqueue_chat("hello".to_owned());
+        cli.handle_json(json::object! {
+            cmd: "warn",
+            text: "You are being rate-limited or blocked.",
+            time: 0,
+        })
+        .unwrap();
+        // It's held back, but not for long.
+        cli.con.pump_outgoing().unwrap();
+        assert_eq!(cli.con.pending_chats(), 1);
+        let later = Instant::now() + cli.con.chat_interval * RATE_LIMIT_SLOWDOWN;
+        cli.con.pump_outgoing_at(later).unwrap();
+        assert_eq!(cli.con.pending_chats(), 0);
+    }
+
+    #[test]
+    fn test_take_echo() {
+        let (mut con, _transport, _display, _client) = mock_connection();
+        con.users = UsersBuilder::new().ourself("me", None).build();
+        con.record_echo("hello ".to_owned());
+        con.record_echo("again".to_owned());
+
+        // Someone else saying the same isn't our echo.
+        assert!(!con.take_echo("bob", "hello"));
+        assert!(con.take_echo("me", "hello"));
+        // Each is only echoed once.
+        assert!(!con.take_echo("me", "hello"));
+        assert!(con.take_echo("me", "again"));
+
+        // Those never echoed back don't pile up.
+        for n in 0..100 {
+            con.record_echo(n.to_string());
+        }
+        assert!(!con.take_echo("me", "0"));
+        assert!(con.take_echo("me", "99"));
+    }
+
+    #[test]
+    fn test_greeting() {
+        let (mut con, _transport, _display, _client) = mock_connection();
+        let now = Instant::now();
+        // Nothing to send.
+        assert!(!con.greet(now));
+
+        con.greeting = Some(Greeting::Emote("waves".to_owned()));
+        assert!(con.greet(now));
+        assert_eq!(con.outgoing.len(), 1);
+        assert!(con.outgoing.peek().unwrap().message.contains("/me waves"));
+
+        // Rejoining soon after, as on a reconnect, doesn't greet again.
+        assert!(!con.greet(now + Duration::from_secs(5)));
+        assert_eq!(con.outgoing.len(), 1);
+
+        assert!(con.greet(now + GREETING_COOLDOWN));
+        assert_eq!(con.outgoing.len(), 2);
+    }
+
+    #[test]
+    fn test_opening_kind() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        let now = Instant::now();
+        // Without a session there is nothing to resume.
+        con.disconnected_at = Some(now);
+        assert_eq!(con.opening_kind(now), OpeningKind::Full);
+
+        con.session_id = Some("abc".into());
+        assert_eq!(
+            con.opening_kind(now + Duration::from_secs(5)),
+            OpeningKind::Resume("abc".into())
+        );
+        assert_eq!(
+            con.opening_kind(now + RESUME_WINDOW + Duration::from_secs(1)),
+            OpeningKind::Full
+        );
+
+        // Resuming only sends the session, while a full opening also joins.
+        con.send_reopening_commands(now).unwrap();
+        assert_eq!(transport.outbound().len(), 1);
+        assert_eq!(con.resuming, Some("abc".into()));
+
+        con.disconnected_at = Some(now);
+        con.send_reopening_commands(now + RESUME_WINDOW * 2)
+            .unwrap();
+        assert_eq!(transport.outbound().len(), 3);
+    }
+
+    #[test]
+    fn test_flush_outgoing() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        con.outgoing.push(OutgoingCommand {
+            priority: Priority::Low,
+            message: "chat".to_owned(),
+        });
+        con.outgoing.push(OutgoingCommand {
+            priority: Priority::High,
+            message: "mod".to_owned(),
+        });
+        con.flush_outgoing().unwrap();
+        assert_eq!(
+            transport.outbound(),
+            vec![
+                Message::Text("mod".to_owned()),
+                Message::Text("chat".to_owned())
+            ]
+        );
+        assert!(con.outgoing.is_empty());
+    }
+
+    #[test]
+    fn test_any_message() {
+        let (con, _transport, _display, _client) = mock_connection();
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.con.users = UsersBuilder::new()
+            .ourself("me", None)
+            .user("bob", None)
+            .build();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let unified = received.clone();
+        cli.handlers.on_any_message(move |_con, _state, message| {
+            unified.borrow_mut().push(message.clone());
+        });
+
+        cli.handle_json(json::parse(&chat_json("bob", "hi").into_text().unwrap()).unwrap())
+            .unwrap();
+        cli.handle_json(json::object! {
+            cmd: "emote",
+            nick: "bob",
+            userid: 1,
+            text: "@bob waves",
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+        cli.handle_json(json::object! {
+            cmd: "info",
+            "type": "whisper",
+            from: "bob",
+            trip: "abcdef",
+            text: "bob whispered: psst",
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+        // Anything else isn't a message from a user.
+        cli.handle_json(json::object! {
+            cmd: "info",
+            text: "Channel is quiet",
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+
+        let received = received.borrow();
+        let kinds: Vec<MessageKind> = received.iter().map(|message| message.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![MessageKind::Chat, MessageKind::Emote, MessageKind::Whisper]
+        );
+        assert!(received
+            .iter()
+            .all(|message| message.nick.as_deref() == Some("bob")));
+        assert_eq!(received[0].text, "hi");
+        assert_eq!(received[2].trip, Some(Trip("abcdef".to_owned())));
+        assert_eq!(received[2].text, "psst");
+    }
+
+    #[test]
+    fn test_chat_for_other_channel() {
+        let (con, _transport, _display, _client) = mock_connection();
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        cli.con.users = UsersBuilder::new()
+            .ourself("me", None)
+            .user("bob", None)
+            .build();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let chats = received.clone();
+        cli.handlers.on_any_message(move |_con, _state, message| {
+            chats.borrow_mut().push(message.text.clone());
+        });
+
+        cli.handle_json(json::object! {
+            cmd: "chat",
+            nick: "bob",
+            text: "elsewhere",
+            channel: "lounge",
+            userid: 1,
+            uType: "user",
+            level: 100,
+            time: 0,
+        })
+        .unwrap();
+        cli.handle_json(json::parse(&chat_json("bob", "here").into_text().unwrap()).unwrap())
+            .unwrap();
+        // Without a channel, it's taken to be for ours.
+        cli.handle_json(json::object! {
+            cmd: "chat",
+            nick: "bob",
+            text: "anywhere",
+            userid: 1,
+            uType: "user",
+            level: 100,
+            time: 0,
+        })
+        .unwrap();
+
+        assert_eq!(*received.borrow(), vec!["here", "anywhere"]);
+    }
+
+    #[test]
+    fn test_whisper_from_info() {
+        let whisper = Whisper::from_info_json(
+            &json::object! {
+                cmd: "info",
+                "type": "whisper",
+                from: "bob",
+                text: "bob whispered: psst: over here",
+            },
+            "me",
+        )
+        .unwrap();
+        assert_eq!(whisper.from, "bob");
+        assert_eq!(whisper.to, "me");
+        assert_eq!(whisper.trip, None);
+        assert_eq!(whisper.text, "psst: over here");
+
+        // Text the server formatted differently is kept as it is.
+        let info = json::object! {
+            cmd: "info",
+            "type": "whisper",
+            from: "bob",
+            text: "psst",
+        };
+        assert_eq!(Whisper::from_info_json(&info, "me").unwrap().text, "psst");
+        let info = json::object! {
+            cmd: "info",
+            text: "bob whispered: psst",
+        };
+        assert_eq!(Whisper::from_info_json(&info, "me"), None);
+    }
+
+    #[test]
+    fn test_send_queue_full() {
+        let (mut con, transport, display, _client) = mock_connection();
+        let command = |message: &str| OutgoingCommand {
+            priority: Priority::Low,
+            message: message.to_owned(),
+        };
+        let sent = || {
+            transport
+                .outbound()
+                .into_iter()
+                .map(|message| message.into_text().unwrap())
+                .collect::<Vec<String>>()
+        };
+        let now = Instant::now();
+        con.outgoing.push(command("one"));
+        con.outgoing.push(command("two"));
+        con.outgoing.push(command("three"));
+
+        // The command that didn't fit is kept, and sends are slowed down.
+        transport.state.borrow_mut().full_writes = 1;
+        con.flush_outgoing_at(now).unwrap();
+        assert!(sent().is_empty());
+        assert_eq!(con.outgoing.len(), 3);
+        assert!(matches!(
+            display.try_recv(),
+            Ok(DisplayAction::SetSlowSends(true))
+        ));
+
+        // Only one is sent per backoff.
+        con.flush_outgoing_at(now).unwrap();
+        assert!(sent().is_empty());
+        let now = now + con.send_backoff;
+        con.flush_outgoing_at(now).unwrap();
+        assert_eq!(sent(), vec!["one"]);
+
+        // A message that failed elsewhere is retried first.
+        con.send_queue_full(Message::Text("retried".to_owned()), now);
+        let now = now + con.send_backoff;
+        con.flush_outgoing_at(now).unwrap();
+        assert_eq!(sent(), vec!["one", "retried"]);
+
+        let now = now + con.send_backoff;
+        con.flush_outgoing_at(now).unwrap();
+        let now = now + con.send_backoff;
+        con.flush_outgoing_at(now).unwrap();
+        assert_eq!(sent(), vec!["one", "retried", "two", "three"]);
+
+        // Once caught up, sends go at full speed again.
+        let now = now + con.send_backoff;
+        con.flush_outgoing_at(now).unwrap();
+        assert!(matches!(
+            display.try_recv(),
+            Ok(DisplayAction::SetSlowSends(false))
+        ));
+        con.outgoing.push(command("four"));
+        con.outgoing.push(command("five"));
+        con.flush_outgoing_at(now).unwrap();
+        assert_eq!(sent().len(), 6);
+    }
+
+    #[test]
+    fn test_flush() {
+        let (mut con, transport, _display, _client) = mock_connection();
+        transport.state.borrow_mut().hold_writes = true;
+        con.write_message(Message::Text("chat".to_owned())).unwrap();
+        assert!(transport.outbound().is_empty());
+
+        con.flush().unwrap();
+        assert_eq!(transport.outbound(), vec![Message::Text("chat".to_owned())]);
+        assert!(transport.state.borrow().pending.is_empty());
+    }
+
+    #[test]
+    fn test_topic_from_json() {
+        let topic = |text: &str| {
+            let json = json::parse(text).unwrap();
+            Topic::from_json(&Envelope::of(&json).unwrap(), &json)
+        };
+        assert_eq!(
+            topic(r#"{"cmd":"topic","channel":"programming","text":"Welcome"}"#).unwrap(),
+            Topic {
+                channel: Some("programming".into()),
+                text: "Welcome".to_owned(),
+            }
+        );
+
+        // Forks may name the field differently, and may not send the channel.
+        assert_eq!(
+            topic(r#"{"cmd":"motd","topic":"Be nice"}"#).unwrap(),
+            Topic {
+                channel: None,
+                text: "Be nice".to_owned(),
+            }
+        );
+
+        assert!(topic(r#"{"cmd":"topic"}"#).is_err());
+    }
+
+    #[test]
+    fn test_command_time() {
+        let (con, _transport, _display, _client) = mock_connection();
+        let mut cli = Client::new(con, ClientState::new(test_log()));
+        let sent_at = SystemTime::UNIX_EPOCH + Duration::from_millis(1_600_000_000_000);
+        cli.handle_json(json::object! { cmd: "unknown", time: 1_600_000_000_000u64 })
+            .unwrap();
+        assert_eq!(cli.con.command_time(), sent_at);
+
+        // Neither a command without a time nor one without an envelope at all keep the last.
+        cli.handle_json(json::object! { cmd: "unknown" }).unwrap();
+        assert!(cli.con.command_time() > sent_at);
+        cli.handle_json(json::object! { cmd: "unknown", time: 1_600_000_000_000u64 })
+            .unwrap();
+        cli.handle_json(json::object! { text: "no command" })
+            .unwrap();
+        assert!(cli.con.envelope.is_none());
+        assert!(cli.con.command_time() > sent_at);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let json = json::parse(
+            r#"{"cmd":"chat","text":"hi","channel":"programming","time":1600000000000}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Envelope::of(&json),
+            Some(Envelope {
+                cmd: "chat".to_owned(),
+                channel: Some("programming".into()),
+                time: Some(1600000000000),
+            })
+        );
+
+        let json = json::parse(r#"{"cmd":"warn","text":"You are being rate-limited"}"#).unwrap();
+        assert_eq!(
+            Envelope::of(&json),
+            Some(Envelope {
+                cmd: "warn".to_owned(),
+                channel: None,
+                time: None,
+            })
+        );
+
+        // Without a command name it can't be handled at all.
+        let json = json::parse(r#"{"channel":"programming"}"#).unwrap();
+        assert_eq!(Envelope::of(&json), None);
+    }
+
+    #[test]
+    fn test_last_seen() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut seen = LastSeen::default();
+        assert_eq!(seen.describe("bob", start), "bob has not been seen");
+
+        seen.record_message("bob", "hello", start);
+        seen.record_leave("bob", start + Duration::from_secs(30))
+            .unwrap();
+        let info = seen.get("bob").unwrap();
+        assert_eq!(info.time, start + Duration::from_secs(30));
+        assert_eq!(info.last_message.as_deref(), Some("hello"));
+        assert_eq!(
+            seen.describe("bob", start + Duration::from_secs(30 + 5 * 60)),
+            "bob was last seen 5m ago, saying: hello"
+        );
+
+        // Leaving without having said anything
+        seen.record_leave("alice", start).unwrap();
+        assert_eq!(
+            seen.describe("alice", start + Duration::from_secs(2)),
+            "alice was last seen 2s ago"
+        );
+
+        // Kept by the store for the next session.
+        let path = std::env::temp_dir().join(format!("fiskar-seen-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let store = || Box::new(FileHistoryStore::seen_file(path.clone()));
+        let mut seen = LastSeen::load(store()).unwrap();
+        seen.record_message("bob", "hello", start);
+        seen.record_leave("bob", start).unwrap();
+        let seen = LastSeen::load(store()).unwrap();
+        assert_eq!(
+            seen.describe("bob", start + Duration::from_secs(60)),
+            "bob was last seen 1m ago, saying: hello"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_secs(0)), "0s");
+        assert_eq!(format_elapsed(Duration::from_secs(59)), "59s");
+        assert_eq!(format_elapsed(Duration::from_secs(60)), "1m");
+        assert_eq!(format_elapsed(Duration::from_secs(60 * 60 * 3 + 5)), "3h");
+        assert_eq!(format_elapsed(Duration::from_secs(60 * 60 * 24 * 2)), "2d");
+    }
+
+    #[test]
+    fn test_format_relative() {
+        assert_eq!(format_relative(Duration::from_secs(0)), "now");
+        assert_eq!(format_relative(Duration::from_secs(59)), "now");
+        assert_eq!(format_relative(Duration::from_secs(60)), "1m ago");
+        assert_eq!(format_relative(Duration::from_secs(60 * 3 + 30)), "3m ago");
+        assert_eq!(format_relative(Duration::from_secs(60 * 60)), "1h ago");
+        assert_eq!(
+            format_relative(Duration::from_secs(60 * 60 * 24 * 7)),
+            "7d ago"
+        );
+    }
 }
-impl ClientState {}
@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Defaults for what would otherwise have to be given on the command line each time, which the
+/// command line overrides.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub channel: Option<String>,
+    /// The websocket address of the server.
+    pub server: Option<String>,
+    /// The command name that the server sends the channel's topic as.
+    pub topic_cmd: Option<String>,
+    /// The most characters a message can be before the input's count turns red, or 0 for no
+    /// limit.
+    pub max_input_length: Option<usize>,
+    /// Keeps the input at this height, rather than growing between the min and max.
+    pub input_height: Option<usize>,
+    pub input_min_height: Option<usize>,
+    pub input_max_height: Option<usize>,
+    /// The widths the nick and trip columns are never cut below on narrow screens.
+    pub min_nick_width: Option<usize>,
+    pub min_trip_width: Option<usize>,
+    /// Text in messages to replace, with what to replace it with, on top of the default escapes.
+    /// From the `[escapes]` table.
+    pub escapes: HashMap<String, String>,
+    /// The keys to bind actions to, by the action's name, as they are written. From the `[keys]`
+    /// table.
+    pub keys: HashMap<String, String>,
+    /// The styles of the themes to add, by the theme's name. From the `[themes.<name>]` tables.
+    pub themes: HashMap<String, HashMap<String, String>>,
+}
+impl Config {
+    /// Loads the config file, or the defaults if there isn't one.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(err.into()),
+        };
+        Config::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let value: toml::Value = text.parse()?;
+        Ok(Config {
+            username: string_field(&value, "username")?,
+            password: string_field(&value, "password")?,
+            channel: string_field(&value, "channel")?,
+            server: string_field(&value, "server")?,
+            topic_cmd: string_field(&value, "topic_cmd")?,
+            max_input_length: usize_field(&value, "max_input_length")?,
+            input_height: usize_field(&value, "input_height")?,
+            input_min_height: usize_field(&value, "input_min_height")?,
+            input_max_height: usize_field(&value, "input_max_height")?,
+            min_nick_width: usize_field(&value, "min_nick_width")?,
+            min_trip_width: usize_field(&value, "min_trip_width")?,
+            escapes: string_table(&value, "escapes")?,
+            keys: string_table(&value, "keys")?,
+            themes: string_tables(&value, "themes")?,
+        })
+    }
+}
+
+/// A table of strings, which is empty if it isn't given.
+fn string_table(value: &toml::Value, key: &str) -> Result<HashMap<String, String>, ConfigError> {
+    strings_in(value.get(key), key)
+}
+
+/// A table of tables of strings, which is empty if it isn't given.
+fn string_tables(
+    value: &toml::Value,
+    key: &str,
+) -> Result<HashMap<String, HashMap<String, String>>, ConfigError> {
+    match value.get(key) {
+        None => Ok(HashMap::new()),
+        Some(toml::Value::Table(tables)) => tables
+            .iter()
+            .map(|(name, table)| {
+                let strings = strings_in(Some(table), &format!("{}.{}", key, name))?;
+                Ok((name.clone(), strings))
+            })
+            .collect(),
+        Some(_) => Err(ConfigError::Invalid {
+            key: key.to_owned(),
+            expected: "a table",
+        }),
+    }
+}
+
+/// The strings of the table, which errors name by its whole path, `key`.
+fn strings_in(
+    value: Option<&toml::Value>,
+    key: &str,
+) -> Result<HashMap<String, String>, ConfigError> {
+    match value {
+        None => Ok(HashMap::new()),
+        Some(toml::Value::Table(table)) => table
+            .iter()
+            .map(|(name, value)| match value.as_str() {
+                Some(value) => Ok((name.clone(), value.to_owned())),
+                None => Err(ConfigError::Invalid {
+                    key: format!("{}.{}", key, name),
+                    expected: "a string",
+                }),
+            })
+            .collect(),
+        Some(_) => Err(ConfigError::Invalid {
+            key: key.to_owned(),
+            expected: "a table",
+        }),
+    }
+}
+
+fn string_field(value: &toml::Value, key: &str) -> Result<Option<String>, ConfigError> {
+    match value.get(key) {
+        None => Ok(None),
+        Some(toml::Value::String(text)) => Ok(Some(text.clone())),
+        Some(_) => Err(ConfigError::Invalid {
+            key: key.to_owned(),
+            expected: "a string",
+        }),
+    }
+}
+
+fn usize_field(value: &toml::Value, key: &str) -> Result<Option<usize>, ConfigError> {
+    match value.get(key) {
+        None => Ok(None),
+        Some(toml::Value::Integer(n)) if *n >= 0 => Ok(Some(*n as usize)),
+        Some(_) => Err(ConfigError::Invalid {
+            key: key.to_owned(),
+            expected: "a whole number",
+        }),
+    }
+}
+
+/// Where the config file is: `fiskar/config.toml` within `$XDG_CONFIG_HOME`, or `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("fiskar").join("config.toml"))
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    /// The key has a value of the wrong type.
+    Invalid {
+        key: String,
+        expected: &'static str,
+    },
+}
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::Toml(err)
+    }
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{}", err),
+            ConfigError::Toml(err) => write!(f, "{}", err),
+            ConfigError::Invalid { key, expected } => write!(f, "'{}' should be {}", key, expected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Config, ConfigError};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+
+        let config = Config::parse(
+            r#"
+            username = "fisher"
+            channel = "programming,lounge"
+            server = "wss://example.com/chat-ws"
+            topic_cmd = "motd"
+            max_input_length = 500
+            input_max_height = 10
+            min_nick_width = 12
+
+            [escapes]
+            "\t" = "    "
+            ":)" = "☺"
+
+            [keys]
+            quit = "ctrl-x"
+
+            [themes.sea]
+            base = "dark"
+            nick = "blue"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.username.as_deref(), Some("fisher"));
+        assert_eq!(config.password, None);
+        assert_eq!(config.channel.as_deref(), Some("programming,lounge"));
+        assert_eq!(config.server.as_deref(), Some("wss://example.com/chat-ws"));
+        assert_eq!(config.topic_cmd.as_deref(), Some("motd"));
+        assert_eq!(config.max_input_length, Some(500));
+        assert_eq!(config.input_height, None);
+        assert_eq!(config.input_max_height, Some(10));
+        assert_eq!(config.min_nick_width, Some(12));
+        assert_eq!(config.min_trip_width, None);
+        assert_eq!(config.escapes.len(), 2);
+        assert_eq!(config.escapes["\t"], "    ");
+        assert_eq!(config.escapes[":)"], "☺");
+        assert_eq!(config.keys.len(), 1);
+        assert_eq!(config.keys["quit"], "ctrl-x");
+        assert_eq!(config.themes.len(), 1);
+        assert_eq!(config.themes["sea"]["base"], "dark");
+        assert_eq!(config.themes["sea"]["nick"], "blue");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(matches!(
+            Config::parse("username = 5"),
+            Err(ConfigError::Invalid { key, .. }) if key == "username"
+        ));
+        assert!(matches!(
+            Config::parse("[escapes]\na = true"),
+            Err(ConfigError::Invalid { key, .. }) if key == "escapes.a"
+        ));
+        assert!(matches!(
+            Config::parse("[keys]\nquit = 5"),
+            Err(ConfigError::Invalid { key, .. }) if key == "keys.quit"
+        ));
+        assert!(matches!(
+            Config::parse("[themes.sea]\nnick = 5"),
+            Err(ConfigError::Invalid { key, .. }) if key == "themes.sea.nick"
+        ));
+        assert!(matches!(
+            Config::parse("[themes]\nsea = \"blue\""),
+            Err(ConfigError::Invalid { key, .. }) if key == "themes.sea"
+        ));
+        assert!(matches!(
+            Config::parse("escapes = \"a\""),
+            Err(ConfigError::Invalid { key, .. }) if key == "escapes"
+        ));
+        assert!(matches!(
+            Config::parse("max_input_length = -1"),
+            Err(ConfigError::Invalid { key, .. }) if key == "max_input_length"
+        ));
+        assert!(matches!(
+            Config::parse("max_input_length = \"500\""),
+            Err(ConfigError::Invalid { key, .. }) if key == "max_input_length"
+        ));
+        assert!(matches!(
+            Config::parse("username = "),
+            Err(ConfigError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_missing() {
+        let path = Path::new("./this-config-does-not-exist.toml");
+        assert_eq!(Config::load(path).unwrap(), Config::default());
+    }
+}
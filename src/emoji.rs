@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+/// The emoji that can be written as `:shortcode:`, using the names GitHub and Slack do.
+pub const EMOJI: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("alien", "👽"),
+    ("angry", "😠"),
+    ("astonished", "😲"),
+    ("bear", "🐻"),
+    ("beer", "🍺"),
+    ("beers", "🍻"),
+    ("bell", "🔔"),
+    ("blush", "😊"),
+    ("boom", "💥"),
+    ("broken_heart", "💔"),
+    ("bug", "🐛"),
+    ("cake", "🍰"),
+    ("cat", "🐱"),
+    ("clap", "👏"),
+    ("coffee", "☕"),
+    ("confused", "😕"),
+    ("cool", "🆒"),
+    ("cry", "😢"),
+    ("crying_cat_face", "😿"),
+    ("dog", "🐶"),
+    ("eyes", "👀"),
+    ("facepalm", "🤦"),
+    ("fire", "🔥"),
+    ("fish", "🐟"),
+    ("flushed", "😳"),
+    ("frowning", "😦"),
+    ("ghost", "👻"),
+    ("gift", "🎁"),
+    ("grimacing", "😬"),
+    ("grin", "😁"),
+    ("grinning", "😀"),
+    ("hammer", "🔨"),
+    ("heart", "❤️"),
+    ("heart_eyes", "😍"),
+    ("hourglass", "⌛"),
+    ("hugs", "🤗"),
+    ("innocent", "😇"),
+    ("joy", "😂"),
+    ("kiss", "💋"),
+    ("kissing_heart", "😘"),
+    ("laughing", "😆"),
+    ("lock", "🔒"),
+    ("mag", "🔍"),
+    ("mask", "😷"),
+    ("moon", "🌙"),
+    ("muscle", "💪"),
+    ("neutral_face", "😐"),
+    ("no_mouth", "😶"),
+    ("ok_hand", "👌"),
+    ("open_mouth", "😮"),
+    ("party", "🥳"),
+    ("pensive", "😔"),
+    ("pizza", "🍕"),
+    ("point_down", "👇"),
+    ("point_left", "👈"),
+    ("point_right", "👉"),
+    ("point_up", "☝️"),
+    ("poop", "💩"),
+    ("pray", "🙏"),
+    ("rage", "😡"),
+    ("raised_hands", "🙌"),
+    ("relieved", "😌"),
+    ("robot", "🤖"),
+    ("rocket", "🚀"),
+    ("rofl", "🤣"),
+    ("rolling_eyes", "🙄"),
+    ("scream", "😱"),
+    ("see_no_evil", "🙈"),
+    ("shrug", "🤷"),
+    ("skull", "💀"),
+    ("sleeping", "😴"),
+    ("slightly_frowning_face", "🙁"),
+    ("slightly_smiling_face", "🙂"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("smirk", "😏"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("stuck_out_tongue", "😛"),
+    ("sunglasses", "😎"),
+    ("sweat", "😓"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("tired_face", "😫"),
+    ("triumph", "😤"),
+    ("unamused", "😒"),
+    ("upside_down_face", "🙃"),
+    ("v", "✌️"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("weary", "😩"),
+    ("wink", "😉"),
+    ("worried", "😟"),
+    ("x", "❌"),
+    ("yum", "😋"),
+    ("zap", "⚡"),
+    ("zipper_mouth_face", "🤐"),
+];
+
+/// Emoji by their shortcode, for expanding the shortcodes in what we send.
+#[derive(Debug, Clone)]
+pub struct EmojiMap {
+    emoji: HashMap<&'static str, &'static str>,
+}
+impl EmojiMap {
+    pub fn new(table: &[(&'static str, &'static str)]) -> Self {
+        Self {
+            emoji: table.iter().copied().collect(),
+        }
+    }
+
+    /// The emoji in [`EMOJI`].
+    pub fn bundled() -> Self {
+        Self::new(EMOJI)
+    }
+
+    /// The emoji of the shortcode, without its colons.
+    pub fn get(&self, code: &str) -> Option<&'static str> {
+        self.emoji.get(code).copied()
+    }
+
+    /// Replaces each `:shortcode:` in the text with its emoji. Shortcodes we don't know of are
+    /// left as they were written, as they're just as likely to be something like a time.
+    pub fn expand(&self, text: &str) -> String {
+        let mut expanded = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(':') {
+            let after = &rest[start + 1..];
+            let len = after
+                .find(|c: char| !is_shortcode_char(c))
+                .unwrap_or_else(|| after.len());
+            let emoji = if after[len..].starts_with(':') {
+                self.get(&after[..len])
+            } else {
+                None
+            };
+            match emoji {
+                Some(emoji) => {
+                    expanded += &rest[..start];
+                    expanded += emoji;
+                    rest = &after[len + 1..];
+                }
+                // The colon that ended it could start the next shortcode.
+                None => {
+                    expanded += &rest[..=start];
+                    rest = after;
+                }
+            }
+        }
+        expanded += rest;
+        expanded
+    }
+
+    /// The shortcodes containing the query, ignoring case, along with their emoji in order of
+    /// their shortcode.
+    pub fn search(&self, query: &str) -> Vec<(&'static str, &'static str)> {
+        let query = query.to_lowercase();
+        let mut found: Vec<(&'static str, &'static str)> = self
+            .emoji
+            .iter()
+            .filter(|(code, _)| code.contains(query.as_str()))
+            .map(|(code, emoji)| (*code, *emoji))
+            .collect();
+        found.sort_unstable();
+        found
+    }
+
+    /// What the search finds, as it is shown to the user.
+    pub fn describe_search(&self, query: &str) -> String {
+        let found = self.search(query);
+        if found.is_empty() {
+            return format!("No emoji have a shortcode containing '{}'", query);
+        }
+        found
+            .iter()
+            .map(|(code, emoji)| format!("{} :{}:", emoji, code))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+impl Default for EmojiMap {
+    fn default() -> Self {
+        Self::bundled()
+    }
+}
+
+fn is_shortcode_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '+' || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmojiMap, EMOJI};
+
+    #[test]
+    fn test_expand() {
+        let emoji = EmojiMap::bundled();
+        assert_eq!(emoji.expand("hi :wave:"), "hi 👋");
+        assert_eq!(emoji.expand(":+1::tada:!"), "👍🎉!");
+        // Unknown shortcodes, and colons that aren't around one, are kept.
+        assert_eq!(emoji.expand(":nope: at 12:30:45"), ":nope: at 12:30:45");
+        assert_eq!(emoji.expand("a:b:smile:"), "a:b😄");
+        assert_eq!(emoji.expand(":: :smile"), ":: :smile");
+        assert_eq!(emoji.expand("über :fire:"), "über 🔥");
+    }
+
+    #[test]
+    fn test_search() {
+        let emoji = EmojiMap::bundled();
+        assert_eq!(
+            emoji.search("SMIL"),
+            vec![
+                ("slightly_smiling_face", "🙂"),
+                ("smile", "😄"),
+                ("smiley", "😃"),
+                ("sweat_smile", "😅"),
+            ]
+        );
+        assert!(emoji.search("nothing like it").is_empty());
+        assert!(emoji.describe_search("nothing").starts_with("No emoji"));
+        assert_eq!(emoji.describe_search("tada"), "🎉 :tada:");
+    }
+
+    #[test]
+    fn test_table() {
+        // Kept in order, so that it's easy to see whether a shortcode is there.
+        let codes: Vec<&str> = EMOJI.iter().map(|(code, _)| *code).collect();
+        let mut sorted = codes.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(codes, sorted);
+    }
+}
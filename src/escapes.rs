@@ -2,31 +2,60 @@ use std::{borrow::Cow, collections::HashMap};
 
 use cursive::{
     theme::Effect,
-    views::{Dialog, TextView},
+    traits::Scrollable,
+    views::{Dialog, ScrollView, TextView},
 };
 
 use crate::styled::{self, StyledIndexedSpan, StyledString};
 
+/// What is done with the characters that can't be seen, such as zero-width spaces and bidi
+/// overrides, which can be used to break the layout or to pass as someone else's nick.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Invisibles {
+    Keep,
+    Strip,
+    /// Replaced with a dim placeholder naming the character, such as `<U+200B>`.
+    Mark,
+}
+
 #[derive(Debug, Clone)]
 pub struct Escapes<'a> {
     /// Mapping of thing to replace with what to replace it with.
     pub escapes: HashMap<Cow<'a, str>, Cow<'a, str>>,
+    pub invisibles: Invisibles,
 }
 impl<'a> Escapes<'a> {
     pub fn new() -> Self {
         Self {
             escapes: HashMap::new(),
+            invisibles: Invisibles::Keep,
         }
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             escapes: HashMap::with_capacity(capacity),
+            invisibles: Invisibles::Keep,
         }
     }
 
     /// Applies escapes to text.
     pub fn apply<S>(&self, text: S) -> Escaped<StyledString>
+    where
+        S: Into<StyledString>,
+    {
+        self.sanitize(self.replace(text))
+    }
+
+    /// Does whatever was chosen with the invisible characters of text that has already had the
+    /// replacements made. Kept apart so that markup can be parsed in between, such as ANSI
+    /// escapes, which are themselves control characters.
+    pub fn sanitize(&self, text: StyledString) -> Escaped<StyledString> {
+        Escaped(sanitize(&text, self.invisibles))
+    }
+
+    /// Makes the replacements, without doing anything about invisible characters.
+    pub fn replace<S>(&self, text: S) -> StyledString
     where
         S: Into<StyledString>,
     {
@@ -47,7 +76,7 @@ impl<'a> Escapes<'a> {
             // }
             styled = new_styled;
         }
-        Escaped(styled)
+        styled
     }
 
     pub fn add<S, V>(&mut self, value: V, escape: S)
@@ -67,6 +96,74 @@ impl<'a> Default for Escapes<'a> {
     }
 }
 
+/// Strips or marks the invisible characters of the text, with its styling kept around them.
+pub fn sanitize(text: &StyledString, invisibles: Invisibles) -> StyledString {
+    let source = text.source();
+    let found: Vec<(usize, char)> = source
+        .char_indices()
+        .filter(|&(i, c)| is_invisible(source, i, c))
+        .collect();
+    if invisibles == Invisibles::Keep || found.is_empty() {
+        return text.clone();
+    }
+    let mut sanitized = StyledString::default();
+    // Where the visible text since the last invisible character starts.
+    let mut start = 0;
+    for (i, c) in found {
+        sanitized.append(text.slice(start..i));
+        if invisibles == Invisibles::Mark {
+            // Dimmed like the ellipsis, so that it isn't mistaken for what was written.
+            sanitized.append_styled(
+                &format!("<U+{:04X}>", u32::from(c)),
+                styled::ellipsis_style(),
+            );
+        }
+        start = i + c.len_utf8();
+    }
+    sanitized.append(text.slice(start..source.len()));
+    sanitized
+}
+
+/// Whether the character at the index of the text is one that can't be seen. Newlines and tabs
+/// are left alone, as is a zero-width joiner between two characters outside of ASCII, as that's
+/// how emoji are put together.
+fn is_invisible(text: &str, idx: usize, c: char) -> bool {
+    match c {
+        '\n' | '\t' => false,
+        '\u{200D}' => {
+            let before = text[..idx].chars().next_back();
+            let after = text[idx + c.len_utf8()..].chars().next();
+            !matches!((before, after), (Some(before), Some(after))
+                if !before.is_ascii() && !after.is_ascii()
+                    && !is_invisible(text, idx - before.len_utf8(), before))
+        }
+        '\u{00AD}'
+        | '\u{061C}'
+        | '\u{180E}'
+        | '\u{200B}'..='\u{200F}'
+        | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{2069}'
+        | '\u{FEFF}' => true,
+        _ => c.is_control(),
+    }
+}
+
+/// Leaves the text as it is, other than what would corrupt the terminal, for seeing exactly what
+/// was sent. Control characters are written out, such as `\u{1b}`, while newlines and tabs are
+/// kept.
+pub fn escape_raw(text: &str) -> Escaped<StyledString> {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_control() && c != '\n' && c != '\t' {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    Escaped(escaped.into())
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct Escaped<T>(T);
 impl<T> Escaped<T> {
@@ -77,6 +174,18 @@ impl<T> Escaped<T> {
     pub fn inner(&self) -> &T {
         &self.0
     }
+
+    /// Transforms the escaped value, which must not reintroduce anything that needs escaping.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Escaped<U> {
+        Escaped(f(self.0))
+    }
+}
+impl Escaped<StyledString> {
+    /// Whether the text spans multiple lines, such as captcha art, and so should be displayed
+    /// with its lines and whitespace kept as-is.
+    pub fn is_preformatted(&self) -> bool {
+        self.0.source().contains('\n')
+    }
 }
 impl<T> Escaped<T>
 where
@@ -100,3 +209,90 @@ where
 {
     TextView::new(text.into_inner().into())
 }
+
+/// Creates a view that does not wrap the text, so that the lines and their alignment are kept.
+/// Lines too long to fit can be scrolled horizontally instead.
+pub fn create_preformatted_view<T>(text: Escaped<T>) -> ScrollView<TextView>
+where
+    T: Into<StyledString>,
+{
+    TextView::new(text.into_inner().into())
+        .no_wrap()
+        .scrollable()
+        .scroll_x(true)
+        .scroll_y(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use cursive::theme::Effect;
+
+    use super::{sanitize, Escapes, Invisibles};
+    use crate::styled::{self, StyledIndexedSpan, StyledString};
+
+    #[test]
+    fn test_preformatted() {
+        let escapes = Escapes::default();
+        assert!(!escapes.apply("single line").is_preformatted());
+
+        let captcha = "  _  _ \n | || |\n |_||_|\n   |_|\0";
+        let escaped = escapes.apply(captcha);
+        assert!(escaped.is_preformatted());
+        // The lines, including their leading whitespace, are kept as they were.
+        let lines: Vec<&str> = escaped.inner().source().lines().collect();
+        assert_eq!(lines, vec!["  _  _ ", " | || |", " |_||_|", "   |_|\\0"]);
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let text = StyledString::from("a\u{200B}dmin \u{202E}olleh\u{1b}[31m\tok\n");
+        assert_eq!(sanitize(&text, Invisibles::Keep), text);
+        assert_eq!(
+            sanitize(&text, Invisibles::Strip).source(),
+            "admin olleh[31m\tok\n"
+        );
+        let marked = sanitize(&text, Invisibles::Mark);
+        assert_eq!(
+            marked.source(),
+            "a<U+200B>dmin <U+202E>olleh<U+001B>[31m\tok\n"
+        );
+        assert_eq!(
+            marked.spans()[0],
+            StyledIndexedSpan::new_range(1..9, styled::ellipsis_style())
+        );
+
+        // Styling is kept around what was taken out.
+        let bold = Effect::Bold.into();
+        let text = StyledString::single_span("b\u{200B}ob", bold);
+        let stripped = sanitize(&text, Invisibles::Strip);
+        assert_eq!(stripped.source(), "bob");
+        assert_eq!(
+            stripped.spans(),
+            &[
+                StyledIndexedSpan::new_range(0..1, bold),
+                StyledIndexedSpan::new_range(1..3, bold),
+            ]
+        );
+
+        // Emoji are joined with zero-width joiners, but nicks aren't.
+        let family = StyledString::from("👨\u{200D}👩\u{200D}👧");
+        assert_eq!(sanitize(&family, Invisibles::Strip), family);
+        assert_eq!(
+            sanitize(&"bob\u{200D}".into(), Invisibles::Strip).source(),
+            "bob"
+        );
+        assert_eq!(
+            sanitize(&"é\u{200B}\u{200D}é".into(), Invisibles::Strip).source(),
+            "éé"
+        );
+    }
+
+    #[test]
+    fn test_apply_sanitizes() {
+        let mut escapes = Escapes::default();
+        escapes.invisibles = Invisibles::Strip;
+        // The replacements are made first, so that they can still escape control characters.
+        let escaped = escapes.apply("x\0y\u{200B}z");
+        assert_eq!(escaped.inner().source(), "x\\0yz");
+    }
+}
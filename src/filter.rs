@@ -0,0 +1,195 @@
+use std::fmt;
+
+use crate::{ChatMessage, MessageName};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MessageKind {
+    User,
+    Server,
+    Warn,
+}
+impl MessageKind {
+    pub fn of(name: &MessageName) -> Option<Self> {
+        match name {
+            MessageName::User(_) | MessageName::Whisper(_) => Some(MessageKind::User),
+            MessageName::Server => Some(MessageKind::Server),
+            MessageName::ServerWarn => Some(MessageKind::Warn),
+            MessageName::None => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterTerm {
+    /// `nick:<nick>`, case insensitive.
+    Nick(String),
+    /// `trip:<trip>`
+    Trip(String),
+    /// `type:<user|server|warn>`
+    Kind(MessageKind),
+    /// Any other word, which the text must contain. Case insensitive.
+    Keyword(String),
+}
+impl FilterTerm {
+    pub fn matches(&self, message: &ChatMessage) -> bool {
+        match self {
+            FilterTerm::Nick(nick) => match &message.from {
+                MessageName::User(from) | MessageName::Whisper(from) => {
+                    from.to_lowercase() == *nick
+                }
+                _ => false,
+            },
+            FilterTerm::Trip(trip) => message.trip.as_ref().map_or(false, |x| &x.0 == trip),
+            FilterTerm::Kind(kind) => MessageKind::of(&message.from) == Some(*kind),
+            FilterTerm::Keyword(keyword) => message.text.to_lowercase().contains(keyword),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterError {
+    Empty,
+    /// A term such as `nick:` had nothing after it.
+    MissingValue(String),
+    UnknownKind(String),
+}
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::Empty => write!(f, "there is nothing to filter by"),
+            FilterError::MissingValue(term) => write!(f, "'{}' needs something after it", term),
+            FilterError::UnknownKind(kind) => write!(
+                f,
+                "'{}' is not a message type, which are user, server and warn",
+                kind
+            ),
+        }
+    }
+}
+
+/// Selects which messages are shown, such as `nick:bob type:user hello`.
+/// Every term has to match for a message to be shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    terms: Vec<FilterTerm>,
+}
+impl Filter {
+    pub fn parse(text: &str) -> Result<Self, FilterError> {
+        let terms = text
+            .split_whitespace()
+            .map(parse_term)
+            .collect::<Result<Vec<_>, _>>()?;
+        if terms.is_empty() {
+            return Err(FilterError::Empty);
+        }
+
+        Ok(Self { terms })
+    }
+
+    pub fn terms(&self) -> &[FilterTerm] {
+        &self.terms
+    }
+
+    pub fn matches(&self, message: &ChatMessage) -> bool {
+        self.terms.iter().all(|term| term.matches(message))
+    }
+}
+
+fn parse_term(word: &str) -> Result<FilterTerm, FilterError> {
+    let (key, value) = if let Some(idx) = word.find(':') {
+        (&word[..idx], &word[idx + 1..])
+    } else {
+        return Ok(FilterTerm::Keyword(word.to_lowercase()));
+    };
+
+    let term = match key {
+        "nick" => FilterTerm::Nick(value.to_lowercase()),
+        "trip" => FilterTerm::Trip(value.to_owned()),
+        "type" => FilterTerm::Kind(match value {
+            "user" => MessageKind::User,
+            "server" => MessageKind::Server,
+            "warn" => MessageKind::Warn,
+            "" => return Err(FilterError::MissingValue(word.to_owned())),
+            _ => return Err(FilterError::UnknownKind(value.to_owned())),
+        }),
+        // Not a key we know, so it's likely something like a url.
+        _ => return Ok(FilterTerm::Keyword(word.to_lowercase())),
+    };
+
+    if value.is_empty() {
+        Err(FilterError::MissingValue(word.to_owned()))
+    } else {
+        Ok(term)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use hack_chat_types::Trip;
+
+    use super::{Filter, FilterError};
+    use crate::{ChatMessage, MessageName};
+
+    fn message(from: MessageName, trip: Option<&str>, text: &str) -> ChatMessage {
+        ChatMessage {
+            from,
+            trip: trip.map(|trip| Trip(trip.to_owned())),
+            text: text.to_owned(),
+            timestamp: SystemTime::now(),
+            id: 0,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let messages = vec![
+            message(
+                MessageName::User("Bob".to_owned()),
+                Some("abcdef"),
+                "Hello there",
+            ),
+            message(MessageName::User("alice".to_owned()), None, "hello bob"),
+            message(MessageName::Server, None, "bob joined"),
+            message(MessageName::ServerWarn, None, "You are being rate limited"),
+        ];
+        let matching = |expr: &str| -> Vec<usize> {
+            let filter = Filter::parse(expr).unwrap();
+            messages
+                .iter()
+                .enumerate()
+                .filter(|(_, message)| filter.matches(message))
+                .map(|(i, _)| i)
+                .collect()
+        };
+
+        assert_eq!(matching("nick:bob"), vec![0]);
+        assert_eq!(matching("trip:abcdef"), vec![0]);
+        assert_eq!(matching("type:user"), vec![0, 1]);
+        assert_eq!(matching("type:server"), vec![2]);
+        assert_eq!(matching("type:warn"), vec![3]);
+        assert_eq!(matching("bob"), vec![1, 2]);
+        assert_eq!(matching("HELLO"), vec![0, 1]);
+        assert_eq!(matching("hello type:user nick:alice"), vec![1]);
+        assert_eq!(matching("https://example.com"), Vec::<usize>::new());
+
+        assert_eq!(Filter::parse("  "), Err(FilterError::Empty));
+        assert_eq!(
+            Filter::parse("nick:"),
+            Err(FilterError::MissingValue("nick:".to_owned()))
+        );
+        assert_eq!(
+            Filter::parse("type:mod"),
+            Err(FilterError::UnknownKind("mod".to_owned()))
+        );
+        assert_eq!(
+            FilterError::MissingValue("nick:".to_owned()).to_string(),
+            "'nick:' needs something after it"
+        );
+        assert_eq!(
+            FilterError::UnknownKind("mod".to_owned()).to_string(),
+            "'mod' is not a message type, which are user, server and warn"
+        );
+    }
+}
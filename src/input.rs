@@ -0,0 +1,1413 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use cursive::{
+    event::{Event, Key},
+    theme::{Effect, Style},
+    traits::Scrollable,
+    view::{SizeConstraint, View},
+    views::{ResizedView, ScrollView},
+};
+use hack_chat_types::{Channel, Nickname};
+
+use crate::{
+    client_manager::{format_elapsed, Ignore},
+    emoji::EmojiMap,
+    filter::Filter,
+    styled::{self, StyledString},
+};
+
+/// Sizes of the message being written, displayed beside the input area.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct InputCount {
+    /// The length in bytes, which is what most servers measure.
+    pub bytes: usize,
+    pub chars: usize,
+    /// The number of columns it takes up when displayed.
+    pub width: usize,
+}
+impl InputCount {
+    pub fn of(text: &str) -> Self {
+        Self {
+            bytes: text.len(),
+            chars: text.chars().count(),
+            width: styled::str_width(text),
+        }
+    }
+
+    /// Whether the draft is past the maximum length, if there is one.
+    pub fn is_over(&self, max_length: Option<usize>) -> bool {
+        max_length.map_or(false, |max_length| self.chars > max_length)
+    }
+}
+
+/// What the user's input is to be treated as.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputCommand {
+    /// Text to be sent as a chat message.
+    Chat(String),
+    /// `/seen <nick>`
+    Seen(Nickname),
+    /// `/filter <expr>` to only show matching messages, or `/filter clear` to show all of them.
+    Filter(Option<Filter>),
+    /// `/spans <n>` to show how the nth displayed message, from zero, is styled.
+    Spans(usize),
+    /// `/whois <nick>`
+    Whois(Nickname),
+    /// `/theme <name>` to switch to another theme.
+    Theme(String),
+    /// `/invite <nick>`
+    Invite(Nickname),
+    /// `/nick <nick>` to change our nick without reconnecting.
+    Nick(Nickname),
+    /// `/whisper <nick> <text>`
+    Whisper { nick: Nickname, text: String },
+    /// `/me <action>`, sent as an emote.
+    Emote(String),
+    /// `/dnd [minutes]` to mute notifications for a while, or unmute them if it is zero.
+    Dnd(Duration),
+    /// `/go [n]` to switch to the nth most recent channel linked in the chat, from one.
+    Go(usize),
+    /// `/open <channel>` to join another channel in a new tab.
+    Open(Channel),
+    /// `/join <channel>` to leave the channel for another, without reconnecting.
+    Join(Channel),
+    /// `/ignore [nick[#trip]]` to hide what a user says, or list who is ignored without one.
+    Ignore(Option<Ignore>),
+    /// `/unignore <nick[#trip]>`
+    Unignore(Ignore),
+    /// `/clear` to wipe the chat area, without leaving the channel.
+    Clear,
+    /// `/emoji <query>` to list the emoji whose shortcodes contain the query.
+    Emoji(String),
+    /// `/copy [n]` to copy the nth most recent message from a user, from one.
+    Copy(usize),
+    /// `/help [command]` to list the commands, or describe the one command by its name.
+    Help(Option<&'static str>),
+}
+impl InputCommand {
+    /// What to tell the user once the command is done, for commands that nothing else answers.
+    pub fn confirmation(&self) -> Option<StyledString> {
+        let bold: Style = Effect::Bold.into();
+        let mut text = StyledString::default();
+        match self {
+            InputCommand::Filter(Some(_)) => {
+                text.append_source("Only showing messages that match the filter")
+            }
+            InputCommand::Filter(None) => text.append_source("Showing every message"),
+            InputCommand::Invite(nick) => {
+                text.append_source("Inviting ");
+                text.append_styled(nick, bold);
+                text.append_source(" to a new channel");
+            }
+            InputCommand::Dnd(duration) if *duration == Duration::from_secs(0) => {
+                text.append_source("Notifications unmuted")
+            }
+            InputCommand::Dnd(duration) => {
+                text.append_source("Notifications muted for ");
+                text.append_styled(&format_elapsed(*duration), bold);
+            }
+            InputCommand::Help(name) => return Some(describe_help(*name)),
+            InputCommand::Chat(_)
+            | InputCommand::Seen(_)
+            | InputCommand::Spans(_)
+            | InputCommand::Whois(_)
+            | InputCommand::Nick(_)
+            | InputCommand::Whisper { .. }
+            | InputCommand::Emote(_)
+            | InputCommand::Theme(_)
+            | InputCommand::Go(_)
+            | InputCommand::Open(_)
+            | InputCommand::Join(_)
+            | InputCommand::Ignore(_)
+            | InputCommand::Unignore(_)
+            | InputCommand::Clear
+            | InputCommand::Emoji(_)
+            | InputCommand::Copy(_) => return None,
+        }
+        Some(text)
+    }
+
+    /// Expands the emoji shortcodes in what is sent to the channel, see [`EmojiMap::expand`].
+    /// This is done once the command is parsed, so that the names of commands are left alone.
+    pub fn expand_emoji(self, emoji: &EmojiMap) -> Self {
+        match self {
+            InputCommand::Chat(text) => InputCommand::Chat(emoji.expand(&text)),
+            InputCommand::Emote(text) => InputCommand::Emote(emoji.expand(&text)),
+            InputCommand::Whisper { nick, text } => InputCommand::Whisper {
+                nick,
+                text: emoji.expand(&text),
+            },
+            command => command,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputError {
+    /// A command was given invalid arguments. Holds how the command should be used.
+    Usage(&'static str),
+    /// A command's arguments could not be parsed, with an explanation of why.
+    Invalid(String),
+}
+
+/// Why a command's arguments couldn't be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgsError {
+    /// The arguments were wrong, so the command's usage is shown.
+    Usage,
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum CommandKind {
+    /// Handled by the server, so it is sent as chat.
+    Server,
+    /// Handled by us, with the arguments parsed into what to do.
+    Local(fn(&str) -> Result<InputCommand, ArgsError>),
+}
+
+/// A slash command, such as `/seen`.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandDef {
+    /// The name, without the slash.
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub usage: &'static str,
+    pub description: &'static str,
+    pub kind: CommandKind,
+}
+impl CommandDef {
+    /// Whether the command is called by the name, which may be an alias.
+    pub fn is_called(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+}
+
+const COMMANDS: &[CommandDef] = &[
+    CommandDef {
+        name: "help",
+        aliases: &[],
+        usage: "/help [command]",
+        description: "Lists the commands, or describes one of them",
+        kind: CommandKind::Local(parse_help),
+    },
+    CommandDef {
+        name: "seen",
+        aliases: &[],
+        usage: "/seen <nick>",
+        description: "Shows when a user was last seen",
+        kind: CommandKind::Local(parse_seen),
+    },
+    CommandDef {
+        name: "filter",
+        aliases: &[],
+        usage: "/filter <expr> | /filter clear",
+        description: "Only shows messages matching the filter, such as 'nick:bob type:user'",
+        kind: CommandKind::Local(parse_filter),
+    },
+    CommandDef {
+        name: "spans",
+        aliases: &[],
+        usage: "/spans <n>",
+        description: "Shows how the nth displayed message, from zero, is styled",
+        kind: CommandKind::Local(parse_spans),
+    },
+    CommandDef {
+        name: "whois",
+        aliases: &[],
+        usage: "/whois <nick>",
+        description: "Shows whether a user is online, and their trip",
+        kind: CommandKind::Local(parse_whois),
+    },
+    CommandDef {
+        name: "theme",
+        aliases: &[],
+        usage: "/theme <name>",
+        description: "Switches to another theme, such as 'dark' or 'light'",
+        kind: CommandKind::Local(parse_theme),
+    },
+    CommandDef {
+        name: "invite",
+        aliases: &[],
+        usage: "/invite <nick>",
+        description: "Invites a user to a new channel",
+        kind: CommandKind::Local(parse_invite),
+    },
+    CommandDef {
+        name: "nick",
+        aliases: &[],
+        usage: "/nick <nick>",
+        description: "Changes your nick, without reconnecting",
+        kind: CommandKind::Local(parse_nick),
+    },
+    CommandDef {
+        name: "dnd",
+        aliases: &[],
+        usage: "/dnd [minutes]",
+        description: "Mutes notifications for a while, 60 minutes by default, or unmutes with 0",
+        kind: CommandKind::Local(parse_dnd),
+    },
+    CommandDef {
+        name: "go",
+        aliases: &[],
+        usage: "/go [n]",
+        description: "Switches to the nth most recent ?channel linked in the chat",
+        kind: CommandKind::Local(parse_go),
+    },
+    CommandDef {
+        name: "open",
+        aliases: &[],
+        usage: "/open <channel>",
+        description: "Joins another channel in a new tab, switched between with Alt+1 to Alt+9",
+        kind: CommandKind::Local(parse_open),
+    },
+    CommandDef {
+        name: "join",
+        aliases: &[],
+        usage: "/join <channel>",
+        description: "Leaves the channel for another, without reconnecting",
+        kind: CommandKind::Local(parse_join),
+    },
+    CommandDef {
+        name: "ignore",
+        aliases: &[],
+        usage: "/ignore [nick[#trip]]",
+        description: "Hides what the user says, or lists who is ignored",
+        kind: CommandKind::Local(parse_ignore),
+    },
+    CommandDef {
+        name: "unignore",
+        aliases: &[],
+        usage: "/unignore <nick[#trip]>",
+        description: "Shows what an ignored user says again",
+        kind: CommandKind::Local(parse_unignore),
+    },
+    CommandDef {
+        name: "clear",
+        aliases: &[],
+        usage: "/clear",
+        description: "Clears the chat, which Ctrl-L also does",
+        kind: CommandKind::Local(parse_clear),
+    },
+    CommandDef {
+        name: "emoji",
+        aliases: &[],
+        usage: "/emoji <query>",
+        description: "Lists the emoji whose :shortcodes: contain the query",
+        kind: CommandKind::Local(parse_emoji),
+    },
+    CommandDef {
+        name: "copy",
+        aliases: &[],
+        usage: "/copy [n]",
+        description:
+            "Copies the nth most recent message to the clipboard, which Alt-C does for the latest",
+        kind: CommandKind::Local(parse_copy),
+    },
+    CommandDef {
+        name: "me",
+        aliases: &[],
+        usage: "/me <action>",
+        description: "Sends an emote",
+        kind: CommandKind::Local(parse_me),
+    },
+    CommandDef {
+        name: "whisper",
+        aliases: &["w"],
+        usage: "/whisper <nick> <text>",
+        description: "Sends a message that only the user sees",
+        kind: CommandKind::Local(parse_whisper),
+    },
+    CommandDef {
+        name: "reply",
+        aliases: &["r"],
+        usage: "/reply <text>",
+        description: "Whispers to whoever last whispered to you",
+        kind: CommandKind::Server,
+    },
+];
+
+/// Every slash command that we know of.
+pub fn commands() -> &'static [CommandDef] {
+    COMMANDS
+}
+
+/// Finds the command called by the name, which may be an alias.
+pub fn find_command(name: &str) -> Option<&'static CommandDef> {
+    COMMANDS.iter().find(|def| def.is_called(name))
+}
+
+/// Every command and how it's used, or everything about the command with the name, for `/help`.
+pub fn describe_help(name: Option<&str>) -> StyledString {
+    let bold: Style = Effect::Bold.into();
+    let mut text = StyledString::default();
+    match name.and_then(find_command) {
+        Some(def) => {
+            text.append_styled(def.usage, bold);
+            text.append_source("\n");
+            text.append_source(def.description);
+            for alias in def.aliases {
+                text.append_source(&format!("\nAlso written as /{}", alias));
+            }
+            if let CommandKind::Server = def.kind {
+                text.append_source("\nThis is sent on to the server, which handles it");
+            }
+        }
+        None => {
+            text.append_source("Commands, with /help <command> for more about one:");
+            for def in COMMANDS {
+                text.append_source("\n");
+                text.append_styled(def.usage, bold);
+                text.append_source(" - ");
+                text.append_source(def.description);
+            }
+            text.append_source("\nAny others are sent on to the server, which may know them");
+        }
+    }
+    text
+}
+
+/// Parses the user's input into what it should be treated as.
+/// Commands we don't know of are sent as chat, as the server handles some commands itself.
+pub fn parse_input(text: &str) -> Result<InputCommand, InputError> {
+    let (name, args) = if let Some(command) = split_command(text) {
+        command
+    } else {
+        return Ok(InputCommand::Chat(text.to_owned()));
+    };
+
+    match find_command(name) {
+        Some(&CommandDef {
+            usage,
+            kind: CommandKind::Local(parse),
+            ..
+        }) => parse(args).map_err(|err| match err {
+            ArgsError::Usage => InputError::Usage(usage),
+            ArgsError::Invalid(reason) => InputError::Invalid(reason),
+        }),
+        _ => Ok(InputCommand::Chat(text.to_owned())),
+    }
+}
+
+fn parse_help(args: &str) -> Result<InputCommand, ArgsError> {
+    if args.trim().is_empty() {
+        return Ok(InputCommand::Help(None));
+    }
+    let name = single_word(args).ok_or(ArgsError::Usage)?;
+    let name = name.strip_prefix('/').unwrap_or(name);
+    find_command(name)
+        .map(|def| InputCommand::Help(Some(def.name)))
+        .ok_or_else(|| ArgsError::Invalid(format!("There is no command /{}", name)))
+}
+
+fn parse_seen(args: &str) -> Result<InputCommand, ArgsError> {
+    single_word(args)
+        .map(|nick| InputCommand::Seen(nick.to_owned()))
+        .ok_or(ArgsError::Usage)
+}
+
+fn parse_whois(args: &str) -> Result<InputCommand, ArgsError> {
+    single_word(args)
+        .map(|nick| InputCommand::Whois(nick.to_owned()))
+        .ok_or(ArgsError::Usage)
+}
+
+fn parse_invite(args: &str) -> Result<InputCommand, ArgsError> {
+    single_word(args)
+        .map(|nick| InputCommand::Invite(nick.to_owned()))
+        .ok_or(ArgsError::Usage)
+}
+
+fn parse_nick(args: &str) -> Result<InputCommand, ArgsError> {
+    single_word(args)
+        .map(|nick| InputCommand::Nick(nick.to_owned()))
+        .ok_or(ArgsError::Usage)
+}
+
+fn parse_whisper(args: &str) -> Result<InputCommand, ArgsError> {
+    let args = args.trim();
+    let (nick, text) = args.split_at(args.find(char::is_whitespace).unwrap_or(args.len()));
+    let text = text.trim();
+    if nick.is_empty() || text.is_empty() {
+        return Err(ArgsError::Usage);
+    }
+    Ok(InputCommand::Whisper {
+        nick: nick.to_owned(),
+        text: text.to_owned(),
+    })
+}
+
+fn parse_me(args: &str) -> Result<InputCommand, ArgsError> {
+    match args.trim() {
+        "" => Err(ArgsError::Usage),
+        action => Ok(InputCommand::Emote(action.to_owned())),
+    }
+}
+
+fn parse_dnd(args: &str) -> Result<InputCommand, ArgsError> {
+    const DEFAULT_MINUTES: u64 = 60;
+    let minutes = match args.trim() {
+        "" => DEFAULT_MINUTES,
+        minutes => minutes.parse().map_err(|_| ArgsError::Usage)?,
+    };
+    let secs = minutes.checked_mul(60).ok_or(ArgsError::Usage)?;
+    Ok(InputCommand::Dnd(Duration::from_secs(secs)))
+}
+
+fn parse_go(args: &str) -> Result<InputCommand, ArgsError> {
+    match args.trim() {
+        "" => Ok(InputCommand::Go(1)),
+        n => match n.parse() {
+            Ok(n) if n > 0 => Ok(InputCommand::Go(n)),
+            _ => Err(ArgsError::Usage),
+        },
+    }
+}
+
+fn parse_open(args: &str) -> Result<InputCommand, ArgsError> {
+    single_channel(args).map(InputCommand::Open)
+}
+
+fn parse_join(args: &str) -> Result<InputCommand, ArgsError> {
+    single_channel(args).map(InputCommand::Join)
+}
+
+/// A single channel, with or without its `?`.
+fn single_channel(args: &str) -> Result<Channel, ArgsError> {
+    let channel = single_word(args).ok_or(ArgsError::Usage)?;
+    match parse_channels(channel) {
+        Ok(mut channels) if channels.len() == 1 => Ok(channels.remove(0)),
+        Ok(_) => Err(ArgsError::Usage),
+        Err(err) => Err(ArgsError::Invalid(format!("Invalid channel: {}", err))),
+    }
+}
+
+fn parse_ignore(args: &str) -> Result<InputCommand, ArgsError> {
+    if args.trim().is_empty() {
+        return Ok(InputCommand::Ignore(None));
+    }
+    single_word(args)
+        .and_then(Ignore::parse)
+        .map(|ignore| InputCommand::Ignore(Some(ignore)))
+        .ok_or(ArgsError::Usage)
+}
+
+fn parse_unignore(args: &str) -> Result<InputCommand, ArgsError> {
+    single_word(args)
+        .and_then(Ignore::parse)
+        .map(InputCommand::Unignore)
+        .ok_or(ArgsError::Usage)
+}
+
+fn parse_clear(args: &str) -> Result<InputCommand, ArgsError> {
+    if args.trim().is_empty() {
+        Ok(InputCommand::Clear)
+    } else {
+        Err(ArgsError::Usage)
+    }
+}
+
+fn parse_emoji(args: &str) -> Result<InputCommand, ArgsError> {
+    single_word(args)
+        .map(|query| InputCommand::Emoji(query.to_owned()))
+        .ok_or(ArgsError::Usage)
+}
+
+fn parse_copy(args: &str) -> Result<InputCommand, ArgsError> {
+    match args.trim() {
+        "" => Ok(InputCommand::Copy(1)),
+        n => match n.parse() {
+            Ok(n) if n > 0 => Ok(InputCommand::Copy(n)),
+            _ => Err(ArgsError::Usage),
+        },
+    }
+}
+
+fn parse_theme(args: &str) -> Result<InputCommand, ArgsError> {
+    single_word(args)
+        .map(|name| InputCommand::Theme(name.to_owned()))
+        .ok_or(ArgsError::Usage)
+}
+
+fn parse_filter(args: &str) -> Result<InputCommand, ArgsError> {
+    match args.trim() {
+        "" => Err(ArgsError::Usage),
+        "clear" => Ok(InputCommand::Filter(None)),
+        expr => Filter::parse(expr)
+            .map(|filter| InputCommand::Filter(Some(filter)))
+            .map_err(|err| ArgsError::Invalid(format!("Invalid filter: {}", err))),
+    }
+}
+
+fn parse_spans(args: &str) -> Result<InputCommand, ArgsError> {
+    single_word(args)
+        .and_then(|n| n.parse().ok())
+        .map(InputCommand::Spans)
+        .ok_or(ArgsError::Usage)
+}
+
+/// The commands, with their slash, that a partially written command could be completed to.
+/// Aliases are only given if they are longer than what was written.
+pub fn complete_command(input: &str) -> Vec<String> {
+    let prefix = match input.strip_prefix('/') {
+        Some(prefix) if !prefix.contains(char::is_whitespace) => prefix,
+        _ => return Vec::new(),
+    };
+
+    let names = COMMANDS
+        .iter()
+        .map(|def| def.name)
+        .filter(|name| name.starts_with(prefix));
+    let aliases = COMMANDS
+        .iter()
+        .flat_map(|def| def.aliases.iter().copied())
+        .filter(|alias| alias.starts_with(prefix) && alias.len() > prefix.len());
+    names
+        .chain(aliases)
+        .map(|name| format!("/{}", name))
+        .collect()
+}
+
+/// Completes slash commands, cycling through the matches when asked to complete one of them.
+#[derive(Debug, Default)]
+pub struct CommandCompleter {
+    matches: Vec<String>,
+    /// Which of the matches was last given.
+    index: usize,
+}
+impl CommandCompleter {
+    /// Gives what to replace the input with, along with the completed command's usage.
+    pub fn complete(&mut self, input: &str) -> Option<(String, &'static str)> {
+        if self.matches.get(self.index).map(String::as_str) == Some(input) {
+            self.index = (self.index + 1) % self.matches.len();
+        } else {
+            self.matches = complete_command(input);
+            self.index = 0;
+        }
+
+        let completed = self.matches.get(self.index)?;
+        let usage = find_command(&completed[1..]).map_or("", |def| def.usage);
+        Some((completed.clone(), usage))
+    }
+}
+
+/// Completes the nick being written before the cursor, from the users in the channel. Completing
+/// again straight away cycles through the other nicks it could be.
+#[derive(Debug, Default)]
+pub struct NickCompleter {
+    matches: Vec<Nickname>,
+    /// Which of the matches was last given.
+    index: usize,
+    /// Where the completed nick starts, along with the input and cursor as it was left, so that
+    /// we can tell whether it's being completed again.
+    last: Option<(usize, String, usize)>,
+}
+impl NickCompleter {
+    /// Gives what to replace the input with and where the cursor goes after the completed nick.
+    /// A nick at the start of a line is followed by `: `, as it's who the line is addressed to.
+    pub fn complete(
+        &mut self,
+        input: &str,
+        cursor: usize,
+        nicks: &[Nickname],
+    ) -> Option<(String, usize)> {
+        let start = match &self.last {
+            Some((start, last, last_cursor)) if last == input && *last_cursor == cursor => {
+                self.index = (self.index + 1) % self.matches.len();
+                *start
+            }
+            _ => {
+                let start = nick_start(input, cursor);
+                let partial = input[start..cursor].to_lowercase();
+                if partial.is_empty() {
+                    return None;
+                }
+                let mut matches: Vec<Nickname> = nicks
+                    .iter()
+                    .filter(|nick| nick.to_lowercase().starts_with(&partial))
+                    .cloned()
+                    .collect();
+                matches.sort_by_key(|nick| nick.to_lowercase());
+                matches.dedup();
+                if matches.is_empty() {
+                    return None;
+                }
+                self.matches = matches;
+                self.index = 0;
+                start
+            }
+        };
+
+        let suffix = if input[..start].is_empty() || input[..start].ends_with('\n') {
+            ": "
+        } else {
+            ""
+        };
+        let mut completed = input[..start].to_owned();
+        completed += &self.matches[self.index];
+        completed += suffix;
+        let end = completed.len();
+        completed += &input[cursor..];
+        self.last = Some((start, completed.clone(), end));
+        Some((completed, end))
+    }
+}
+
+/// Where the nick being written before the cursor starts, after any `@` it's mentioned with.
+fn nick_start(input: &str, cursor: usize) -> usize {
+    let start = input[..cursor]
+        .char_indices()
+        .rev()
+        .find(|(_, ch)| ch.is_whitespace())
+        .map_or(0, |(idx, ch)| idx + ch.len_utf8());
+    if input[start..cursor].starts_with('@') {
+        start + 1
+    } else {
+        start
+    }
+}
+
+/// Whether a chat message should be sent, as blank ones are usually mistakes that the server would
+/// reject anyway.
+pub fn is_sendable(text: &str, send_blank: bool) -> bool {
+    send_blank || !text.trim().is_empty()
+}
+
+/// Splits `/name args` into the name and the arguments.
+fn split_command(text: &str) -> Option<(&str, &str)> {
+    let text = text.strip_prefix('/')?;
+    Some(match text.find(char::is_whitespace) {
+        Some(idx) => (&text[..idx], &text[idx..]),
+        None => (text, ""),
+    })
+}
+
+/// Gets the only word in the arguments, if there is exactly one.
+fn single_word(args: &str) -> Option<&str> {
+    let word = args.trim();
+    if word.is_empty() || word.contains(char::is_whitespace) {
+        None
+    } else {
+        Some(word)
+    }
+}
+
+/// Which key sends the message, with the other inserting a newline.
+/// Terminals often report Shift+Enter as a plain Enter, so Alt+Enter is treated the same as it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SubmitKey {
+    Enter,
+    ShiftEnter,
+}
+impl SubmitKey {
+    /// The events that may be given an action, depending on the submit key.
+    pub const EVENTS: [Event; 3] = [
+        Event::Key(Key::Enter),
+        Event::Shift(Key::Enter),
+        Event::Alt(Key::Enter),
+    ];
+
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "enter" => Some(SubmitKey::Enter),
+            "shift-enter" => Some(SubmitKey::ShiftEnter),
+            _ => None,
+        }
+    }
+
+    /// What the event does in the input area, if it is one we handle.
+    pub fn action(self, event: &Event) -> Option<InputKeyAction> {
+        let modified = match event {
+            Event::Key(Key::Enter) => false,
+            Event::Shift(Key::Enter) | Event::Alt(Key::Enter) => true,
+            _ => return None,
+        };
+        Some(match (self, modified) {
+            (SubmitKey::Enter, false) | (SubmitKey::ShiftEnter, true) => InputKeyAction::Submit,
+            (SubmitKey::Enter, true) | (SubmitKey::ShiftEnter, false) => InputKeyAction::Newline,
+        })
+    }
+}
+impl Default for SubmitKey {
+    fn default() -> Self {
+        SubmitKey::Enter
+    }
+}
+
+/// How the input area is sized as more is written in it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InputSizing {
+    /// Grows with its lines between the heights, and scrolls past the max.
+    Grow { min: usize, max: usize },
+    /// Always the height, scrolling if there are more lines. Suits small terminals.
+    Fixed(usize),
+}
+impl InputSizing {
+    pub const MIN_WIDTH: usize = 40;
+    /// The heights it grows between by default.
+    pub const DEFAULT_MIN_HEIGHT: usize = 2;
+    pub const DEFAULT_MAX_HEIGHT: usize = 6;
+
+    /// Fixed at the height if one is given, or else growing between the heights, which default
+    /// to [`InputSizing::DEFAULT_MIN_HEIGHT`] and [`InputSizing::DEFAULT_MAX_HEIGHT`]. The max
+    /// follows a min given past it, unless the max was given too.
+    pub fn from_heights(
+        height: Option<usize>,
+        min: Option<usize>,
+        max: Option<usize>,
+    ) -> Result<Self, String> {
+        if let Some(height) = height {
+            if height == 0 {
+                return Err("The input height must be at least 1".to_owned());
+            }
+            return Ok(InputSizing::Fixed(height));
+        }
+        let min = min.unwrap_or(Self::DEFAULT_MIN_HEIGHT);
+        let max = max.unwrap_or_else(|| Self::DEFAULT_MAX_HEIGHT.max(min));
+        if min == 0 {
+            return Err("The input's minimum height must be at least 1".to_owned());
+        }
+        if max < min {
+            return Err(format!(
+                "The input's maximum height {} is less than its minimum {}",
+                max, min
+            ));
+        }
+        Ok(InputSizing::Grow { min, max })
+    }
+
+    /// The constraints on the height of the input, before and after the width is constrained.
+    pub fn height_constraints(self) -> (SizeConstraint, SizeConstraint) {
+        match self {
+            InputSizing::Grow { min, max } => {
+                (SizeConstraint::AtLeast(min), SizeConstraint::AtMost(max))
+            }
+            InputSizing::Fixed(height) => (SizeConstraint::Fixed(height), SizeConstraint::Free),
+        }
+    }
+
+    /// Sizes the input view, making it scrollable.
+    pub fn apply<V: View>(self, view: V) -> ScrollView<ResizedView<ResizedView<V>>> {
+        let (inner, outer) = self.height_constraints();
+        let view = ResizedView::new(SizeConstraint::AtLeast(Self::MIN_WIDTH), inner, view);
+        ResizedView::new(SizeConstraint::Free, outer, view).scrollable()
+    }
+}
+impl Default for InputSizing {
+    fn default() -> Self {
+        InputSizing::Grow {
+            min: Self::DEFAULT_MIN_HEIGHT,
+            max: Self::DEFAULT_MAX_HEIGHT,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InputKeyAction {
+    /// Send what has been written, as the send button does.
+    Submit,
+    Newline,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelParseError {
+    /// There was an empty entry, such as from `a,,b`.
+    Empty,
+    /// The channel name contained whitespace.
+    Whitespace(String),
+}
+impl fmt::Display for ChannelParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelParseError::Empty => write!(f, "one of the channel names is empty"),
+            ChannelParseError::Whitespace(name) => {
+                write!(
+                    f,
+                    "'{}' has whitespace in it, which a channel name can't",
+                    name
+                )
+            }
+        }
+    }
+}
+
+/// Drops a message that is the same as the one sent just before it, as that is most likely from
+/// pressing Enter twice. Repeating a message on purpose works once the window has passed.
+#[derive(Debug, Clone)]
+pub struct SendDebounce {
+    pub window: Duration,
+    /// The last message that was sent, and when.
+    last: Option<(String, Instant)>,
+}
+impl SendDebounce {
+    pub const DEFAULT_WINDOW: Duration = Duration::from_millis(300);
+
+    pub fn new(window: Duration) -> Self {
+        Self { window, last: None }
+    }
+
+    /// Whether the text should be sent at `now`, remembering it if so.
+    pub fn should_send(&mut self, text: &str, now: Instant) -> bool {
+        if let Some((last, at)) = &self.last {
+            if last == text && now.saturating_duration_since(*at) < self.window {
+                return false;
+            }
+        }
+        self.last = Some((text.to_owned(), now));
+        true
+    }
+}
+impl Default for SendDebounce {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_WINDOW)
+    }
+}
+
+/// Parses a comma-separated list of channels, such as `programming,?math`.
+/// The leading `?` that channels are usually written with is optional.
+pub fn parse_channels(text: &str) -> Result<Vec<Channel>, ChannelParseError> {
+    text.split(',')
+        .map(|name| {
+            let name = name.trim();
+            let name = name.strip_prefix('?').unwrap_or(name);
+            if name.is_empty() {
+                Err(ChannelParseError::Empty)
+            } else if name.contains(char::is_whitespace) {
+                Err(ChannelParseError::Whitespace(name.to_owned()))
+            } else {
+                Ok(Channel::from(name))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use cursive::{
+        event::{Event, Key},
+        view::{SizeConstraint, View},
+        views::TextArea,
+        Vec2,
+    };
+
+    use super::{
+        commands, complete_command, describe_help, find_command, is_sendable, parse_channels,
+        parse_input, ChannelParseError, CommandCompleter, InputCommand, InputCount, InputError,
+        InputKeyAction, InputSizing, NickCompleter, SendDebounce, SubmitKey,
+    };
+    use crate::{client_manager::Ignore, emoji::EmojiMap, filter::Filter};
+
+    #[test]
+    fn test_input_count() {
+        assert_eq!(
+            InputCount::of(""),
+            InputCount {
+                bytes: 0,
+                chars: 0,
+                width: 0
+            }
+        );
+        // 'é' is two bytes, the CJK characters are three bytes each and two columns wide.
+        let count = InputCount::of("héllo 世界");
+        assert_eq!(
+            count,
+            InputCount {
+                bytes: 13,
+                chars: 8,
+                width: 10
+            }
+        );
+        assert!(!count.is_over(None));
+        assert!(!count.is_over(Some(8)));
+        assert!(count.is_over(Some(7)));
+    }
+
+    #[test]
+    fn test_parse_channels() {
+        assert_eq!(
+            parse_channels("programming").unwrap(),
+            vec!["programming".into()]
+        );
+        assert_eq!(
+            parse_channels("programming, ?math,lounge").unwrap(),
+            vec!["programming".into(), "math".into(), "lounge".into()]
+        );
+        assert_eq!(parse_channels(""), Err(ChannelParseError::Empty));
+        assert_eq!(parse_channels("a,,b"), Err(ChannelParseError::Empty));
+        assert_eq!(parse_channels("a,?"), Err(ChannelParseError::Empty));
+        assert_eq!(
+            parse_channels("a,b c"),
+            Err(ChannelParseError::Whitespace("b c".to_owned()))
+        );
+        assert_eq!(
+            ChannelParseError::Empty.to_string(),
+            "one of the channel names is empty"
+        );
+        assert_eq!(
+            ChannelParseError::Whitespace("b c".to_owned()).to_string(),
+            "'b c' has whitespace in it, which a channel name can't"
+        );
+    }
+
+    #[test]
+    fn test_parse_input() {
+        assert_eq!(
+            parse_input("hello"),
+            Ok(InputCommand::Chat("hello".to_owned()))
+        );
+        assert_eq!(parse_input(""), Ok(InputCommand::Chat("".to_owned())));
+        // Unknown commands are left for the server.
+        assert_eq!(
+            parse_input("/myhash"),
+            Ok(InputCommand::Chat("/myhash".to_owned()))
+        );
+        assert_eq!(
+            parse_input("/seen bob"),
+            Ok(InputCommand::Seen("bob".to_owned()))
+        );
+        assert_eq!(
+            parse_input("/seen   bob  "),
+            Ok(InputCommand::Seen("bob".to_owned()))
+        );
+        assert_eq!(parse_input("/seen"), Err(InputError::Usage("/seen <nick>")));
+        assert_eq!(
+            parse_input("/seen bob alice"),
+            Err(InputError::Usage("/seen <nick>"))
+        );
+        assert_eq!(
+            parse_input("/filter nick:bob"),
+            Ok(InputCommand::Filter(Some(
+                Filter::parse("nick:bob").unwrap()
+            )))
+        );
+        assert_eq!(parse_input("/filter clear"), Ok(InputCommand::Filter(None)));
+        assert_eq!(parse_input("/spans 3"), Ok(InputCommand::Spans(3)));
+        assert_eq!(parse_input("/spans"), Err(InputError::Usage("/spans <n>")));
+        assert_eq!(
+            parse_input("/spans -1"),
+            Err(InputError::Usage("/spans <n>"))
+        );
+        assert!(matches!(
+            parse_input("/filter type:nothing"),
+            Err(InputError::Invalid(_))
+        ));
+        assert_eq!(
+            parse_input("/emoji smile"),
+            Ok(InputCommand::Emoji("smile".to_owned()))
+        );
+        assert_eq!(
+            parse_input("/emoji"),
+            Err(InputError::Usage("/emoji <query>"))
+        );
+    }
+
+    #[test]
+    fn test_expand_emoji() {
+        let emoji = EmojiMap::bundled();
+        let expand = |text: &str| parse_input(text).unwrap().expand_emoji(&emoji);
+        assert_eq!(expand("hi :wave:"), InputCommand::Chat("hi 👋".to_owned()));
+        assert_eq!(expand("/me :wave:"), InputCommand::Emote("👋".to_owned()));
+        assert_eq!(
+            expand("/w bob :tada:"),
+            InputCommand::Whisper {
+                nick: "bob".to_owned(),
+                text: "🎉".to_owned()
+            }
+        );
+        // Only what is sent is expanded.
+        assert_eq!(
+            expand("/emoji :wave:"),
+            InputCommand::Emoji(":wave:".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_commands() {
+        for name in &[
+            "seen", "filter", "spans", "whois", "theme", "invite", "nick", "dnd", "go", "open",
+            "ignore", "unignore", "clear", "emoji", "copy", "help", "me", "whisper", "reply",
+        ] {
+            assert!(find_command(name).is_some(), "Missing command {}", name);
+        }
+        assert_eq!(find_command("w").map(|def| def.name), Some("whisper"));
+        assert!(find_command("nothing").is_none());
+
+        let mut names: Vec<&str> = commands()
+            .iter()
+            .flat_map(|def| std::iter::once(&def.name).chain(def.aliases.iter()))
+            .copied()
+            .collect();
+        let count = names.len();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(
+            names.len(),
+            count,
+            "Command names and aliases must be unique"
+        );
+
+        assert_eq!(
+            parse_input("/theme dark"),
+            Ok(InputCommand::Theme("dark".to_owned()))
+        );
+        assert_eq!(
+            parse_input("/invite bob"),
+            Ok(InputCommand::Invite("bob".to_owned()))
+        );
+        assert_eq!(
+            parse_input("/nick  bob "),
+            Ok(InputCommand::Nick("bob".to_owned()))
+        );
+        assert_eq!(parse_input("/nick"), Err(InputError::Usage("/nick <nick>")));
+        assert_eq!(
+            parse_input("/theme"),
+            Err(InputError::Usage("/theme <name>"))
+        );
+        assert_eq!(
+            parse_input("/dnd"),
+            Ok(InputCommand::Dnd(Duration::from_secs(60 * 60)))
+        );
+        assert_eq!(
+            parse_input("/dnd 5"),
+            Ok(InputCommand::Dnd(Duration::from_secs(5 * 60)))
+        );
+        assert_eq!(
+            parse_input("/dnd soon"),
+            Err(InputError::Usage("/dnd [minutes]"))
+        );
+        assert_eq!(
+            parse_input(&format!("/dnd {}", u64::MAX)),
+            Err(InputError::Usage("/dnd [minutes]"))
+        );
+        assert_eq!(parse_input("/go"), Ok(InputCommand::Go(1)));
+        assert_eq!(parse_input("/go 2"), Ok(InputCommand::Go(2)));
+        assert_eq!(parse_input("/go 0"), Err(InputError::Usage("/go [n]")));
+        assert_eq!(parse_input("/copy"), Ok(InputCommand::Copy(1)));
+        assert_eq!(parse_input("/copy 3"), Ok(InputCommand::Copy(3)));
+        assert_eq!(
+            parse_input("/copy last"),
+            Err(InputError::Usage("/copy [n]"))
+        );
+        assert_eq!(parse_input("/clear"), Ok(InputCommand::Clear));
+        assert_eq!(parse_input("/clear all"), Err(InputError::Usage("/clear")));
+        assert_eq!(
+            parse_input("/open ?math"),
+            Ok(InputCommand::Open("math".into()))
+        );
+        assert_eq!(
+            parse_input("/join ?math"),
+            Ok(InputCommand::Join("math".into()))
+        );
+        assert_eq!(
+            parse_input("/join"),
+            Err(InputError::Usage("/join <channel>"))
+        );
+        assert_eq!(
+            parse_input("/open a,b"),
+            Err(InputError::Usage("/open <channel>"))
+        );
+        assert_eq!(
+            parse_input("/open"),
+            Err(InputError::Usage("/open <channel>"))
+        );
+        assert_eq!(parse_input("/ignore "), Ok(InputCommand::Ignore(None)));
+        assert_eq!(
+            parse_input("/ignore bob#abcdef"),
+            Ok(InputCommand::Ignore(Ignore::parse("bob#abcdef")))
+        );
+        assert_eq!(
+            parse_input("/ignore bob alice"),
+            Err(InputError::Usage("/ignore [nick[#trip]]"))
+        );
+        assert_eq!(
+            parse_input("/unignore bob"),
+            Ok(InputCommand::Unignore(Ignore::parse("bob").unwrap()))
+        );
+        assert_eq!(
+            parse_input("/unignore"),
+            Err(InputError::Usage("/unignore <nick[#trip]>"))
+        );
+
+        assert_eq!(
+            parse_input("/w bob  hi there "),
+            Ok(InputCommand::Whisper {
+                nick: "bob".to_owned(),
+                text: "hi there".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_input("/whisper bob"),
+            Err(InputError::Usage("/whisper <nick> <text>"))
+        );
+        assert_eq!(
+            parse_input("/me waves "),
+            Ok(InputCommand::Emote("waves".to_owned()))
+        );
+        assert_eq!(parse_input("/me  "), Err(InputError::Usage("/me <action>")));
+        // Commands the server handles are sent as they are.
+        assert_eq!(
+            parse_input("/r hi"),
+            Ok(InputCommand::Chat("/r hi".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_confirmation() {
+        let confirmation = |text: &str| {
+            parse_input(text)
+                .unwrap()
+                .confirmation()
+                .map(|text| text.source().to_owned())
+        };
+        assert_eq!(
+            confirmation("/dnd 5").as_deref(),
+            Some("Notifications muted for 5m")
+        );
+        assert_eq!(
+            confirmation("/dnd 0").as_deref(),
+            Some("Notifications unmuted")
+        );
+        assert_eq!(
+            confirmation("/filter clear").as_deref(),
+            Some("Showing every message")
+        );
+        // The server answers these itself.
+        assert_eq!(confirmation("/seen bob"), None);
+        assert_eq!(confirmation("hello"), None);
+    }
+
+    #[test]
+    fn test_help() {
+        assert_eq!(parse_input("/help"), Ok(InputCommand::Help(None)));
+        assert_eq!(
+            parse_input("/help whisper"),
+            Ok(InputCommand::Help(Some("whisper")))
+        );
+        // Aliases and slashes lead to the command itself.
+        assert_eq!(
+            parse_input("/help /w"),
+            Ok(InputCommand::Help(Some("whisper")))
+        );
+        assert_eq!(
+            parse_input("/help nothing"),
+            Err(InputError::Invalid(
+                "There is no command /nothing".to_owned()
+            ))
+        );
+
+        // Every command is listed, so that a new one is too.
+        let help = describe_help(None);
+        assert_eq!(help.source().lines().count(), commands().len() + 2);
+        for def in commands() {
+            assert!(help.source().contains(def.usage), "Missing {}", def.name);
+        }
+
+        let help = describe_help(Some("reply"));
+        assert!(help.source().starts_with("/reply <text>\n"));
+        assert!(help.source().contains("Also written as /r"));
+        assert!(help.source().contains("server"));
+        // It's shown in the chat as what the command is answered with.
+        assert_eq!(
+            parse_input("/help seen").unwrap().confirmation(),
+            Some(describe_help(Some("seen")))
+        );
+    }
+
+    #[test]
+    fn test_complete_command() {
+        assert_eq!(complete_command("/wh"), vec!["/whois", "/whisper"]);
+        // The alias is given too, as it's longer than what was written.
+        assert_eq!(complete_command("/"), {
+            let mut all: Vec<String> = commands()
+                .iter()
+                .map(|def| format!("/{}", def.name))
+                .collect();
+            all.extend(vec!["/w".to_owned(), "/r".to_owned()]);
+            all
+        });
+        assert_eq!(complete_command("/fil"), vec!["/filter"]);
+        assert!(complete_command("/nothing").is_empty());
+        assert!(complete_command("wh").is_empty());
+        assert!(complete_command("/whois bob").is_empty());
+
+        let mut completer = CommandCompleter::default();
+        assert_eq!(
+            completer.complete("/wh"),
+            Some(("/whois".to_owned(), "/whois <nick>"))
+        );
+        // Completing again cycles through the matches.
+        assert_eq!(
+            completer.complete("/whois"),
+            Some(("/whisper".to_owned(), "/whisper <nick> <text>"))
+        );
+        assert_eq!(
+            completer.complete("/whisper"),
+            Some(("/whois".to_owned(), "/whois <nick>"))
+        );
+        // Something new was written, so it starts over.
+        assert_eq!(
+            completer.complete("/se"),
+            Some(("/seen".to_owned(), "/seen <nick>"))
+        );
+        assert_eq!(completer.complete("hello"), None);
+    }
+
+    #[test]
+    fn test_nick_completer() {
+        let nicks: Vec<String> = ["bob", "Bobby", "alice", "bob"]
+            .iter()
+            .map(|nick| (*nick).to_owned())
+            .collect();
+        let mut completer = NickCompleter::default();
+        // Addressed at the start of the line, ignoring case.
+        assert_eq!(
+            completer.complete("BO", 2, &nicks),
+            Some(("bob: ".to_owned(), 5))
+        );
+        // Completing again cycles through the matches, which are only given once each.
+        assert_eq!(
+            completer.complete("bob: ", 5, &nicks),
+            Some(("Bobby: ".to_owned(), 7))
+        );
+        assert_eq!(
+            completer.complete("Bobby: ", 7, &nicks),
+            Some(("bob: ".to_owned(), 5))
+        );
+
+        // Elsewhere, the nick is only completed, and what's after the cursor is kept.
+        let mut completer = NickCompleter::default();
+        assert_eq!(
+            completer.complete("hi al there", 5, &nicks),
+            Some(("hi alice there".to_owned(), 8))
+        );
+        assert_eq!(
+            completer.complete("hi @b", 5, &nicks),
+            Some(("hi @bob".to_owned(), 7))
+        );
+        assert_eq!(
+            completer.complete("one\nal", 6, &nicks),
+            Some(("one\nalice: ".to_owned(), 11))
+        );
+
+        assert_eq!(completer.complete("hi ", 3, &nicks), None);
+        assert_eq!(completer.complete("hi zed", 6, &nicks), None);
+    }
+
+    #[test]
+    fn test_input_sizing() {
+        let grow = InputSizing::Grow { min: 2, max: 6 };
+        assert_eq!(
+            grow.height_constraints(),
+            (SizeConstraint::AtLeast(2), SizeConstraint::AtMost(6))
+        );
+        let fixed = InputSizing::Fixed(3);
+        assert_eq!(
+            fixed.height_constraints(),
+            (SizeConstraint::Fixed(3), SizeConstraint::Free)
+        );
+
+        let height = |sizing: InputSizing, lines: usize| {
+            let text = vec!["line"; lines].join("\n");
+            let mut view = sizing.apply(TextArea::new().content(text));
+            view.required_size(Vec2::new(80, 24)).y
+        };
+        // Grows with the lines until the max.
+        assert_eq!(height(grow, 1), 2);
+        assert_eq!(height(grow, 4), 4);
+        assert_eq!(height(grow, 20), 6);
+        // Stays the same height.
+        assert_eq!(height(fixed, 1), 3);
+        assert_eq!(height(fixed, 20), 3);
+    }
+
+    #[test]
+    fn test_input_sizing_from_heights() {
+        assert_eq!(
+            InputSizing::from_heights(None, None, None),
+            Ok(InputSizing::default())
+        );
+        assert_eq!(
+            InputSizing::from_heights(Some(3), Some(1), None),
+            Ok(InputSizing::Fixed(3))
+        );
+        assert_eq!(
+            InputSizing::from_heights(None, Some(1), Some(10)),
+            Ok(InputSizing::Grow { min: 1, max: 10 })
+        );
+        // The max makes room for a larger min, unless it was given.
+        assert_eq!(
+            InputSizing::from_heights(None, Some(8), None),
+            Ok(InputSizing::Grow { min: 8, max: 8 })
+        );
+        assert!(InputSizing::from_heights(None, Some(8), Some(4)).is_err());
+        assert!(InputSizing::from_heights(None, Some(0), None).is_err());
+        assert!(InputSizing::from_heights(Some(0), None, None).is_err());
+    }
+
+    #[test]
+    fn test_is_sendable() {
+        assert!(is_sendable("hello", false));
+        assert!(is_sendable("  hello\n", false));
+        assert!(!is_sendable("", false));
+        assert!(!is_sendable(" \t\n ", false));
+        // Unless configured to send them
+        assert!(is_sendable(" \t\n ", true));
+        assert!(is_sendable("", true));
+    }
+
+    #[test]
+    fn test_submit_key() {
+        let enter = Event::Key(Key::Enter);
+        let shift_enter = Event::Shift(Key::Enter);
+        let alt_enter = Event::Alt(Key::Enter);
+
+        let submit_key = SubmitKey::Enter;
+        assert_eq!(submit_key.action(&enter), Some(InputKeyAction::Submit));
+        assert_eq!(
+            submit_key.action(&shift_enter),
+            Some(InputKeyAction::Newline)
+        );
+        assert_eq!(submit_key.action(&alt_enter), Some(InputKeyAction::Newline));
+
+        let submit_key = SubmitKey::ShiftEnter;
+        assert_eq!(submit_key.action(&enter), Some(InputKeyAction::Newline));
+        assert_eq!(
+            submit_key.action(&shift_enter),
+            Some(InputKeyAction::Submit)
+        );
+        assert_eq!(submit_key.action(&alt_enter), Some(InputKeyAction::Submit));
+
+        assert_eq!(SubmitKey::Enter.action(&Event::Char('a')), None);
+        assert_eq!(SubmitKey::ShiftEnter.action(&Event::Key(Key::Tab)), None);
+
+        assert_eq!(SubmitKey::parse("enter"), Some(SubmitKey::Enter));
+        assert_eq!(SubmitKey::parse("shift-enter"), Some(SubmitKey::ShiftEnter));
+        assert_eq!(SubmitKey::parse("ctrl-enter"), None);
+    }
+
+    #[test]
+    fn test_send_debounce() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut debounce = SendDebounce::new(Duration::from_millis(300));
+        assert!(debounce.should_send("hi", ms(0)));
+        // A double press.
+        assert!(!debounce.should_send("hi", ms(50)));
+        // Something else is sent straight away.
+        assert!(debounce.should_send("hello", ms(100)));
+        assert!(debounce.should_send("hi", ms(150)));
+        // Repeating on purpose, after the window.
+        assert!(!debounce.should_send("hi", ms(449)));
+        assert!(debounce.should_send("hi", ms(450)));
+
+        // Without a window, nothing is dropped.
+        let mut debounce = SendDebounce::new(Duration::from_millis(0));
+        assert!(debounce.should_send("hi", ms(0)));
+        assert!(debounce.should_send("hi", ms(0)));
+    }
+}
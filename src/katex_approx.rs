@@ -1,4 +1,6 @@
-use cursive::utils::markup::StyledString;
+use cursive::theme::Effect;
+
+use crate::styled::StyledString;
 
 #[derive(Debug)]
 pub enum KatexError {}
@@ -29,45 +31,414 @@ impl Default for KatexOptions {
 // \theta \delta \Delta has a unicode
 // \R \N \Z \Q, etc could probably be written with unicode. Italic if no unicode?
 
+/// Macros that are written as a symbol, as (name, unicode, plain).
+const SYMBOLS: &[(&str, &str, &str)] = &[
+    ("alpha", "α", "alpha"),
+    ("beta", "β", "beta"),
+    ("gamma", "γ", "gamma"),
+    ("delta", "δ", "delta"),
+    ("epsilon", "ε", "epsilon"),
+    ("zeta", "ζ", "zeta"),
+    ("eta", "η", "eta"),
+    ("theta", "θ", "theta"),
+    ("iota", "ι", "iota"),
+    ("kappa", "κ", "kappa"),
+    ("lambda", "λ", "lambda"),
+    ("mu", "μ", "mu"),
+    ("nu", "ν", "nu"),
+    ("xi", "ξ", "xi"),
+    ("pi", "π", "pi"),
+    ("rho", "ρ", "rho"),
+    ("sigma", "σ", "sigma"),
+    ("tau", "τ", "tau"),
+    ("upsilon", "υ", "upsilon"),
+    ("phi", "φ", "phi"),
+    ("chi", "χ", "chi"),
+    ("psi", "ψ", "psi"),
+    ("omega", "ω", "omega"),
+    ("Gamma", "Γ", "Gamma"),
+    ("Delta", "Δ", "Delta"),
+    ("Theta", "Θ", "Theta"),
+    ("Lambda", "Λ", "Lambda"),
+    ("Xi", "Ξ", "Xi"),
+    ("Pi", "Π", "Pi"),
+    ("Sigma", "Σ", "Sigma"),
+    ("Upsilon", "Υ", "Upsilon"),
+    ("Phi", "Φ", "Phi"),
+    ("Psi", "Ψ", "Psi"),
+    ("Omega", "Ω", "Omega"),
+    ("R", "ℝ", "R"),
+    ("N", "ℕ", "N"),
+    ("Z", "ℤ", "Z"),
+    ("Q", "ℚ", "Q"),
+    ("C", "ℂ", "C"),
+    ("cdot", "·", "*"),
+    ("times", "×", "x"),
+    ("div", "÷", "/"),
+    ("pm", "±", "+-"),
+    ("le", "≤", "<="),
+    ("leq", "≤", "<="),
+    ("ge", "≥", ">="),
+    ("geq", "≥", ">="),
+    ("ne", "≠", "!="),
+    ("neq", "≠", "!="),
+    ("approx", "≈", "~"),
+    ("infty", "∞", "inf"),
+    ("to", "→", "->"),
+    ("rightarrow", "→", "->"),
+    ("leftarrow", "←", "<-"),
+    ("in", "∈", "in"),
+    ("sum", "∑", "sum"),
+    ("prod", "∏", "prod"),
+    ("int", "∫", "int"),
+    ("partial", "∂", "d"),
+    ("forall", "∀", "forall"),
+    ("exists", "∃", "exists"),
+    ("ldots", "…", "..."),
+    ("cdots", "⋯", "..."),
+    ("sin", "sin", "sin"),
+    ("cos", "cos", "cos"),
+    ("tan", "tan", "tan"),
+    ("log", "log", "log"),
+    ("ln", "ln", "ln"),
+    ("exp", "exp", "exp"),
+    ("lim", "lim", "lim"),
+    ("min", "min", "min"),
+    ("max", "max", "max"),
+];
+
+/// Converts the math within the enclosures in the text into an approximation of how KaTeX would
+/// render it, italicized so that it stands out from the rest of the text.
+/// Enclosures that aren't closed, and escaped enclosures such as `\$`, are kept as they are.
 pub fn convert_to_approximate(
     text: &str,
     options: KatexOptions,
 ) -> Result<StyledString, KatexError> {
-    // let mut iter = text.char_indices().peekable();
-    // let mut styled = StyledString::new();
-
-    // // The active span
-    // let mut span: Range<usize> = 0..0;
-    // while let Some((i, ch)) = iter.next() {
-    //     if ch == options.enclosure {
-    //         if let Some((i_next, ch_next)) = iter.peek() {
-    //         } else {
-    //             // EOF, so we just print the $
-    //             span.end = i;
-    //         }
-    //     } else {
-    //         span.end = i;
-    //     }
-    //     match ch {
-    //         '$' => {
-    //             match iter.peek() {
-    //                 Some((i_next, '$')) => {
-    //                     // Consume $
-    //                     debug_assert_eq!(iter.next().is_some());
-    //                 }
-    //                 // EOF, so we just print the $
-    //                 None => span.end = i,
-    //             }
-    //         }
-    //         _ => span.end = i,
-    //     }
-    // }
-
-    Ok(StyledString::from(text.to_owned()))
+    let enclosure = options.enclosure;
+    let mut styled = StyledString::default();
+    // The text since the last math, which is appended all at once.
+    let mut plain = String::new();
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        rest = &rest[ch.len_utf8()..];
+        if ch == '\\' && rest.starts_with(enclosure) {
+            // An escaped enclosure is just the character itself.
+            plain.push(enclosure);
+            rest = &rest[enclosure.len_utf8()..];
+            continue;
+        } else if ch != enclosure {
+            plain.push(ch);
+            continue;
+        }
+
+        let double = rest.starts_with(enclosure);
+        let inner = if double {
+            &rest[enclosure.len_utf8()..]
+        } else {
+            rest
+        };
+        match find_enclosure_end(inner, enclosure, double) {
+            Some((math, after)) if !math.trim().is_empty() => {
+                styled.append_source(&plain);
+                plain.clear();
+                styled.append_styled(&convert_math(math, options.unicode), Effect::Italic.into());
+                rest = after;
+            }
+            // Unmatched, so it is just text. For a double enclosure the second is tried on its
+            // own after this.
+            _ => plain.push(ch),
+        }
+    }
+    styled.append_source(&plain);
+
+    Ok(styled)
+}
+
+/// Finds the closing enclosure, returning the math before it and the text after it.
+fn find_enclosure_end(text: &str, enclosure: char, double: bool) -> Option<(&str, &str)> {
+    let mut iter = text.char_indices().peekable();
+    while let Some((i, ch)) = iter.next() {
+        if ch == '\\' {
+            // Whatever is escaped can't close it.
+            iter.next();
+        } else if ch == enclosure {
+            if !double {
+                return Some((&text[..i], &text[i + ch.len_utf8()..]));
+            } else if let Some(&(next, _)) = iter.peek().filter(|(_, next)| *next == enclosure) {
+                return Some((&text[..i], &text[next + ch.len_utf8()..]));
+            }
+        }
+    }
+    None
+}
+
+/// Converts the math, without its enclosures, into plain text.
+fn convert_math(math: &str, unicode: bool) -> String {
+    let mut result = String::with_capacity(math.len());
+    let mut rest = math;
+    while let Some(ch) = rest.chars().next() {
+        rest = &rest[ch.len_utf8()..];
+        match ch {
+            '\\' => {
+                let (name, after) = take_command(rest);
+                rest = after;
+                convert_command(name, &mut rest, unicode, &mut result);
+            }
+            '^' | '_' => {
+                let (argument, after) = take_argument(rest);
+                rest = after;
+                let argument = convert_math(argument, unicode);
+                result.push_str(&convert_script(&argument, ch == '^', unicode));
+            }
+            '{' => {
+                let (group, after) = take_group(rest);
+                rest = after;
+                result.push_str(&convert_math(group, unicode));
+            }
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Converts the command, with the text after it which it may take arguments from.
+fn convert_command(name: &str, rest: &mut &str, unicode: bool, result: &mut String) {
+    match name {
+        "frac" | "dfrac" | "tfrac" => {
+            let (numerator, after) = take_argument(*rest);
+            let (denominator, after) = take_argument(after);
+            *rest = after;
+            result.push('(');
+            result.push_str(&convert_math(numerator, unicode));
+            result.push('/');
+            result.push_str(&convert_math(denominator, unicode));
+            result.push(')');
+        }
+        "sqrt" => {
+            let (argument, after) = take_argument(*rest);
+            *rest = after;
+            result.push_str(if unicode { "√(" } else { "sqrt(" });
+            result.push_str(&convert_math(argument, unicode));
+            result.push(')');
+        }
+        // Spacing
+        "," | ";" | ":" | " " | "quad" | "qquad" => result.push(' '),
+        "!" => {}
+        // A trailing backslash
+        "" => result.push('\\'),
+        _ => {
+            if let Some((_, symbol, plain)) = SYMBOLS.iter().find(|(symbol, _, _)| *symbol == name)
+            {
+                result.push_str(if unicode { symbol } else { plain });
+            } else if name.chars().all(|ch| ch.is_ascii_alphabetic()) {
+                // Unknown, so it's kept as it is.
+                result.push('\\');
+                result.push_str(name);
+            } else {
+                // An escaped symbol, such as `\$` or `\{`
+                result.push_str(name);
+            }
+        }
+    }
+}
+
+/// Converts the argument of a `^` or `_`.
+/// Written with unicode super/subscripts when every character has one, otherwise as it was.
+fn convert_script(argument: &str, superscript: bool, unicode: bool) -> String {
+    let to_script = if superscript {
+        superscript_char
+    } else {
+        subscript_char
+    };
+    if unicode {
+        if let Some(script) = argument.chars().map(to_script).collect::<Option<String>>() {
+            if !script.is_empty() {
+                return script;
+            }
+        }
+    }
+
+    let marker = if superscript { '^' } else { '_' };
+    if argument.chars().count() == 1 {
+        format!("{}{}", marker, argument)
+    } else {
+        format!("{}{{{}}}", marker, argument)
+    }
+}
+
+fn superscript_char(ch: char) -> Option<char> {
+    Some(match ch {
+        '0' => '⁰',
+        '1' => '¹',
+        '2' => '²',
+        '3' => '³',
+        '4' => '⁴',
+        '5' => '⁵',
+        '6' => '⁶',
+        '7' => '⁷',
+        '8' => '⁸',
+        '9' => '⁹',
+        '+' => '⁺',
+        '-' => '⁻',
+        '=' => '⁼',
+        '(' => '⁽',
+        ')' => '⁾',
+        'i' => 'ⁱ',
+        'n' => 'ⁿ',
+        _ => return None,
+    })
+}
+
+fn subscript_char(ch: char) -> Option<char> {
+    Some(match ch {
+        '0' => '₀',
+        '1' => '₁',
+        '2' => '₂',
+        '3' => '₃',
+        '4' => '₄',
+        '5' => '₅',
+        '6' => '₆',
+        '7' => '₇',
+        '8' => '₈',
+        '9' => '₉',
+        '+' => '₊',
+        '-' => '₋',
+        '=' => '₌',
+        '(' => '₍',
+        ')' => '₎',
+        'a' => 'ₐ',
+        'e' => 'ₑ',
+        'o' => 'ₒ',
+        'x' => 'ₓ',
+        'i' => 'ᵢ',
+        'j' => 'ⱼ',
+        _ => return None,
+    })
+}
+
+/// Takes the name of a command, after its backslash. This is either a run of letters, or a single
+/// symbol.
+fn take_command(text: &str) -> (&str, &str) {
+    let end = match text.find(|ch: char| !ch.is_ascii_alphabetic()) {
+        // A symbol, like `\$`
+        Some(0) => text.chars().next().map_or(0, char::len_utf8),
+        Some(end) => end,
+        None => text.len(),
+    };
+    text.split_at(end)
+}
+
+/// Takes the argument of a command: a group, a command, or a single character.
+fn take_argument(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.chars().next() {
+        Some('{') => take_group(&text[1..]),
+        Some('\\') => {
+            let (name, _) = take_command(&text[1..]);
+            text.split_at(1 + name.len())
+        }
+        Some(ch) => text.split_at(ch.len_utf8()),
+        None => ("", ""),
+    }
+}
+
+/// Takes the contents of a group, after its opening brace, up to its matching closing brace.
+/// A group that is never closed takes the rest of the text.
+fn take_group(text: &str) -> (&str, &str) {
+    let mut depth = 0usize;
+    let mut iter = text.char_indices();
+    while let Some((i, ch)) = iter.next() {
+        match ch {
+            '\\' => {
+                // Escaped braces don't count.
+                iter.next();
+            }
+            '{' => depth += 1,
+            '}' if depth == 0 => return (&text[..i], &text[i + 1..]),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    (text, "")
 }
 
 #[cfg(test)]
 mod tests {
+    use cursive::theme::Effect;
+
+    use super::{convert_to_approximate, KatexOptions};
+    use crate::styled::StyledIndexedSpan;
+
+    fn convert(text: &str) -> String {
+        convert_to_approximate(text, KatexOptions::default())
+            .unwrap()
+            .source()
+            .to_owned()
+    }
+
+    fn convert_plain(text: &str) -> String {
+        let options = KatexOptions {
+            unicode: false,
+            ..KatexOptions::default()
+        };
+        convert_to_approximate(text, options)
+            .unwrap()
+            .source()
+            .to_owned()
+    }
+
+    #[test]
+    fn test_conversion() {
+        assert_eq!(convert("no math here"), "no math here");
+        assert_eq!(convert("$\\frac{a}{b}$"), "(a/b)");
+        assert_eq!(convert("$\\frac 1 2$"), "(1/2)");
+        assert_eq!(convert("$x^2 + y^{25}$"), "x² + y²⁵");
+        assert_eq!(convert_plain("$x^2 + y^{25}$"), "x^2 + y^{25}");
+        // Without a superscript for every character, it's left as it was.
+        assert_eq!(convert("$e^{xy}$"), "e^{xy}");
+        assert_eq!(convert("$a_1$"), "a₁");
+        assert_eq!(convert("$\\theta + \\Delta$"), "θ + Δ");
+        assert_eq!(convert_plain("$\\theta$"), "theta");
+        assert_eq!(convert("$x \\in \\R, n \\in \\N$"), "x ∈ ℝ, n ∈ ℕ");
+        assert_eq!(convert("$\\unknown{x}$"), "\\unknownx");
+        assert_eq!(convert("$$\\sqrt{2}$$"), "√(2)");
+        assert_eq!(convert("a $b$ c $d$"), "a b c d");
+    }
+
+    #[test]
+    fn test_nested_braces() {
+        assert_eq!(convert("$\\frac{\\frac{1}{2}}{3}$"), "((1/2)/3)");
+        assert_eq!(convert("$x^{{2}{3}}$"), "x²³");
+        assert_eq!(convert("$\\frac{a^{2}}{\\{b\\}}$"), "(a²/{b})");
+        // Never closed, so it takes the rest.
+        assert_eq!(convert("$\\frac{a}{b$"), "(a/b)");
+    }
+
     #[test]
-    fn test_conversion() {}
+    fn test_escaped_dollars() {
+        assert_eq!(convert("costs \\$5"), "costs $5");
+        assert_eq!(convert("\\$5 and \\$10"), "$5 and $10");
+        assert_eq!(convert("$\\$5$"), "$5");
+        assert_eq!(convert("$a \\$ b$"), "a $ b");
+    }
+
+    #[test]
+    fn test_unmatched() {
+        assert_eq!(convert("$"), "$");
+        assert_eq!(convert("trailing $"), "trailing $");
+        assert_eq!(convert("$x^2"), "$x^2");
+        assert_eq!(convert("$$"), "$$");
+        assert_eq!(convert("$ $"), "$ $");
+        assert_eq!(convert("$x$ and $"), "x and $");
+    }
+
+    #[test]
+    fn test_italic() {
+        let text = convert_to_approximate("is $x^2$.", KatexOptions::default()).unwrap();
+        assert_eq!(text.source(), "is x².");
+        assert_eq!(
+            text.spans(),
+            &[StyledIndexedSpan::new_range(3..6, Effect::Italic.into())]
+        );
+    }
 }
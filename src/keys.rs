@@ -0,0 +1,238 @@
+use std::{collections::HashMap, fmt};
+
+use cursive::event::{Event, Key};
+
+/// The keys bound to what can be done from anywhere in the chat, which the `[keys]` table of the
+/// config file can change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    /// Sends the message, on top of the key chosen by `--submit-key`.
+    pub send: Option<Event>,
+    pub quit: Event,
+    pub clear: Event,
+    pub search: Event,
+    pub next_tab: Event,
+    /// Shows or hides the users panel.
+    pub users: Event,
+    /// Copies the latest message to the clipboard.
+    pub copy: Event,
+}
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            send: None,
+            quit: Event::CtrlChar('q'),
+            clear: Event::CtrlChar('l'),
+            search: Event::CtrlChar('f'),
+            next_tab: Event::Alt(Key::Right),
+            users: Event::AltChar('u'),
+            copy: Event::AltChar('c'),
+        }
+    }
+}
+impl KeyBindings {
+    /// The names of the actions, as they are given in the config file.
+    pub const ACTIONS: [&'static str; 7] = [
+        "send", "quit", "clear", "search", "next-tab", "users", "copy",
+    ];
+
+    /// The defaults, with the actions in the table bound to the keys they're given. Anything
+    /// that can't be bound is left as it was, and described in the errors, so that a mistake
+    /// in the config doesn't keep the chat from starting.
+    pub fn from_table(table: &HashMap<String, String>) -> (Self, Vec<KeyError>) {
+        let mut keys = Self::default();
+        let mut errors = Vec::new();
+        for (action, key) in table {
+            let event = match parse_key(key) {
+                Ok(event) => event,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+            match action.as_str() {
+                "send" => keys.send = Some(event),
+                "quit" => keys.quit = event,
+                "clear" => keys.clear = event,
+                "search" => keys.search = event,
+                "next-tab" => keys.next_tab = event,
+                "users" => keys.users = event,
+                "copy" => keys.copy = event,
+                _ => errors.push(KeyError::UnknownAction(action.clone())),
+            }
+        }
+        // The table has no order, so neither would the errors.
+        errors.sort_by_key(|err| err.to_string());
+        (keys, errors)
+    }
+}
+
+/// Parses a key such as `ctrl-q`, `alt-right`, `shift-enter`, `f5` or `x`. The modifiers and
+/// named keys ignore case, while characters on their own are taken as written, so `N` is Shift+N.
+pub fn parse_key(text: &str) -> Result<Event, KeyError> {
+    let invalid = || KeyError::InvalidKey(text.to_owned());
+    let text = text.trim();
+    // Split at the last dash, so that the key can be a dash itself.
+    let (modifiers, key) = if let Some(modifiers) = text.strip_suffix("--") {
+        (modifiers, "-")
+    } else {
+        match text.rfind('-') {
+            Some(i) if i + 1 < text.len() => (&text[..i], &text[i + 1..]),
+            _ => ("", text),
+        }
+    };
+    let (mut ctrl, mut alt, mut shift) = (false, false, false);
+    for modifier in modifiers.split('-').filter(|modifier| !modifier.is_empty()) {
+        let flag = match modifier.to_lowercase().as_str() {
+            "ctrl" | "c" => &mut ctrl,
+            "alt" | "meta" | "m" => &mut alt,
+            "shift" | "s" => &mut shift,
+            _ => return Err(invalid()),
+        };
+        if *flag {
+            return Err(invalid());
+        }
+        *flag = true;
+    }
+
+    let mut chars = key.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        return match (ctrl, alt, shift) {
+            (false, false, false) => Ok(Event::Char(ch)),
+            // Terminals can't tell the case of a control character.
+            (true, false, false) => Ok(Event::CtrlChar(ch.to_ascii_lowercase())),
+            (false, true, false) => Ok(Event::AltChar(ch)),
+            // Shifted characters are their own characters, such as `N`.
+            _ => Err(invalid()),
+        };
+    }
+    let key = named_key(&key.to_lowercase()).ok_or_else(invalid)?;
+    Ok(match (ctrl, alt, shift) {
+        (false, false, false) => Event::Key(key),
+        (true, false, false) => Event::Ctrl(key),
+        (false, true, false) => Event::Alt(key),
+        (false, false, true) => Event::Shift(key),
+        (true, true, false) => Event::CtrlAlt(key),
+        (true, false, true) => Event::CtrlShift(key),
+        (false, true, true) => Event::AltShift(key),
+        (true, true, true) => return Err(invalid()),
+    })
+}
+
+fn named_key(name: &str) -> Option<Key> {
+    Some(match name {
+        "enter" | "return" => Key::Enter,
+        "tab" => Key::Tab,
+        "backspace" => Key::Backspace,
+        "esc" | "escape" => Key::Esc,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "ins" | "insert" => Key::Ins,
+        "del" | "delete" => Key::Del,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        _ => match name.strip_prefix('f')?.parse() {
+            Ok(n) if n <= 12 => Key::from_f(n),
+            _ => return None,
+        },
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyError {
+    /// There's no action with the name to bind.
+    UnknownAction(String),
+    InvalidKey(String),
+}
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::UnknownAction(action) => write!(
+                f,
+                "There is no action '{}' to bind, only {}",
+                action,
+                KeyBindings::ACTIONS.join(", ")
+            ),
+            KeyError::InvalidKey(key) => write!(f, "'{}' isn't a key that can be bound", key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cursive::event::{Event, Key};
+
+    use super::{parse_key, KeyBindings, KeyError};
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("x"), Ok(Event::Char('x')));
+        assert_eq!(parse_key("N"), Ok(Event::Char('N')));
+        assert_eq!(parse_key("-"), Ok(Event::Char('-')));
+        assert_eq!(parse_key("ctrl-q"), Ok(Event::CtrlChar('q')));
+        assert_eq!(parse_key("Ctrl-Q"), Ok(Event::CtrlChar('q')));
+        assert_eq!(parse_key("alt-n"), Ok(Event::AltChar('n')));
+        assert_eq!(parse_key("alt--"), Ok(Event::AltChar('-')));
+        assert_eq!(parse_key("enter"), Ok(Event::Key(Key::Enter)));
+        assert_eq!(parse_key("shift-enter"), Ok(Event::Shift(Key::Enter)));
+        assert_eq!(parse_key("alt-right"), Ok(Event::Alt(Key::Right)));
+        assert_eq!(
+            parse_key("ctrl-alt-pageup"),
+            Ok(Event::CtrlAlt(Key::PageUp))
+        );
+        assert_eq!(parse_key("F5"), Ok(Event::Key(Key::F5)));
+
+        for invalid in &[
+            "",
+            "ctrl-",
+            "hyper-x",
+            "ctrl-ctrl-x",
+            "shift-x",
+            "f13",
+            "nope",
+        ] {
+            assert_eq!(
+                parse_key(invalid),
+                Err(KeyError::InvalidKey((*invalid).to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_table() {
+        let table = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(action, key)| ((*action).to_owned(), (*key).to_owned()))
+                .collect()
+        };
+
+        let (keys, errors) = KeyBindings::from_table(&HashMap::new());
+        assert_eq!(keys, KeyBindings::default());
+        assert!(errors.is_empty());
+
+        let (keys, errors) = KeyBindings::from_table(&table(&[
+            ("send", "alt-enter"),
+            ("next-tab", "ctrl-n"),
+            ("scroll", "j"),
+            ("quit", "ctrl-nope"),
+        ]));
+        assert_eq!(keys.send, Some(Event::Alt(Key::Enter)));
+        assert_eq!(keys.next_tab, Event::CtrlChar('n'));
+        // What couldn't be bound is left as it was.
+        assert_eq!(keys.quit, KeyBindings::default().quit);
+        assert_eq!(
+            errors,
+            vec![
+                KeyError::InvalidKey("ctrl-nope".to_owned()),
+                KeyError::UnknownAction("scroll".to_owned()),
+            ]
+        );
+    }
+}
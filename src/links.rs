@@ -0,0 +1,68 @@
+use std::ops::Range;
+
+use cursive::theme::{Effect, Style};
+
+use crate::styled::{StyledIndexedSpan, StyledString};
+
+/// Finds the links to channels in the text, written as `?channel`, along with where they are.
+/// The range covers the `?` too.
+pub fn channel_links(text: &str) -> Vec<(Range<usize>, &str)> {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let mut links = Vec::new();
+    let mut prev = None;
+    for (start, c) in text.char_indices() {
+        // Only at the start of a word, so that questions like `what?no` aren't links.
+        let at_word_start = prev.map_or(true, char::is_whitespace);
+        prev = Some(c);
+        if c != '?' || !at_word_start {
+            continue;
+        }
+        let name_start = start + c.len_utf8();
+        let name_end = text[name_start..]
+            .find(|c: char| !is_name_char(c))
+            .map_or(text.len(), |end| name_start + end);
+        if name_end > name_start {
+            links.push((start..name_end, &text[name_start..name_end]));
+        }
+    }
+    links
+}
+
+/// Underlines the links to channels, so that they stand out as something that can be followed.
+pub fn style_channel_links(mut text: StyledString) -> StyledString {
+    let style: Style = Effect::Underline.into();
+    let ranges: Vec<Range<usize>> = channel_links(text.source())
+        .into_iter()
+        .map(|(range, _)| range)
+        .collect();
+    for range in ranges {
+        text.add_span_intersect(StyledIndexedSpan::new_range(range, style));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel_links;
+
+    #[test]
+    fn test_channel_links() {
+        fn names(text: &str) -> Vec<&str> {
+            channel_links(text)
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect()
+        }
+        assert_eq!(names("come to ?programming"), vec!["programming"]);
+        assert_eq!(
+            names("?foo and ?bar-baz, or ?qux."),
+            vec!["foo", "bar-baz", "qux"]
+        );
+        // Question marks that aren't at the start of a word, or have no name, aren't links.
+        assert!(names("what?no").is_empty());
+        assert!(names("really ?").is_empty());
+        assert!(names("why??").is_empty());
+
+        assert_eq!(channel_links("join ?foo")[0].0, 5..9);
+    }
+}
@@ -1,39 +1,91 @@
 #![forbid(unsafe_code)]
 
 use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io::{self, Write},
     ops::{DerefMut, Range},
+    path::{Path, PathBuf},
+    rc::Rc,
     sync::mpsc::Sender,
-    sync::mpsc::{Receiver, TryRecvError},
+    sync::mpsc::{Receiver, SendError, TryRecvError},
+    time::{Duration, Instant, SystemTime},
 };
 
-use client_manager::{Client, ClientAction, ClientState, Connection, ReadJsonMessageError};
+use chat_log::{FileHistoryStore, HistoryStore};
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Local, NaiveDate, TimeZone,
+};
+use client_manager::{
+    Client, ClientAction, ClientState, Connection, Greeting, LastSeen, MessageKind,
+    ReadJsonMessageError, ReconnectPolicy, SendAndWaitError, Topic, UserLevel, WarnKind,
+    DEFAULT_KEEPALIVE_INTERVAL, DEFAULT_SEND_BACKOFF, QUIT_TIMEOUT,
+};
+use config::Config;
 use cursive::{
-    theme::{Color, ColorType, Effect, Style},
+    align::HAlign,
+    backend::Backend,
+    event::{Event, EventResult, Key},
+    theme::{BaseColor, Color, ColorStyle, ColorType, Effect, Style},
     traits::Scrollable,
     traits::{Boxable, Nameable},
-    view::ScrollStrategy,
+    view::{ScrollStrategy, View},
     views::Dialog,
     views::EditView,
+    views::HideableView,
     views::LinearLayout,
+    views::NamedView,
+    views::OnEventView,
+    views::Panel,
     views::ResizedView,
+    views::ScrollView,
     views::TextArea,
-    Cursive, CursiveRunner,
+    views::TextContent,
+    views::TextView,
+    Cursive, CursiveRunner, Vec2,
 };
 
-use escapes::{Escaped, Escapes};
+use emoji::EmojiMap;
+use escapes::{Escaped, Escapes, Invisibles};
+use filter::Filter;
 use hack_chat_types::{
-    client, server, util::IntoJson, Channel, Nickname, Password, ServerApi, Text, Trip,
+    server, util::IntoJson, Channel, Nickname, Password, ServerApi, Text, Trip, Users,
 };
+use input::{
+    CommandCompleter, InputCommand, InputCount, InputError, InputKeyAction, InputSizing,
+    NickCompleter, SendDebounce, SubmitKey,
+};
+use katex_approx::KatexOptions;
+use keys::KeyBindings;
+use search::Search;
 use slog::{crit, info, warn};
 use slog_unwrap::{OptionExt, ResultExt};
 use sloggers::Build;
-use styled::{InsertMode, StyledString};
+use styled::{InsertMode, StyledIndexedSpan, StyledString};
+use tabs::{TabAction, Tabs};
+use tee::Tee;
+use theme::ChatTheme;
 use tungstenite::{client::AutoStream, Message, WebSocket};
 use url::Url;
 
+mod ansi;
+mod chat_log;
 mod client_manager;
+mod config;
+mod emoji;
 mod escapes;
+mod filter;
+mod input;
+mod katex_approx;
+mod keys;
+mod links;
+mod search;
 mod styled;
+mod tabs;
+mod tee;
+mod theme;
+mod transport;
 
 pub enum DisplayAction {
     /// Simple dialog display.
@@ -41,8 +93,68 @@ pub enum DisplayAction {
     CreateChat,
     /// Add a message to the current message log.
     AddChatMessage(ChatMessage),
+    /// The connection was closed after we asked to quit, so the socket thread has stopped.
     Exit,
-    AlertReconnecting,
+    /// We're about to try reconnecting, after waiting for the duration.
+    AlertReconnecting(Duration),
+    /// The connection was re-established, which is marked in the chat.
+    Reconnected,
+    /// Add a websocket frame to the debugging pane, if it exists.
+    DebugFrame(String),
+    /// Only show messages matching the filter, or all of them if there is none.
+    SetFilter(Option<Filter>),
+    /// Put text in the input, if the user hasn't started writing something.
+    PrefillInput(String),
+    /// Show how the nth displayed message is styled, for debugging the styling.
+    ShowSpans(usize),
+    /// The nick was taken while joining, so ask for another.
+    PromptNick(Nickname),
+    /// Whether the user is in the channel, following `UserInfo::online`.
+    SetOnline(Nickname, bool),
+    /// Everyone in the channel, sorted by nick, replacing who we thought was. Sent whenever
+    /// someone joins or leaves.
+    UpdateUsers(Vec<UserDisplay>),
+    /// Show the users panel if it is hidden, or hide it if it is shown.
+    ToggleUsers,
+    /// Copy the text of the nth most recent message from a user, from one, to the clipboard.
+    CopyMessage(usize),
+    /// The staff of the channel, replacing who we thought they were.
+    UpdateLevels(HashMap<Nickname, UserLevel>),
+    /// Switch to the theme with the name.
+    SetTheme(String),
+    /// We were told to wait this long before sending again.
+    StartCooldown(Duration),
+    /// Whether our sends are being slowed down, as the socket couldn't keep up.
+    SetSlowSends(bool),
+    /// Someone mentioned or whispered to us, which we may be notified of.
+    Notify {
+        title: String,
+        body: String,
+    },
+    /// Mute notifications for the duration, or unmute them if it is zero.
+    SetDnd(Duration),
+    /// Tell the user that their command was done. It is only shown, rather than kept as a message.
+    Confirm(StyledString),
+    /// Switch to the nth most recent channel linked in the chat, from one.
+    GoToLink(usize),
+    /// The connection was lost, so ask the user whether to reconnect.
+    PromptReconnect,
+    /// The nick we are in the channel as, for highlighting messages that mention us.
+    SetOwnNick(Nickname),
+    /// Search back through the chat for the text, ignoring case, or stop searching if it's empty.
+    Search(String),
+    /// Move to the next older hit of the search, or the next newer one if false.
+    CycleSearch(bool),
+    /// Join the channel in a new tab, as we were asked to by the connection.
+    OpenTab(Channel),
+    /// Wipe the chat area and the messages kept, without anything being fetched again.
+    ClearChat,
+    /// How the connection is doing, shown in the status bar.
+    SetStatus(ConnectionStatus),
+    /// The channel's topic, shown in the status bar.
+    SetTopic(Option<Text>),
+    /// Keep the history in the log of the channel we're now in, in place of the old one's.
+    ReopenHistory(Channel),
 }
 #[derive(Debug, Clone, PartialEq)]
 pub struct ChatMessage {
@@ -51,74 +163,573 @@ pub struct ChatMessage {
     pub from: MessageName,
     pub trip: Option<Trip>,
     pub text: Text,
+    /// When the server says the message was sent, or when we received it if it didn't say.
+    pub timestamp: SystemTime,
+    /// Unique to the message, increasing in the order they were added to the display. It is 0
+    /// until then.
+    pub id: u64,
+}
+/// Someone in the channel, as they're listed in the users panel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserDisplay {
+    pub nick: Nickname,
+    pub trip: Option<Trip>,
 }
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum MessageName {
     Server,
     ServerWarn,
     User(String),
+    /// A whisper from the user, which only we were sent.
+    Whisper(String),
     None,
 }
 
+/// How the connection to the server is doing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionStatus {
+    /// The first connection hasn't been made yet.
+    Connecting,
+    /// Connected, and in the channel.
+    Connected(Channel),
+    /// The connection was lost, and we're about to try again.
+    Reconnecting,
+    /// The connection was lost, and we aren't trying again unless asked to.
+    Disconnected,
+}
+impl ConnectionStatus {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            ConnectionStatus::Connecting => "Connecting",
+            ConnectionStatus::Connected(_) => "Connected",
+            ConnectionStatus::Reconnecting => "Reconnecting",
+            ConnectionStatus::Disconnected => "Disconnected",
+        }
+    }
+
+    /// Green when all is well, yellow while getting there, and red when it isn't.
+    pub fn style(&self) -> Style {
+        let color = match self {
+            ConnectionStatus::Connected(_) => BaseColor::Green,
+            ConnectionStatus::Connecting | ConnectionStatus::Reconnecting => BaseColor::Yellow,
+            ConnectionStatus::Disconnected => BaseColor::Red,
+        };
+        ColorStyle::front(Color::Dark(color)).into()
+    }
+}
+
+/// Sends what has been written in the input area, whether from the send button or a key.
+#[derive(Clone)]
+struct InputSubmitter {
+    sender: Sender<ClientAction>,
+    display_sender: Sender<DisplayAction>,
+    log: slog::Logger,
+    send_blank: bool,
+    /// Shared by the clones, as each key that sends has its own.
+    debounce: Rc<RefCell<SendDebounce>>,
+    emoji: Rc<EmojiMap>,
+    hints: Rc<RefCell<InputHints>>,
+}
+impl InputSubmitter {
+    fn submit(&self, siv: &mut Cursive) {
+        let content = siv
+            .call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
+                view.get_content().to_owned()
+            })
+            .unwrap_or_default();
+        let command = input::parse_input(&content).map(|command| command.expand_emoji(&self.emoji));
+        let confirmation = command.as_ref().ok().and_then(InputCommand::confirmation);
+        // TODO: don't panic here.
+        match command {
+            // Left as is, as there's nothing worth clearing.
+            Ok(InputCommand::Chat(text)) if !input::is_sendable(&text, self.send_blank) => return,
+            Ok(InputCommand::Chat(text)) => {
+                // The input is still cleared, as it was already sent.
+                if self
+                    .debounce
+                    .borrow_mut()
+                    .should_send(&text, Instant::now())
+                {
+                    self.sender
+                        .send(ClientAction::SendChatMessage(text))
+                        .expect_or_log(&self.log, "Failed to send chat message action.");
+                }
+            }
+            Ok(InputCommand::Seen(nick)) => self
+                .sender
+                .send(ClientAction::Seen(nick))
+                .expect_or_log(&self.log, "Failed to send seen action."),
+            Ok(InputCommand::Filter(filter)) => self
+                .display_sender
+                .send(DisplayAction::SetFilter(filter))
+                .expect_or_log(&self.log, "Failed to send filter action."),
+            Ok(InputCommand::Whois(nick)) => self
+                .sender
+                .send(ClientAction::Whois(nick))
+                .expect_or_log(&self.log, "Failed to send whois action."),
+            Ok(InputCommand::Invite(nick)) => self
+                .sender
+                .send(ClientAction::Invite(nick))
+                .expect_or_log(&self.log, "Failed to send invite action."),
+            Ok(InputCommand::Whisper { nick, text }) => self
+                .sender
+                .send(ClientAction::SendWhisper { nick, text })
+                .expect_or_log(&self.log, "Failed to send whisper action."),
+            Ok(InputCommand::Emote(text)) => self
+                .sender
+                .send(ClientAction::SendEmote(text))
+                .expect_or_log(&self.log, "Failed to send emote action."),
+            Ok(InputCommand::Nick(nick)) => self
+                .sender
+                .send(ClientAction::ChangeNick(nick))
+                .expect_or_log(&self.log, "Failed to send change nick action."),
+            Ok(InputCommand::Theme(name)) => self
+                .display_sender
+                .send(DisplayAction::SetTheme(name))
+                .expect_or_log(&self.log, "Failed to send theme action."),
+            Ok(InputCommand::Dnd(duration)) => self
+                .display_sender
+                .send(DisplayAction::SetDnd(duration))
+                .expect_or_log(&self.log, "Failed to send dnd action."),
+            Ok(InputCommand::Go(n)) => self
+                .display_sender
+                .send(DisplayAction::GoToLink(n))
+                .expect_or_log(&self.log, "Failed to send go action."),
+            Ok(InputCommand::Open(channel)) => self
+                .sender
+                .send(ClientAction::OpenChannel(channel))
+                .expect_or_log(&self.log, "Failed to send open channel action."),
+            Ok(InputCommand::Join(channel)) => self
+                .sender
+                .send(ClientAction::JoinChannel(channel))
+                .expect_or_log(&self.log, "Failed to send join channel action."),
+            Ok(InputCommand::Ignore(Some(ignore))) => self
+                .sender
+                .send(ClientAction::Ignore(ignore))
+                .expect_or_log(&self.log, "Failed to send ignore action."),
+            Ok(InputCommand::Ignore(None)) => self
+                .sender
+                .send(ClientAction::ListIgnored)
+                .expect_or_log(&self.log, "Failed to send list ignored action."),
+            Ok(InputCommand::Unignore(ignore)) => self
+                .sender
+                .send(ClientAction::Unignore(ignore))
+                .expect_or_log(&self.log, "Failed to send unignore action."),
+            Ok(InputCommand::Spans(n)) => self
+                .display_sender
+                .send(DisplayAction::ShowSpans(n))
+                .expect_or_log(&self.log, "Failed to send spans action."),
+            Ok(InputCommand::Clear) => self
+                .display_sender
+                .send(DisplayAction::ClearChat)
+                .expect_or_log(&self.log, "Failed to send clear action."),
+            Ok(InputCommand::Emoji(query)) => {
+                siv.add_layer(Dialog::info(self.emoji.describe_search(&query)))
+            }
+            Ok(InputCommand::Copy(n)) => self
+                .display_sender
+                .send(DisplayAction::CopyMessage(n))
+                .expect_or_log(&self.log, "Failed to send copy action."),
+            // Answered with its confirmation.
+            Ok(InputCommand::Help(_)) => {}
+            // The input is kept so that it can be corrected.
+            Err(InputError::Usage(usage)) => {
+                siv.add_layer(Dialog::info(format!("Usage: {}", usage)));
+                return;
+            }
+            Err(InputError::Invalid(reason)) => {
+                siv.add_layer(Dialog::info(reason));
+                return;
+            }
+        }
+        if let Some(text) = confirmation {
+            self.display_sender
+                .send(DisplayAction::Confirm(text))
+                .expect_or_log(&self.log, "Failed to send confirmation action.");
+        }
+        siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| view.set_content(""));
+        let mut hints = self.hints.borrow_mut();
+        hints.usage = None;
+        hints.show(siv);
+    }
+}
+
+/// What is shown beneath the input. Each part is set and cleared by what it's about, and they're
+/// shown together, so that the search's status doesn't hide the cooldown, for example.
+#[derive(Debug, Default)]
+struct InputHints {
+    /// How the command that was just completed is used, until the input is sent.
+    usage: Option<&'static str>,
+    /// Where the search is at, while searching.
+    search: Option<StyledString>,
+    /// The cooldown's countdown.
+    cooldown: Option<String>,
+    /// Whether sends are being slowed down for the rate limit.
+    slow_sends: bool,
+}
+impl InputHints {
+    fn describe(&self) -> StyledString {
+        let mut parts = Vec::new();
+        parts.extend(self.usage.map(StyledString::from));
+        parts.extend(self.search.clone());
+        parts.extend(self.cooldown.as_deref().map(StyledString::from));
+        if self.slow_sends {
+            parts.push(StyledString::from("Slowing down sends"));
+        }
+        let mut text = StyledString::default();
+        for (i, part) in parts.into_iter().enumerate() {
+            if i > 0 {
+                text.append_source(" · ");
+            }
+            text.append(part);
+        }
+        text
+    }
+
+    fn show(&self, siv: &mut Cursive) {
+        let text = self.describe();
+        siv.call_on_name(INPUT_HINT_NAME, |view: &mut TextView| {
+            view.set_content(text)
+        });
+    }
+}
+
 const TEXT_AREA_NAME: &str = "chat_text_area";
 const CHAT_AREA_NAME: &str = "chat_area";
+const CHAT_SCROLL_NAME: &str = "chat_scroll";
+const INPUT_COUNT_NAME: &str = "chat_input_count";
+const INPUT_HINT_NAME: &str = "chat_input_hint";
+const DEBUG_PANE_NAME: &str = "debug_frame_pane";
+const TAB_BAR_NAME: &str = "chat_tab_bar";
+const STATUS_BAR_NAME: &str = "chat_status_bar";
+const USERS_PANEL_NAME: &str = "chat_users_panel";
+const USERS_LIST_NAME: &str = "chat_users_list";
+/// This isn't a limit the server imposes, but past it messages get unwieldy to read.
+const DEFAULT_MAX_INPUT_LENGTH: usize = 2048;
+/// How many messages of the previous sessions are shown when importing the chat log.
+const IMPORTED_MESSAGES: usize = 100;
+/// How long `--send` waits for the server to echo the message.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Past this many views in the chat area, rendering slows down noticeably.
+const DEFAULT_MAX_CHAT_VIEWS: usize = 1000;
+/// Enough to search well back, while keeping a long-running session's memory bounded.
+const DEFAULT_MAX_HISTORY: usize = 10_000;
+/// How long after a notification any more are dropped, so that a burst of mentions rings once.
+const NOTIFY_THROTTLE: Duration = Duration::from_secs(5);
+/// Whether notifications are muted, and for how long.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Mute {
+    Off,
+    Until(Instant),
+    /// For longer than can be kept as a time.
+    UntilUnmuted,
+}
+
+/// The widths of the fields in the gutter beside messages, which shrink to fit narrow screens.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct GutterSizes {
+    pub nick: usize,
+    pub trip: usize,
+}
+impl GutterSizes {
+    pub const FULL: GutterSizes = GutterSizes { nick: 24, trip: 6 };
+    pub const MIN: GutterSizes = GutterSizes { nick: 8, trip: 3 };
+
+    /// Shrinks the fields proportionally so that the gutter takes up at most a third of the
+    /// screen's width, though never below `min`, which is itself capped at the full widths.
+    pub fn fit(width: usize, min: GutterSizes) -> Self {
+        let full = Self::FULL.nick + Self::FULL.trip;
+        let budget = width / 3;
+        if budget >= full {
+            return Self::FULL;
+        }
+        Self {
+            nick: (Self::FULL.nick * budget / full).max(min.nick.min(Self::FULL.nick)),
+            trip: (Self::FULL.trip * budget / full).max(min.trip.min(Self::FULL.trip)),
+        }
+    }
+}
+
+/// How many columns of the screen aren't given to messages, which are taken by the dialog's
+/// borders and padding on either side, and the chat area's scrollbar.
+const CHAT_AREA_MARGIN: usize = 6;
+
+/// The chat area, within the view that scrolls it.
+type ChatScrollView = ScrollView<NamedView<LinearLayout>>;
+
+/// The list of users beside the chat area, which can be hidden.
+type UsersPanel = HideableView<ResizedView<Panel<ScrollView<NamedView<TextView>>>>>;
+
+/// How many columns the users panel takes, including its borders.
+const USERS_PANEL_WIDTH: usize = 22;
+
 pub struct ChatDisplay<'a> {
     pub receiver: Receiver<DisplayAction>,
+    /// Sends to our own receiver, so that views can give us actions to perform.
+    pub display_sender: Sender<DisplayAction>,
     pub sender: Sender<ClientAction>,
     pub messages: Vec<ChatMessage>,
     pub log: slog::Logger,
     pub escapes: Escapes<'a>,
+    /// The length past which the input count is displayed as a warning.
+    pub max_input_length: Option<usize>,
+    /// The most views kept in the chat area, with the oldest removed past it. The messages
+    /// themselves are still kept, so they come back if the chat is rendered again.
+    pub max_chat_views: Option<usize>,
+    /// The most messages kept, with the oldest dropped past it. Dropped messages aren't rendered
+    /// again, so this should be at least `max_chat_views`.
+    pub max_history: Option<usize>,
+    /// The last count of the input that was displayed, so we only update it when it changes.
+    input_count: Option<InputCount>,
+    /// Whether to show a pane of the raw websocket frames, for debugging the protocol.
+    pub debug_pane: bool,
+    /// Only messages matching this are shown.
+    filter: Option<Filter>,
+    /// Whether to send messages that are empty or only whitespace.
+    pub send_blank: bool,
+    /// How soon the same message can be sent again, as it's likely an accidental double send.
+    pub send_debounce: Duration,
+    /// Whether to show users as `nick#trip`, rather than putting the trip in its own column.
+    pub combined_identity: bool,
+    /// The styles that the chat is drawn with, from `--theme` or `/theme`.
+    pub theme: ChatTheme,
+    /// The themes that `/theme` can switch to, built in and from the config file.
+    pub themes: Vec<ChatTheme>,
+    /// Whether to leave out the trip column while none of the shown messages have a trip.
+    pub collapse_trips: bool,
+    /// Whether any of the shown messages have a trip.
+    shown_trips: bool,
+    /// The widths of the gutter, fitted to the screen.
+    gutter: GutterSizes,
+    /// The widths the gutter never shrinks below, from `--min-nick-width` and `--min-trip-width`.
+    pub gutter_min: GutterSizes,
+    /// How many columns messages have to be wrapped within, once the screen's size is known.
+    chat_width: Option<usize>,
+    /// The channels linked in the chat as `?channel`, oldest first.
+    channel_links: Vec<Channel>,
+    /// Which key sends the message, rather than inserting a newline.
+    pub submit_key: SubmitKey,
+    /// The keys bound to actions on the chat.
+    pub keys: KeyBindings,
+    /// The `:shortcodes:` that are expanded to emoji in what we send.
+    emoji: Rc<EmojiMap>,
+    /// Whether to turn ANSI escape sequences in messages into styling.
+    pub ansi: bool,
+    /// Whether to show the math in messages, between `$`s, as an approximation of it.
+    pub katex: bool,
+    /// Whether to style the inline markdown in messages, such as `*bold*`.
+    pub markdown: bool,
+    /// Whether to show messages exactly as they were sent, without any of the transforms.
+    pub raw_text: bool,
+    /// Whether to dim the messages of users that have left.
+    pub dim_offline: bool,
+    /// Whether to color each user's nick by who they are, see [`nick_color`].
+    pub nick_colors: bool,
+    /// Users that have left the channel.
+    offline: HashSet<Nickname>,
+    /// Users in the channel, shared with the input area for completing their nicks.
+    users: Rc<RefCell<Vec<Nickname>>>,
+    /// Users in the channel along with their trips, as they're listed in the users panel.
+    roster: Vec<UserDisplay>,
+    /// Whether the users panel is shown beside the chat.
+    pub show_users: bool,
+    /// The staff of the channel, whose nicks are shown with a badge.
+    levels: HashMap<Nickname, UserLevel>,
+    /// Where received messages are kept across sessions, if anywhere.
+    pub history: Option<Box<dyn HistoryStore>>,
+    /// The directory that the history is logged to, so that it can follow us between channels.
+    pub log_dir: Option<PathBuf>,
+    /// Where the shown messages are mirrored as plain text, if anywhere.
+    pub tee: Option<Tee>,
+    /// How many of the messages, from the start, were imported from a previous session.
+    imported: usize,
+    /// The ids of the messages imported from a previous session, which are dimmed as history.
+    imported_ids: Range<u64>,
+    /// The id that the next message added is given.
+    next_id: u64,
+    /// How the input area is sized as more is written.
+    pub input_sizing: InputSizing,
+    /// Whether to show how long ago each message was received.
+    pub relative_time: bool,
+    /// The strftime-style format of the local time shown before each message, if any.
+    pub timestamp_format: Option<String>,
+    /// The time prefix of each rendered message, which is updated as time passes.
+    time_views: Vec<(SystemTime, TextContent)>,
+    /// When the time prefixes were last updated.
+    times_updated_at: Instant,
+    /// When the server will let us send again, after warning us to slow down.
+    cooldown_until: Option<Instant>,
+    /// The seconds of the cooldown that were last displayed.
+    cooldown_shown: Option<u64>,
+    /// What is shown beneath the input, shared with its callbacks.
+    hints: Rc<RefCell<InputHints>>,
+    /// Whether to ring the terminal bell when someone mentions us, as well as showing a desktop
+    /// notification if built with them.
+    pub notifications_enabled: bool,
+    /// Whether notifications are muted, from `/dnd`.
+    dnd: Mute,
+    /// When we last notified, for throttling them.
+    last_notified: Option<Instant>,
+    /// Whether to highlight messages that mention us or one of the `highlight_words`.
+    pub highlight_mentions: bool,
+    /// Words that are highlighted as if they were our nick, such as a nickname we go by.
+    pub highlight_words: Vec<String>,
+    /// The nick we are in the channel as, once the server has told us.
+    own_nick: Option<Nickname>,
+    /// The search through the chat, while there is one.
+    search: Option<Search>,
+    /// How many messages have been shown since the tab was last looked at.
+    pub unread: usize,
+    /// Where to send what to do with the tabs, once the display is in one.
+    pub tab_sender: Option<Sender<TabAction>>,
+    /// Whether the connection has been closed after quitting, so nothing more will come of it.
+    pub closed: bool,
+    /// How the connection is doing.
+    status: ConnectionStatus,
+    /// The channel we were last connected to.
+    channel: Option<Channel>,
+    /// The topic of that channel, if the server sent one.
+    topic: Option<Text>,
 }
 impl<'a> ChatDisplay<'a> {
     pub fn new(
         receiver: Receiver<DisplayAction>,
+        display_sender: Sender<DisplayAction>,
         sender: Sender<ClientAction>,
         escapes: Escapes<'a>,
         log: slog::Logger,
     ) -> Self {
         Self {
             receiver,
+            display_sender,
             sender,
             log,
             escapes,
             messages: Vec::with_capacity(512),
+            max_input_length: Some(DEFAULT_MAX_INPUT_LENGTH),
+            max_chat_views: Some(DEFAULT_MAX_CHAT_VIEWS),
+            max_history: Some(DEFAULT_MAX_HISTORY),
+            input_count: None,
+            debug_pane: false,
+            filter: None,
+            send_blank: false,
+            send_debounce: SendDebounce::DEFAULT_WINDOW,
+            combined_identity: false,
+            theme: ChatTheme::default(),
+            themes: theme::themes(),
+            collapse_trips: false,
+            shown_trips: false,
+            gutter: GutterSizes::FULL,
+            gutter_min: GutterSizes::MIN,
+            chat_width: None,
+            channel_links: Vec::new(),
+            submit_key: SubmitKey::default(),
+            keys: KeyBindings::default(),
+            emoji: Rc::new(EmojiMap::bundled()),
+            ansi: false,
+            katex: false,
+            markdown: false,
+            raw_text: false,
+            dim_offline: false,
+            nick_colors: false,
+            offline: HashSet::new(),
+            users: Rc::new(RefCell::new(Vec::new())),
+            roster: Vec::new(),
+            show_users: true,
+            levels: HashMap::new(),
+            history: None,
+            log_dir: None,
+            tee: None,
+            imported: 0,
+            imported_ids: 0..0,
+            next_id: 1,
+            relative_time: false,
+            timestamp_format: None,
+            input_sizing: InputSizing::default(),
+            time_views: Vec::new(),
+            times_updated_at: Instant::now(),
+            cooldown_until: None,
+            cooldown_shown: None,
+            hints: Rc::default(),
+            notifications_enabled: false,
+            dnd: Mute::Off,
+            last_notified: None,
+            highlight_mentions: true,
+            highlight_words: Vec::new(),
+            own_nick: None,
+            search: None,
+            unread: 0,
+            tab_sender: None,
+            closed: false,
+            status: ConnectionStatus::Connecting,
+            channel: None,
+            topic: None,
         }
     }
 
     fn format_sender(&self, nick: MessageName, trip: Option<String>) -> StyledString {
         const NICK_TRIP_SEPARATOR: &str = " ";
         const TEXT_SEPARATOR: &str = "| ";
-        const NICKNAME_SIZE: usize = 24;
-        const TRIP_SIZE: usize = 6;
-        const NICK_TRIP_SEPARATOR_SIZE: usize = NICK_TRIP_SEPARATOR.len();
-        const TEXT_SEPARATOR_SIZE: usize = TEXT_SEPARATOR.len();
-        const SIZE: usize =
-            NICKNAME_SIZE + TRIP_SIZE + NICK_TRIP_SEPARATOR_SIZE + TEXT_SEPARATOR_SIZE;
-
-        let trip_separator = if trip.is_some() {
-            NICK_TRIP_SEPARATOR
+        // Everything here is measured in columns rather than bytes, so that the text lines up
+        // after nicks with wide or multi-byte characters.
+        let gutter = self.gutter;
+        let text_separator_width = styled::str_width(TEXT_SEPARATOR);
+        let size = if self.collapse_trips && !self.shown_trips {
+            gutter.nick + text_separator_width
         } else {
-            ""
+            gutter.nick
+                + gutter.trip
+                + styled::str_width(NICK_TRIP_SEPARATOR)
+                + text_separator_width
+        };
+        // Chosen before the trip is cut down, as the color is of the whole trip.
+        let nick_style = match &nick {
+            MessageName::Whisper(_) => self.theme.whisper,
+            MessageName::User(user) if self.nick_colors => {
+                let color = ColorStyle::front(nick_color(user, trip.as_deref()));
+                Style::merge(&[self.theme.nick, color.into()])
+            }
+            _ => self.theme.nick,
+        };
+        // Whispers don't say who is staff.
+        let level = match &nick {
+            MessageName::User(user) => self.levels.get(user).copied(),
+            _ => None,
         };
-        let trip = trip.as_deref().unwrap_or("");
+        // Cut down to their fields, so that a narrow gutter doesn't push the text along.
+        let trip = trip.map(|trip| styled::truncate_to_width(&trip, gutter.trip).to_owned());
+
+        let trip_style = self.theme.trip;
         let mut text = StyledString::default();
-        text.append_styled(
-            trip,
-            Style::merge(&[
-                Effect::Italic.into(),
-                ColorType::Color(Color::Rgb(0x33, 0x33, 0x33)).into(),
-            ]),
-        );
-        text.append_source(trip_separator);
+        // When combined, the trip is put after the nick rather than in its own gutter.
+        if !self.combined_identity {
+            if let Some(trip) = &trip {
+                text.append_styled(trip, trip_style);
+                text.append_source(NICK_TRIP_SEPARATOR);
+            }
+        }
         match nick {
             MessageName::None => {}
-            MessageName::Server => text.append_source("*"),
-            MessageName::ServerWarn => text.append_source("!"),
-            MessageName::User(user) => text.append_source(user.as_str()),
+            MessageName::Server => append_themed(&mut text, "*", self.theme.server),
+            MessageName::ServerWarn => append_themed(&mut text, "!", self.theme.warn),
+            MessageName::User(user) | MessageName::Whisper(user) => {
+                // Ended with an ellipsis when cut, so it's clear that it's not the whole nick.
+                let mut name = StyledString::default();
+                if let Some(level) = level {
+                    append_themed(&mut name, level.badge(), self.theme.badge);
+                }
+                append_themed(&mut name, &user, nick_style);
+                name.truncate_with_ellipsis(gutter.nick);
+                text.append(name);
+                if let (true, Some(trip)) = (self.combined_identity, &trip) {
+                    text.append_styled(&format!("#{}", trip), trip_style);
+                }
+            }
         }
         text.append_source(TEXT_SEPARATOR);
-        if text.len() < SIZE {
-            let amount = SIZE - text.len();
+        if text.width() < size {
+            let amount = size - text.width();
             text.insert_str(0, " ".repeat(amount).as_str(), InsertMode::BreakApart);
         }
         text
@@ -132,58 +743,268 @@ impl<'a> ChatDisplay<'a> {
                         let text = self.escapes.apply(text);
                         self.display_dialog(siv, text)
                     }
-                    DisplayAction::AddChatMessage(message) => {
-                        let user = self.format_sender(message.from, message.trip.map(|x| x.0));
-                        let user = self.escapes.apply(user);
-                        let text = self.escapes.apply(message.text);
-                        self.add_message(siv, user, text);
+                    // Added in the order they arrive rather than by when the server says they
+                    // were sent. The server relays them in order, and the ids that messages are
+                    // found by are given in the order they're kept.
+                    DisplayAction::AddChatMessage(mut message) => {
+                        message.id = self.take_id();
+                        // Messages that arrive before the chat exists are kept, and rendered
+                        // once it is created.
+                        let has_chat = siv.find_name::<LinearLayout>(CHAT_AREA_NAME).is_some();
+                        let shown = self.is_shown(&message);
+                        // The first trip brings back the trip column for every message.
+                        let first_trip = shown && message.trip.is_some() && !self.shown_trips;
+                        self.shown_trips |= first_trip;
+                        let relayout = first_trip && self.collapse_trips;
+                        if has_chat && shown && !relayout {
+                            self.render_message(siv, &message);
+                        }
+                        if let Some(history) = &mut self.history {
+                            if let Err(err) = history.append(&message) {
+                                warn!(self.log, "Failed to log message: {}", err);
+                            }
+                        }
+                        if let MessageName::User(_) = message.from {
+                            for (_, channel) in links::channel_links(&message.text) {
+                                // Linked again, it becomes the most recent.
+                                self.channel_links.retain(|linked| linked != channel);
+                                self.channel_links.push(channel.to_owned());
+                            }
+                        }
+                        if shown {
+                            self.unread += 1;
+                        }
+                        if let (true, Some(tee)) = (shown, &mut self.tee) {
+                            if let Err(err) = tee.write(&message) {
+                                warn!(self.log, "Failed to tee message: {}", err);
+                            }
+                        }
+                        self.messages.push(message);
+                        self.trim_history();
+                        if relayout {
+                            self.rerender_messages(siv);
+                        }
+                    }
+                    DisplayAction::SetFilter(filter) => {
+                        self.filter = filter;
+                        self.rerender_messages(siv);
                     }
                     DisplayAction::CreateChat => {
-                        // Clone the sender, which gives us access to the same place, and allows us
-                        // to take ownership of it to send actions.
+                        // The chat is kept across reconnects, so that the scrollback isn't lost.
+                        if siv.find_name::<LinearLayout>(CHAT_AREA_NAME).is_none() {
+                            self.create_chat(siv);
+                            // Render anything that arrived before the chat existed.
+                            self.rerender_messages(siv);
+                        }
+                    }
+                    DisplayAction::PrefillInput(text) => {
+                        siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
+                            if view.get_content().is_empty() {
+                                view.set_content(text);
+                            }
+                        });
+                    }
+                    DisplayAction::ShowSpans(n) => {
+                        let text = self
+                            .describe_message_spans(n)
+                            .unwrap_or_else(|| format!("There is no message {}.", n));
+                        siv.add_layer(Dialog::info(text));
+                    }
+                    DisplayAction::SetOnline(nick, online) => {
+                        let mut users = self.users.borrow_mut();
+                        users.retain(|user| *user != nick);
+                        if online {
+                            users.push(nick.clone());
+                        }
+                        drop(users);
+                        let changed = if online {
+                            self.offline.remove(&nick)
+                        } else {
+                            self.offline.insert(nick)
+                        };
+                        if changed && self.dim_offline {
+                            self.rerender_messages(siv);
+                        }
+                    }
+                    DisplayAction::UpdateUsers(users) => {
+                        *self.users.borrow_mut() =
+                            users.iter().map(|user| user.nick.clone()).collect();
+                        self.roster = users;
+                        self.update_users_panel(siv);
+                    }
+                    DisplayAction::ToggleUsers => self.toggle_users(siv),
+                    DisplayAction::CopyMessage(n) => self.copy_message(siv, n),
+                    DisplayAction::UpdateLevels(levels) => {
+                        if levels != self.levels {
+                            self.levels = levels;
+                            self.rerender_messages(siv);
+                            self.update_users_panel(siv);
+                        }
+                    }
+                    DisplayAction::Notify { title, body } => {
+                        if self.take_notification(Instant::now()) {
+                            if let Err(err) = ring_bell() {
+                                warn!(self.log, "Failed to ring the bell: {}", err);
+                            }
+                            if let Err(err) = show_desktop_notification(&title, &body) {
+                                warn!(self.log, "Failed to show a desktop notification: {}", err);
+                            }
+                        }
+                    }
+                    DisplayAction::SetDnd(duration) => self.set_dnd(duration, Instant::now()),
+                    DisplayAction::ClearChat => self.clear_chat(siv),
+                    DisplayAction::ReopenHistory(channel) => self.reopen_history(&channel),
+                    DisplayAction::SetStatus(status) => {
+                        if let ConnectionStatus::Connected(channel) = &status {
+                            // The topic was of the channel we were in before.
+                            if self.channel.as_ref() != Some(channel) {
+                                self.topic = None;
+                            }
+                            self.channel = Some(channel.clone());
+                        }
+                        self.status = status;
+                        self.update_status_bar(siv);
+                    }
+                    DisplayAction::SetTopic(topic) => {
+                        self.topic = topic;
+                        self.update_status_bar(siv);
+                    }
+                    DisplayAction::SetOwnNick(nick) => {
+                        let changed = self.own_nick.as_ref() != Some(&nick);
+                        self.own_nick = Some(nick);
+                        if changed && self.highlight_mentions {
+                            self.rerender_messages(siv);
+                        }
+                        self.update_status_bar(siv);
+                    }
+                    DisplayAction::Confirm(text) => {
+                        self.confirm(siv, text);
+                    }
+                    DisplayAction::GoToLink(n) => {
+                        match self.channel_links.iter().rev().nth(n - 1) {
+                            Some(channel) => {
+                                let channel = channel.clone();
+                                let mut text = StyledString::default();
+                                text.append_source("Switching to ");
+                                text.append_styled(&format!("?{}", channel), Effect::Bold.into());
+                                self.sender
+                                    .send(ClientAction::SwitchChannel(channel))
+                                    .expect_or_log(
+                                        &self.log,
+                                        "Failed to send switch channel action",
+                                    );
+                                self.confirm(siv, text);
+                            }
+                            None => siv.add_layer(Dialog::info(format!(
+                                "There are only {} channel links in the chat",
+                                self.channel_links.len()
+                            ))),
+                        }
+                    }
+                    DisplayAction::Search(query) => {
+                        if query.trim().is_empty() {
+                            self.stop_search(siv);
+                        } else {
+                            let search = Search::new(
+                                &query,
+                                self.messages
+                                    .iter()
+                                    .filter(|message| self.is_shown(message)),
+                            );
+                            self.search = Some(search);
+                            self.rerender_messages(siv);
+                            self.show_search_hit(siv);
+                        }
+                    }
+                    DisplayAction::CycleSearch(older) => {
+                        if let Some(search) = &mut self.search {
+                            if older {
+                                search.older();
+                            } else {
+                                search.newer();
+                            }
+                            self.show_search_hit(siv);
+                        }
+                    }
+                    DisplayAction::SetSlowSends(slow) => {
+                        let mut hints = self.hints.borrow_mut();
+                        hints.slow_sends = slow;
+                        hints.show(siv);
+                    }
+                    DisplayAction::StartCooldown(duration) => {
+                        self.cooldown_until = Instant::now().checked_add(duration);
+                        self.cooldown_shown = None;
+                    }
+                    DisplayAction::SetTheme(name) => match theme::find_theme(&self.themes, &name) {
+                        Some(theme) => {
+                            siv.set_theme(theme.cursive_theme());
+                            self.theme = theme.clone();
+                            self.rerender_messages(siv);
+                        }
+                        None => {
+                            let names: Vec<&str> = self
+                                .themes
+                                .iter()
+                                .map(|theme| theme.name.as_str())
+                                .collect();
+                            siv.add_layer(Dialog::info(format!(
+                                "There is no theme '{}'. The themes are: {}",
+                                name,
+                                names.join(", ")
+                            )));
+                        }
+                    },
+                    DisplayAction::PromptNick(taken) => {
                         let sender = self.sender.clone();
                         let log = self.log.clone();
-                        // Create the text input area.
-                        // TODO: configurable min and max dimensions.
-                        let text_area = TextArea::new()
-                            .with_name(TEXT_AREA_NAME)
-                            .min_height(2)
-                            .min_width(40)
-                            .max_height(6)
-                            .scrollable();
-                        // Create the area where chat messages are stored.
-                        let chat_area = LinearLayout::vertical()
-                            .with_name(CHAT_AREA_NAME)
-                            .scrollable()
-                            .scroll_strategy(ScrollStrategy::StickToBottom);
-                        // Create the dialog that is displayed.
-                        // Displays messages (chat area) above the user input (text area)
-                        let dialog = Dialog::around(
-                            LinearLayout::vertical().child(chat_area).child(text_area),
-                        )
-                        // Handle the send button.
-                        .button("Send", move |siv| {
-                            siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
-                                let content = view.get_content();
-                                // TODO: don't panic here.
-                                sender
-                                    .send(ClientAction::SendChatMessage(content.to_owned()))
-                                    .expect_or_log(&log, "Failed to send chat message action.");
-                                view.set_content("");
-                            });
-                        });
-                        // Create a resized view that puts this at full screen since its the main
-                        // thing we're displaying.
-                        let resized_view = ResizedView::with_full_screen(dialog);
-                        siv.add_layer(resized_view);
+                        let dialog = show_username_dialog(self.log.clone(), move |nick| {
+                            sender
+                                .send(ClientAction::Rejoin(nick))
+                                .expect_or_log(&log, "Failed to send rejoin action.")
+                        })
+                        .title(format!("'{}' is taken, pick another", taken));
+                        siv.add_layer(dialog);
+                    }
+                    DisplayAction::Reconnected => {
+                        self.add_seam(siv, "reconnected");
+                    }
+                    DisplayAction::Exit => self.closed = true,
+                    DisplayAction::DebugFrame(text) => {
+                        let text = self.escapes.apply(text);
+                        // Frames from before the chat was created have nowhere to go.
+                        if let Some(mut pane) = siv.find_name::<LinearLayout>(DEBUG_PANE_NAME) {
+                            pane.add_child(escapes::create_text_view(text));
+                        }
                     }
-                    DisplayAction::Exit => {
-                        std::process::exit(0);
+                    DisplayAction::PromptReconnect => {
+                        let sender = self.sender.clone();
+                        let log = self.log.clone();
+                        let reconnect = move |siv: &mut Cursive| {
+                            siv.pop_layer();
+                            sender
+                                .send(ClientAction::Reconnect)
+                                .expect_or_log(&log, "Failed to send reconnect action");
+                        };
+                        let dialog = Dialog::text("Disconnected, press R to reconnect")
+                            .button("Reconnect", reconnect.clone());
+                        siv.add_layer(
+                            OnEventView::new(dialog)
+                                .on_event('r', reconnect.clone())
+                                .on_event('R', reconnect),
+                        );
                     }
-                    DisplayAction::AlertReconnecting => {
+                    DisplayAction::OpenTab(channel) => match &self.tab_sender {
+                        Some(tab_sender) => tab_sender
+                            .send(TabAction::Open(channel))
+                            .expect_or_log(&self.log, "Failed to send open tab action"),
+                        None => warn!(self.log, "Not in a tab, so ?{} can't be opened", channel),
+                    },
+                    DisplayAction::AlertReconnecting(wait) => {
                         let user = self.format_sender(MessageName::Server, None);
                         let user = self.escapes.apply(user);
-                        self.add_message(siv, user, self.escapes.apply("Reconnecting"));
+                        let text = format!("Reconnecting in {:.1}s", wait.as_secs_f64());
+                        let text = self.escapes.apply(text);
+                        self.add_message(siv, user, text, SystemTime::now(), None);
                     }
                 };
                 return true;
@@ -199,17 +1020,335 @@ impl<'a> ChatDisplay<'a> {
         false
     }
 
+    /// Updates the count displayed beside the input area if the input has changed.
+    /// Returns `true` if it was updated.
+    pub fn update_input_count(&mut self, siv: &mut Cursive) -> bool {
+        let count = siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
+            InputCount::of(view.get_content())
+        });
+        let count = match count {
+            // The chat hasn't been created yet.
+            None => return false,
+            Some(count) => count,
+        };
+        if self.input_count == Some(count) {
+            return false;
+        }
+        self.input_count = Some(count);
+
+        let text = format!("{} chars\n{} cols", count.chars, count.width);
+        let text = if count.is_over(self.max_input_length) {
+            StyledString::single_span(text, ColorType::Color(Color::Rgb(0xDD, 0x22, 0x22)).into())
+        } else {
+            StyledString::from(text)
+        };
+        siv.call_on_name(INPUT_COUNT_NAME, |view: &mut TextView| {
+            view.set_content(text)
+        })
+        .is_some()
+    }
+
+    fn is_shown(&self, message: &ChatMessage) -> bool {
+        self.filter
+            .as_ref()
+            .map_or(true, |filter| filter.matches(message))
+    }
+
+    /// The sender and text of a message, styled as they are displayed.
+    fn style_message(
+        &self,
+        message: &ChatMessage,
+    ) -> (Escaped<StyledString>, Escaped<StyledString>) {
+        let user = self.format_sender(message.from.clone(), message.trip.clone().map(|x| x.0));
+        let user = self.escapes.apply(user);
+        if self.raw_text {
+            return (user, escapes::escape_raw(&message.text));
+        }
+        let text = self.escapes.replace(message.text.clone());
+        // Done after the replacements, as they don't keep styling, and before the invisible
+        // characters are dealt with, as the ANSI escapes are made of them.
+        let text = if self.ansi {
+            ansi::parse_ansi(text.source())
+        } else if self.katex {
+            match katex_approx::convert_to_approximate(text.source(), KatexOptions::default()) {
+                Ok(converted) => converted,
+                Err(err) => match err {},
+            }
+        } else {
+            text
+        };
+        // Only around what the ANSI escapes or the math styled, which are kept as they were.
+        let text = if self.markdown {
+            styled::parse_inline_markdown_around(&text)
+        } else {
+            text
+        };
+        let text = self.escapes.sanitize(text);
+        let text = text.map(links::style_channel_links);
+        let text = text.map(|text| text.linkify());
+        let text = match message.from {
+            MessageName::Whisper(_) => text.map(|text| restyle(text, self.theme.whisper)),
+            _ if self.is_highlighted(message) => text.map(|text| restyle(text, self.theme.mention)),
+            _ => text,
+        };
+        let text = match &self.search {
+            Some(search) => {
+                text.map(|text| search::style_hits(text, search.query(), self.theme.search))
+            }
+            None => text,
+        };
+        if self.is_dimmed(message) {
+            (user.map(dim), text.map(dim))
+        } else {
+            (user, text)
+        }
+    }
+
+    /// Whether someone else's message mentions us, or one of the words we highlight.
+    fn is_highlighted(&self, message: &ChatMessage) -> bool {
+        let from = match &message.from {
+            MessageName::User(from) if self.highlight_mentions => from,
+            _ => return false,
+        };
+        if self.own_nick.as_ref() == Some(from) {
+            return false;
+        }
+        self.own_nick
+            .iter()
+            .chain(self.highlight_words.iter())
+            .any(|word| mentions(&message.text, word))
+    }
+
+    fn is_dimmed(&self, message: &ChatMessage) -> bool {
+        if self.imported_ids.contains(&message.id) {
+            return true;
+        }
+        match &message.from {
+            MessageName::User(nick) => self.dim_offline && self.offline.contains(nick),
+            _ => false,
+        }
+    }
+
+    fn render_message(&mut self, siv: &mut Cursive, message: &ChatMessage) -> bool {
+        let (user, text) = self.style_message(message);
+        self.add_message(siv, user, text, message.timestamp, Some(message.id))
+    }
+
+    /// Describes the spans of the nth shown message, if there is one.
+    fn describe_message_spans(&self, n: usize) -> Option<String> {
+        let message = self
+            .messages
+            .iter()
+            .filter(|message| self.is_shown(message))
+            .nth(n)?;
+        let (user, text) = self.style_message(message);
+        Some(format!(
+            "Sender {:?}:\n{}\n\nText {:?}:\n{}",
+            user.inner().source(),
+            user.inner().describe_spans(),
+            text.inner().source(),
+            text.inner().describe_spans()
+        ))
+    }
+
+    /// The nth most recent shown message from a user, from one.
+    fn recent_message(&self, n: usize) -> Option<&ChatMessage> {
+        self.messages
+            .iter()
+            .rev()
+            .filter(|message| {
+                matches!(message.from, MessageName::User(_) | MessageName::Whisper(_))
+            })
+            .filter(|message| self.is_shown(message))
+            .nth(n.checked_sub(1)?)
+    }
+
+    /// Copies the text of the nth most recent message from a user to the clipboard, as it was
+    /// sent rather than how it is styled.
+    fn copy_message(&mut self, siv: &mut Cursive, n: usize) {
+        let (from, text) = match self.recent_message(n) {
+            Some(ChatMessage {
+                from: MessageName::User(from),
+                text,
+                ..
+            })
+            | Some(ChatMessage {
+                from: MessageName::Whisper(from),
+                text,
+                ..
+            }) => (from.clone(), text.clone()),
+            _ => {
+                siv.add_layer(Dialog::info(format!("There is no message {} to copy.", n)));
+                return;
+            }
+        };
+        match copy_to_clipboard(&text) {
+            Ok(()) => {
+                let mut confirmation = StyledString::default();
+                confirmation.append_source("Copied the message from ");
+                confirmation.append_styled(&from, Effect::Bold.into());
+                self.confirm(siv, confirmation);
+            }
+            // Such as over SSH, where there's no clipboard to copy to.
+            Err(err) => {
+                warn!(self.log, "Failed to copy to the clipboard: {}", err);
+                siv.add_layer(Dialog::info(format!("Failed to copy the message: {}", err)));
+            }
+        }
+    }
+
+    fn take_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// The nick we are in the channel as, once the server has told us.
+    pub fn own_nick(&self) -> Option<&Nickname> {
+        self.own_nick.as_ref()
+    }
+
+    /// Finds the message with the id, if it is still kept.
+    pub fn message_by_id(&self, id: u64) -> Option<&ChatMessage> {
+        // Messages are kept in the order they were added, so they are sorted by id.
+        self.messages
+            .binary_search_by_key(&id, |message| message.id)
+            .ok()
+            .map(|index| &self.messages[index])
+    }
+
+    /// The name of the view that the message is rendered in.
+    fn message_view_name(id: u64) -> String {
+        format!("chat_message_{}", id)
+    }
+
+    /// Puts the messages of a previous session before any others, separated by a seam.
+    /// This has to be done before any messages are added, so that their ids stay in order.
+    fn import_history(&mut self, siv: &mut Cursive, mut history: Vec<ChatMessage>) {
+        let start = self.next_id;
+        for message in history.iter_mut() {
+            message.id = self.take_id();
+        }
+        self.imported_ids = start..self.next_id;
+        self.imported += history.len();
+        self.messages.splice(0..0, history);
+        self.trim_history();
+        self.rerender_messages(siv);
+    }
+
+    /// Logs the history to the channel's file from now on, if it is logged at all.
+    fn reopen_history(&mut self, channel: &str) {
+        let dir = match &self.log_dir {
+            Some(dir) => dir,
+            None => return,
+        };
+        match FileHistoryStore::open(dir, channel) {
+            Ok(history) => self.history = Some(Box::new(history)),
+            // Better to stop logging than to log to the wrong channel's file.
+            Err(err) => {
+                warn!(self.log, "Failed to open chat log in {:?}: {}", dir, err);
+                self.history = None;
+            }
+        }
+    }
+
+    /// The messages that are kept, oldest first, whether or not they are shown.
+    pub fn history(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    /// Drops the oldest messages past `max_history`.
+    fn trim_history(&mut self) {
+        let max = if let Some(max) = self.max_history {
+            max
+        } else {
+            return;
+        };
+        if self.messages.len() > max {
+            let excess = self.messages.len() - max;
+            self.messages.drain(..excess);
+            self.imported = self.imported.saturating_sub(excess);
+        }
+    }
+
+    /// Rebuilds the chat area from the retained messages, such as after the filter changes.
+    fn rerender_messages(&mut self, siv: &mut Cursive) {
+        self.shown_trips = self
+            .messages
+            .iter()
+            .any(|message| message.trip.is_some() && self.is_shown(message));
+
+        if let Some(mut chat_area) = siv.find_name::<LinearLayout>(CHAT_AREA_NAME) {
+            chat_area.clear();
+        } else {
+            return;
+        }
+
+        self.time_views.clear();
+
+        // Taken so that we can render while iterating.
+        let messages = std::mem::take(&mut self.messages);
+        for (i, message) in messages.iter().enumerate() {
+            if i == self.imported && i != 0 {
+                self.add_seam(siv, "previous session");
+            }
+            if self.is_shown(message) {
+                self.render_message(siv, message);
+            }
+        }
+        if self.imported != 0 && self.imported == messages.len() {
+            self.add_seam(siv, "previous session");
+        }
+        self.messages = messages;
+    }
+
     fn add_message(
         &mut self,
         siv: &mut Cursive,
         user: Escaped<StyledString>,
         text: Escaped<StyledString>,
+        time: SystemTime,
+        id: Option<u64>,
     ) -> bool {
         if let Some(mut chat_area) = siv.find_name::<LinearLayout>(CHAT_AREA_NAME) {
-            let user = escapes::create_text_view(user);
-            let text = escapes::create_text_view(text);
-            let message_box = LinearLayout::horizontal().child(user).child(text);
-            chat_area.add_child(message_box);
+            let mut message_box = LinearLayout::horizontal();
+            // What is left for the sender and text, once the time prefixes have been put before.
+            let mut width = self.chat_width;
+            if self.relative_time {
+                let prefix = relative_time_prefix(time, SystemTime::now());
+                width = width.map(|width| width.saturating_sub(styled::str_width(&prefix)));
+                let content = TextContent::new(prefix);
+                message_box.add_child(TextView::new_with_content(content.clone()));
+                self.time_views.push((time, content));
+            }
+            if let Some(format) = &self.timestamp_format {
+                let stamp = timestamp_prefix(time, format);
+                width = width.map(|width| width.saturating_sub(styled::str_width(&stamp)));
+                message_box.add_child(TextView::new(StyledString::single_span(
+                    stamp,
+                    self.theme.trip,
+                )));
+            }
+            // Multiline messages such as captchas rely on their alignment, so we don't wrap them.
+            let message_box = if text.is_preformatted() {
+                message_box
+                    .child(escapes::create_text_view(user))
+                    .child(escapes::create_preformatted_view(text))
+            } else {
+                // Wrapped here rather than by the view, so that the lines after the first are
+                // indented to line up beneath the text rather than the sender. Until the width is
+                // known, the view wraps it as best it can.
+                let width = width.unwrap_or(usize::MAX);
+                let block = user.map(|user| text.inner().wrap_hanging(&user, width));
+                message_box.child(escapes::create_text_view(block))
+            };
+            // Named so that the view can be found from the message's id.
+            match id {
+                Some(id) => chat_area.add_child(message_box.with_name(Self::message_view_name(id))),
+                None => chat_area.add_child(message_box),
+            }
+            drop(chat_area);
+            self.trim_chat_views(siv);
             true
         } else {
             warn!(
@@ -222,280 +1361,983 @@ impl<'a> ChatDisplay<'a> {
         }
     }
 
-    fn display_dialog<T>(&self, siv: &mut Cursive, text: Escaped<T>)
-    where
-        T: Into<StyledString>,
-    {
-        siv.add_layer(escapes::create_info_dialog(text))
+    /// Scrolls to the hit of the search being looked at, telling the user which it is.
+    fn show_search_hit(&mut self, siv: &mut Cursive) {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return,
+        };
+        let status = match search.current() {
+            Some(id) if self.scroll_to_message(siv, id) => format!(
+                "Match {} of {} for '{}', n/N for older/newer, Esc to stop",
+                search.position(),
+                search.hits().len(),
+                search.query()
+            ),
+            // Such as if it was trimmed from the chat area.
+            Some(_) => format!(
+                "Match {} of {} for '{}' can't be scrolled to",
+                search.position(),
+                search.hits().len(),
+                search.query()
+            ),
+            None => format!("No messages match '{}'", search.query()),
+        };
+        let status = self.escapes.apply(status).into_inner();
+        let mut hints = self.hints.borrow_mut();
+        hints.search = Some(status);
+        hints.show(siv);
     }
-}
 
-#[derive(Debug, Clone)]
-enum ErrorMode {
-    None,
-    Reconnect,
-    Exit,
-}
+    /// Clears the search, going back to the newest messages.
+    fn stop_search(&mut self, siv: &mut Cursive) {
+        if self.search.take().is_none() {
+            return;
+        }
+        self.rerender_messages(siv);
+        siv.call_on_name(CHAT_SCROLL_NAME, |scroll: &mut ChatScrollView| {
+            scroll.set_scroll_strategy(ScrollStrategy::StickToBottom)
+        });
+        let mut hints = self.hints.borrow_mut();
+        hints.search = None;
+        hints.show(siv);
+    }
 
-fn main() {
-    let log = {
-        // Set up logging.
-        let file = sloggers::file::FileLoggerBuilder::new("./log.txt")
-            .build()
-            .expect("Failed to start logging system.");
+    /// The channel, our nick, and how the connection is doing, for the status bar.
+    pub fn describe_status(&self) -> StyledString {
+        let bold: Style = Effect::Bold.into();
+        let mut text = StyledString::default();
+        if let Some(channel) = &self.channel {
+            text.append_styled(&format!("?{}", channel), bold);
+            text.append_source("  ");
+        }
+        if let Some(nick) = &self.own_nick {
+            text.append_source(nick);
+            text.append_source("  ");
+        }
+        text.append_styled(self.status.describe(), self.status.style());
+        // Only its first line, as the bar is a single line.
+        if let Some(line) = self.topic.as_deref().and_then(|topic| topic.lines().next()) {
+            text.append_source("  ");
+            text.append_source(line);
+        }
+        text
+    }
 
-        let logger = slog::Logger::root(file, slog::o!());
-        info!(logger, "Started logging.");
-        logger
-    };
+    /// The count of users in the channel, followed by each of them with their badge and trip.
+    fn describe_users(&self) -> StyledString {
+        let mut text = StyledString::default();
+        let count = match self.roster.len() {
+            1 => "1 user".to_owned(),
+            count => format!("{} users", count),
+        };
+        text.append_styled(&count, Effect::Bold.into());
+        for user in &self.roster {
+            text.append_source("\n");
+            if let Some(level) = self.levels.get(&user.nick) {
+                append_themed(&mut text, level.badge(), self.theme.badge);
+            }
+            let trip = user.trip.as_ref().map(|trip| trip.0.as_str());
+            // Colored as their messages are.
+            let nick_style = if self.nick_colors {
+                let color = ColorStyle::front(nick_color(&user.nick, trip));
+                Style::merge(&[self.theme.nick, color.into()])
+            } else {
+                self.theme.nick
+            };
+            append_themed(&mut text, &user.nick, nick_style);
+            if let Some(trip) = trip {
+                text.append_source(" ");
+                append_themed(&mut text, trip, self.theme.trip);
+            }
+        }
+        text
+    }
 
-    let matches = clap::App::new("Fiskar")
-        .version("0.2")
-        .author("MinusGix")
-        .about("Hack.chat websocket client for the terminal")
-        .arg(clap::Arg::with_name("username").short("u").long("username").value_name("NICK").help("Sets the username that you will join with").takes_value(true))
-        .arg(clap::Arg::with_name("password").short("p").long("password").value_name("PASS").help("Sets the password that you will join with. Note that this may appear in your shell history!").takes_value(true))
-        .arg(clap::Arg::with_name("channel").short("c").long("channel").value_name("CHANNEL").help("Sets the channel that you wish to join.")).get_matches();
+    fn update_users_panel(&self, siv: &mut Cursive) {
+        let text = self.escapes.apply(self.describe_users()).into_inner();
+        siv.call_on_name(USERS_LIST_NAME, |view: &mut TextView| {
+            view.set_content(text)
+        });
+    }
 
-    let nickname = matches.value_of("username");
-    let password = matches.value_of("password");
-    let channel = matches.value_of("channel").unwrap_or("programming");
+    /// Shows or hides the users panel, fitting the messages to the room it leaves.
+    fn toggle_users(&mut self, siv: &mut Cursive) {
+        self.show_users = !self.show_users;
+        let show_users = self.show_users;
+        siv.call_on_name(USERS_PANEL_NAME, |view: &mut UsersPanel| {
+            view.set_visible(show_users)
+        });
+        self.update_gutter(siv);
+    }
+
+    fn update_status_bar(&self, siv: &mut Cursive) {
+        let text = self.escapes.apply(self.describe_status()).into_inner();
+        siv.call_on_name(STATUS_BAR_NAME, |view: &mut TextView| {
+            view.set_content(text)
+        });
+    }
+
+    /// Removes every message from the chat area and forgets them, back to a fresh screen that
+    /// sticks to the newest messages.
+    fn clear_chat(&mut self, siv: &mut Cursive) {
+        self.messages.clear();
+        self.imported = 0;
+        self.time_views.clear();
+        // Its hits were among what was cleared.
+        self.search = None;
+        self.shown_trips = false;
+        if let Some(mut chat_area) = siv.find_name::<LinearLayout>(CHAT_AREA_NAME) {
+            chat_area.clear();
+        }
+        siv.call_on_name(CHAT_SCROLL_NAME, |scroll: &mut ChatScrollView| {
+            scroll.set_scroll_strategy(ScrollStrategy::StickToBottom);
+            scroll.scroll_to_bottom();
+        });
+        let mut hints = self.hints.borrow_mut();
+        hints.search = None;
+        hints.show(siv);
+    }
+
+    /// Scrolls the chat so that the message is at the top, if its view is in the chat area.
+    fn scroll_to_message(&self, siv: &mut Cursive, id: u64) -> bool {
+        let width = match siv.call_on_name(CHAT_SCROLL_NAME, |scroll: &mut ChatScrollView| {
+            scroll.content_viewport().width()
+        }) {
+            // Not laid out yet, so there's no telling where it is.
+            Some(0) | None => return false,
+            Some(width) => width,
+        };
+        let top = if let Some(mut chat_area) = siv.find_name::<LinearLayout>(CHAT_AREA_NAME) {
+            let index = match chat_area.find_child_from_name(&Self::message_view_name(id)) {
+                Some(index) => index,
+                None => return false,
+            };
+            // The views above it, laid out as they are on screen.
+            (0..index)
+                .filter_map(|i| chat_area.get_child_mut(i))
+                .map(|child| child.required_size(Vec2::new(width, usize::MAX)).y)
+                .sum()
+        } else {
+            return false;
+        };
+        siv.call_on_name(CHAT_SCROLL_NAME, |scroll: &mut ChatScrollView| {
+            // Otherwise the next message would take it back to the bottom.
+            scroll.set_scroll_strategy(ScrollStrategy::KeepRow);
+            scroll.set_offset(Vec2::new(0, top));
+        })
+        .is_some()
+    }
+
+    /// Removes the oldest views in the chat area past `max_chat_views`, keeping the messages
+    /// that were being looked at in place if the chat is scrolled up.
+    fn trim_chat_views(&mut self, siv: &mut Cursive) {
+        let max = if let Some(max) = self.max_chat_views {
+            max
+        } else {
+            return;
+        };
+
+        // The width that the removed views were laid out in, if the chat is scrolled up. At the
+        // bottom it sticks there anyway.
+        let scrolled_width = siv
+            .call_on_name(CHAT_SCROLL_NAME, |scroll: &mut ChatScrollView| {
+                if scroll.is_at_bottom() {
+                    None
+                } else {
+                    Some(scroll.content_viewport().width())
+                }
+            })
+            .flatten();
+
+        let mut removed_height = 0;
+        if let Some(mut chat_area) = siv.find_name::<LinearLayout>(CHAT_AREA_NAME) {
+            while chat_area.len() > max {
+                let child = chat_area.remove_child(0);
+                if let (Some(mut child), Some(width)) = (child, scrolled_width) {
+                    removed_height += child.required_size(Vec2::new(width, usize::MAX)).y;
+                }
+            }
+        }
+        // Time prefixes are only on some of the views, so there can't be more of them.
+        if self.time_views.len() > max {
+            let excess = self.time_views.len() - max;
+            self.time_views.drain(..excess);
+        }
+
+        if removed_height != 0 {
+            siv.call_on_name(CHAT_SCROLL_NAME, |scroll: &mut ChatScrollView| {
+                let offset = scroll.content_viewport().top_left();
+                scroll.set_offset(offset.map_y(|y| y.saturating_sub(removed_height)));
+            });
+        }
+    }
+
+    fn input_submitter(&self) -> InputSubmitter {
+        // Clone the sender, which gives us access to the same place, and allows us
+        // to take ownership of it to send actions.
+        InputSubmitter {
+            sender: self.sender.clone(),
+            display_sender: self.display_sender.clone(),
+            log: self.log.clone(),
+            send_blank: self.send_blank,
+            debounce: Rc::new(RefCell::new(SendDebounce::new(self.send_debounce))),
+            emoji: self.emoji.clone(),
+            hints: self.hints.clone(),
+        }
+    }
+
+    fn create_chat(&mut self, siv: &mut Cursive) {
+        let submitter = self.input_submitter();
+        // Create the text input area, which sends or adds a newline depending on the submit key.
+        let mut text_area = OnEventView::new(TextArea::new().with_name(TEXT_AREA_NAME));
+        for event in SubmitKey::EVENTS.iter() {
+            match self.submit_key.action(event) {
+                Some(InputKeyAction::Submit) => {
+                    let submitter = submitter.clone();
+                    text_area.set_on_pre_event(event.clone(), move |siv| submitter.submit(siv));
+                }
+                // The text area already inserts a newline on a plain enter.
+                Some(InputKeyAction::Newline) if *event != Event::Key(Key::Enter) => {
+                    text_area.set_on_pre_event_inner(event.clone(), |view, _| {
+                        Some(view.on_event(Event::Key(Key::Enter)))
+                    });
+                }
+                _ => {}
+            }
+        }
+        if let Some(event) = self.keys.send.clone() {
+            let submitter = submitter.clone();
+            text_area.set_on_pre_event(event, move |siv| submitter.submit(siv));
+        }
+        // Completes slash commands, showing how the completed one is used, or else the nick
+        // being written.
+        let completer = Rc::new(RefCell::new(CommandCompleter::default()));
+        let nick_completer = Rc::new(RefCell::new(NickCompleter::default()));
+        let users = self.users.clone();
+        let hints = self.hints.clone();
+        text_area.set_on_pre_event_inner(Event::Key(Key::Tab), move |view, _| {
+            let mut text_area = view.get_mut();
+            if let Some((completed, usage)) =
+                completer.borrow_mut().complete(text_area.get_content())
+            {
+                text_area.set_content(completed.as_str());
+                text_area.set_cursor(completed.len());
+                let hints = hints.clone();
+                return Some(EventResult::with_cb(move |siv| {
+                    let mut hints = hints.borrow_mut();
+                    hints.usage = Some(usage);
+                    hints.show(siv);
+                }));
+            }
+            // Tab is left to move focus when there's nothing to complete.
+            let (completed, cursor) = nick_completer.borrow_mut().complete(
+                text_area.get_content(),
+                text_area.cursor(),
+                &users.borrow(),
+            )?;
+            text_area.set_content(completed);
+            text_area.set_cursor(cursor);
+            Some(EventResult::Consumed(None))
+        });
+        let text_area = self.input_sizing.apply(text_area);
+        // Sits beside the text area, and is updated as the user types.
+        let input_count = TextView::new("").with_name(INPUT_COUNT_NAME);
+        let input_area = LinearLayout::horizontal()
+            .child(text_area)
+            .child(input_count);
+        // Create the area where chat messages are stored.
+        let chat_area = LinearLayout::vertical()
+            .with_name(CHAT_AREA_NAME)
+            .scrollable()
+            .scroll_strategy(ScrollStrategy::StickToBottom)
+            .with_name(CHAT_SCROLL_NAME);
+        // Cycles through the hits of a search, once it has moved the focus to the chat.
+        let search_action = |action: fn() -> DisplayAction| {
+            let display_sender = self.display_sender.clone();
+            let log = self.log.clone();
+            move |_: &mut Cursive| {
+                display_sender
+                    .send(action())
+                    .expect_or_log(&log, "Failed to send search action");
+            }
+        };
+        let stop_search = search_action(|| DisplayAction::Search(String::new()));
+        let chat_area = OnEventView::new(chat_area)
+            .on_event('n', search_action(|| DisplayAction::CycleSearch(true)))
+            .on_event('N', search_action(|| DisplayAction::CycleSearch(false)))
+            .on_event(Event::Key(Key::Esc), move |siv| {
+                stop_search(siv);
+                // Back to writing.
+                let _ = siv.focus_name(TEXT_AREA_NAME);
+            });
+        // Beside the chat area, listing who is in the channel.
+        let users = self.escapes.apply(self.describe_users()).into_inner();
+        let users_panel: UsersPanel = HideableView::new(
+            Panel::new(TextView::new(users).with_name(USERS_LIST_NAME).scrollable())
+                .title("Users")
+                .fixed_width(USERS_PANEL_WIDTH),
+        )
+        .visible(self.show_users);
+        let chat_row = LinearLayout::horizontal()
+            .child(chat_area.full_width())
+            .child(users_panel.with_name(USERS_PANEL_NAME));
+        // Displays messages (chat area) above the user input (text area), beneath the status bar
+        // and the tabs. The tabs are left empty until there's more than one.
+        let status = self.escapes.apply(self.describe_status()).into_inner();
+        let mut layout = LinearLayout::vertical()
+            .child(TextView::new(status).with_name(STATUS_BAR_NAME))
+            .child(TextView::new("").with_name(TAB_BAR_NAME))
+            .child(chat_row);
+        if self.debug_pane {
+            let debug_pane = LinearLayout::vertical()
+                .with_name(DEBUG_PANE_NAME)
+                .scrollable()
+                .scroll_strategy(ScrollStrategy::StickToBottom)
+                .max_height(10);
+            layout.add_child(Panel::new(debug_pane).title("Frames"));
+        }
+        layout.add_child(input_area);
+        layout.add_child(TextView::new("").with_name(INPUT_HINT_NAME));
+        // Create the dialog that is displayed.
+        let dialog = Dialog::around(layout)
+            // Handle the send button.
+            .button("Send", move |siv| submitter.submit(siv));
+        // Searching and clearing are bound here rather than globally, so that they act on the tab
+        // they're in.
+        let display_sender = self.display_sender.clone();
+        let log = self.log.clone();
+        let clear = move |_: &mut Cursive| {
+            display_sender
+                .send(DisplayAction::ClearChat)
+                .expect_or_log(&log, "Failed to send clear action");
+        };
+        let display_sender = self.display_sender.clone();
+        let log = self.log.clone();
+        let toggle_users = move |_: &mut Cursive| {
+            display_sender
+                .send(DisplayAction::ToggleUsers)
+                .expect_or_log(&log, "Failed to send toggle users action");
+        };
+        let display_sender = self.display_sender.clone();
+        let log = self.log.clone();
+        let copy = move |_: &mut Cursive| {
+            display_sender
+                .send(DisplayAction::CopyMessage(1))
+                .expect_or_log(&log, "Failed to send copy action");
+        };
+        let display_sender = self.display_sender.clone();
+        let log = self.log.clone();
+        let dialog = OnEventView::new(dialog)
+            .on_event(self.keys.clear.clone(), clear)
+            .on_event(self.keys.users.clone(), toggle_users)
+            .on_event(self.keys.copy.clone(), copy);
+        let dialog = dialog.on_event(self.keys.search.clone(), move |siv| {
+            let display_sender = display_sender.clone();
+            let log = log.clone();
+            let search = EditView::new().on_submit(move |siv, query| {
+                siv.pop_layer();
+                display_sender
+                    .send(DisplayAction::Search(query.to_owned()))
+                    .expect_or_log(&log, "Failed to send search action");
+                // So that n/N go to the chat, rather than being typed.
+                let _ = siv.focus_name(CHAT_SCROLL_NAME);
+            });
+            siv.add_layer(
+                Dialog::around(search.fixed_width(40))
+                    .title("Search the chat")
+                    .dismiss_button("Cancel"),
+            );
+        });
+        // Create a resized view that puts this at full screen since its the main
+        // thing we're displaying.
+        let resized_view = ResizedView::with_full_screen(dialog);
+        siv.add_layer(resized_view);
+    }
+
+    /// Updates the relative times of messages, every so often. Returns whether any changed.
+    /// As the chat sticks to the bottom, only the newest messages, as many as could fit on the
+    /// screen, are updated.
+    pub fn update_relative_times(&mut self, siv: &mut Cursive) -> bool {
+        const UPDATE_INTERVAL: Duration = Duration::from_secs(15);
+        if !self.relative_time || self.times_updated_at.elapsed() < UPDATE_INTERVAL {
+            return false;
+        }
+        self.times_updated_at = Instant::now();
+
+        let now = SystemTime::now();
+        let rows = siv.screen_size().y;
+        let mut changed = false;
+        for (time, content) in self.time_views.iter().rev().take(rows) {
+            let prefix = relative_time_prefix(*time, now);
+            if content.get_content().source() != prefix {
+                content.set_content(prefix);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Shows a line telling the user that their command was done.
+    fn confirm(&mut self, siv: &mut Cursive, text: StyledString) -> bool {
+        let user = self.format_sender(MessageName::Server, None);
+        let user = self.escapes.apply(user);
+        let text = self.escapes.apply(text);
+        self.add_message(siv, user, text, SystemTime::now(), None)
+    }
+
+    /// Fits the gutter and the wrapping of messages to the screen, rendering the messages again
+    /// if either changed. Returns whether they did.
+    pub fn update_gutter(&mut self, siv: &mut Cursive) -> bool {
+        let width = siv.screen_size().x;
+        let gutter = GutterSizes::fit(width, self.gutter_min);
+        let panel_width = if self.show_users {
+            USERS_PANEL_WIDTH
+        } else {
+            0
+        };
+        let chat_width = Some(width.saturating_sub(CHAT_AREA_MARGIN + panel_width));
+        if gutter == self.gutter && chat_width == self.chat_width {
+            return false;
+        }
+        self.gutter = gutter;
+        self.chat_width = chat_width;
+        self.rerender_messages(siv);
+        true
+    }
+
+    /// Mutes notifications for the duration from `now`, or unmutes them if it is zero. One too
+    /// long to be a time mutes them until they're unmuted.
+    fn set_dnd(&mut self, duration: Duration, now: Instant) {
+        self.dnd = if duration == Duration::from_secs(0) {
+            Mute::Off
+        } else {
+            now.checked_add(duration)
+                .map_or(Mute::UntilUnmuted, Mute::Until)
+        };
+    }
+
+    /// Whether a mention at `now` should notify us, which it doesn't while muted. The mute is
+    /// cleared once it has run out.
+    fn should_notify(&mut self, now: Instant) -> bool {
+        match self.dnd {
+            Mute::Until(until) if now < until => false,
+            Mute::Until(_) => {
+                self.dnd = Mute::Off;
+                self.notifications_enabled
+            }
+            Mute::UntilUnmuted => false,
+            Mute::Off => self.notifications_enabled,
+        }
+    }
+
+    /// Whether to notify of a mention at `now`, as [`should_notify`](Self::should_notify), but
+    /// only once for a burst of them. A notification made is recorded for throttling the next.
+    fn take_notification(&mut self, now: Instant) -> bool {
+        let throttled = self.last_notified.map_or(false, |last| {
+            now.saturating_duration_since(last) < NOTIFY_THROTTLE
+        });
+        if throttled || !self.should_notify(now) {
+            return false;
+        }
+        self.last_notified = Some(now);
+        true
+    }
+
+    /// Counts down the cooldown beneath the input, clearing it once it is over. Returns whether
+    /// the countdown changed.
+    pub fn update_cooldown(&mut self, siv: &mut Cursive) -> bool {
+        let until = match self.cooldown_until {
+            Some(until) => until,
+            None => return false,
+        };
+        let remaining = until.saturating_duration_since(Instant::now());
+        // Rounded up, so that it only reaches zero once we can send again.
+        let secs = (remaining.as_millis() as u64 + 999) / 1000;
+        if self.cooldown_shown == Some(secs) {
+            return false;
+        }
+        let mut hints = self.hints.borrow_mut();
+        hints.cooldown = if secs == 0 {
+            self.cooldown_until = None;
+            self.cooldown_shown = None;
+            None
+        } else {
+            self.cooldown_shown = Some(secs);
+            Some(format!("Wait {}s before sending again", secs))
+        };
+        hints.show(siv);
+        true
+    }
+
+    /// Adds a line dividing the messages before it from those after, such as across a reconnect.
+    fn add_seam(&mut self, siv: &mut Cursive, label: &str) -> bool {
+        let text = StyledString::single_span(format!("── {} ──", label), self.theme.seam);
+        if let Some(mut chat_area) = siv.find_name::<LinearLayout>(CHAT_AREA_NAME) {
+            chat_area.add_child(TextView::new(text).h_align(HAlign::Center));
+            drop(chat_area);
+            self.trim_chat_views(siv);
+            true
+        } else {
+            warn!(self.log, "Failed to find chat area to add '{}' seam", label);
+            false
+        }
+    }
+
+    fn display_dialog<T>(&self, siv: &mut Cursive, text: Escaped<T>)
+    where
+        T: Into<StyledString>,
+    {
+        siv.add_layer(escapes::create_info_dialog(text))
+    }
+}
+
+/// Appends the text, only giving it a span if the theme styles it.
+fn append_themed(text: &mut StyledString, source: &str, style: Style) {
+    if style == Style::none() {
+        text.append_source(source);
+    } else {
+        text.append_styled(source, style);
+    }
+}
+
+/// Colors that are readable on both light and dark backgrounds, and far from the grey of trips.
+const NICK_COLORS: [Color; 12] = [
+    Color::Rgb(0xE0, 0x5A, 0x47),
+    Color::Rgb(0xE0, 0x8A, 0x2E),
+    Color::Rgb(0xC9, 0xA2, 0x27),
+    Color::Rgb(0x8A, 0xB5, 0x2E),
+    Color::Rgb(0x3C, 0xB3, 0x71),
+    Color::Rgb(0x2E, 0xB5, 0xA8),
+    Color::Rgb(0x30, 0x9F, 0xD6),
+    Color::Rgb(0x4F, 0x7C, 0xE8),
+    Color::Rgb(0x8A, 0x6C, 0xE8),
+    Color::Rgb(0xB8, 0x5C, 0xD6),
+    Color::Rgb(0xD9, 0x4F, 0x9E),
+    Color::Rgb(0xC7, 0x6E, 0x55),
+];
+
+/// A color for the user, which is the same every time they're seen, even across sessions.
+/// A user with a trip never shares a color with someone using their nick without one.
+pub fn nick_color(nick: &str, trip: Option<&str>) -> Color {
+    // FNV-1a, as the standard library's hasher isn't promised to be stable.
+    let hash = |text: &str| {
+        text.bytes().fold(0xCBF2_9CE4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+        })
+    };
+    let count = NICK_COLORS.len() as u64;
+    let base = hash(nick) % count;
+    let index = match trip {
+        Some(trip) => (base + 1 + hash(trip) % (count - 1)) % count,
+        None => base,
+    };
+    NICK_COLORS[index as usize]
+}
+
+/// Greys out the text, keeping its other styles such as bold.
+fn dim(text: StyledString) -> StyledString {
+    restyle(
+        text,
+        ColorStyle::front(Color::Light(BaseColor::Black)).into(),
+    )
+}
+
+/// Puts the style over the whole text, keeping the styles it already has where they don't clash.
+fn restyle(text: StyledString, style: Style) -> StyledString {
+    let mut restyled = StyledString::single_span(text.source(), style);
+    for span in text.spans() {
+        restyled.add_span_intersect(StyledIndexedSpan::new_range(
+            span.range.clone(),
+            span.attr.combine(style),
+        ));
+    }
+    restyled
+}
+
+/// The relative time shown before a message, padded so that the messages stay aligned.
+fn relative_time_prefix(time: SystemTime, now: SystemTime) -> String {
+    let elapsed = now.duration_since(time).unwrap_or_default();
+    format!("{:>7} ", client_manager::format_relative(elapsed))
+}
+
+/// The local time in the strftime-style format, padded to the widest that the format gets so that
+/// the messages stay aligned.
+fn timestamp_prefix(time: SystemTime, format: &str) -> String {
+    // A Wednesday in September, which have the longest names.
+    let widest = NaiveDate::from_ymd_opt(2000, 9, 27)
+        .and_then(|date| date.and_hms_opt(23, 59, 59))
+        .and_then(|widest| Local.from_local_datetime(&widest).single())
+        .map_or(0, |widest| {
+            styled::str_width(&widest.format(format).to_string())
+        });
+    let stamp = DateTime::<Local>::from(time).format(format).to_string();
+    let padding = widest.saturating_sub(styled::str_width(&stamp));
+    format!("{}{} ", stamp, " ".repeat(padding))
+}
+
+#[derive(Debug, Clone)]
+enum ErrorMode {
+    None,
+    Reconnect,
+    /// Stop without reconnecting, as the server would refuse us, telling the user why.
+    Fatal(String),
+    Exit,
+}
+
+fn main() {
+    let log = {
+        // Set up logging.
+        let file = sloggers::file::FileLoggerBuilder::new("./log.txt")
+            .build()
+            .expect("Failed to start logging system.");
+
+        let logger = slog::Logger::root(file, slog::o!());
+        info!(logger, "Started logging.");
+        logger
+    };
+
+    // Loaded first, as it supplies the defaults for the arguments.
+    let config = match config::default_path() {
+        Some(path) => match Config::load(&path) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("Invalid config file '{}': {}", path.display(), err);
+                std::process::exit(1);
+            }
+        },
+        None => Config::default(),
+    };
+
+    let matches = clap::App::new("Fiskar")
+        .version("0.2")
+        .author("MinusGix")
+        .about("Hack.chat websocket client for the terminal")
+        .arg(clap::Arg::with_name("username").short("u").long("username").value_name("NICK").help("Sets the username that you will join with").takes_value(true))
+        .arg(clap::Arg::with_name("send").long("send").value_name("TEXT").help("Sends the message and exits once the server has received it, without showing the chat").takes_value(true))
+        .arg(clap::Arg::with_name("password").short("p").long("password").value_name("PASS").help("Sets the password that you will join with. Note that this may appear in your shell history!").takes_value(true))
+        .arg(clap::Arg::with_name("server").short("s").long("server").value_name("URL").help("Sets the websocket address of the server, which defaults to hack.chat").takes_value(true))
+        .arg(clap::Arg::with_name("no-tls").long("no-tls").help("Allows connecting to a ws:// server address without TLS, such as a local server for testing. Everything sent to it can be read along the way"))
+        .arg(clap::Arg::with_name("api").long("api").value_name("API").help("Sets which version of the hack.chat protocol the server speaks, with legacy being for servers without sessions or user ids").possible_values(&["v2", "legacy"]).default_value("v2").takes_value(true))
+        .arg(clap::Arg::with_name("channel").short("c").long("channel").value_name("CHANNEL").help("Sets the channel that you wish to join. Multiple channels may be separated by commas.").takes_value(true))
+        .arg(clap::Arg::with_name("nick-suffix").long("nick-suffix").value_name("SUFFIX").help("Adds the suffix to the nick and joins again if it is taken, rather than asking for another").takes_value(true))
+        .arg(clap::Arg::with_name("seen-file").long("seen-file").value_name("PATH").help("Saves when users were last seen to the file, so that it is kept between runs").takes_value(true))
+        .arg(clap::Arg::with_name("collapse-trips").long("collapse-trips").help("Leaves out the trip column until someone with a trip sends a message"))
+        .arg(clap::Arg::with_name("theme").long("theme").value_name("NAME").help("Sets the theme, which can be changed later with /theme. More can be defined in the config file's [themes.<name>] tables").takes_value(true))
+        .arg(clap::Arg::with_name("combined-identity").long("combined-identity").help("Shows users as nick#trip, so that they can be told apart across nick changes"))
+        .arg(clap::Arg::with_name("max-input-length").long("max-input-length").value_name("CHARS").help("Turns the count beside the input red past this many characters, which defaults to 2048. 0 never does").takes_value(true))
+        .arg(clap::Arg::with_name("input-height").long("input-height").value_name("LINES").help("Keeps the input at a fixed height, scrolling rather than growing as more is written").takes_value(true))
+        .arg(clap::Arg::with_name("min-nick-width").long("min-nick-width").value_name("COLUMNS").help("Sets the width nicks are never cut below on narrow screens, which defaults to 8").takes_value(true))
+        .arg(clap::Arg::with_name("min-trip-width").long("min-trip-width").value_name("COLUMNS").help("Sets the width trips are never cut below on narrow screens, which defaults to 3").takes_value(true))
+        .arg(clap::Arg::with_name("input-min-height").long("input-min-height").value_name("LINES").conflicts_with("input-height").help("Sets the height the input starts at, which defaults to 2").takes_value(true))
+        .arg(clap::Arg::with_name("input-max-height").long("input-max-height").value_name("LINES").conflicts_with("input-height").help("Sets the height the input grows to before it scrolls, which defaults to 6").takes_value(true))
+        .arg(clap::Arg::with_name("submit-key").long("submit-key").value_name("KEY").help("Sets which key sends the message, with the other inserting a newline").possible_values(&["enter", "shift-enter"]).takes_value(true))
+        .arg(clap::Arg::with_name("greeting").long("greeting").value_name("TEXT").help("Sends a message after joining the channel").takes_value(true))
+        .arg(clap::Arg::with_name("greet-as-emote").long("greet-as-emote").requires("greeting").help("Sends the greeting as an emote, like /me"))
+        .arg(clap::Arg::with_name("relative-time").long("relative-time").help("Shows how long ago each message was received, such as '3m ago'"))
+        .arg(clap::Arg::with_name("timestamp-format").long("timestamp-format").value_name("FORMAT").help("Shows the time each message was sent at, in a strftime format such as '%H:%M'").takes_value(true))
+        .arg(clap::Arg::with_name("ansi").long("ansi").help("Shows ANSI colors and styles in messages, rather than their raw escape codes"))
+        .arg(clap::Arg::with_name("katex").long("katex").conflicts_with("ansi").help("Shows the math in messages, written between '$'s, as an approximation of how it would be rendered"))
+        .arg(clap::Arg::with_name("no-markdown").long("no-markdown").help("Shows the inline markdown in messages, such as *bold*, _italic_ and `code`, as it was written rather than styling it"))
+        .arg(clap::Arg::with_name("invisibles").long("invisibles").value_name("MODE").help("Sets what is done with characters that can't be seen, such as zero-width spaces and bidi overrides, which can break the layout or pass as another's nick. mark shows a placeholder in their place").possible_values(&["keep", "strip", "mark"]).default_value("keep").takes_value(true))
+        .arg(clap::Arg::with_name("raw-text").long("raw-text").help("Shows messages exactly as the server sent them, without escapes, styling, or links, for debugging"))
+        .arg(clap::Arg::with_name("dim-offline").long("dim-offline").help("Greys out the messages of users that have left the channel"))
+        .arg(clap::Arg::with_name("no-nick-colors").long("no-nick-colors").help("Shows nicks in the theme's color, rather than coloring each user's by their nick and trip"))
+        .arg(clap::Arg::with_name("highlight").long("highlight").value_name("WORD").help("Highlights messages containing the word, as well as those mentioning your nick. May be given more than once").takes_value(true).multiple(true).number_of_values(1))
+        .arg(clap::Arg::with_name("no-highlight").long("no-highlight").conflicts_with("highlight").help("Doesn't highlight messages that mention you"))
+        .arg(clap::Arg::with_name("bell").long("bell").help("Rings the terminal bell when someone mentions or whispers to you, at most once every few seconds, unless muted with /dnd"))
+        .arg(clap::Arg::with_name("max-chat-views").long("max-chat-views").value_name("COUNT").help("Sets how many messages are kept on screen, with older ones removed as more arrive").takes_value(true))
+        .arg(clap::Arg::with_name("max-history").long("max-history").value_name("COUNT").help("Sets how many messages are kept for searching and rendering again, which defaults to 10000").takes_value(true))
+        .arg(clap::Arg::with_name("log-dir").long("log-dir").value_name("DIR").help("Logs the messages of each channel to a file in the directory").takes_value(true))
+        .arg(clap::Arg::with_name("import-log").long("import-log").requires("log-dir").help("Shows the end of the channel's log from previous sessions when starting"))
+        .arg(clap::Arg::with_name("replay").long("replay").value_name("COUNT").requires("log-dir").help("Shows the last COUNT messages of the channel's log from previous sessions when starting, rather than the 100 of --import-log").takes_value(true))
+        .arg(clap::Arg::with_name("tee").long("tee").value_name("PATH").help("Mirrors the shown messages as plain text to a file, or to stdout if it is -").takes_value(true))
+        .arg(clap::Arg::with_name("send-debounce").long("send-debounce").value_name("MS").help("Drops a message that is the same as one sent less than this many milliseconds before, which defaults to 300").takes_value(true))
+        .arg(clap::Arg::with_name("topic-cmd").long("topic-cmd").value_name("CMD").help("Sets the command that the server sends the channel's topic as, for forks of hack.chat that differ, which defaults to 'topic'").takes_value(true))
+        .arg(clap::Arg::with_name("keepalive").long("keepalive").value_name("SECS").help("Pings the server once the connection has been quiet this long, reconnecting if it doesn't answer, which defaults to 60. 0 never pings").takes_value(true))
+        .arg(clap::Arg::with_name("send-backoff").long("send-backoff").value_name("MS").help("Sets how long to wait between sends once the connection can't keep up, which defaults to 500").takes_value(true))
+        .arg(clap::Arg::with_name("no-auto-reconnect").long("no-auto-reconnect").help("Asks before reconnecting after the connection is lost, rather than reconnecting by itself"))
+        .arg(clap::Arg::with_name("send-blank").long("send-blank").help("Sends messages even if they are empty or only whitespace"))
+        .arg(clap::Arg::with_name("debug-frames").long("debug-frames").help("Logs every websocket frame sent and received"))
+        .arg(clap::Arg::with_name("debug-frames-pane").long("debug-frames-pane").help("Logs every websocket frame, and shows them in a pane beneath the chat")).get_matches();
+
+    let nickname = matches
+        .value_of("username")
+        .or_else(|| config.username.as_deref());
+    let password = matches
+        .value_of("password")
+        .or_else(|| config.password.as_deref());
+    let seen_file = matches.value_of("seen-file").map(PathBuf::from);
+    let greeting = matches.value_of("greeting").map(|text| {
+        if matches.is_present("greet-as-emote") {
+            Greeting::Emote(text.to_owned())
+        } else {
+            Greeting::Chat(text.to_owned())
+        }
+    });
+    let debug_frames_pane = matches.is_present("debug-frames-pane");
+    let debug_frames = debug_frames_pane || matches.is_present("debug-frames");
+    let channels = matches
+        .value_of("channel")
+        .or_else(|| config.channel.as_deref())
+        .unwrap_or("programming");
+    // Each is joined in a tab of its own.
+    let channels = match input::parse_channels(channels) {
+        Ok(channels) => channels,
+        Err(err) => {
+            eprintln!("Invalid channel list '{}': {}", channels, err);
+            std::process::exit(1);
+        }
+    };
 
     let mut siv = Cursive::new();
 
-    let server_address = "wss://hack.chat/chat-ws";
+    let server_address = matches
+        .value_of("server")
+        .or_else(|| config.server.as_deref())
+        .unwrap_or("wss://hack.chat/chat-ws")
+        .to_owned();
+    if let Err(err) =
+        client_manager::check_server_address(&server_address, matches.is_present("no-tls"))
+    {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    let topic_cmd = matches
+        .value_of("topic-cmd")
+        .or_else(|| config.topic_cmd.as_deref())
+        .unwrap_or(Topic::DEFAULT_CMD)
+        .to_owned();
+    let server_api = match matches.value_of("api") {
+        Some("legacy") => ServerApi::HackChatLegacy,
+        _ => ServerApi::HackChatV2,
+    };
 
-    // (Client -> Display) action channel
-    let (display_sender, display_receiver): (Sender<DisplayAction>, Receiver<DisplayAction>) =
-        std::sync::mpsc::channel();
-    let (client_sender, client_receiver): (Sender<ClientAction>, Receiver<ClientAction>) =
-        std::sync::mpsc::channel();
-    info!(
-        log,
-        "Created channels to communicate actions between socket and main thread"
-    );
+    if let Some(text) = matches.value_of("send") {
+        let nick = match nickname {
+            Some(nick) => nick,
+            None => {
+                eprintln!("Sending requires a username, from --username or the config file");
+                std::process::exit(1);
+            }
+        };
+        // Nothing reads these, but they have to be kept open for the connection.
+        let (display_sender, _display_receiver) = std::sync::mpsc::channel();
+        let (_client_sender, client_receiver) = std::sync::mpsc::channel();
+        let result = Connection::connect(
+            display_sender,
+            client_receiver,
+            server_address,
+            server_api,
+            nick.to_owned(),
+            password.map(Password::from),
+            // Sent to the first channel, as the rest would each need a connection of their own.
+            channels[0].clone(),
+        )
+        .map_err(SendAndWaitError::from)
+        .and_then(|con| {
+            Client::new(con, ClientState::new(log.clone())).send_once(text.to_owned(), SEND_TIMEOUT)
+        });
+        if let Err(err) = result {
+            crit!(log, "Failed to send message: {}", err);
+            eprintln!("Failed to send message: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let escapes = Escapes::default();
+    let themes = match theme::load_themes(&config.themes) {
+        Ok(themes) => themes,
+        Err(err) => {
+            eprintln!("Invalid theme in the config file: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Some(name) = matches.value_of("theme") {
+        match theme::find_theme(&themes, name) {
+            Some(theme) => siv.set_theme(theme.cursive_theme()),
+            None => {
+                eprintln!("There is no theme '{}'", name);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let mut display = ChatDisplay::new(display_receiver, client_sender, escapes, log.clone());
+    let mut escapes = Escapes::default();
+    for (value, escape) in config.escapes.iter() {
+        escapes.add(value.clone(), escape.clone());
+    }
+    escapes.invisibles = match matches.value_of("invisibles") {
+        Some("strip") => Invisibles::Strip,
+        Some("mark") => Invisibles::Mark,
+        _ => Invisibles::Keep,
+    };
+    // A mistaken binding is only warned about, so that the chat can still be used to fix it.
+    let (keys, key_errors) = KeyBindings::from_table(&config.keys);
+    for err in &key_errors {
+        warn!(log, "Failed to bind a key from the config file: {}", err);
+    }
 
-    info!(log, "Created chat display structure");
+    let nick_suffix = matches.value_of("nick-suffix").map(str::to_owned);
+    let reconnect_policy = if matches.is_present("no-auto-reconnect") {
+        ReconnectPolicy::Manual
+    } else {
+        ReconnectPolicy::Automatic
+    };
+    let mut send_backoff = DEFAULT_SEND_BACKOFF;
+    if let Some(ms) = matches.value_of("send-backoff") {
+        match ms.parse() {
+            Ok(ms) => send_backoff = Duration::from_millis(ms),
+            Err(_) => {
+                eprintln!("Invalid send backoff '{}'", ms);
+                std::process::exit(1);
+            }
+        }
+    }
+    let mut keepalive = Some(DEFAULT_KEEPALIVE_INTERVAL);
+    if let Some(secs) = matches.value_of("keepalive") {
+        match secs.parse() {
+            Ok(0) => keepalive = None,
+            Ok(secs) => keepalive = Some(Duration::from_secs(secs)),
+            Err(_) => {
+                eprintln!("Invalid keepalive interval '{}'", secs);
+                std::process::exit(1);
+            }
+        }
+    }
+    let settings = ConnectionSettings {
+        server_address,
+        server_api,
+        password: password.map(Password::from),
+        greeting,
+        nick_suffix,
+        send_backoff,
+        reconnect_policy,
+        keepalive,
+        seen_file,
+        topic_cmd,
+        debug_frames,
+        debug_frames_pane,
+    };
+
+    let log_dir = matches.value_of("log-dir").map(PathBuf::from);
+    let import_log = match matches.value_of("replay") {
+        Some(count) => match count.parse() {
+            Ok(count) if count > 0 => Some(count),
+            _ => {
+                eprintln!("Invalid replay count '{}'", count);
+                std::process::exit(1);
+            }
+        },
+        None if matches.is_present("import-log") => Some(IMPORTED_MESSAGES),
+        None => None,
+    };
 
-    // This is bad code here.
-    // Explanation of the following several lines code:
-    // So, show_username_dialog originally took an `Fn`, but I didn't want to construct the log
-    // file, server address, channels, and ChatDisplay *after* it gets username, especially as there
-    // is only one ChatDisplay and socket.
-    // but I can't make the function an FnOnce, because there's no way of assuring it that it will
-    // only be called once by the code. I couldn't even find a way to make it do nothing if it was
-    // called again. This meant that I couldn't declare things before the dialog was done, and then
-    // move them into the callback as Rust did not know that the callback could/should only be
-    // called a single time.
-    // So, we come to here.
-    // `show_username_dialog` was changed to take an `FnMut`, this would let it modify the outside
-    // world, but I still needed to take ownership of values (I don't want to clone it!). So,
-    // I stuffed them in Options, so they have the option of not existing or not. They should always
-    // exist, and if they don't then that's a hard error as that means we were called more than
-    // once.
-
-    // TODO: verify that this clone isn't doing something wasteful.
-    let mut log_opt = Some(log.clone());
-    let mut display_sender = Some(display_sender);
-    let mut client_receiver = Some(client_receiver);
-    let mut server_address = Some(server_address);
-    let mut channel = Some(Channel::from(channel));
-    let mut password = password.map(Password::from);
-    let mut join_as_callback = move |nick: String| {
-        // TODO: make these expects log if failed
-        let log = log_opt.take().expect("Failed to take ownership of log.");
-        let display_sender = display_sender
-            .take()
-            .expect("Failed to take ownership of display sender");
-        let client_receiver = client_receiver
-            .take()
-            .expect("Failed to take ownership of display_receiver");
-        let server_address = server_address
-            .take()
-            .expect("Failed to take ownership of server address");
-        let channel = channel.take().expect("Failed to take ownership of channel");
-        // The password being None is perfectly fine.
-        let password = password.take();
-
-        // Start the thread that the socket is created upon.
-        std::thread::spawn(move || {
-            info!(log, "Created thread, Connecting socket");
-
-            let connection = Connection::connect(
+    // Channels opened later, such as with `/open`, are joined straight away as we already have a
+    // nick by then.
+    let open_tab: tabs::OpenTab<'static> = {
+        let matches = matches.clone();
+        let config = config.clone();
+        let escapes = escapes.clone();
+        let keys = keys.clone();
+        let themes = themes.clone();
+        let log_dir = log_dir.clone();
+        // Only the connections started with are given the last seen file.
+        let settings = ConnectionSettings {
+            seen_file: None,
+            ..settings.clone()
+        };
+        let log = log.clone();
+        Box::new(move |siv: &mut Cursive, channel: Channel, nick: Nickname| {
+            let (mut display, display_sender, client_receiver) = new_display(
+                &matches,
+                &config,
+                escapes.clone(),
+                keys.clone(),
+                themes.clone(),
+                &log,
+            );
+            if let Some(dir) = &log_dir {
+                if let Err(err) = open_chat_log(&mut display, siv, dir, import_log, &channel) {
+                    warn!(log, "Failed to open chat log in {:?}: {}", dir, err);
+                }
+            }
+            spawn_connection(
+                settings.clone(),
+                nick,
+                channel,
                 display_sender,
                 client_receiver,
-                server_address.to_owned(),
-                ServerApi::HackChatV2,
-                nick.clone(),
-                password,
-                channel,
-            )
-            .expect_or_log(&log, "Failed to connect to chat.");
+                log.clone(),
+            );
+            display
+        })
+    };
+    let mut tabs = Tabs::new(&mut siv, open_tab, &keys, log.clone());
 
-            info!(log, "Socket connected");
+    // The connections of the channels we start in, which are only started once we have a nick.
+    let mut connections = Vec::with_capacity(channels.len());
+    for (i, channel) in channels.into_iter().enumerate() {
+        let (mut display, display_sender, client_receiver) = new_display(
+            &matches,
+            &config,
+            escapes.clone(),
+            keys.clone(),
+            themes.clone(),
+            &log,
+        );
 
-            // Set up the chat
-            connection
-                .action_sender
-                .send(DisplayAction::CreateChat)
-                .expect_or_log(
-                    &log,
-                    "Failed to send action telling main thread to create chat.",
-                );
-
-            let mut cli = make_client(connection, log);
-
-            cli.con
-                .send_opening_commands()
-                .expect_or_log(&cli.log(), "Failed to send opening commands");
-
-            loop {
-                // Non-blocking read of json value.
-                let error_mode = match cli.con.read_json_message() {
-                    Ok(json) => {
-                        if let Some(json) = json {
-                            cli.handle_json(json).expect_or_log(
-                                cli.log(),
-                                "Failed to handle server-command's JSON properly.",
-                            );
-                        }
-                        ErrorMode::None
-                    }
-                    Err(ReadJsonMessageError::Socket(socket_err)) => match socket_err {
-                        // TODO: properly drop connection socket,
-                        // TODO: Do reconnect shenanigans as well.
-                        // TODO: we can inform user that these broke on most/all of these since ui
-                        // is probably still alive.
-                        // The connection was closed
-                        tungstenite::Error::ConnectionClosed => {
-                            crit!(cli.log(), "Socket connection closed");
-                            ErrorMode::Reconnect
-                        }
-                        // The connection was closed and we're trying to mess with it!
-                        tungstenite::Error::AlreadyClosed => {
-                            crit!(cli.log(), "Connection was closed yet we didn't stop!");
-                            ErrorMode::Reconnect
-                        }
-                        tungstenite::Error::Io(err) => {
-                            crit!(cli.log(), "Socket I/O Error: {}", err);
-                            ErrorMode::Reconnect
-                        }
-                        tungstenite::Error::Tls(err) => {
-                            crit!(cli.log(), "Socket TLS Error: {}", err);
-                            ErrorMode::Reconnect
-                        }
-                        // TODO: Alert user we received too large message and ignore it.
-                        // unsure as to what the parameter in it is. the message?
-                        tungstenite::Error::Capacity(err) => {
-                            crit!(cli.log(), "Received too large message on socket: '{}'", err);
-                            ErrorMode::None
-                        }
-                        // This may mean that we aren't connecting to socket
-                        // end point. Unsure as to what the parameter is.
-                        tungstenite::Error::Protocol(err) => {
-                            crit!(cli.log(), "Received socket protocol error!: '{}'", err);
-                            ErrorMode::Reconnect
-                        }
-                        // This would be impressive/worrying as the default is unlimited, but we
-                        // didn't run into OOM, since rust would combust if that happened.
-                        tungstenite::Error::SendQueueFull(err) => {
-                            crit!(cli.log(), "The socket send queue was full: '{}'", err);
-                            ErrorMode::None
-                        }
-                        // This is unfortunate, and I don't think this should happen?
-                        tungstenite::Error::Utf8 => {
-                            crit!(cli.log(), "Socket received invalid utf8");
-                            ErrorMode::None
-                        }
-                        tungstenite::Error::Url(err) => {
-                            // TODO: is this sensible?
-                            crit!(cli.log(), "Invalid socket url: '{}'", err);
-                            ErrorMode::Reconnect
-                        }
-                        tungstenite::Error::Http(status) => {
-                            // TODO: is this sensible?
-                            crit!(
-                                cli.log(),
-                                "Failed to connect, received status code: {}",
-                                status
-                            );
-                            ErrorMode::Reconnect
-                        }
-                        tungstenite::Error::HttpFormat(err) => {
-                            // TODO: is this sensible?
-                            crit!(cli.log(), "Socket http format error: {}", err);
-                            ErrorMode::Reconnect
-                        }
-                    },
-                    // TODO: display that we got invalid json, and then ignore it.
-                    Err(ReadJsonMessageError::Json(_)) => {
-                        crit!(cli.log(), "Received invalid json from server");
-                        ErrorMode::None
-                    }
-                };
-                // If we dced then do a while loop using sleep to make so we wait until timeout is
-                // done to try reconnecting?
-                match error_mode {
-                    ErrorMode::None => {}
-                    ErrorMode::Reconnect => {
-                        loop {
-                            // Sleep for a bit before reconnecting.
-                            cli.con
-                                .act(DisplayAction::AlertReconnecting)
-                                .expect_or_log(&cli.log(), "Failed to send reconnecting message");
-                            std::thread::sleep(cli.timeout);
-                            if let Err(_err) = cli.con.reconnect() {
-                                // Ignore and so we reloop and try reconnecting.
-                            } else {
-                                // Send the opening salvo
-                                cli.con
-                                    .send_opening_commands()
-                                    .expect_or_log(&cli.log(), "Failed to send opening salvo");
-                                // Break out of the loop since we have reconnected.
-                                break;
-                            }
-                        }
-                        // Skip past action processing after reconnect.
-                        continue;
-                    }
-                    ErrorMode::Exit => {
-                        cli.con.act(DisplayAction::Exit).expect_or_log(
-                            &cli.log(),
-                            "Failed to send exit action over channel to main thread",
-                        );
-                        // Break out of the loop so the socket thread ends.
-                        break;
-                    }
-                };
+        if let Some(dir) = &log_dir {
+            if let Err(err) = open_chat_log(&mut display, &mut siv, dir, import_log, &channel) {
+                eprintln!("Failed to open chat log in {:?}: {}", dir, err);
+                std::process::exit(1);
+            }
+        }
 
-                // Handle actions sent by Display, non-blocking.
-
-                let con = &mut cli.con;
-                let log = &mut cli.state.log;
-                let action_receiver = &mut con.action_receiver;
-                let socket = &mut con.socket;
-                for action in action_receiver.try_iter() {
-                    match action {
-                        ClientAction::SendChatMessage(text) => {
-                            let msg = client::Chat {
-                                channel: Some(con.channel.clone()),
-                                text,
-                            };
-                            // TODO: it'd be nice not to have to manually send whilst processing
-                            // actions
-                            // TODO: don't panic if we failed to send!
-                            socket
-                                .write_message(Message::Text(msg.into_json(con.server_api).dump()))
-                                .expect_or_log(log, "Failed to send chat message.")
-                        }
-                    };
+        // The channels would be interleaved in a single mirror, so only the first is mirrored.
+        if let (0, Some(path)) = (i, matches.value_of("tee")) {
+            match Tee::open(path) {
+                Ok(tee) => display.tee = Some(tee),
+                Err(err) => {
+                    eprintln!("Failed to open tee output '{}': {}", path, err);
+                    std::process::exit(1);
                 }
             }
-        });
+        }
+
+        tabs.add(&mut siv, channel.clone(), display);
+        connections.push((channel, display_sender, client_receiver));
+    }
+
+    info!(log, "Created chat display structure");
+
+    // `show_username_dialog` only ever calls this once, so it can take ownership of everything
+    // the socket threads need rather than cloning it.
+    let join_log = log.clone();
+    let join_as_callback = move |nick: String| {
+        let mut settings = settings;
+        for (channel, display_sender, client_receiver) in connections {
+            spawn_connection(
+                settings.clone(),
+                nick.clone(),
+                channel,
+                display_sender,
+                client_receiver,
+                join_log.clone(),
+            );
+            // Each connection would overwrite the others' last seen times.
+            settings.seen_file = None;
+        }
+    };
+
+    // Done before connecting, so that we don't join only to leave straight away.
+    let backend = match init_backend(cursive::backends::curses::n::Backend::init) {
+        Ok(backend) => backend,
+        Err(message) => {
+            crit!(log, "{}", message);
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
     };
 
     if let Some(nickname) = nickname {
@@ -504,38 +2346,841 @@ fn main() {
         let join_dialog = show_username_dialog(log.clone(), join_as_callback);
         siv.add_layer(join_dialog);
     }
+    if !key_errors.is_empty() {
+        let errors: Vec<String> = key_errors.iter().map(ToString::to_string).collect();
+        siv.add_layer(Dialog::info(format!(
+            "Some keys in the config file couldn't be bound, so their defaults are used:\n{}",
+            errors.join("\n")
+        )));
+    }
 
-    let backend = cursive::backends::curses::n::Backend::init().unwrap();
     let mut runner = siv.runner(backend);
 
     runner.refresh();
     // Set up view, drawing it to the screen.
 
     while runner.is_running() {
-        let ran_action = display.handle_actions(runner.deref_mut());
+        let ran_action = tabs.handle_actions(runner.deref_mut());
+        if tabs.has_quit(Instant::now()) {
+            runner.quit();
+        }
+        let received_events = runner.process_events();
+        // Only what is shown needs keeping up to date.
+        let display = tabs.active_display();
+        // Done after processing events so that it reflects what was just typed.
+        let updated_count = display.update_input_count(runner.deref_mut());
+        let updated_times = display.update_relative_times(runner.deref_mut());
+        let updated_cooldown = display.update_cooldown(runner.deref_mut());
+        // Done after processing events, as that is when a resize is noticed.
+        let updated_gutter = display.update_gutter(runner.deref_mut());
 
         // Passing in true to `post_events` will cause it to call refresh in a normal manner, so it
         // is essentially the same as calling refresh ourselves. This might also avoid two draws on
         // any update?
-        let received_something = runner.process_events() || ran_action;
+        let received_something = received_events
+            || ran_action
+            || updated_count
+            || updated_times
+            || updated_cooldown
+            || updated_gutter;
         runner.post_events(received_something);
     }
 }
 
-fn show_username_dialog<F>(log: slog::Logger, mut cb: F) -> Dialog
-where
-    F: FnMut(String) + 'static,
-{
-    const USERNAME_INPUT_NAME: &str = "joining-username-input";
-    let receive_username = move |siv: &mut Cursive, name: &str| {
-        if name.is_empty() {
-            siv.add_layer(Dialog::info("Please enter a username!"));
-        } else {
-            // Get rid of the input box.
-            // TODO: may be able to take the String from this so that we don't have to do another
+/// What every connection is made with, whichever channel it joins.
+#[derive(Clone)]
+struct ConnectionSettings {
+    server_address: String,
+    server_api: ServerApi,
+    password: Option<Password>,
+    greeting: Option<Greeting>,
+    nick_suffix: Option<String>,
+    send_backoff: Duration,
+    reconnect_policy: ReconnectPolicy,
+    /// How long the connection can be quiet before pinging the server, if ever.
+    keepalive: Option<Duration>,
+    /// Where the times users were last seen are loaded from, if anywhere.
+    seen_file: Option<PathBuf>,
+    /// The command name that the server sends the channel's topic as.
+    topic_cmd: String,
+    debug_frames: bool,
+    debug_frames_pane: bool,
+}
+
+/// A display configured by the arguments, and then the config, along with the other ends of its
+/// channels, which its connection is started with.
+fn new_display<'a>(
+    matches: &clap::ArgMatches,
+    config: &Config,
+    escapes: Escapes<'a>,
+    keys: KeyBindings,
+    themes: Vec<ChatTheme>,
+    log: &slog::Logger,
+) -> (
+    ChatDisplay<'a>,
+    Sender<DisplayAction>,
+    Receiver<ClientAction>,
+) {
+    // (Client -> Display) action channel
+    let (display_sender, display_receiver): (Sender<DisplayAction>, Receiver<DisplayAction>) =
+        std::sync::mpsc::channel();
+    let (client_sender, client_receiver): (Sender<ClientAction>, Receiver<ClientAction>) =
+        std::sync::mpsc::channel();
+    info!(
+        log,
+        "Created channels to communicate actions between socket and main thread"
+    );
+
+    let mut display = ChatDisplay::new(
+        display_receiver,
+        display_sender.clone(),
+        client_sender,
+        escapes,
+        log.clone(),
+    );
+    display.debug_pane = matches.is_present("debug-frames-pane");
+    display.send_blank = matches.is_present("send-blank");
+    if let Some(ms) = matches.value_of("send-debounce") {
+        match ms.parse() {
+            Ok(ms) => display.send_debounce = Duration::from_millis(ms),
+            Err(_) => {
+                eprintln!("Invalid send debounce '{}'", ms);
+                std::process::exit(1);
+            }
+        }
+    }
+    display.submit_key = matches
+        .value_of("submit-key")
+        .and_then(SubmitKey::parse)
+        .unwrap_or_default();
+    display.keys = keys;
+    display.combined_identity = matches.is_present("combined-identity");
+    display.collapse_trips = matches.is_present("collapse-trips");
+    // Checked once the arguments are read, along with setting the theme of the views.
+    let theme = matches
+        .value_of("theme")
+        .and_then(|name| theme::find_theme(&themes, name));
+    if let Some(theme) = theme {
+        display.theme = theme.clone();
+    }
+    display.themes = themes;
+    display.ansi = matches.is_present("ansi");
+    display.katex = matches.is_present("katex");
+    // The ANSI and math transforms take the place of it.
+    display.markdown = !matches.is_present("no-markdown");
+    display.raw_text = matches.is_present("raw-text");
+    display.dim_offline = matches.is_present("dim-offline");
+    display.nick_colors = !matches.is_present("no-nick-colors");
+    display.notifications_enabled = matches.is_present("bell");
+    display.highlight_mentions = !matches.is_present("no-highlight");
+    display.highlight_words = matches
+        .values_of("highlight")
+        .map(|words| words.map(str::to_owned).collect())
+        .unwrap_or_default();
+    display.relative_time = matches.is_present("relative-time");
+    if let Some(format) = matches.value_of("timestamp-format") {
+        // Formatting with an invalid specifier panics, so it's checked up front.
+        if StrftimeItems::new(format).any(|item| item == Item::Error) {
+            eprintln!("Invalid timestamp format '{}'", format);
+            std::process::exit(1);
+        }
+        display.timestamp_format = Some(format.to_owned());
+    }
+    if let Some(max) = matches.value_of("max-chat-views") {
+        match max.parse() {
+            Ok(max) if max > 0 => display.max_chat_views = Some(max),
+            _ => {
+                eprintln!("Invalid maximum chat views '{}'", max);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(max) = matches.value_of("max-history") {
+        match max.parse() {
+            Ok(max) if max > 0 => display.max_history = Some(max),
+            _ => {
+                eprintln!("Invalid maximum history '{}'", max);
+                std::process::exit(1);
+            }
+        }
+    }
+    match usize_setting(matches, "max-input-length", config.max_input_length) {
+        Some(0) => display.max_input_length = None,
+        Some(max) => display.max_input_length = Some(max),
+        None => {}
+    }
+    if let Some(nick) = usize_setting(matches, "min-nick-width", config.min_nick_width) {
+        display.gutter_min.nick = nick;
+    }
+    if let Some(trip) = usize_setting(matches, "min-trip-width", config.min_trip_width) {
+        display.gutter_min.trip = trip;
+    }
+    let sizing = InputSizing::from_heights(
+        usize_setting(matches, "input-height", config.input_height),
+        usize_setting(matches, "input-min-height", config.input_min_height),
+        usize_setting(matches, "input-max-height", config.input_max_height),
+    );
+    match sizing {
+        Ok(sizing) => display.input_sizing = sizing,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+    (display, display_sender, client_receiver)
+}
+
+/// The number given to the flag, or else the one in the config, exiting if the flag's isn't one.
+fn usize_setting(matches: &clap::ArgMatches, flag: &str, config: Option<usize>) -> Option<usize> {
+    let value = match matches.value_of(flag) {
+        Some(value) => value,
+        None => return config,
+    };
+    match value.parse() {
+        Ok(n) => Some(n),
+        Err(_) => {
+            eprintln!("Invalid --{} '{}'", flag, value);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Logs the display's messages to the channel's file in the directory, first showing as many
+/// messages from the end of it as are to be imported.
+fn open_chat_log(
+    display: &mut ChatDisplay,
+    siv: &mut Cursive,
+    dir: &Path,
+    import: Option<usize>,
+    channel: &str,
+) -> io::Result<()> {
+    let history = FileHistoryStore::open(dir, channel)?;
+    // Read before this session's messages are added to it.
+    if let Some(count) = import {
+        match history.recent(count) {
+            Ok(messages) => display.import_history(siv, messages),
+            Err(err) => warn!(
+                display.log,
+                "Failed to import chat log in {:?}: {}", dir, err
+            ),
+        }
+    }
+    display.history = Some(Box::new(history));
+    display.log_dir = Some(dir.to_owned());
+    Ok(())
+}
+
+/// Starts the thread that connects to the channel as the nick, and then talks to the server for
+/// the display.
+fn spawn_connection(
+    settings: ConnectionSettings,
+    nick: Nickname,
+    channel: Channel,
+    display_sender: Sender<DisplayAction>,
+    client_receiver: Receiver<ClientAction>,
+    log: slog::Logger,
+) {
+    let ConnectionSettings {
+        server_address,
+        server_api,
+        password,
+        greeting,
+        nick_suffix,
+        send_backoff,
+        reconnect_policy,
+        keepalive,
+        seen_file,
+        topic_cmd,
+        debug_frames,
+        debug_frames_pane,
+    } = settings;
+
+    // Start the thread that the socket is created upon.
+    std::thread::spawn(move || {
+        info!(log, "Created thread, Connecting socket");
+
+        // Kept so that we can still tell the user if connecting fails.
+        let error_sender = display_sender.clone();
+        let connection = Connection::connect(
+            display_sender,
+            client_receiver,
+            server_address,
+            server_api,
+            nick.clone(),
+            password,
+            channel,
+        );
+        let mut connection = match connection {
+            Ok(connection) => connection,
+            Err(err) => {
+                crit!(log, "Failed to connect to chat: {}", err);
+                let text = client_manager::connect_error_hint(&err)
+                    .unwrap_or_else(|| format!("Failed to connect to chat: {}", err));
+                error_sender
+                    .send(DisplayAction::DisplayDialog(text))
+                    .expect_or_log(&log, "Failed to send connection failure dialog");
+                error_sender
+                    .send(DisplayAction::SetStatus(ConnectionStatus::Disconnected))
+                    .expect_or_log(&log, "Failed to send status action");
+                return;
+            }
+        };
+
+        info!(log, "Socket connected");
+
+        // Only said once, as reconnecting keeps to the same address.
+        if client_manager::is_unencrypted(&connection.address) {
+            warn!(
+                log,
+                "Connected to {} WITHOUT TLS, everything sent can be read along the way",
+                connection.address
+            );
+            connection
+                .act(DisplayAction::AddChatMessage(ChatMessage {
+                    from: MessageName::ServerWarn,
+                    trip: None,
+                    text: format!(
+                        "The connection to {} is unencrypted, so anything sent, including your \
+                         password, can be read along the way",
+                        connection.address
+                    ),
+                    timestamp: SystemTime::now(),
+                    id: 0,
+                }))
+                .expect_or_log(&log, "Failed to send unencrypted connection notice");
+        }
+
+        connection.greeting = greeting;
+        connection.nick_suffix = nick_suffix;
+        connection.send_backoff = send_backoff;
+        connection.reconnect_policy = reconnect_policy;
+
+        if let Some(seen_file) = seen_file {
+            match LastSeen::load(Box::new(FileHistoryStore::seen_file(seen_file))) {
+                Ok(last_seen) => connection.last_seen = last_seen,
+                Err(err) => warn!(log, "Failed to load last seen file: {}", err),
+            }
+        }
+
+        if debug_frames {
+            let frame_log = log.clone();
+            let frame_sender = connection.action_sender.clone();
+            connection.frame_observer = Some(Box::new(move |record| {
+                info!(frame_log, "Frame {}", record);
+                if debug_frames_pane {
+                    // This is only for debugging, so failing to display it is not an issue.
+                    let _ = frame_sender.send(DisplayAction::DebugFrame(record.to_string()));
+                }
+            }));
+        }
+
+        // Set up the chat
+        connection
+            .action_sender
+            .send(DisplayAction::CreateChat)
+            .expect_or_log(
+                &log,
+                "Failed to send action telling main thread to create chat.",
+            );
+
+        let mut cli = make_client(connection, log);
+        cli.keepalive_interval = keepalive;
+        cli.topic_cmd = topic_cmd;
+
+        cli.con
+            .send_opening_commands()
+            .expect_or_log(&cli.log(), "Failed to send opening commands");
+        cli.con
+            .act(DisplayAction::SetStatus(ConnectionStatus::Connected(
+                cli.con.channel.clone(),
+            )))
+            .expect_or_log(&cli.log(), "Failed to send status action");
+
+        let mut quitting = false;
+        loop {
+            // Non-blocking read of json value.
+            let error_mode = match cli.con.read_json_message() {
+                Ok(json) => {
+                    if let Some(json) = json {
+                        cli.handle_json(json).expect_or_log(
+                            cli.log(),
+                            "Failed to handle server-command's JSON properly.",
+                        );
+                    }
+                    ErrorMode::None
+                }
+                Err(ReadJsonMessageError::Socket(socket_err)) => match socket_err {
+                    // TODO: properly drop connection socket,
+                    // TODO: Do reconnect shenanigans as well.
+                    // TODO: we can inform user that these broke on most/all of these since ui
+                    // is probably still alive.
+                    // The connection was closed
+                    tungstenite::Error::ConnectionClosed => {
+                        crit!(cli.log(), "Socket connection closed");
+                        // Fatal reasons are shown once we stop, the rest before reconnecting.
+                        let close = cli.con.close.take();
+                        if let Some(close) = close.filter(|close| !close.is_fatal()) {
+                            cli.con
+                                .act(DisplayAction::AddChatMessage(ChatMessage {
+                                    from: MessageName::Server,
+                                    trip: None,
+                                    text: close.to_string(),
+                                    timestamp: SystemTime::now(),
+                                    id: 0,
+                                }))
+                                .expect_or_log(cli.log(), "Failed to send close reason");
+                        }
+                        ErrorMode::Reconnect
+                    }
+                    // The connection was closed and we're trying to mess with it!
+                    tungstenite::Error::AlreadyClosed => {
+                        crit!(cli.log(), "Connection was closed yet we didn't stop!");
+                        ErrorMode::Reconnect
+                    }
+                    tungstenite::Error::Io(err) => {
+                        crit!(cli.log(), "Socket I/O Error: {}", err);
+                        ErrorMode::Reconnect
+                    }
+                    tungstenite::Error::Tls(err) => {
+                        crit!(cli.log(), "Socket TLS Error: {}", err);
+                        ErrorMode::Reconnect
+                    }
+                    // TODO: Alert user we received too large message and ignore it.
+                    // unsure as to what the parameter in it is. the message?
+                    tungstenite::Error::Capacity(err) => {
+                        crit!(cli.log(), "Received too large message on socket: '{}'", err);
+                        ErrorMode::None
+                    }
+                    // This may mean that we aren't connecting to socket
+                    // end point. Unsure as to what the parameter is.
+                    tungstenite::Error::Protocol(err) => {
+                        crit!(cli.log(), "Received socket protocol error!: '{}'", err);
+                        ErrorMode::Reconnect
+                    }
+                    // Replying to a ping found the send queue full, so we back off.
+                    tungstenite::Error::SendQueueFull(message) => {
+                        warn!(cli.log(), "The socket send queue was full: '{}'", message);
+                        cli.con.send_queue_full(message, Instant::now());
+                        ErrorMode::None
+                    }
+                    // This is unfortunate, and I don't think this should happen?
+                    tungstenite::Error::Utf8 => {
+                        crit!(cli.log(), "Socket received invalid utf8");
+                        ErrorMode::None
+                    }
+                    tungstenite::Error::Url(err) => {
+                        // TODO: is this sensible?
+                        crit!(cli.log(), "Invalid socket url: '{}'", err);
+                        ErrorMode::Reconnect
+                    }
+                    tungstenite::Error::Http(status) => {
+                        // TODO: is this sensible?
+                        crit!(
+                            cli.log(),
+                            "Failed to connect, received status code: {}",
+                            status
+                        );
+                        ErrorMode::Reconnect
+                    }
+                    tungstenite::Error::HttpFormat(err) => {
+                        // TODO: is this sensible?
+                        crit!(cli.log(), "Socket http format error: {}", err);
+                        ErrorMode::Reconnect
+                    }
+                },
+                // TODO: display that we got invalid json, and then ignore it.
+                Err(ReadJsonMessageError::Json(_)) => {
+                    crit!(cli.log(), "Received invalid json from server");
+                    ErrorMode::None
+                }
+            };
+            // A quiet connection can die without us hearing of it, which pinging finds out.
+            let error_mode = match error_mode {
+                ErrorMode::None => match cli.keep_alive(Instant::now()) {
+                    Ok(true) => ErrorMode::None,
+                    Ok(false) => {
+                        crit!(cli.log(), "The server didn't answer our ping");
+                        ErrorMode::Reconnect
+                    }
+                    // Reading will find out if the connection is gone.
+                    Err(err) => {
+                        warn!(cli.log(), "Failed to ping the server: {}", err);
+                        ErrorMode::None
+                    }
+                },
+                error_mode => error_mode,
+            };
+            // Quitting takes priority over anything else, and then being kicked or banned, as
+            // reconnecting would only be refused.
+            let error_mode = if quitting {
+                ErrorMode::Exit
+            } else if let Some(reason) = &cli.con.fatal {
+                ErrorMode::Fatal(reason.clone())
+            } else {
+                error_mode
+            };
+            // If we dced then do a while loop using sleep to make so we wait until timeout is
+            // done to try reconnecting?
+            match error_mode {
+                ErrorMode::None => {}
+                ErrorMode::Fatal(reason) => {
+                    crit!(
+                        cli.log(),
+                        "Not reconnecting, as the server refused us: {}",
+                        reason
+                    );
+                    cli.con
+                        .act(DisplayAction::DisplayDialog(format!(
+                            "Disconnected: {}\nNot reconnecting, as the server would refuse us.",
+                            reason
+                        )))
+                        .expect_or_log(&cli.log(), "Failed to send disconnection dialog");
+                    cli.con
+                        .act(DisplayAction::SetStatus(ConnectionStatus::Disconnected))
+                        .expect_or_log(&cli.log(), "Failed to send status action");
+                    // Break out of the loop so the socket thread ends, leaving the chat open
+                    // so that it can still be read.
+                    break;
+                }
+                ErrorMode::Reconnect => {
+                    cli.con
+                        .act(DisplayAction::SetStatus(ConnectionStatus::Disconnected))
+                        .expect_or_log(&cli.log(), "Failed to send status action");
+                    let reconnect_now = cli
+                        .con
+                        .lost_connection()
+                        .expect_or_log(cli.log(), "Failed to send reconnect prompt");
+                    // They quit, or the display is gone, so there's no one to reconnect for.
+                    if !reconnect_now && !cli.con.wait_for_reconnect() {
+                        // There's no connection left to close, so the display only has to know
+                        // that we've stopped, if it's still there.
+                        let _ = cli.con.act(DisplayAction::Exit);
+                        break;
+                    }
+                    loop {
+                        // Sleep for a bit before reconnecting, longer the more attempts fail.
+                        let wait = cli.next_backoff();
+                        cli.con
+                            .act(DisplayAction::AlertReconnecting(wait))
+                            .expect_or_log(&cli.log(), "Failed to send reconnecting message");
+                        cli.con
+                            .act(DisplayAction::SetStatus(ConnectionStatus::Reconnecting))
+                            .expect_or_log(&cli.log(), "Failed to send status action");
+                        std::thread::sleep(wait);
+                        if let Err(err) = cli.con.reconnect() {
+                            // It won't work however many times we try, which is reported
+                            // once we're back in the main loop.
+                            if cli.con.fatal.is_some() {
+                                break;
+                            }
+                            warn!(cli.log(), "Failed to reconnect: {}", err);
+                            // Ignore and so we reloop and try reconnecting.
+                        } else {
+                            // Send the opening salvo, or just resume if we were quick enough.
+                            cli.con
+                                .send_reopening_commands(Instant::now())
+                                .expect_or_log(&cli.log(), "Failed to send opening salvo");
+                            cli.reset_backoff();
+                            cli.con
+                                .act(DisplayAction::Reconnected)
+                                .expect_or_log(&cli.log(), "Failed to send reconnected action");
+                            cli.con
+                                .act(DisplayAction::SetStatus(ConnectionStatus::Connected(
+                                    cli.con.channel.clone(),
+                                )))
+                                .expect_or_log(&cli.log(), "Failed to send status action");
+                            // Break out of the loop since we have reconnected.
+                            break;
+                        }
+                    }
+                    // Skip past action processing after reconnect.
+                    continue;
+                }
+                ErrorMode::Exit => {
+                    if let Err(err) = cli.con.quit(QUIT_TIMEOUT) {
+                        warn!(cli.log(), "Failed to close the connection cleanly: {}", err);
+                    }
+                    cli.con.act(DisplayAction::Exit).expect_or_log(
+                        &cli.log(),
+                        "Failed to send exit action over channel to main thread",
+                    );
+                    // Break out of the loop so the socket thread ends.
+                    break;
+                }
+            };
+
+            // Handle actions sent by Display, non-blocking.
+
+            // They're collected first so the connection isn't borrowed while performing them.
+            let actions: Vec<ClientAction> = cli.con.action_receiver.try_iter().collect();
+            let con = &mut cli.con;
+            let log = &cli.state.log;
+            let ignored = &mut cli.state.ignored;
+            for action in actions {
+                match action {
+                    ClientAction::SendChatMessage(text) => {
+                        echo_chat(con, &text).expect_or_log(log, "Failed to send echo action.");
+                        con.enqueue_chat(text);
+                    }
+                    ClientAction::Rejoin(nick) => {
+                        con.joined_nick = nick;
+                        // TODO: don't panic if we failed to send!
+                        con.send_join()
+                            .expect_or_log(log, "Failed to send join with new nick.");
+                    }
+                    ClientAction::Invite(nick) => con.invite(nick),
+                    ClientAction::ChangeNick(nick) => {
+                        con.act(DisplayAction::SetOwnNick(nick.clone()))
+                            .expect_or_log(log, "Failed to send own nick action");
+                        con.change_nick(nick);
+                    }
+                    ClientAction::SendWhisper { nick, text } => con.whisper(nick, text),
+                    ClientAction::SendEmote(text) => con.emote(text),
+                    // The reconnect once the socket closes joins the new channel.
+                    ClientAction::SwitchChannel(channel) => {
+                        if let Err(err) = con.switch_channel(channel.clone()) {
+                            warn!(log, "Failed to leave for the new channel: {}", err);
+                        }
+                        // What's said from now on is in the new channel, so it's logged there.
+                        con.act(DisplayAction::ReopenHistory(channel))
+                            .expect_or_log(log, "Failed to send reopen history action");
+                    }
+                    // A connection is only ever in one channel, so the other is joined in a tab
+                    // with a connection of its own.
+                    ClientAction::OpenChannel(channel) => con
+                        .act(DisplayAction::OpenTab(channel))
+                        .expect_or_log(log, "Failed to send open tab action"),
+                    // Unlike switching, the connection is kept, and the chat is started afresh once
+                    // the server lets us in.
+                    ClientAction::JoinChannel(channel) => {
+                        if let Err(err) = con.join_channel(channel.clone()) {
+                            warn!(log, "Failed to join ?{}: {}", channel, err);
+                            con.reject_join();
+                        }
+                    }
+                    // Only meaningful while waiting to reconnect, which is handled there.
+                    ClientAction::Reconnect => {}
+                    // The connection is closed on the next time around.
+                    ClientAction::Quit => quitting = true,
+                    ClientAction::Whois(nick) => {
+                        let text = match con.users.find_online_nick(&nick) {
+                            Some((_, info)) => match Option::<Trip>::from(info.trip.clone()) {
+                                Some(trip) => {
+                                    format!("{} is online, with trip {}", nick, trip.0)
+                                }
+                                None => format!("{} is online, without a known trip", nick),
+                            },
+                            None => format!("{} is not in the channel", nick),
+                        };
+                        con.act(DisplayAction::AddChatMessage(ChatMessage {
+                            from: MessageName::Server,
+                            trip: None,
+                            text,
+                            timestamp: SystemTime::now(),
+                            id: 0,
+                        }))
+                        .expect_or_log(log, "Failed to send whois related action");
+                    }
+                    ClientAction::Seen(nick) => {
+                        let text = if con.users.find_online_nick(&nick).is_some() {
+                            format!("{} is online", nick)
+                        } else {
+                            con.last_seen.describe(&nick, SystemTime::now())
+                        };
+                        con.act(DisplayAction::AddChatMessage(ChatMessage {
+                            from: MessageName::Server,
+                            trip: None,
+                            text,
+                            timestamp: SystemTime::now(),
+                            id: 0,
+                        }))
+                        .expect_or_log(log, "Failed to send seen related action");
+                    }
+                    ClientAction::Ignore(ignore) => {
+                        let text = if ignored.insert(ignore.clone()) {
+                            format!("Ignoring {}", ignore)
+                        } else {
+                            format!("Already ignoring {}", ignore)
+                        };
+                        con.act(DisplayAction::AddChatMessage(ChatMessage {
+                            from: MessageName::Server,
+                            trip: None,
+                            text,
+                            timestamp: SystemTime::now(),
+                            id: 0,
+                        }))
+                        .expect_or_log(log, "Failed to send ignore related action");
+                    }
+                    ClientAction::Unignore(ignore) => {
+                        let text = if ignored.remove(&ignore) {
+                            format!("No longer ignoring {}", ignore)
+                        } else {
+                            format!("{} wasn't being ignored", ignore)
+                        };
+                        con.act(DisplayAction::AddChatMessage(ChatMessage {
+                            from: MessageName::Server,
+                            trip: None,
+                            text,
+                            timestamp: SystemTime::now(),
+                            id: 0,
+                        }))
+                        .expect_or_log(log, "Failed to send unignore related action");
+                    }
+                    ClientAction::ListIgnored => {
+                        let mut names: Vec<String> =
+                            ignored.iter().map(ToString::to_string).collect();
+                        names.sort_unstable();
+                        let text = if names.is_empty() {
+                            "Nobody is being ignored".to_owned()
+                        } else {
+                            format!("Ignoring: {}", names.join(", "))
+                        };
+                        con.act(DisplayAction::AddChatMessage(ChatMessage {
+                            from: MessageName::Server,
+                            trip: None,
+                            text,
+                            timestamp: SystemTime::now(),
+                            id: 0,
+                        }))
+                        .expect_or_log(log, "Failed to send ignored list action");
+                    }
+                };
+            }
+            // TODO: don't panic if we failed to send!
+            con.pump_outgoing()
+                .expect_or_log(log, "Failed to send queued commands.");
+            // A lost connection is noticed when reading, so this only needs to be logged.
+            if let Err(err) = con.flush() {
+                warn!(log, "Failed to flush pending writes: {}", err);
+            }
+        }
+    });
+}
+
+/// Shows our chat message as soon as it is sent, rather than once the server echoes it back,
+/// which it only does once the message has had its turn to be sent.
+fn echo_chat(con: &mut Connection, text: &Text) -> Result<(), SendError<DisplayAction>> {
+    con.record_echo(text.clone());
+    con.act(DisplayAction::AddChatMessage(ChatMessage {
+        from: MessageName::User(con.own_nick().to_owned()),
+        trip: con.own_trip(),
+        text: text.clone(),
+        timestamp: SystemTime::now(),
+        id: 0,
+    }))
+}
+
+fn ring_bell() -> io::Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(b"\x07")?;
+    stdout.flush()
+}
+
+/// Shows the notification on the desktop, which is only done when built with the
+/// `desktop-notifications` feature.
+#[cfg(feature = "desktop-notifications")]
+fn show_desktop_notification(title: &str, body: &str) -> Result<(), String> {
+    notify_rust::Notification::new()
+        .summary(title)
+        .body(body)
+        .show()
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "desktop-notifications"))]
+fn show_desktop_notification(_title: &str, _body: &str) -> Result<(), String> {
+    Ok(())
+}
+
+/// Puts the text on the system clipboard, which can only be done when built with the
+/// `clipboard` feature.
+#[cfg(feature = "clipboard")]
+fn copy_to_clipboard(text: &str) -> Result<(), String> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text.to_owned()))
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn copy_to_clipboard(_text: &str) -> Result<(), String> {
+    Err("Fiskar was built without the clipboard feature".to_owned())
+}
+
+/// Whether the text mentions the nick, as a word of its own such as `@bob` or `bob:`.
+fn mentions(text: &str, nick: &str) -> bool {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .any(|word| word.eq_ignore_ascii_case(nick))
+}
+
+/// The nick of whoever sent an emote, which the server puts at the start of its text as `@nick`.
+fn emote_nick(text: &str) -> Option<&str> {
+    text.strip_prefix('@')?.split_whitespace().next()
+}
+
+/// The trip of whoever is online with the nick, for messages that only carry the nick, such as
+/// emotes.
+fn online_trip(users: &Users, nick: &str) -> Option<Trip> {
+    let (_, info) = users.find_online_nick(nick)?;
+    info.trip.clone().into()
+}
+
+/// Wraps an `FnOnce` so that it can be called from places that may call it more than once, such as
+/// a submit callback. Only the first call does anything.
+struct OnceCallback<F> {
+    inner: Option<F>,
+}
+impl<F> OnceCallback<F> {
+    fn new(inner: F) -> Self {
+        Self { inner: Some(inner) }
+    }
+
+    /// Calls the inner function if it hasn't been already, returning whether it was.
+    fn call<A>(&mut self, arg: A) -> bool
+    where
+        F: FnOnce(A),
+    {
+        if let Some(inner) = self.inner.take() {
+            inner(arg);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Sets up the terminal with the backend, describing what is needed if it can't be.
+fn init_backend<F>(init: F) -> Result<Box<dyn Backend>, String>
+where
+    F: FnOnce() -> io::Result<Box<dyn Backend>>,
+{
+    init().map_err(|err| {
+        format!(
+            "Failed to set up the terminal: {}\n\
+            fiskar has to be run in an interactive terminal that curses supports, with TERM set.",
+            err
+        )
+    })
+}
+
+fn show_username_dialog<F>(log: slog::Logger, cb: F) -> Dialog
+where
+    F: FnOnce(String) + 'static,
+{
+    const USERNAME_INPUT_NAME: &str = "joining-username-input";
+    let mut cb = OnceCallback::new(cb);
+    let receive_username = move |siv: &mut Cursive, name: &str| {
+        if name.is_empty() {
+            siv.add_layer(Dialog::info("Please enter a username!"));
+        } else {
+            // Get rid of the input box.
+            // TODO: may be able to take the String from this so that we don't have to do another
             // heap allocation
             siv.pop_layer();
-            cb(name.to_owned());
+            // Submitting again, such as by a quick double press, is ignored.
+            if !cb.call(name.to_owned()) {
+                warn!(log, "Ignored a second username submission");
+            }
         }
     };
 
@@ -556,10 +3201,50 @@ where
     // })
 }
 
+/// Who is in the channel, shared between the connection's handlers, so that the users panel can
+/// be sent all of them whenever someone comes or goes.
+#[derive(Debug, Clone, Default)]
+struct Roster(Rc<RefCell<HashMap<Nickname, Option<Trip>>>>);
+impl Roster {
+    fn set(&self, users: impl Iterator<Item = (Nickname, Option<Trip>)>) {
+        *self.0.borrow_mut() = users.collect();
+    }
+
+    fn insert(&self, nick: Nickname, trip: Option<Trip>) {
+        self.0.borrow_mut().insert(nick, trip);
+    }
+
+    fn remove(&self, nick: &str) {
+        self.0.borrow_mut().remove(nick);
+    }
+
+    /// Everyone, sorted by nick, ignoring case.
+    fn users(&self) -> Vec<UserDisplay> {
+        let mut users: Vec<UserDisplay> = self
+            .0
+            .borrow()
+            .iter()
+            .map(|(nick, trip)| UserDisplay {
+                nick: nick.clone(),
+                trip: trip.clone(),
+            })
+            .collect();
+        users.sort_by(|a, b| {
+            a.nick
+                .to_lowercase()
+                .cmp(&b.nick.to_lowercase())
+                .then_with(|| a.nick.cmp(&b.nick))
+        });
+        users
+    }
+}
+
 fn make_client(connection: Connection, log: slog::Logger) -> Client {
-    let mut client = Client::new(connection, ClientState { log });
+    let mut client = Client::new(connection, ClientState::new(log));
+    let roster = Roster::default();
 
-    client.handlers.online_set.addg(|con, state, cmd| {
+    let online = roster.clone();
+    client.handlers.online_set.addg(move |con, state, cmd| {
         let text = if let Some(nicks) = &cmd.nicks {
             let mut text = String::with_capacity(nicks.len() * 10);
             text += "Online Users: ";
@@ -575,14 +3260,47 @@ fn make_client(connection: Connection, log: slog::Logger) -> Client {
             from: MessageName::Server,
             trip: None,
             text,
+            timestamp: con.command_time(),
+            id: 0,
         }))
         .expect_or_log(&state.log, "Failed to send online set action");
+        // Known by now, as the connection's own handler has found us among the users.
+        con.act(DisplayAction::SetOwnNick(con.own_nick().to_owned()))
+            .expect_or_log(&state.log, "Failed to send own nick action");
+        // Only some servers say the trips of who was already here.
+        match &cmd.users {
+            Some(users) => online.set(
+                users
+                    .iter()
+                    .map(|user| (user.nick.clone(), user.trip.clone().into())),
+            ),
+            None => online.set(cmd.nicks.iter().flatten().map(|nick| (nick.clone(), None))),
+        }
+        con.act(DisplayAction::UpdateUsers(online.users()))
+            .expect_or_log(&state.log, "Failed to send users action");
+        con.act(DisplayAction::UpdateLevels(con.levels.clone()))
+            .expect_or_log(&state.log, "Failed to send levels action");
+        // Users that came back while we were disconnected.
+        for nick in cmd.nicks.iter().flatten() {
+            con.act(DisplayAction::SetOnline(nick.clone(), true))
+                .expect_or_log(&state.log, "Failed to send online action");
+        }
     });
     client.handlers.chat.addg(|con, state, cmd| {
+        let trip: Option<Trip> = cmd.trip.clone().into();
+        if state.is_ignored(&cmd.nick, trip.as_ref().map(|trip| trip.0.as_str())) {
+            return;
+        }
+        // Already shown when we sent it.
+        if con.take_echo(&cmd.nick, &cmd.text) {
+            return;
+        }
         con.act(DisplayAction::AddChatMessage(ChatMessage {
             from: MessageName::User(cmd.nick.clone()),
             trip: cmd.trip.clone().into(),
             text: cmd.text.clone(),
+            timestamp: con.command_time(),
+            id: 0,
         }))
         .expect_or_log(&state.log, "Failed to send chat message action");
     });
@@ -594,23 +3312,81 @@ fn make_client(connection: Connection, log: slog::Logger) -> Client {
             from: MessageName::Server,
             trip: None,
             text: cmd.text.clone(),
+            timestamp: con.command_time(),
+            id: 0,
         }))
         .expect_or_log(&state.log, "Failed to send info action");
     });
+    client.handlers.whisper.addg(|con, state, cmd| {
+        if state.is_ignored(&cmd.from, cmd.trip.as_ref().map(|trip| trip.0.as_str())) {
+            return;
+        }
+        con.act(DisplayAction::AddChatMessage(ChatMessage {
+            from: MessageName::Whisper(cmd.from.clone()),
+            trip: cmd.trip.clone(),
+            text: cmd.text.clone(),
+            timestamp: con.command_time(),
+            id: 0,
+        }))
+        .expect_or_log(&state.log, "Failed to send whisper action");
+    });
+    // Whispers are always for us, while anything else has to mention us.
+    client.handlers.on_any_message(|con, state, message| {
+        let own_nick = con.own_nick();
+        let from_us = message.nick.as_deref() == Some(own_nick);
+        let for_us = message.kind == MessageKind::Whisper || mentions(&message.text, own_nick);
+        let nick = message
+            .nick
+            .as_deref()
+            .or_else(|| emote_nick(&message.text).filter(|_| message.kind == MessageKind::Emote));
+        let trip = message
+            .trip
+            .clone()
+            .or_else(|| online_trip(&con.users, nick?));
+        let trip = trip.as_ref().map(|trip| trip.0.as_str());
+        let ignored = nick.map_or(false, |nick| state.is_ignored(nick, trip));
+        if for_us && !from_us && !ignored {
+            let title = match (message.kind, nick) {
+                (MessageKind::Whisper, Some(nick)) => format!("{} whispered to you", nick),
+                (_, Some(nick)) => format!("{} mentioned you in ?{}", nick, con.channel),
+                (_, None) => format!("You were mentioned in ?{}", con.channel),
+            };
+            con.act(DisplayAction::Notify {
+                title,
+                body: message.text.to_string(),
+            })
+            .expect_or_log(&state.log, "Failed to send notify action");
+        }
+    });
     client.handlers.captcha.addg(|con, state, cmd| {
         con.act(DisplayAction::AddChatMessage(ChatMessage {
             from: MessageName::Server,
             trip: None,
             text: cmd.text.clone(),
+            timestamp: con.command_time(),
+            id: 0,
         }))
         .expect_or_log(&state.log, "Failed to send captcha action");
+        // The answer is sent as a chat message, so we put it in the input for the user to check.
+        if let Some(answer) = state.captcha_solver.solve(&cmd.text) {
+            con.act(DisplayAction::PrefillInput(answer))
+                .expect_or_log(&state.log, "Failed to send captcha answer action");
+        }
     });
     client.handlers.emote.addg(|con, state, cmd| {
+        if let Some(nick) = emote_nick(&cmd.text) {
+            let trip = online_trip(&con.users, nick);
+            if state.is_ignored(nick, trip.as_ref().map(|trip| trip.0.as_str())) {
+                return;
+            }
+        }
         // TODO: make this use the actual user's nick.
         con.act(DisplayAction::AddChatMessage(ChatMessage {
             from: MessageName::Server,
             trip: None,
             text: cmd.text.clone(),
+            timestamp: con.command_time(),
+            id: 0,
         }))
         .expect_or_log(&state.log, "Failed to send emote related action");
     });
@@ -631,33 +3407,1537 @@ fn make_client(connection: Connection, log: slog::Logger) -> Client {
                 from: MessageName::Server,
                 trip: None,
                 text: format!("{} invited {} to ?{}", from, to, cmd.invite_channel),
+                timestamp: con.command_time(),
+                id: 0,
             }))
             .expect_or_log(&state.log, "Failed to send invite related action");
     });
-    client.handlers.online_add.addg(|con, state, cmd| {
-        con.act(DisplayAction::AddChatMessage(ChatMessage {
-            from: MessageName::Server,
-            trip: None,
-            text: format!("{} joined", cmd.nick),
-        }))
-        .expect_or_log(&state.log, "Failed to send online add related action");
+    // Whether ignored users are online is still tracked, only their comings and goings are hidden.
+    let online = roster.clone();
+    client.handlers.online_add.addg(move |con, state, cmd| {
+        let trip: Option<Trip> = cmd.trip.clone().into();
+        if !state.is_ignored(&cmd.nick, trip.as_ref().map(|trip| trip.0.as_str())) {
+            con.act(DisplayAction::AddChatMessage(ChatMessage {
+                from: MessageName::Server,
+                trip: None,
+                text: format!("{} joined", cmd.nick),
+                timestamp: con.command_time(),
+                id: 0,
+            }))
+            .expect_or_log(&state.log, "Failed to send online add related action");
+        }
+        con.act(DisplayAction::SetOnline(cmd.nick.clone(), true))
+            .expect_or_log(&state.log, "Failed to send online action");
+        online.insert(cmd.nick.clone(), cmd.trip.clone().into());
+        con.act(DisplayAction::UpdateUsers(online.users()))
+            .expect_or_log(&state.log, "Failed to send users action");
+        con.act(DisplayAction::UpdateLevels(con.levels.clone()))
+            .expect_or_log(&state.log, "Failed to send levels action");
+    });
+    let online = roster.clone();
+    client.handlers.online_remove.addg(move |con, state, cmd| {
+        // Leaving doesn't say the trip, so only those ignored by nick alone are hidden.
+        if !state.is_ignored(&cmd.nick, None) {
+            con.act(DisplayAction::AddChatMessage(ChatMessage {
+                from: MessageName::Server,
+                trip: None,
+                text: format!("{} left", cmd.nick),
+                timestamp: con.command_time(),
+                id: 0,
+            }))
+            .expect_or_log(&state.log, "Failed to send online remove related action");
+        }
+        con.act(DisplayAction::SetOnline(cmd.nick.clone(), false))
+            .expect_or_log(&state.log, "Failed to send offline action");
+        online.remove(&cmd.nick);
+        con.act(DisplayAction::UpdateUsers(online.users()))
+            .expect_or_log(&state.log, "Failed to send users action");
     });
-    client.handlers.online_remove.addg(|con, state, cmd| {
+    client.handlers.topic.addg(|con, state, cmd| {
         con.act(DisplayAction::AddChatMessage(ChatMessage {
             from: MessageName::Server,
             trip: None,
-            text: format!("{} left", cmd.nick),
+            text: format!("Topic: {}", cmd.text),
+            timestamp: con.command_time(),
+            id: 0,
         }))
-        .expect_or_log(&state.log, "Failed to send online remove related action");
+        .expect_or_log(&state.log, "Failed to send topic related action");
+        con.act(DisplayAction::SetTopic(Some(cmd.text.clone())))
+            .expect_or_log(&state.log, "Failed to send topic action");
+    });
+    let online = roster;
+    client.handlers.nick_change.addg(move |con, state, cmd| {
+        // The user has already been renamed, keeping their trip.
+        let user_trip: Option<Trip> = cmd
+            .user_id
+            .and_then(|id| con.users.get(id))
+            .and_then(|info| info.trip.clone().into());
+        let trip = user_trip.as_ref().map(|trip| trip.0.as_str());
+        if !state.is_ignored(&cmd.old_nick, trip) && !state.is_ignored(&cmd.nick, trip) {
+            con.act(DisplayAction::AddChatMessage(ChatMessage {
+                from: MessageName::Server,
+                trip: None,
+                text: format!("{} is now known as {}", cmd.old_nick, cmd.nick),
+                timestamp: con.command_time(),
+                id: 0,
+            }))
+            .expect_or_log(&state.log, "Failed to send nick change related action");
+        }
+        con.act(DisplayAction::SetOnline(cmd.old_nick.clone(), false))
+            .expect_or_log(&state.log, "Failed to send offline action");
+        con.act(DisplayAction::SetOnline(cmd.nick.clone(), true))
+            .expect_or_log(&state.log, "Failed to send online action");
+        if cmd.user_id.is_some() && cmd.user_id == con.users.ourself {
+            con.act(DisplayAction::SetOwnNick(cmd.nick.clone()))
+                .expect_or_log(&state.log, "Failed to send own nick action");
+        }
+        online.remove(&cmd.old_nick);
+        online.insert(cmd.nick.clone(), user_trip);
+        con.act(DisplayAction::UpdateUsers(online.users()))
+            .expect_or_log(&state.log, "Failed to send users action");
+        con.act(DisplayAction::UpdateLevels(con.levels.clone()))
+            .expect_or_log(&state.log, "Failed to send levels action");
     });
     client.handlers.warn.addg(|con, state, cmd| {
+        let text = match WarnKind::classify(&cmd.text) {
+            WarnKind::Cooldown { seconds } => {
+                con.act(DisplayAction::StartCooldown(Duration::from_secs(seconds)))
+                    .expect_or_log(&state.log, "Failed to send cooldown related action");
+                format!("Slow down, you can send again in {}s", seconds)
+            }
+            _ => cmd.text.clone(),
+        };
         con.act(DisplayAction::AddChatMessage(ChatMessage {
             from: MessageName::ServerWarn,
             trip: None,
-            text: cmd.text.clone(),
+            text,
+            timestamp: con.command_time(),
+            id: 0,
         }))
         .expect_or_log(&state.log, "Failed to send warn related action");
     });
 
     client
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        collections::HashMap,
+        fs, io,
+        rc::Rc,
+        sync::mpsc::{self, Receiver, Sender},
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use cursive::{
+        event::{Event, Key},
+        theme::{BaseColor, Color, ColorStyle, Style},
+        views::{LinearLayout, NamedView, TextArea, TextView},
+        Cursive,
+    };
+    use hack_chat_types::Trip;
+
+    use super::{
+        dim, echo_chat, init_backend, make_client, mentions, nick_color, open_chat_log,
+        timestamp_prefix, ChatDisplay, ChatMessage, ConnectionStatus, DisplayAction, GutterSizes,
+        InputHints, MessageName, Mute, OnceCallback, UserDisplay, UsersPanel, CHAT_AREA_NAME,
+        NOTIFY_THROTTLE, STATUS_BAR_NAME, TEXT_AREA_NAME, USERS_LIST_NAME, USERS_PANEL_NAME,
+    };
+    use crate::{
+        chat_log,
+        client_manager::{
+            test_util::{mock_connection, test_log},
+            CaptchaSolver, ClientAction, Ignore, UserLevel,
+        },
+        escapes::{Escapes, Invisibles},
+        filter::Filter,
+        styled::{self, StyledIndexedSpan, StyledString},
+        theme::{self, ChatTheme},
+    };
+
+    /// A display along with the other ends of its channels.
+    pub fn test_display() -> (
+        ChatDisplay<'static>,
+        Sender<DisplayAction>,
+        Receiver<ClientAction>,
+    ) {
+        let (display_sender, display_receiver) = mpsc::channel();
+        let (client_sender, client_receiver) = mpsc::channel();
+        let log = test_log();
+        let display = ChatDisplay::new(
+            display_receiver,
+            display_sender.clone(),
+            client_sender,
+            Escapes::default(),
+            log,
+        );
+        (display, display_sender, client_receiver)
+    }
+
+    pub fn message(text: &str) -> ChatMessage {
+        ChatMessage {
+            from: MessageName::User("bob".to_owned()),
+            trip: None,
+            text: text.to_owned(),
+            timestamp: SystemTime::now(),
+            id: 0,
+        }
+    }
+
+    pub fn chat(text: &str) -> DisplayAction {
+        DisplayAction::AddChatMessage(message(text))
+    }
+
+    /// Performs every action that has been sent.
+    fn run(display: &mut ChatDisplay, siv: &mut Cursive) {
+        while display.handle_actions(siv) {}
+    }
+
+    fn chat_area_len(siv: &mut Cursive) -> usize {
+        siv.find_name::<LinearLayout>(CHAT_AREA_NAME).unwrap().len()
+    }
+
+    #[test]
+    fn test_katex() {
+        let (mut display, _actions, _client) = test_display();
+        let message = message("area is $\\pi r^2$ \\$5");
+        let (_user, text) = display.style_message(&message);
+        assert_eq!(text.inner().source(), message.text);
+
+        display.katex = true;
+        let (_user, text) = display.style_message(&message);
+        assert_eq!(text.inner().source(), "area is π r² $5");
+    }
+
+    #[test]
+    fn test_markdown() {
+        let (mut display, _actions, _client) = test_display();
+        let message = message("some *bold* text");
+        let (_user, text) = display.style_message(&message);
+        assert_eq!(text.inner().source(), message.text);
+
+        display.markdown = true;
+        let (_user, text) = display.style_message(&message);
+        assert_eq!(text.inner().source(), "some bold text");
+        assert!(!text.inner().spans().is_empty());
+
+        // It is still styled around the colors and the math, though not within them.
+        display.ansi = true;
+        let colored = message("*bold* \u{1b}[31m*red*\u{1b}[0m");
+        let (_user, text) = display.style_message(&colored);
+        assert_eq!(text.inner().source(), "bold *red*");
+        display.ansi = false;
+        display.katex = true;
+        let math = message("*area* is $\\pi r^2$");
+        let (_user, text) = display.style_message(&math);
+        assert_eq!(text.inner().source(), "area is π r²");
+    }
+
+    #[test]
+    fn test_invisibles() {
+        let (mut display, _actions, _client) = test_display();
+        let mut message = message("hi \u{200B}*there*");
+        message.from = MessageName::User("b\u{200B}ob".to_owned());
+        display.markdown = true;
+        let (user, text) = display.style_message(&message);
+        assert!(user.inner().source().contains('\u{200B}'));
+        assert_eq!(text.inner().source(), "hi \u{200B}there");
+
+        display.escapes.invisibles = Invisibles::Mark;
+        let (user, text) = display.style_message(&message);
+        assert!(user.inner().source().contains("b<U+200B>ob"));
+        assert_eq!(text.inner().source(), "hi <U+200B>there");
+        // The placeholder keeps its style through the markdown.
+        assert!(text.inner().spans().contains(&StyledIndexedSpan::new_range(
+            3..11,
+            styled::ellipsis_style()
+        )));
+
+        // The ANSI escapes are parsed before they would be taken out.
+        display.escapes.invisibles = Invisibles::Strip;
+        display.ansi = true;
+        message.text = "\u{1b}[31mred\u{1b}[0m\u{202E}".to_owned();
+        let (user, text) = display.style_message(&message);
+        assert!(user.inner().source().contains("bob"));
+        assert_eq!(text.inner().source(), "red");
+    }
+
+    #[test]
+    fn test_raw_text() {
+        let (mut display, _actions, _client) = test_display();
+        display.ansi = true;
+        let message = message("**bold** $x^2$ ?programming \u{1b}[31mred\0");
+        let (_user, text) = display.style_message(&message);
+        assert_ne!(text.inner().source(), message.text);
+
+        // Only what would corrupt the terminal is changed.
+        display.raw_text = true;
+        let (_user, text) = display.style_message(&message);
+        assert_eq!(
+            text.inner().source(),
+            "**bold** $x^2$ ?programming \\u{1b}[31mred\\u{0}"
+        );
+        assert!(text.inner().spans().is_empty());
+    }
+
+    #[test]
+    fn test_links_styled() {
+        let (display, _actions, _client) = test_display();
+        let (_user, text) = display.style_message(&message("see https://example.com."));
+        assert_eq!(
+            text.inner().spans(),
+            &[StyledIndexedSpan::new_range(4..23, styled::link_style())]
+        );
+    }
+
+    #[test]
+    fn test_timestamp_prefix() {
+        let time = SystemTime::now();
+        assert_eq!(timestamp_prefix(time, "%H:%M").len(), "00:00 ".len());
+        // Padded to the longest name, so that every day lines up.
+        for day in 0..7 {
+            let time = time + Duration::from_secs(day * 24 * 60 * 60);
+            assert_eq!(timestamp_prefix(time, "%A").len(), "Wednesday ".len());
+        }
+        assert_eq!(timestamp_prefix(time, "").as_str(), " ");
+    }
+
+    #[test]
+    fn test_whisper_style() {
+        let (display, _actions, _client) = test_display();
+        let whisper = ChatMessage {
+            from: MessageName::Whisper("bob".to_owned()),
+            ..message("hi")
+        };
+        let style = display.theme.whisper;
+        let (user, text) = display.style_message(&whisper);
+        assert_eq!(
+            text.inner().spans(),
+            [StyledIndexedSpan::new_range(0..2, style)]
+        );
+        assert!(user
+            .inner()
+            .spans()
+            .iter()
+            .any(|span| span.resolve(user.inner().source()) == "bob" && span.attr == style));
+
+        // Other messages are left as they are.
+        let (_user, text) = display.style_message(&message("hi"));
+        assert!(text.inner().spans().is_empty());
+    }
+
+    #[test]
+    fn test_dim_offline() {
+        let (mut display, actions, _client) = test_display();
+        let mut siv = Cursive::new();
+        let message = message("hi");
+        let dimmed = |display: &ChatDisplay| {
+            let (_user, text) = display.style_message(&message);
+            text.inner().spans()
+                == [StyledIndexedSpan::new_range(
+                    0..2,
+                    ColorStyle::front(Color::Light(BaseColor::Black)).into(),
+                )]
+        };
+
+        actions
+            .send(DisplayAction::SetOnline("bob".to_owned(), false))
+            .unwrap();
+        run(&mut display, &mut siv);
+        // Only when enabled.
+        assert!(!dimmed(&display));
+        display.dim_offline = true;
+        assert!(dimmed(&display));
+
+        actions
+            .send(DisplayAction::SetOnline("bob".to_owned(), true))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert!(!dimmed(&display));
+    }
+
+    #[test]
+    fn test_dnd() {
+        let (mut display, _actions, _client) = test_display();
+        let now = Instant::now();
+        // Nothing notifies unless the bell is on.
+        assert!(!display.should_notify(now));
+        display.notifications_enabled = true;
+        assert!(display.should_notify(now));
+
+        display.set_dnd(Duration::from_secs(60), now);
+        assert!(!display.should_notify(now));
+        assert!(!display.should_notify(now + Duration::from_secs(59)));
+        // Once it runs out, notifications resume by themselves.
+        assert!(display.should_notify(now + Duration::from_secs(60)));
+        assert_eq!(display.dnd, Mute::Off);
+
+        display.set_dnd(Duration::from_secs(60), now);
+        display.set_dnd(Duration::from_secs(0), now);
+        assert!(display.should_notify(now));
+
+        // Too long to be a time, it lasts until notifications are unmuted.
+        display.set_dnd(Duration::from_secs(u64::MAX), now);
+        assert_eq!(display.dnd, Mute::UntilUnmuted);
+        assert!(!display.should_notify(now + Duration::from_secs(60 * 60 * 24 * 365)));
+        display.set_dnd(Duration::from_secs(0), now);
+        assert!(display.should_notify(now));
+    }
+
+    #[test]
+    fn test_notify_throttle() {
+        let (mut display, _actions, _client) = test_display();
+        let now = Instant::now();
+        assert!(!display.take_notification(now));
+        display.notifications_enabled = true;
+        assert!(display.take_notification(now));
+        // A burst of mentions only notifies once.
+        assert!(!display.take_notification(now + Duration::from_secs(1)));
+        assert!(!display.take_notification(now + NOTIFY_THROTTLE / 2));
+        assert!(display.take_notification(now + NOTIFY_THROTTLE));
+
+        // Muted notifications don't hold back the next one.
+        display.set_dnd(Duration::from_secs(60), now);
+        let later = now + NOTIFY_THROTTLE * 2;
+        assert!(!display.take_notification(later));
+        display.set_dnd(Duration::from_secs(0), now);
+        assert!(display.take_notification(later));
+    }
+
+    #[test]
+    fn test_mentions() {
+        assert!(mentions("hi bob", "bob"));
+        assert!(mentions("@Bob: hi", "bob"));
+        assert!(!mentions("bobby", "bob"));
+        assert!(!mentions("hi", "bob"));
+    }
+
+    #[test]
+    fn test_highlight_mentions() {
+        let (mut display, _actions, _client) = test_display();
+        let mention = display.theme.mention;
+        let highlighted = |display: &ChatDisplay, message: &ChatMessage| {
+            let (_user, text) = display.style_message(message);
+            text.inner().spans().iter().any(|span| span.attr == mention)
+        };
+        // Nothing is highlighted until we know our nick.
+        assert!(!highlighted(&display, &message("hi Me")));
+
+        display.own_nick = Some("me".to_owned());
+        assert!(highlighted(&display, &message("hi Me")));
+        assert!(highlighted(&display, &message("@me: look")));
+        assert!(!highlighted(&display, &message("meme")));
+        // Talking about ourselves isn't a mention.
+        let own = ChatMessage {
+            from: MessageName::User("me".to_owned()),
+            ..message("me again")
+        };
+        assert!(!highlighted(&display, &own));
+
+        display.highlight_words = vec!["fiskar".to_owned()];
+        assert!(highlighted(&display, &message("fiskar is neat")));
+
+        display.highlight_mentions = false;
+        assert!(!highlighted(&display, &message("hi me")));
+    }
+
+    #[test]
+    fn test_combined_identity() {
+        let (mut display, _actions, _client) = test_display();
+        let user = || MessageName::User("bob".to_owned());
+        let trip = || Some("abcdef".to_owned());
+
+        // The trip is normally in its own column, before the nick.
+        let text = display.format_sender(user(), trip());
+        assert_eq!(text.source().trim_start(), "abcdef bob| ");
+
+        display.combined_identity = true;
+        let text = display.format_sender(user(), trip());
+        assert_eq!(text.source().trim_start(), "bob#abcdef| ");
+        let text = display.format_sender(user(), None);
+        assert_eq!(text.source().trim_start(), "bob| ");
+    }
+
+    #[test]
+    fn test_nick_color() {
+        // The same user always gets the same color.
+        assert_eq!(nick_color("bob", None), nick_color("bob", None));
+        assert_eq!(
+            nick_color("bob", Some("abcdef")),
+            nick_color("bob", Some("abcdef"))
+        );
+        for trip in &["abcdef", "ghijkl", "mnopqr"] {
+            assert_ne!(nick_color("bob", Some(trip)), nick_color("bob", None));
+        }
+
+        let (mut display, _actions, _client) = test_display();
+        let user = || MessageName::User("bob".to_owned());
+        let styles = |display: &ChatDisplay| -> Vec<Style> {
+            let text = display.format_sender(user(), None);
+            text.spans().iter().map(|span| span.attr).collect()
+        };
+        assert!(styles(&display).is_empty());
+        display.nick_colors = true;
+        assert_eq!(
+            styles(&display),
+            vec![ColorStyle::front(nick_color("bob", None)).into()]
+        );
+        // The whole trip is used, even when the gutter only shows part of it.
+        display.gutter = GutterSizes::MIN;
+        let text = display.format_sender(user(), Some("abcdef".to_owned()));
+        let color: Style = ColorStyle::front(nick_color("bob", Some("abcdef"))).into();
+        assert_eq!(text.spans().last().map(|span| span.attr), Some(color));
+    }
+
+    #[test]
+    fn test_gutter_sizes() {
+        let min = GutterSizes::MIN;
+        assert_eq!(GutterSizes::fit(200, min), GutterSizes::FULL);
+        assert_eq!(GutterSizes::fit(60, min), GutterSizes { nick: 16, trip: 4 });
+        assert_eq!(GutterSizes::fit(30, min), GutterSizes::MIN);
+        assert_eq!(GutterSizes::fit(0, min), GutterSizes::MIN);
+        // A configured minimum is kept to, up to the full widths.
+        let wide = GutterSizes { nick: 12, trip: 10 };
+        assert_eq!(GutterSizes::fit(0, wide), GutterSizes { nick: 12, trip: 6 });
+        assert_eq!(GutterSizes::fit(200, wide), GutterSizes::FULL);
+
+        let (mut display, _actions, _client) = test_display();
+        let user = || MessageName::User("a_very_long_nickname".to_owned());
+        let trip = || Some("abcdef".to_owned());
+        display.gutter = GutterSizes::fit(30, GutterSizes::MIN);
+        // The fields are cut down to the minimum, rather than overflowing it.
+        let text = display.format_sender(user(), trip());
+        assert_eq!(text.source(), "abc a_very_…| ");
+        let text = display.format_sender(MessageName::Server, None);
+        assert_eq!(text.width(), "abc a_very_l| ".len());
+    }
+
+    #[test]
+    fn test_sender_width() {
+        let (mut display, _actions, _client) = test_display();
+        let user = |nick: &str| MessageName::User(nick.to_owned());
+        let trip = || Some("abcdef".to_owned());
+        let nicks = [
+            "bob",
+            "🐟fish",
+            "e\u{301}e\u{301}",
+            "日本語",
+            "日本語日本語日本語日本語日本語",
+            "🐟🐟🐟🐟🐟🐟🐟🐟🐟🐟🐟🐟🐟🐟",
+        ];
+        for gutter in &[GutterSizes::FULL, GutterSizes::MIN] {
+            display.gutter = *gutter;
+            let expected = display.format_sender(user("bob"), trip()).width();
+            // The text starts at the same column whatever the nick is made of.
+            for nick in &nicks {
+                let text = display.format_sender(user(nick), trip());
+                assert_eq!(text.width(), expected, "{:?}", nick);
+                let text = display.format_sender(user(nick), None);
+                assert_eq!(text.width(), expected, "{:?}", nick);
+            }
+        }
+
+        // Combining marks take up no columns of their own, so they aren't cut off.
+        display.gutter = GutterSizes::FULL;
+        let text = display.format_sender(user("e\u{301}e\u{301}"), None);
+        assert!(text.source().ends_with(" e\u{301}e\u{301}| "));
+        // A wide character that doesn't fit is left out, with the column it leaves padded.
+        display.gutter = GutterSizes::MIN;
+        let text = display.format_sender(user("日本語日本語"), None);
+        assert!(text.source().ends_with("  日本語…| "));
+    }
+
+    #[test]
+    fn test_level_badges() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        let mut levels = HashMap::new();
+        levels.insert("alice".to_owned(), UserLevel::Mod);
+        levels.insert("bob".to_owned(), UserLevel::Admin);
+        actions.send(DisplayAction::UpdateLevels(levels)).unwrap();
+        run(&mut display, &mut siv);
+
+        let user = |nick: &str| MessageName::User(nick.to_owned());
+        assert!(display
+            .format_sender(user("alice"), None)
+            .source()
+            .ends_with(" @alice| "));
+        assert!(display
+            .format_sender(user("bob"), None)
+            .source()
+            .ends_with(" &bob| "));
+        assert!(display
+            .format_sender(user("carol"), None)
+            .source()
+            .ends_with(" carol| "));
+        // The badge is within the nick's field, so the text still lines up.
+        assert_eq!(
+            display.format_sender(user("alice"), None).width(),
+            display.format_sender(user("carol"), None).width()
+        );
+        // The server doesn't say whether whispers are from staff.
+        let whisper = MessageName::Whisper("alice".to_owned());
+        assert!(display
+            .format_sender(whisper, None)
+            .source()
+            .ends_with(" alice| "));
+    }
+
+    #[test]
+    fn test_collapse_trips() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        let user = || MessageName::User("bob".to_owned());
+        let uncollapsed = display.format_sender(user(), None).len();
+
+        display.collapse_trips = true;
+        actions.send(DisplayAction::CreateChat).unwrap();
+        actions.send(chat("hi")).unwrap();
+        run(&mut display, &mut siv);
+        // Without any trips, the trip and its separator aren't given room.
+        let collapsed = display.format_sender(user(), None).len();
+        assert_eq!(collapsed, uncollapsed - "abcdef ".len());
+
+        let mut tripped = message("hello");
+        tripped.trip = Some(Trip("abcdef".to_owned()));
+        actions
+            .send(DisplayAction::AddChatMessage(tripped))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(display.format_sender(user(), None).len(), uncollapsed);
+        assert_eq!(chat_area_len(&mut siv), 2);
+    }
+
+    #[test]
+    fn test_submit_on_enter() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, client) = test_display();
+        actions.send(DisplayAction::CreateChat).unwrap();
+        run(&mut display, &mut siv);
+        siv.focus_name(TEXT_AREA_NAME).unwrap();
+        let content = |siv: &mut Cursive| {
+            siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
+                view.get_content().to_owned()
+            })
+            .unwrap()
+        };
+
+        // Alt+Enter stands in for Shift+Enter, which terminals often can't tell from Enter.
+        siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| view.set_content("hi"));
+        siv.on_event(Event::Alt(Key::Enter));
+        assert!(content(&mut siv).contains('\n'));
+        assert!(client.try_recv().is_err());
+
+        // Enter sends the same way as the button, clearing the input.
+        siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
+            view.set_content("hello")
+        });
+        siv.on_event(Event::Key(Key::Enter));
+        assert!(matches!(
+            client.try_recv(),
+            Ok(ClientAction::SendChatMessage(text)) if text == "hello"
+        ));
+        assert_eq!(content(&mut siv), "");
+    }
+
+    #[test]
+    fn test_complete_nick() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        actions.send(DisplayAction::CreateChat).unwrap();
+        actions
+            .send(DisplayAction::UpdateUsers(vec![
+                UserDisplay {
+                    nick: "bob".to_owned(),
+                    trip: None,
+                },
+                UserDisplay {
+                    nick: "alice".to_owned(),
+                    trip: None,
+                },
+            ]))
+            .unwrap();
+        actions
+            .send(DisplayAction::SetOnline("albert".to_owned(), true))
+            .unwrap();
+        actions
+            .send(DisplayAction::SetOnline("bob".to_owned(), false))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(*display.users.borrow(), vec!["alice", "albert"]);
+
+        siv.focus_name(TEXT_AREA_NAME).unwrap();
+        let content = |siv: &mut Cursive| {
+            siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
+                view.get_content().to_owned()
+            })
+            .unwrap()
+        };
+        // Written with the cursor at the end, as if typed.
+        let write = |siv: &mut Cursive, text: &str| {
+            siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
+                view.set_content(text);
+                view.set_cursor(text.len());
+            });
+        };
+        write(&mut siv, "Al");
+        siv.on_event(Event::Key(Key::Tab));
+        assert_eq!(content(&mut siv), "albert: ");
+        siv.on_event(Event::Key(Key::Tab));
+        assert_eq!(content(&mut siv), "alice: ");
+        // Bob has left.
+        write(&mut siv, "hi b");
+        siv.on_event(Event::Key(Key::Tab));
+        assert_eq!(content(&mut siv), "hi b");
+    }
+
+    #[test]
+    fn test_confirmation() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, client) = test_display();
+        actions.send(DisplayAction::CreateChat).unwrap();
+        run(&mut display, &mut siv);
+
+        siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
+            view.set_content("/invite bob")
+        });
+        display.input_submitter().submit(&mut siv);
+        run(&mut display, &mut siv);
+        assert!(matches!(
+            client.try_recv(),
+            Ok(ClientAction::Invite(nick)) if nick == "bob"
+        ));
+
+        // It is shown as a line from the server, without being kept as a message.
+        assert_eq!(chat_area_len(&mut siv), 1);
+        assert!(display.messages.is_empty());
+        let chat_area = siv.find_name::<LinearLayout>(CHAT_AREA_NAME).unwrap();
+        let line = chat_area
+            .get_child(0)
+            .and_then(|view| view.downcast_ref::<LinearLayout>())
+            .unwrap();
+        let text = line
+            .get_child(0)
+            .and_then(|view| view.downcast_ref::<TextView>())
+            .map(|view| view.get_content().source().to_owned())
+            .unwrap();
+        assert_eq!(text.trim_start(), "*| Inviting bob to a new channel");
+    }
+
+    #[test]
+    fn test_status_bar() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        let bar = |siv: &mut Cursive| {
+            siv.call_on_name(STATUS_BAR_NAME, |view: &mut TextView| {
+                view.get_content().source().to_owned()
+            })
+            .unwrap()
+        };
+        actions.send(DisplayAction::CreateChat).unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(bar(&mut siv), "Connecting");
+
+        actions
+            .send(DisplayAction::SetStatus(ConnectionStatus::Connected(
+                "programming".into(),
+            )))
+            .unwrap();
+        actions
+            .send(DisplayAction::SetOwnNick("me".to_owned()))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(bar(&mut siv), "?programming  me  Connected");
+        let status = display.describe_status();
+        let last = status.spans().last().unwrap();
+        assert_eq!(last.resolve(status.source()), "Connected");
+        assert_eq!(
+            last.attr,
+            ColorStyle::front(Color::Dark(BaseColor::Green)).into()
+        );
+
+        // The channel is kept while we're away from it.
+        actions
+            .send(DisplayAction::SetStatus(ConnectionStatus::Reconnecting))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(bar(&mut siv), "?programming  me  Reconnecting");
+
+        // The topic follows, until we're in another channel.
+        actions
+            .send(DisplayAction::SetTopic(Some(
+                "Be nice\nand on topic".to_owned(),
+            )))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(bar(&mut siv), "?programming  me  Reconnecting  Be nice");
+        actions
+            .send(DisplayAction::SetStatus(ConnectionStatus::Connected(
+                "programming".into(),
+            )))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(bar(&mut siv), "?programming  me  Connected  Be nice");
+        actions
+            .send(DisplayAction::SetStatus(ConnectionStatus::Connected(
+                "math".into(),
+            )))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(bar(&mut siv), "?math  me  Connected");
+    }
+
+    #[test]
+    fn test_topic() {
+        let (con, _transport, display, _client) = mock_connection();
+        let mut cli = make_client(con, test_log());
+        cli.topic_cmd = "motd".to_owned();
+        cli.handle_json(json::object! {
+            cmd: "motd",
+            text: "Be nice",
+            time: 0,
+        })
+        .unwrap();
+
+        assert_eq!(cli.con.topic.as_deref(), Some("Be nice"));
+        let actions: Vec<DisplayAction> = display.try_iter().collect();
+        assert!(actions.iter().any(|action| matches!(
+            action,
+            DisplayAction::AddChatMessage(message) if message.text == "Topic: Be nice"
+        )));
+        assert!(actions.iter().any(|action| matches!(
+            action,
+            DisplayAction::SetTopic(Some(topic)) if topic == "Be nice"
+        )));
+    }
+
+    #[test]
+    fn test_expand_emoji() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, client) = test_display();
+        actions.send(DisplayAction::CreateChat).unwrap();
+        run(&mut display, &mut siv);
+
+        siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
+            view.set_content("/me :wave:")
+        });
+        display.input_submitter().submit(&mut siv);
+        assert!(matches!(
+            client.try_recv(),
+            Ok(ClientAction::SendEmote(text)) if text == "👋"
+        ));
+    }
+
+    #[test]
+    fn test_clear_chat() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, client) = test_display();
+        actions.send(DisplayAction::CreateChat).unwrap();
+        actions.send(chat("hi")).unwrap();
+        actions.send(chat("hello")).unwrap();
+        actions
+            .send(DisplayAction::Search("hi".to_owned()))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(chat_area_len(&mut siv), 2);
+
+        siv.call_on_name(TEXT_AREA_NAME, |view: &mut TextArea| {
+            view.set_content("/clear")
+        });
+        display.input_submitter().submit(&mut siv);
+        run(&mut display, &mut siv);
+        assert_eq!(chat_area_len(&mut siv), 0);
+        assert!(display.messages.is_empty());
+        assert!(display.search.is_none());
+        // Nothing is asked of the server.
+        assert!(client.try_recv().is_err());
+
+        // Messages carry on as before.
+        actions.send(chat("back")).unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(chat_area_len(&mut siv), 1);
+        assert_eq!(display.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_wrapped_messages() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        display.gutter = GutterSizes::MIN;
+        display.collapse_trips = true;
+        display.chat_width = Some(24);
+        actions.send(DisplayAction::CreateChat).unwrap();
+        actions.send(chat("a message long enough to wrap")).unwrap();
+        run(&mut display, &mut siv);
+
+        let chat_area = siv.find_name::<LinearLayout>(CHAT_AREA_NAME).unwrap();
+        let text = chat_area
+            .get_child(0)
+            .and_then(|view| view.downcast_ref::<NamedView<LinearLayout>>())
+            .and_then(|line| {
+                line.get_ref()
+                    .get_child(0)
+                    .and_then(|view| view.downcast_ref::<TextView>())
+                    .map(|view| view.get_content().source().to_owned())
+            })
+            .unwrap();
+        // The lines after the first line up beneath the text, rather than the sender.
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            vec!["     bob| a message long", "          enough to wrap"]
+        );
+    }
+
+    #[test]
+    fn test_set_theme() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        let user = || MessageName::User("bob".to_owned());
+        let styles = |display: &ChatDisplay, name: MessageName| -> Vec<Style> {
+            let text = display.format_sender(name, Some("abcdef".to_owned()));
+            text.spans().iter().map(|span| span.attr).collect()
+        };
+        let default = ChatTheme::default();
+        assert_eq!(styles(&display, user()), vec![default.trip]);
+
+        actions
+            .send(DisplayAction::SetTheme("dark".to_owned()))
+            .unwrap();
+        run(&mut display, &mut siv);
+        let dark = theme::find_theme(&display.themes, "dark").unwrap().clone();
+        assert_eq!(display.theme, dark);
+        assert_ne!(dark.trip, default.trip);
+        assert_eq!(styles(&display, user()), vec![dark.trip, dark.nick]);
+        assert_eq!(
+            styles(&display, MessageName::Server),
+            vec![dark.trip, dark.server]
+        );
+
+        // An unknown theme leaves it as it was.
+        actions
+            .send(DisplayAction::SetTheme("missing".to_owned()))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(display.theme, dark);
+
+        // As can themes from the config file.
+        let mut sea = HashMap::new();
+        sea.insert("nick".to_owned(), "blue".to_owned());
+        let mut tables = HashMap::new();
+        tables.insert("sea".to_owned(), sea);
+        display.themes = theme::load_themes(&tables).unwrap();
+        actions
+            .send(DisplayAction::SetTheme("sea".to_owned()))
+            .unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(display.theme.name, "sea");
+        let blue: Style = ColorStyle::front(Color::Dark(BaseColor::Blue)).into();
+        assert_eq!(styles(&display, user()), vec![default.trip, blue]);
+    }
+
+    #[test]
+    fn test_input_hints() {
+        let mut hints = InputHints::default();
+        assert_eq!(hints.describe().source(), "");
+        hints.search = Some(StyledString::from("No messages match 'x'"));
+        hints.cooldown = Some("Wait 3s before sending again".to_owned());
+        assert_eq!(
+            hints.describe().source(),
+            "No messages match 'x' · Wait 3s before sending again"
+        );
+        // Clearing one part leaves the others shown.
+        hints.search = None;
+        hints.slow_sends = true;
+        assert_eq!(
+            hints.describe().source(),
+            "Wait 3s before sending again · Slowing down sends"
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        actions.send(DisplayAction::CreateChat).unwrap();
+        for text in &["hello", "other", "Hello again"] {
+            actions.send(chat(text)).unwrap();
+        }
+        actions
+            .send(DisplayAction::Search("HELLO".to_owned()))
+            .unwrap();
+        run(&mut display, &mut siv);
+        let search = display.search.as_ref().unwrap();
+        assert_eq!(search.hits(), &[3, 1]);
+        assert_eq!(search.current(), Some(3));
+
+        // The hits are highlighted.
+        let (_user, text) = display.style_message(&display.messages[2]);
+        let hits: Vec<&str> = text
+            .inner()
+            .spans()
+            .iter()
+            .map(|span| span.resolve(text.inner().source()))
+            .collect();
+        assert_eq!(hits, vec!["Hello"]);
+
+        actions.send(DisplayAction::CycleSearch(true)).unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(display.search.as_ref().unwrap().current(), Some(1));
+
+        actions.send(DisplayAction::Search(String::new())).unwrap();
+        run(&mut display, &mut siv);
+        assert!(display.search.is_none());
+        let (_user, text) = display.style_message(&display.messages[2]);
+        assert!(text.inner().spans().is_empty());
+        assert_eq!(chat_area_len(&mut siv), 3);
+    }
+
+    #[test]
+    fn test_reconnect_seam() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        actions.send(DisplayAction::CreateChat).unwrap();
+        actions.send(chat("before")).unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(chat_area_len(&mut siv), 1);
+
+        actions.send(DisplayAction::Reconnected).unwrap();
+        // Creating the chat again must not replace the existing one.
+        actions.send(DisplayAction::CreateChat).unwrap();
+        actions.send(chat("after")).unwrap();
+        run(&mut display, &mut siv);
+        // The message from before, the seam, and the message from after.
+        assert_eq!(chat_area_len(&mut siv), 3);
+        assert_eq!(display.messages.len(), 2);
+    }
+
+    #[test]
+    fn test_import_history() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        let history = chat_log::parse_log(&format!(
+            "{}\n{}\n",
+            chat_log::format_line(&message("one")),
+            chat_log::format_line(&message("two"))
+        ));
+        assert_eq!(history.len(), 2);
+        display.import_history(&mut siv, history);
+
+        actions.send(chat("live")).unwrap();
+        actions.send(DisplayAction::CreateChat).unwrap();
+        run(&mut display, &mut siv);
+        // The imported messages, the seam, and the live message.
+        assert_eq!(chat_area_len(&mut siv), 4);
+        let texts: Vec<&str> = display.messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, vec!["one", "two", "live"]);
+
+        // Still divided after the messages are rendered again.
+        actions.send(DisplayAction::SetFilter(None)).unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(chat_area_len(&mut siv), 4);
+
+        // Only the imported messages are dimmed, to set them apart from the live ones.
+        let dimmed: Vec<bool> = display
+            .messages
+            .iter()
+            .map(|message| display.is_dimmed(message))
+            .collect();
+        assert_eq!(dimmed, vec![true, true, false]);
+        let (_, text) = display.style_message(&display.messages[0]);
+        assert_eq!(text.into_inner(), dim(StyledString::from("one")));
+    }
+
+    #[test]
+    fn test_reopen_history() {
+        let mut siv = Cursive::new();
+        let dir = std::env::temp_dir().join(format!("fiskar-reopen-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (mut display, actions, _client) = test_display();
+        open_chat_log(&mut display, &mut siv, &dir, None, "programming").unwrap();
+
+        actions.send(chat("before")).unwrap();
+        actions
+            .send(DisplayAction::ReopenHistory("math".into()))
+            .unwrap();
+        actions.send(chat("after")).unwrap();
+        run(&mut display, &mut siv);
+        // Each message is logged to the channel it was said in.
+        let logged = |channel: &str| -> Vec<String> {
+            chat_log::load(&chat_log::log_path(&dir, channel), 10)
+                .unwrap()
+                .into_iter()
+                .map(|message| message.text)
+                .collect()
+        };
+        assert_eq!(logged("programming"), vec!["before".to_owned()]);
+        assert_eq!(logged("math"), vec!["after".to_owned()]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_message_ids() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        display.import_history(&mut siv, vec![message("old")]);
+        actions.send(DisplayAction::CreateChat).unwrap();
+        for text in ["one", "two", "three"].iter() {
+            actions.send(chat(text)).unwrap();
+        }
+        run(&mut display, &mut siv);
+
+        let ids: Vec<u64> = display.messages.iter().map(|message| message.id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+        let text = |id| {
+            display
+                .message_by_id(id)
+                .map(|message| message.text.as_str())
+        };
+        assert_eq!(text(1), Some("old"));
+        assert_eq!(text(3), Some("two"));
+        assert_eq!(text(0), None);
+        assert_eq!(text(5), None);
+        assert!(siv
+            .find_name::<LinearLayout>(&ChatDisplay::message_view_name(3))
+            .is_some());
+    }
+
+    #[test]
+    fn test_max_chat_views() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        display.max_chat_views = Some(3);
+        actions.send(DisplayAction::CreateChat).unwrap();
+        for text in ["one", "two", "three", "four", "five"].iter() {
+            actions.send(chat(text)).unwrap();
+        }
+        run(&mut display, &mut siv);
+
+        assert_eq!(chat_area_len(&mut siv), 3);
+        // The messages are given ids from 1, so the views of the first two are gone.
+        let has_view = |siv: &mut Cursive, id| {
+            siv.find_name::<LinearLayout>(&ChatDisplay::message_view_name(id))
+                .is_some()
+        };
+        assert!(!has_view(&mut siv, 2));
+        assert!(has_view(&mut siv, 3));
+        assert!(has_view(&mut siv, 5));
+        // Only the views are removed, the messages are all kept.
+        assert_eq!(display.messages.len(), 5);
+    }
+
+    #[test]
+    fn test_max_history() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        display.max_history = Some(3);
+        display.import_history(&mut siv, vec![message("old"), message("older")]);
+        actions.send(DisplayAction::CreateChat).unwrap();
+        for text in ["one", "two", "three"].iter() {
+            actions.send(chat(text)).unwrap();
+        }
+        run(&mut display, &mut siv);
+
+        let texts: Vec<&str> = display
+            .history()
+            .iter()
+            .map(|message| message.text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["one", "two", "three"]);
+        // None of the imported messages are left to divide from the live ones.
+        assert_eq!(display.imported, 0);
+        assert_eq!(
+            display.message_by_id(3).map(|m| m.text.as_str()),
+            Some("one")
+        );
+    }
+
+    #[test]
+    fn test_describe_message_spans() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        actions.send(chat("first")).unwrap();
+        actions.send(chat("second")).unwrap();
+        run(&mut display, &mut siv);
+
+        let description = display.describe_message_spans(1).unwrap();
+        assert!(description.contains("Text \"second\""));
+        assert!(display.describe_message_spans(2).is_none());
+
+        // It counts the messages that are shown, rather than every message.
+        display.filter = Some(Filter::parse("second").unwrap());
+        let description = display.describe_message_spans(0).unwrap();
+        assert!(description.contains("Text \"second\""));
+    }
+
+    #[test]
+    fn test_recent_message() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        actions.send(chat("first")).unwrap();
+        actions.send(chat("second")).unwrap();
+        actions
+            .send(DisplayAction::AddChatMessage(ChatMessage {
+                from: MessageName::Server,
+                ..message("from the server")
+            }))
+            .unwrap();
+        run(&mut display, &mut siv);
+
+        // Only messages from users are counted.
+        assert_eq!(display.recent_message(1).unwrap().text, "second");
+        assert_eq!(display.recent_message(2).unwrap().text, "first");
+        assert!(display.recent_message(3).is_none());
+        assert!(display.recent_message(0).is_none());
+
+        display.filter = Some(Filter::parse("first").unwrap());
+        assert_eq!(display.recent_message(1).unwrap().text, "first");
+    }
+
+    #[test]
+    fn test_init_backend() {
+        let err = init_backend(|| Err(io::Error::new(io::ErrorKind::NotFound, "no tty"))).err();
+        let message = err.unwrap();
+        assert!(message.contains("no tty"));
+        assert!(message.contains("interactive terminal"));
+    }
+
+    #[test]
+    fn test_once_callback() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = calls.clone();
+        // Owns something, so that it can only be an FnOnce.
+        let owned = String::from("joined as ");
+        let mut cb = OnceCallback::new(move |nick: String| {
+            recorded.borrow_mut().push(owned + &nick);
+        });
+
+        assert!(cb.call("bob".to_owned()));
+        assert!(!cb.call("alice".to_owned()));
+        assert!(!cb.call("carol".to_owned()));
+        assert_eq!(*calls.borrow(), vec!["joined as bob".to_owned()]);
+    }
+
+    #[test]
+    fn test_messages_before_chat() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        // The server can send messages before the chat has been created.
+        actions.send(chat("first")).unwrap();
+        actions.send(chat("second")).unwrap();
+        run(&mut display, &mut siv);
+        assert!(siv.find_name::<LinearLayout>(CHAT_AREA_NAME).is_none());
+
+        actions.send(DisplayAction::CreateChat).unwrap();
+        actions.send(chat("third")).unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(chat_area_len(&mut siv), 3);
+        let texts: Vec<&str> = display
+            .messages
+            .iter()
+            .map(|message| message.text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["first", "second", "third"]);
+    }
+
+    struct StubSolver;
+    impl CaptchaSolver for StubSolver {
+        fn solve(&self, _captcha: &str) -> Option<String> {
+            Some("abc".to_owned())
+        }
+    }
+
+    #[test]
+    fn test_captcha_solver() {
+        let (con, _transport, display, _client) = mock_connection();
+        let mut cli = make_client(con, test_log());
+        let captcha = json::object! {
+            cmd: "captcha",
+            text: " _\n|_|",
+            channel: "programming",
+            time: 0,
+        };
+
+        // By default the captcha is left to the user.
+        cli.handle_json(captcha.clone()).unwrap();
+        assert!(!display
+            .try_iter()
+            .any(|action| matches!(action, DisplayAction::PrefillInput(_))));
+
+        cli.state.captcha_solver = Box::new(StubSolver);
+        cli.handle_json(captcha).unwrap();
+        assert!(display.try_iter().any(
+            |action| matches!(action, DisplayAction::PrefillInput(answer) if answer == "abc")
+        ));
+    }
+
+    #[test]
+    fn test_echo_chat() {
+        let (con, _transport, display, _client) = mock_connection();
+        let mut cli = make_client(con, test_log());
+        let chats = || {
+            display
+                .try_iter()
+                .filter_map(|action| match action {
+                    DisplayAction::AddChatMessage(message) => Some(message),
+                    _ => None,
+                })
+                .collect::<Vec<ChatMessage>>()
+        };
+        let chat = |nick: &str| {
+            json::object! {
+                cmd: "chat",
+                nick: nick,
+                text: "hi",
+                channel: "programming",
+                time: 0,
+            }
+        };
+
+        echo_chat(&mut cli.con, &"hi".to_owned()).unwrap();
+        let echoed = chats();
+        assert_eq!(echoed.len(), 1);
+        assert_eq!(echoed[0].from, MessageName::User(cli.own_nick().to_owned()));
+        assert_eq!(echoed[0].text, "hi");
+
+        // The server's echo isn't shown again, while someone else saying the same is.
+        let own_nick = cli.own_nick().to_owned();
+        cli.handle_json(chat(&own_nick)).unwrap();
+        assert!(chats().is_empty());
+        cli.handle_json(chat("bob")).unwrap();
+        assert_eq!(chats().len(), 1);
+    }
+
+    #[test]
+    fn test_server_time() {
+        let (con, _transport, display, _client) = mock_connection();
+        let mut cli = make_client(con, test_log());
+        let mut timestamp = |json| {
+            cli.handle_json(json).unwrap();
+            display
+                .try_iter()
+                .find_map(|action| match action {
+                    DisplayAction::AddChatMessage(message) => Some(message.timestamp),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let time = timestamp(json::object! {
+            cmd: "chat",
+            nick: "bob",
+            text: "hi",
+            channel: "programming",
+            time: 1600000000000u64,
+        });
+        assert_eq!(
+            time,
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1600000000000)
+        );
+
+        // Without a time from the server, it is when we received it.
+        let before = SystemTime::now();
+        let time = timestamp(json::object! {
+            cmd: "info",
+            text: "hello",
+            channel: "programming",
+        });
+        assert!(time >= before);
+    }
+
+    #[test]
+    fn test_notify_mention() {
+        let (con, _transport, display, _client) = mock_connection();
+        let own_nick = con.own_nick().to_owned();
+        let mut cli = make_client(con, test_log());
+        let mut notified = |text: String| {
+            cli.handle_json(json::object! {
+                cmd: "chat",
+                nick: "bob",
+                text: text,
+                channel: "programming",
+            })
+            .unwrap();
+            display.try_iter().find_map(|action| match action {
+                DisplayAction::Notify { title, body } => Some((title, body)),
+                _ => None,
+            })
+        };
+
+        assert_eq!(notified("hello".to_owned()), None);
+        let text = format!("hi @{}", own_nick);
+        assert_eq!(
+            notified(text.clone()),
+            Some(("bob mentioned you in ?programming".to_owned(), text))
+        );
+    }
+
+    #[test]
+    fn test_ignored_chat() {
+        let (con, _transport, display, _client) = mock_connection();
+        let mut cli = make_client(con, test_log());
+        cli.state.ignored.insert(Ignore::parse("Bob").unwrap());
+        let chat = |nick: &str| {
+            json::object! {
+                cmd: "chat",
+                nick: nick,
+                text: "hi",
+                channel: "programming",
+            }
+        };
+        let shown = |display: &Receiver<DisplayAction>| {
+            display
+                .try_iter()
+                .filter(|action| matches!(action, DisplayAction::AddChatMessage(_)))
+                .count()
+        };
+
+        cli.handle_json(chat("bob")).unwrap();
+        assert_eq!(shown(&display), 0);
+        cli.handle_json(chat("alice")).unwrap();
+        assert_eq!(shown(&display), 1);
+
+        cli.state.ignored.clear();
+        cli.handle_json(chat("bob")).unwrap();
+        assert_eq!(shown(&display), 1);
+
+        // Emotes only carry the nick, though ignoring by trip still hides them.
+        cli.state
+            .ignored
+            .insert(Ignore::parse("carol#Xk2Fo3").unwrap());
+        cli.handle_json(json::object! {
+            cmd: "onlineAdd",
+            nick: "carol",
+            trip: "Xk2Fo3",
+            uType: "user",
+            hash: "abcdef",
+            level: 100,
+            userid: 4,
+            isBot: false,
+            color: false,
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+        display.try_iter().for_each(drop);
+        cli.handle_json(json::object! {
+            cmd: "emote",
+            nick: "carol",
+            userid: 4,
+            text: "@carol waves",
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(shown(&display), 0);
+    }
+
+    #[test]
+    fn test_users_panel() {
+        let mut siv = Cursive::new();
+        let (mut display, actions, _client) = test_display();
+        let panel = |siv: &mut Cursive| {
+            siv.call_on_name(USERS_LIST_NAME, |view: &mut TextView| {
+                view.get_content().source().to_owned()
+            })
+            .unwrap()
+        };
+        actions.send(DisplayAction::CreateChat).unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(panel(&mut siv), "0 users");
+
+        actions
+            .send(DisplayAction::UpdateUsers(vec![
+                UserDisplay {
+                    nick: "alice".to_owned(),
+                    trip: Some(Trip("Xk2Fo3".to_owned())),
+                },
+                UserDisplay {
+                    nick: "bob".to_owned(),
+                    trip: None,
+                },
+            ]))
+            .unwrap();
+        let mut levels = HashMap::new();
+        levels.insert("bob".to_owned(), UserLevel::Mod);
+        actions.send(DisplayAction::UpdateLevels(levels)).unwrap();
+        run(&mut display, &mut siv);
+        assert_eq!(panel(&mut siv), "2 users\nalice Xk2Fo3\n@bob");
+        // They can still be completed.
+        assert_eq!(*display.users.borrow(), vec!["alice", "bob"]);
+
+        actions.send(DisplayAction::ToggleUsers).unwrap();
+        run(&mut display, &mut siv);
+        assert!(!display.show_users);
+        let visible = siv.call_on_name(USERS_PANEL_NAME, |view: &mut UsersPanel| view.is_visible());
+        assert_eq!(visible, Some(false));
+    }
+
+    #[test]
+    fn test_roster() {
+        let (con, _transport, display, _client) = mock_connection();
+        let mut cli = make_client(con, test_log());
+        let last_users = |display: &Receiver<DisplayAction>| {
+            display
+                .try_iter()
+                .filter_map(|action| match action {
+                    DisplayAction::UpdateUsers(users) => Some(users),
+                    _ => None,
+                })
+                .last()
+                .unwrap()
+                .into_iter()
+                .map(|user| (user.nick, user.trip.map(|trip| trip.0)))
+                .collect::<Vec<_>>()
+        };
+
+        cli.handle_json(json::object! {
+            cmd: "onlineSet",
+            nicks: ["me", "bob", "Alice"],
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(
+            last_users(&display),
+            vec![
+                ("Alice".to_owned(), None),
+                ("bob".to_owned(), None),
+                ("me".to_owned(), None),
+            ]
+        );
+
+        cli.handle_json(json::object! {
+            cmd: "onlineAdd",
+            nick: "carol",
+            trip: "Xk2Fo3",
+            uType: "user",
+            hash: "abcdef",
+            level: 100,
+            userid: 4,
+            isBot: false,
+            color: false,
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+        cli.handle_json(json::object! {
+            cmd: "onlineRemove",
+            nick: "bob",
+            userid: 2,
+            channel: "programming",
+            time: 0,
+        })
+        .unwrap();
+        assert_eq!(
+            last_users(&display),
+            vec![
+                ("Alice".to_owned(), None),
+                ("carol".to_owned(), Some("Xk2Fo3".to_owned())),
+                ("me".to_owned(), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::ops::Range;
+
+use cursive::theme::Style;
+
+use crate::{
+    styled::{StyledIndexedSpan, StyledString},
+    ChatMessage,
+};
+
+/// A search back through the chat for some text, and the messages it was found in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Search {
+    query: String,
+    /// The ids of the messages that the query was found in, newest first.
+    hits: Vec<u64>,
+    /// The index of the hit being looked at.
+    current: usize,
+}
+impl Search {
+    /// Searches the messages, given oldest first, for the query ignoring case.
+    pub fn new<'a, I>(query: &str, messages: I) -> Self
+    where
+        I: DoubleEndedIterator<Item = &'a ChatMessage>,
+    {
+        let hits = messages
+            .rev()
+            .filter(|message| !find_ignore_case(&message.text, query).is_empty())
+            .map(|message| message.id)
+            .collect();
+        Self {
+            query: query.to_owned(),
+            hits,
+            current: 0,
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn hits(&self) -> &[u64] {
+        &self.hits
+    }
+
+    /// The id of the message being looked at, if anything was found.
+    pub fn current(&self) -> Option<u64> {
+        self.hits.get(self.current).copied()
+    }
+
+    /// The position of the hit being looked at, from one, for showing to the user.
+    pub fn position(&self) -> usize {
+        self.current + 1
+    }
+
+    /// Moves to the next older hit, wrapping around to the newest.
+    pub fn older(&mut self) -> Option<u64> {
+        if !self.hits.is_empty() {
+            self.current = (self.current + 1) % self.hits.len();
+        }
+        self.current()
+    }
+
+    /// Moves to the next newer hit, wrapping around to the oldest.
+    pub fn newer(&mut self) -> Option<u64> {
+        if !self.hits.is_empty() {
+            self.current = match self.current {
+                0 => self.hits.len() - 1,
+                current => current - 1,
+            };
+        }
+        self.current()
+    }
+}
+
+/// Where the query is in the text, ignoring case, as byte ranges of the text.
+pub fn find_ignore_case(text: &str, query: &str) -> Vec<Range<usize>> {
+    let mut found = Vec::new();
+    if query.is_empty() {
+        return found;
+    }
+    let mut start = 0;
+    while let Some(ch) = text[start..].chars().next() {
+        if let Some(len) = match_len_ignore_case(&text[start..], query) {
+            found.push(start..start + len);
+            start += len;
+        } else {
+            start += ch.len_utf8();
+        }
+    }
+    found
+}
+
+/// How many bytes at the start of the text match the query, ignoring case.
+fn match_len_ignore_case(text: &str, query: &str) -> Option<usize> {
+    // Lowercasing can turn one character into several, so each is paired with where the
+    // character it came from ends.
+    let mut text_chars = text.char_indices().flat_map(|(i, ch)| {
+        let end = i + ch.len_utf8();
+        ch.to_lowercase().map(move |lower| (end, lower))
+    });
+    let mut len = 0;
+    for query_ch in query.chars().flat_map(char::to_lowercase) {
+        let (end, text_ch) = text_chars.next()?;
+        if text_ch != query_ch {
+            return None;
+        }
+        len = end;
+    }
+    Some(len)
+}
+
+/// Styles every place the query is found in the text.
+pub fn style_hits(mut text: StyledString, query: &str, style: Style) -> StyledString {
+    for range in find_ignore_case(text.source(), query) {
+        text.add_span_intersect(StyledIndexedSpan::new_range(range, style));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::{find_ignore_case, Search};
+    use crate::{ChatMessage, MessageName};
+
+    #[test]
+    fn test_find_ignore_case() {
+        assert_eq!(find_ignore_case("Hello hello", "hello"), vec![0..5, 6..11]);
+        assert_eq!(find_ignore_case("HELLO", "hElLo"), vec![0..5]);
+        assert_eq!(find_ignore_case("aaaa", "aa"), vec![0..2, 2..4]);
+        assert!(find_ignore_case("hello", "").is_empty());
+        assert!(find_ignore_case("hel", "hello").is_empty());
+        // Ranges are of the text, even when it isn't ascii.
+        assert_eq!(find_ignore_case("über ÜBER", "über"), vec![0..5, 6..11]);
+    }
+
+    #[test]
+    fn test_search() {
+        let messages: Vec<ChatMessage> = ["hi there", "nope", "Hi again", "hiding"]
+            .iter()
+            .zip(1..)
+            .map(|(text, id)| ChatMessage {
+                from: MessageName::User("bob".to_owned()),
+                trip: None,
+                text: (*text).to_owned(),
+                timestamp: SystemTime::now(),
+                id,
+            })
+            .collect();
+
+        let mut search = Search::new("HI", messages.iter());
+        // Newest first.
+        assert_eq!(search.hits(), &[4, 3, 1]);
+        assert_eq!(search.current(), Some(4));
+        assert_eq!(search.older(), Some(3));
+        assert_eq!(search.older(), Some(1));
+        assert_eq!(search.position(), 3);
+        // Wraps around both ways.
+        assert_eq!(search.older(), Some(4));
+        assert_eq!(search.newer(), Some(1));
+        assert_eq!(search.newer(), Some(3));
+
+        let mut search = Search::new("missing", messages.iter());
+        assert_eq!(search.current(), None);
+        assert_eq!(search.older(), None);
+        assert_eq!(search.newer(), None);
+    }
+}
@@ -0,0 +1,424 @@
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+use cursive::{
+    event::Event,
+    theme::{Effect, Style},
+    views::{Dialog, TextView},
+    Cursive, ScreenId,
+};
+use hack_chat_types::{Channel, Nickname};
+use slog::warn;
+
+use crate::{
+    client_manager::{ClientAction, QUIT_TIMEOUT},
+    keys::KeyBindings,
+    styled::StyledString,
+    ChatDisplay, TAB_BAR_NAME,
+};
+
+/// What to do with the tabs, from key bindings and the displays of the tabs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TabAction {
+    /// Join the channel in a new tab, or switch to its tab if it already has one.
+    Open(Channel),
+    /// Switch to the nth tab, from zero.
+    Switch(usize),
+    /// Switch to the next tab, wrapping around to the first.
+    Next,
+    /// Close every tab's connection, and then stop.
+    Quit,
+}
+
+/// Creates the display of a new tab, starting a connection that joins the channel as the nick.
+/// It is called with the tab's screen active, so that anything it shows goes to the tab.
+pub type OpenTab<'a> = Box<dyn FnMut(&mut Cursive, Channel, Nickname) -> ChatDisplay<'a> + 'a>;
+
+/// A channel being chatted in, with its own connection, shown on a screen of its own.
+pub struct Tab<'a> {
+    pub channel: Channel,
+    pub display: ChatDisplay<'a>,
+    screen: ScreenId,
+}
+
+/// The channels we're in, each in a tab, with one of them shown at a time.
+pub struct Tabs<'a> {
+    tabs: Vec<Tab<'a>>,
+    /// The index of the tab being shown.
+    active: usize,
+    /// Given to each display, and to the key bindings, so that they can act on the tabs.
+    sender: Sender<TabAction>,
+    receiver: Receiver<TabAction>,
+    open_tab: OpenTab<'a>,
+    log: slog::Logger,
+    /// When we started quitting, if we have.
+    quitting_since: Option<Instant>,
+}
+impl<'a> Tabs<'a> {
+    /// How many tabs can be switched to with a key, as Alt+1 to Alt+9.
+    pub const SHORTCUTS: usize = 9;
+
+    /// How long to wait for the connections to close when quitting, after which we stop anyway.
+    /// It's a bit longer than each one waits, so that they get the chance to give up themselves.
+    pub const QUIT_GRACE: Duration = Duration::from_secs(QUIT_TIMEOUT.as_secs() + 1);
+
+    pub fn new(
+        siv: &mut Cursive,
+        open_tab: OpenTab<'a>,
+        keys: &KeyBindings,
+        log: slog::Logger,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        for n in 0..Self::SHORTCUTS {
+            let sender = sender.clone();
+            let digit = std::char::from_digit(n as u32 + 1, 10).unwrap();
+            siv.add_global_callback(Event::AltChar(digit), move |_| {
+                // The tabs outlive the views, so this can't fail while the keys work.
+                let _ = sender.send(TabAction::Switch(n));
+            });
+        }
+        let quit_sender = sender.clone();
+        siv.add_global_callback(keys.quit.clone(), move |_| {
+            let _ = quit_sender.send(TabAction::Quit);
+        });
+        let next_sender = sender.clone();
+        siv.add_global_callback(keys.next_tab.clone(), move |_| {
+            let _ = next_sender.send(TabAction::Next);
+        });
+        Self {
+            tabs: Vec::new(),
+            active: 0,
+            sender,
+            receiver,
+            open_tab,
+            log,
+            quitting_since: None,
+        }
+    }
+
+    /// Adds a tab for a display whose connection is started elsewhere, such as for the channels
+    /// joined on startup. The first tab is on the screen that is active, and the rest each get
+    /// a new one.
+    pub fn add(&mut self, siv: &mut Cursive, channel: Channel, mut display: ChatDisplay<'a>) {
+        display.tab_sender = Some(self.sender.clone());
+        let screen = if self.tabs.is_empty() {
+            siv.active_screen()
+        } else {
+            siv.add_screen()
+        };
+        self.tabs.push(Tab {
+            channel,
+            display,
+            screen,
+        });
+    }
+
+    pub fn tabs(&self) -> &[Tab<'a>] {
+        &self.tabs
+    }
+
+    /// The index of the tab being shown.
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// The display of the tab being shown.
+    pub fn active_display(&mut self) -> &mut ChatDisplay<'a> {
+        &mut self.tabs[self.active].display
+    }
+
+    /// Performs an action of every tab's display, on the tab's own screen, along with any
+    /// actions on the tabs themselves. Returns whether anything was done.
+    pub fn handle_actions(&mut self, siv: &mut Cursive) -> bool {
+        let shown = siv.active_screen();
+        let mut ran = false;
+        for tab in &mut self.tabs {
+            siv.set_screen(tab.screen);
+            ran |= tab.display.handle_actions(siv);
+        }
+        siv.set_screen(shown);
+
+        while let Ok(action) = self.receiver.try_recv() {
+            match action {
+                TabAction::Open(channel) => self.open(siv, channel),
+                TabAction::Switch(index) => self.switch(siv, index),
+                TabAction::Next => self.next(siv),
+                TabAction::Quit => self.quit(Instant::now()),
+            }
+            ran = true;
+        }
+
+        // What is shown has been read.
+        if let Some(tab) = self.tabs.get_mut(self.active) {
+            tab.display.unread = 0;
+        }
+        if ran {
+            self.update_tab_bars(siv);
+        }
+        ran
+    }
+
+    /// Shows the nth tab, from zero, if there is one.
+    pub fn switch(&mut self, siv: &mut Cursive, index: usize) {
+        if let Some(tab) = self.tabs.get_mut(index) {
+            self.active = index;
+            tab.display.unread = 0;
+            siv.set_screen(tab.screen);
+        }
+    }
+
+    /// Shows the tab after the one being shown, or the first if it's the last.
+    pub fn next(&mut self, siv: &mut Cursive) {
+        if !self.tabs.is_empty() {
+            self.switch(siv, (self.active + 1) % self.tabs.len());
+        }
+    }
+
+    /// Joins the channel in a new tab, as the nick we have in the tab being shown, and switches
+    /// to it. If it already has a tab, that is switched to instead.
+    pub fn open(&mut self, siv: &mut Cursive, channel: Channel) {
+        if let Some(index) = self.tabs.iter().position(|tab| tab.channel == channel) {
+            self.switch(siv, index);
+            return;
+        }
+        let nick = match self
+            .tabs
+            .get(self.active)
+            .and_then(|tab| tab.display.own_nick())
+        {
+            Some(nick) => nick.clone(),
+            None => {
+                warn!(self.log, "Not opening ?{} before we've joined", channel);
+                siv.add_layer(Dialog::info(
+                    "Wait until you've joined before opening a channel",
+                ));
+                return;
+            }
+        };
+        let shown = siv.active_screen();
+        let screen = siv.add_screen();
+        siv.set_screen(screen);
+        let mut display = (self.open_tab)(siv, channel.clone(), nick);
+        siv.set_screen(shown);
+        display.tab_sender = Some(self.sender.clone());
+        self.tabs.push(Tab {
+            channel,
+            display,
+            screen,
+        });
+        self.switch(siv, self.tabs.len() - 1);
+    }
+
+    /// Asks every tab's connection to close, which they each tell their display once done.
+    pub fn quit(&mut self, now: Instant) {
+        if self.quitting_since.is_some() {
+            return;
+        }
+        self.quitting_since = Some(now);
+        for tab in &mut self.tabs {
+            // The socket thread has already stopped, so there is nothing to close.
+            if tab.display.sender.send(ClientAction::Quit).is_err() {
+                tab.display.closed = true;
+            }
+        }
+    }
+
+    /// Whether we've quit and every connection has closed, or they've had long enough to.
+    pub fn has_quit(&self, now: Instant) -> bool {
+        match self.quitting_since {
+            Some(since) => {
+                self.tabs.iter().all(|tab| tab.display.closed)
+                    || now.saturating_duration_since(since) >= Self::QUIT_GRACE
+            }
+            None => false,
+        }
+    }
+
+    /// The tabs, numbered by their shortcut, with how many messages each has unread. It is empty
+    /// while there is only one, as there is nothing to switch between.
+    pub fn describe(&self) -> StyledString {
+        let mut text = StyledString::default();
+        if self.tabs.len() < 2 {
+            return text;
+        }
+        for (i, tab) in self.tabs.iter().enumerate() {
+            if i > 0 {
+                text.append_source("  ");
+            }
+            let mut label = format!("{}:?{}", i + 1, tab.channel);
+            if tab.display.unread > 0 {
+                label += &format!(" ({})", tab.display.unread);
+            }
+            let style: Style = if i == self.active {
+                Effect::Reverse.into()
+            } else if tab.display.unread > 0 {
+                Effect::Bold.into()
+            } else {
+                Style::none()
+            };
+            text.append_styled(&label, style);
+        }
+        text
+    }
+
+    /// Shows the tabs at the top of every tab's chat.
+    fn update_tab_bars(&mut self, siv: &mut Cursive) {
+        let text = self.describe();
+        let shown = siv.active_screen();
+        for tab in &self.tabs {
+            siv.set_screen(tab.screen);
+            siv.call_on_name(TAB_BAR_NAME, |view: &mut TextView| {
+                view.set_content(text.clone())
+            });
+        }
+        siv.set_screen(shown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use cursive::{views::TextView, Cursive};
+    use hack_chat_types::Channel;
+
+    use super::{TabAction, Tabs};
+    use crate::{
+        client_manager::{test_util::test_log, ClientAction},
+        keys::KeyBindings,
+        tests::{chat, test_display},
+        DisplayAction, TAB_BAR_NAME,
+    };
+
+    /// Tabs whose opened displays are discarded along with their channels, as nothing in the
+    /// tests connects.
+    fn test_tabs(siv: &mut Cursive) -> Tabs<'static> {
+        Tabs::new(
+            siv,
+            Box::new(|_siv: &mut Cursive, _channel, _nick| test_display().0),
+            &KeyBindings::default(),
+            test_log(),
+        )
+    }
+
+    #[test]
+    fn test_unread() {
+        let mut siv = Cursive::new();
+        let mut tabs = test_tabs(&mut siv);
+        let mut senders = Vec::new();
+        for channel in &["programming", "math"] {
+            let (display, display_sender, _client) = test_display();
+            display_sender.send(DisplayAction::CreateChat).unwrap();
+            senders.push(display_sender);
+            tabs.add(&mut siv, Channel::from(*channel), display);
+        }
+        while tabs.handle_actions(&mut siv) {}
+        assert_eq!(tabs.describe().source(), "1:?programming  2:?math");
+
+        // Only the tab that isn't shown has unread messages.
+        senders[0].send(chat("hi")).unwrap();
+        senders[1].send(chat("hello")).unwrap();
+        senders[1].send(chat("anyone?")).unwrap();
+        while tabs.handle_actions(&mut siv) {}
+        assert_eq!(tabs.tabs()[0].display.unread, 0);
+        assert_eq!(tabs.tabs()[1].display.unread, 2);
+        assert_eq!(tabs.describe().source(), "1:?programming  2:?math (2)");
+        let bar = siv
+            .call_on_name(TAB_BAR_NAME, |view: &mut TextView| {
+                view.get_content().source().to_owned()
+            })
+            .unwrap();
+        assert_eq!(bar, "1:?programming  2:?math (2)");
+
+        // Switching to it marks them as read.
+        tabs.sender.send(TabAction::Switch(1)).unwrap();
+        tabs.handle_actions(&mut siv);
+        assert_eq!(tabs.active(), 1);
+        assert_eq!(siv.active_screen(), 1);
+        assert_eq!(tabs.tabs()[1].display.unread, 0);
+
+        // There's no tab to switch to.
+        tabs.switch(&mut siv, 5);
+        assert_eq!(tabs.active(), 1);
+
+        // The next tab after the last is the first.
+        tabs.sender.send(TabAction::Next).unwrap();
+        tabs.handle_actions(&mut siv);
+        assert_eq!(tabs.active(), 0);
+        assert_eq!(siv.active_screen(), 0);
+    }
+
+    #[test]
+    fn test_open() {
+        let mut siv = Cursive::new();
+        let mut tabs = test_tabs(&mut siv);
+        let (display, display_sender, _client) = test_display();
+        tabs.add(&mut siv, "programming".into(), display);
+
+        // Nothing is opened until we know who we've joined as.
+        tabs.open(&mut siv, "math".into());
+        assert_eq!(tabs.tabs().len(), 1);
+
+        display_sender
+            .send(DisplayAction::SetOwnNick("me".to_owned()))
+            .unwrap();
+        display_sender
+            .send(DisplayAction::OpenTab("math".into()))
+            .unwrap();
+        while tabs.handle_actions(&mut siv) {}
+        assert_eq!(tabs.tabs().len(), 2);
+        assert_eq!(tabs.active(), 1);
+        assert_eq!(siv.active_screen(), tabs.tabs()[1].screen);
+
+        // Opening it again switches back to it.
+        tabs.switch(&mut siv, 0);
+        tabs.open(&mut siv, "math".into());
+        assert_eq!(tabs.tabs().len(), 2);
+        assert_eq!(tabs.active(), 1);
+    }
+
+    #[test]
+    fn test_quit() {
+        let mut siv = Cursive::new();
+        let mut tabs = test_tabs(&mut siv);
+        let mut displays = Vec::new();
+        for channel in &["programming", "math"] {
+            let (display, display_sender, client) = test_display();
+            displays.push((display_sender, client));
+            tabs.add(&mut siv, Channel::from(*channel), display);
+        }
+        let now = Instant::now();
+        assert!(!tabs.has_quit(now));
+
+        tabs.sender.send(TabAction::Quit).unwrap();
+        tabs.handle_actions(&mut siv);
+        for (_, client) in &displays {
+            assert!(matches!(client.try_recv(), Ok(ClientAction::Quit)));
+        }
+        assert!(!tabs.has_quit(Instant::now()));
+
+        // Only once every connection has closed.
+        displays[0].0.send(DisplayAction::Exit).unwrap();
+        tabs.handle_actions(&mut siv);
+        assert!(!tabs.has_quit(Instant::now()));
+        displays[1].0.send(DisplayAction::Exit).unwrap();
+        tabs.handle_actions(&mut siv);
+        assert!(tabs.has_quit(Instant::now()));
+    }
+
+    #[test]
+    fn test_quit_timeout() {
+        let mut siv = Cursive::new();
+        let mut tabs = test_tabs(&mut siv);
+        let (display, _display_sender, _client) = test_display();
+        tabs.add(&mut siv, "programming".into(), display);
+
+        let now = Instant::now();
+        tabs.quit(now);
+        assert!(!tabs.has_quit(now));
+        // A connection that never closes doesn't keep us from stopping.
+        assert!(tabs.has_quit(now + Tabs::QUIT_GRACE));
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{ChatMessage, MessageName};
+
+/// Mirrors the displayed messages as plain text, for reading the chat outside of the TUI.
+pub struct Tee {
+    out: Box<dyn Write>,
+}
+impl Tee {
+    /// Opens the file at the path to append to, or stdout if the path is `-`.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let out: Box<dyn Write> = if path == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(Path::new(path))?,
+            )
+        };
+        Ok(Self { out })
+    }
+
+    pub fn write(&mut self, message: &ChatMessage) -> io::Result<()> {
+        writeln!(self.out, "{}", to_plain(message))?;
+        // Flushed so that whatever is following the output sees each message as it arrives.
+        self.out.flush()
+    }
+}
+
+/// Renders a message as plain text, with continuation lines indented beneath the first.
+pub fn to_plain(message: &ChatMessage) -> String {
+    let sender = match &message.from {
+        MessageName::User(nick) | MessageName::Whisper(nick) => match &message.trip {
+            Some(trip) => format!("{}#{}", nick, trip.0),
+            None => nick.clone(),
+        },
+        MessageName::Server => "*".to_owned(),
+        MessageName::ServerWarn => "!".to_owned(),
+        MessageName::None => String::new(),
+    };
+    let prefix = format!("{}| ", sender);
+    let indent = " ".repeat(prefix.chars().count());
+    let mut plain = String::with_capacity(prefix.len() + message.text.len());
+    for (i, line) in message.text.lines().enumerate() {
+        if i == 0 {
+            plain += &prefix;
+        } else {
+            plain.push('\n');
+            plain += &indent;
+        }
+        plain += line;
+    }
+    if plain.is_empty() {
+        plain = prefix;
+    }
+    plain
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use hack_chat_types::Trip;
+
+    use super::to_plain;
+    use crate::{ChatMessage, MessageName};
+
+    #[test]
+    fn test_to_plain() {
+        let message = |from, trip: Option<&str>, text: &str| ChatMessage {
+            from,
+            trip: trip.map(|trip| Trip(trip.to_owned())),
+            text: text.to_owned(),
+            timestamp: SystemTime::UNIX_EPOCH,
+            id: 0,
+        };
+
+        let user = |nick: &str| MessageName::User(nick.to_owned());
+        assert_eq!(to_plain(&message(user("bob"), None, "hi")), "bob| hi");
+        assert_eq!(
+            to_plain(&message(user("bob"), Some("abcdef"), "hi")),
+            "bob#abcdef| hi"
+        );
+        assert_eq!(
+            to_plain(&message(MessageName::Server, None, "bob joined")),
+            "*| bob joined"
+        );
+        assert_eq!(
+            to_plain(&message(MessageName::ServerWarn, None, "slow down")),
+            "!| slow down"
+        );
+        assert_eq!(to_plain(&message(user("bob"), None, "")), "bob| ");
+        // Later lines line up with the start of the text.
+        assert_eq!(
+            to_plain(&message(user("bob"), None, "one\ntwo")),
+            "bob| one\n     two"
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use cursive::theme::{
+    BaseColor, Color, ColorStyle, ColorType, Effect, Palette, PaletteColor, Style, Theme,
+};
+
+/// The colors that the chat is drawn with, along with cursive's own palette.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatTheme {
+    pub name: String,
+    pub nick: Style,
+    pub trip: Style,
+    /// The badge before the nicks of the channel's staff.
+    pub badge: Style,
+    /// The marker of messages from the server.
+    pub server: Style,
+    /// The marker of warnings from the server.
+    pub warn: Style,
+    /// Whispers, which only we were sent.
+    pub whisper: Style,
+    /// Seams between sections of the chat, such as where we reconnected.
+    pub seam: Style,
+    /// Messages that mention us, or one of the highlighted words.
+    pub mention: Style,
+    /// Where the text being searched for was found.
+    pub search: Style,
+    pub palette: Palette,
+}
+impl ChatTheme {
+    pub const DEFAULT: &'static str = "default";
+
+    /// The theme for cursive's views, such as the dialog around the chat.
+    pub fn cursive_theme(&self) -> Theme {
+        Theme {
+            palette: self.palette.clone(),
+            ..Theme::default()
+        }
+    }
+
+    /// Sets one of its styles, or the `background` or `text` color of the palette, by name.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let style = match key {
+            "background" | "text" => {
+                let color = parse_color(value)
+                    .ok_or_else(|| format!("'{}' isn't a color, for {}", value, key))?;
+                if key == "background" {
+                    self.palette[PaletteColor::Background] = color;
+                    self.palette[PaletteColor::View] = color;
+                } else {
+                    self.palette[PaletteColor::Primary] = color;
+                }
+                return Ok(());
+            }
+            "nick" => &mut self.nick,
+            "trip" => &mut self.trip,
+            "badge" => &mut self.badge,
+            "server" => &mut self.server,
+            "warn" => &mut self.warn,
+            "whisper" => &mut self.whisper,
+            "seam" => &mut self.seam,
+            "mention" => &mut self.mention,
+            "search" => &mut self.search,
+            _ => return Err(format!("There is no '{}' to style", key)),
+        };
+        *style = parse_style(value).map_err(|err| format!("{}, for {}", err, key))?;
+        Ok(())
+    }
+}
+impl Default for ChatTheme {
+    fn default() -> Self {
+        Self {
+            name: Self::DEFAULT.to_owned(),
+            nick: Style::none(),
+            trip: Style::merge(&[Effect::Italic.into(), color(Color::Rgb(0x33, 0x33, 0x33))]),
+            badge: Effect::Bold.into(),
+            server: Style::none(),
+            warn: Style::none(),
+            whisper: Style::merge(&[
+                Effect::Italic.into(),
+                color(Color::Dark(BaseColor::Magenta)),
+            ]),
+            seam: color(Color::Rgb(0x55, 0x55, 0x55)),
+            mention: Style::merge(&[Effect::Bold.into(), back(Color::Rgb(0xFF, 0xF0, 0xB3))]),
+            search: Effect::Reverse.into(),
+            palette: Palette::default(),
+        }
+    }
+}
+
+fn color(color: Color) -> Style {
+    ColorType::Color(color).into()
+}
+
+fn back(color: Color) -> Style {
+    ColorStyle::back(color).into()
+}
+
+fn dark() -> ChatTheme {
+    let mut palette = Palette::default();
+    palette[PaletteColor::Background] = Color::Dark(BaseColor::Black);
+    palette[PaletteColor::View] = Color::Dark(BaseColor::Black);
+    palette[PaletteColor::Primary] = Color::Light(BaseColor::White);
+    palette[PaletteColor::TitlePrimary] = Color::Light(BaseColor::Cyan);
+    ChatTheme {
+        name: "dark".to_owned(),
+        nick: color(Color::Light(BaseColor::Cyan)),
+        trip: Style::merge(&[Effect::Italic.into(), color(Color::Light(BaseColor::Black))]),
+        badge: Style::merge(&[Effect::Bold.into(), color(Color::Light(BaseColor::Green))]),
+        server: color(Color::Light(BaseColor::Yellow)),
+        warn: color(Color::Light(BaseColor::Red)),
+        whisper: Style::merge(&[
+            Effect::Italic.into(),
+            color(Color::Light(BaseColor::Magenta)),
+        ]),
+        seam: color(Color::Light(BaseColor::Black)),
+        mention: Style::merge(&[Effect::Bold.into(), back(Color::Dark(BaseColor::Blue))]),
+        search: Effect::Reverse.into(),
+        palette,
+    }
+}
+
+fn light() -> ChatTheme {
+    let mut palette = Palette::default();
+    palette[PaletteColor::Background] = Color::Light(BaseColor::White);
+    palette[PaletteColor::View] = Color::Light(BaseColor::White);
+    palette[PaletteColor::Primary] = Color::Dark(BaseColor::Black);
+    ChatTheme {
+        name: "light".to_owned(),
+        nick: color(Color::Dark(BaseColor::Blue)),
+        trip: Style::merge(&[Effect::Italic.into(), color(Color::Rgb(0x88, 0x88, 0x88))]),
+        badge: Style::merge(&[Effect::Bold.into(), color(Color::Dark(BaseColor::Green))]),
+        server: color(Color::Dark(BaseColor::Magenta)),
+        warn: color(Color::Dark(BaseColor::Red)),
+        whisper: Style::merge(&[
+            Effect::Italic.into(),
+            color(Color::Dark(BaseColor::Magenta)),
+        ]),
+        seam: color(Color::Rgb(0xAA, 0xAA, 0xAA)),
+        mention: Style::merge(&[Effect::Bold.into(), back(Color::Light(BaseColor::Yellow))]),
+        search: Effect::Reverse.into(),
+        palette,
+    }
+}
+
+/// The themes that come with fiskar.
+pub fn themes() -> Vec<ChatTheme> {
+    vec![ChatTheme::default(), dark(), light()]
+}
+
+/// The built-in themes along with those of the config's `[themes.<name>]` tables, which replace
+/// any built-in theme of the same name. Each starts from the built-in theme named by its `base`,
+/// or the default, and sets the styles and colors given by the rest of its keys.
+pub fn load_themes(
+    tables: &HashMap<String, HashMap<String, String>>,
+) -> Result<Vec<ChatTheme>, String> {
+    let mut loaded = themes();
+    // Sorted so that the themes are listed the same way each time.
+    let mut names: Vec<&String> = tables.keys().collect();
+    names.sort();
+    for name in names {
+        let table = &tables[name];
+        let base = table.get("base").map_or(ChatTheme::DEFAULT, String::as_str);
+        let mut theme = find_theme(&themes(), base)
+            .cloned()
+            .ok_or_else(|| format!("There is no theme '{}' for {} to be based on", base, name))?;
+        theme.name = name.clone();
+        for (key, value) in table.iter().filter(|(key, _)| *key != "base") {
+            theme
+                .set(key, value)
+                .map_err(|err| format!("{} in theme {}", err, name))?;
+        }
+        loaded.retain(|other| !other.name.eq_ignore_ascii_case(name));
+        loaded.push(theme);
+    }
+    Ok(loaded)
+}
+
+/// Finds the theme by its name, ignoring case.
+pub fn find_theme<'a>(themes: &'a [ChatTheme], name: &str) -> Option<&'a ChatTheme> {
+    themes
+        .iter()
+        .find(|theme| theme.name.eq_ignore_ascii_case(name))
+}
+
+/// Parses a style written as words, such as `bold italic light cyan on #202020`: any effects,
+/// the color of the text, and the color of the background after `on`. `none` is no style.
+pub fn parse_style(text: &str) -> Result<Style, String> {
+    let mut styles = Vec::new();
+    let mut background = false;
+    let mut words = text.split_whitespace();
+    while let Some(word) = words.next() {
+        let effect = match word {
+            "none" => continue,
+            "bold" => Effect::Bold,
+            "italic" => Effect::Italic,
+            "underline" => Effect::Underline,
+            "reverse" => Effect::Reverse,
+            "strikethrough" => Effect::Strikethrough,
+            "on" => {
+                background = true;
+                continue;
+            }
+            _ => {
+                let name = match word {
+                    "light" => format!("light {}", words.next().unwrap_or_default()),
+                    _ => word.to_owned(),
+                };
+                let color = parse_color(&name)
+                    .ok_or_else(|| format!("'{}' isn't an effect or a color", name))?;
+                styles.push(if background {
+                    back(color)
+                } else {
+                    self::color(color)
+                });
+                continue;
+            }
+        };
+        styles.push(effect.into());
+    }
+    Ok(Style::merge(&styles))
+}
+
+/// Parses one of the eight terminal colors, `light` ones, or `#rrggbb`.
+fn parse_color(text: &str) -> Option<Color> {
+    if let Some(hex) = text.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+    let (light, name) = match text.strip_prefix("light ") {
+        Some(name) => (true, name.trim()),
+        None => (false, text.trim()),
+    };
+    let base = match name {
+        "black" => BaseColor::Black,
+        "red" => BaseColor::Red,
+        "green" => BaseColor::Green,
+        "yellow" => BaseColor::Yellow,
+        "blue" => BaseColor::Blue,
+        "magenta" => BaseColor::Magenta,
+        "cyan" => BaseColor::Cyan,
+        "white" => BaseColor::White,
+        _ => return None,
+    };
+    Some(if light {
+        Color::Light(base)
+    } else {
+        Color::Dark(base)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cursive::theme::{BaseColor, Color, ColorStyle, ColorType, Effect, PaletteColor, Style};
+
+    use super::{find_theme, load_themes, parse_style, themes, ChatTheme};
+
+    #[test]
+    fn test_find_theme() {
+        let themes = themes();
+        assert_eq!(find_theme(&themes, "default"), Some(&ChatTheme::default()));
+        assert_eq!(
+            find_theme(&themes, "DARK").map(|theme| theme.name.as_str()),
+            Some("dark")
+        );
+        assert_eq!(find_theme(&themes, "missing"), None);
+
+        // Names have to be unique to be found.
+        let names: Vec<&str> = themes.iter().map(|theme| theme.name.as_str()).collect();
+        for name in names.iter() {
+            assert_eq!(names.iter().filter(|other| other == &name).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_parse_style() {
+        let front = |color| Style::from(ColorType::Color(color));
+        assert_eq!(parse_style("none"), Ok(Style::none()));
+        assert_eq!(parse_style("bold"), Ok(Effect::Bold.into()));
+        assert_eq!(
+            parse_style("italic light cyan"),
+            Ok(Style::merge(&[
+                Effect::Italic.into(),
+                front(Color::Light(BaseColor::Cyan))
+            ]))
+        );
+        assert_eq!(
+            parse_style("red on #102030"),
+            Ok(Style::merge(&[
+                front(Color::Dark(BaseColor::Red)),
+                ColorStyle::back(Color::Rgb(0x10, 0x20, 0x30)).into()
+            ]))
+        );
+        assert!(parse_style("bright red").is_err());
+        assert!(parse_style("#12345").is_err());
+        assert!(parse_style("light").is_err());
+    }
+
+    #[test]
+    fn test_load_themes() {
+        let table = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let mut tables = HashMap::new();
+        tables.insert(
+            "sea".to_owned(),
+            table(&[
+                ("base", "dark"),
+                ("nick", "blue"),
+                ("background", "#001020"),
+            ]),
+        );
+        tables.insert("light".to_owned(), table(&[("server", "bold")]));
+        let loaded = load_themes(&tables).unwrap();
+        assert_eq!(loaded.len(), themes().len() + 1);
+
+        let sea = find_theme(&loaded, "sea").unwrap();
+        let dark = find_theme(&themes(), "dark").unwrap().clone();
+        assert_eq!(
+            sea.nick,
+            ColorType::Color(Color::Dark(BaseColor::Blue)).into()
+        );
+        assert_eq!(sea.trip, dark.trip);
+        assert_eq!(
+            sea.palette[PaletteColor::Background],
+            Color::Rgb(0x00, 0x10, 0x20)
+        );
+        // The built-in theme is replaced by the one in the config.
+        let light = find_theme(&loaded, "light").unwrap();
+        assert_eq!(light.server, Effect::Bold.into());
+
+        let mut tables = HashMap::new();
+        tables.insert("bad".to_owned(), table(&[("nick", "fuchsia")]));
+        assert!(load_themes(&tables).is_err());
+        let mut tables = HashMap::new();
+        tables.insert("bad".to_owned(), table(&[("base", "missing")]));
+        assert!(load_themes(&tables).is_err());
+        let mut tables = HashMap::new();
+        tables.insert("bad".to_owned(), table(&[("border", "red")]));
+        assert!(load_themes(&tables).is_err());
+    }
+}
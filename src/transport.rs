@@ -0,0 +1,153 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use tungstenite::{client::AutoStream, Message, WebSocket};
+
+/// The socket that a connection reads from and writes to.
+/// This is a trait so that tests can replace the socket with an in-memory one.
+pub trait Transport {
+    fn read_message(&mut self) -> tungstenite::Result<Message>;
+
+    fn write_message(&mut self, message: Message) -> tungstenite::Result<()>;
+
+    /// Writes what earlier writes queued but couldn't send yet.
+    fn write_pending(&mut self) -> tungstenite::Result<()>;
+}
+impl Transport for WebSocket<AutoStream> {
+    fn read_message(&mut self) -> tungstenite::Result<Message> {
+        WebSocket::read_message(self)
+    }
+
+    fn write_message(&mut self, message: Message) -> tungstenite::Result<()> {
+        WebSocket::write_message(self, message)
+    }
+
+    fn write_pending(&mut self) -> tungstenite::Result<()> {
+        WebSocket::write_pending(self)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FrameDirection {
+    Inbound,
+    Outbound,
+}
+
+/// A websocket frame that was sent or received, kept for debugging the protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameRecord {
+    pub direction: FrameDirection,
+    pub time: SystemTime,
+    pub description: String,
+}
+impl FrameRecord {
+    pub fn new(direction: FrameDirection, message: &Message) -> Self {
+        let description = match message {
+            Message::Text(text) => format!("text: {}", text),
+            Message::Binary(data) => format!("binary: {} bytes", data.len()),
+            Message::Ping(data) => format!("ping: {} bytes", data.len()),
+            Message::Pong(data) => format!("pong: {} bytes", data.len()),
+            Message::Close(frame) => format!("close: {:?}", frame),
+        };
+        Self {
+            direction,
+            time: SystemTime::now(),
+            description,
+        }
+    }
+}
+impl fmt::Display for FrameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = self
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        let arrow = match self.direction {
+            FrameDirection::Inbound => "<-",
+            FrameDirection::Outbound => "->",
+        };
+        write!(
+            f,
+            "[{}.{:03}] {} {}",
+            time.as_secs(),
+            time.subsec_millis(),
+            arrow,
+            self.description
+        )
+    }
+}
+
+/// Called with every frame that is sent or received.
+pub type FrameObserver = Box<dyn FnMut(&FrameRecord)>;
+
+#[cfg(test)]
+pub mod mock {
+    use std::{cell::RefCell, collections::VecDeque, io, rc::Rc};
+
+    use tungstenite::Message;
+
+    use super::Transport;
+
+    #[derive(Debug, Default)]
+    pub struct MockState {
+        /// Messages that will be read, from the front.
+        pub inbound: VecDeque<Message>,
+        /// Messages that have been written.
+        pub outbound: Vec<Message>,
+        /// When set, written messages wait in `pending` until `write_pending` is called, like a
+        /// socket that would block.
+        pub hold_writes: bool,
+        pub pending: Vec<Message>,
+        /// How many of the next writes fail as if the send queue were full.
+        pub full_writes: usize,
+    }
+
+    /// A transport that reads from and writes to in-memory queues.
+    /// Clones share the same queues, so a test can keep one to inspect what a connection did.
+    #[derive(Debug, Clone, Default)]
+    pub struct MockTransport {
+        pub state: Rc<RefCell<MockState>>,
+    }
+    impl MockTransport {
+        pub fn push_inbound(&self, message: Message) {
+            self.state.borrow_mut().inbound.push_back(message);
+        }
+
+        pub fn outbound(&self) -> Vec<Message> {
+            self.state.borrow().outbound.clone()
+        }
+    }
+    impl Transport for MockTransport {
+        /// Behaves like a non-blocking socket with nothing to read when the queue is empty.
+        fn read_message(&mut self) -> tungstenite::Result<Message> {
+            self.state
+                .borrow_mut()
+                .inbound
+                .pop_front()
+                .ok_or_else(|| tungstenite::Error::Io(io::ErrorKind::WouldBlock.into()))
+        }
+
+        fn write_message(&mut self, message: Message) -> tungstenite::Result<()> {
+            let mut state = self.state.borrow_mut();
+            if state.full_writes > 0 {
+                state.full_writes -= 1;
+                return Err(tungstenite::Error::SendQueueFull(message));
+            }
+            if state.hold_writes {
+                state.pending.push(message);
+            } else {
+                state.outbound.push(message);
+            }
+            Ok(())
+        }
+
+        fn write_pending(&mut self) -> tungstenite::Result<()> {
+            let mut state = self.state.borrow_mut();
+            let pending = std::mem::take(&mut state.pending);
+            state.outbound.extend(pending);
+            Ok(())
+        }
+    }
+}